@@ -0,0 +1 @@
+ALTER TABLE backup_files DROP COLUMN hash
@@ -0,0 +1,6 @@
+CREATE TABLE source_hashes (
+  path BLOB NOT NULL PRIMARY KEY,
+  size BIGINT NOT NULL,
+  mtime BIGINT NOT NULL,
+  hash TEXT NOT NULL
+)
@@ -0,0 +1,7 @@
+CREATE TABLE pruned_files (
+  uuid BLOB NOT NULL PRIMARY KEY,
+  prune_uuid BLOB NOT NULL,
+  pruned_at BIGINT NOT NULL,
+  original_path BLOB NOT NULL,
+  trash_id BLOB
+)
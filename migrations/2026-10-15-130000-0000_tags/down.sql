@@ -0,0 +1,2 @@
+ALTER TABLE backup_files DROP COLUMN comment;
+ALTER TABLE backup_files DROP COLUMN tags;
@@ -0,0 +1,2 @@
+ALTER TABLE backup_files ADD COLUMN tags TEXT;
+ALTER TABLE backup_files ADD COLUMN comment TEXT;
@@ -0,0 +1,19 @@
+CREATE TABLE audit_log (
+  uuid BLOB NOT NULL PRIMARY KEY,
+  recorded_at BIGINT NOT NULL,
+  operation TEXT NOT NULL,
+  reason TEXT NOT NULL,
+  path BLOB NOT NULL,
+  size BIGINT,
+  hash TEXT
+);
+
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+  SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+  SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
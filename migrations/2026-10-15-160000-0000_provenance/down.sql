@@ -0,0 +1,3 @@
+ALTER TABLE backup_files DROP COLUMN tool_version;
+ALTER TABLE backup_files DROP COLUMN hostname;
+ALTER TABLE backup_files DROP COLUMN source_path;
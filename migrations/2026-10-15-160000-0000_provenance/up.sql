@@ -0,0 +1,3 @@
+ALTER TABLE backup_files ADD COLUMN source_path BLOB;
+ALTER TABLE backup_files ADD COLUMN hostname TEXT;
+ALTER TABLE backup_files ADD COLUMN tool_version TEXT;
@@ -0,0 +1 @@
+ALTER TABLE backup_files ADD COLUMN created_at BIGINT;
@@ -0,0 +1 @@
+ALTER TABLE backup_files ADD COLUMN quarantined_at BIGINT;
@@ -0,0 +1,3 @@
+ALTER TABLE backup_files DROP COLUMN promoted_tier;
+ALTER TABLE backup_files DROP COLUMN keep_quarterly;
+ALTER TABLE backup_files DROP COLUMN keep_weekly;
@@ -0,0 +1,3 @@
+ALTER TABLE backup_files ADD COLUMN keep_weekly INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE backup_files ADD COLUMN keep_quarterly INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE backup_files ADD COLUMN promoted_tier TEXT;
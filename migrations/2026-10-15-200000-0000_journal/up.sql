@@ -0,0 +1,7 @@
+CREATE TABLE journal (
+  uuid BLOB NOT NULL PRIMARY KEY,
+  started_at BIGINT NOT NULL,
+  operation TEXT NOT NULL,
+  path BLOB NOT NULL,
+  hash TEXT
+);
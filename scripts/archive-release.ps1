@@ -33,3 +33,10 @@ if ($IsWindows) {
 } else {
     tar cfJ ./target/$ArchiveName.tar.xz ./README.md ./LICENSE ./target/$TargetTriple/optimized/$bin ./CHANGELOG.md
 }
+
+# Plain binary with checksum, downloaded by `self-update`.
+$exe = if ($IsWindows) { ".exe" } else { "" }
+$BinaryName = "$bin-$TargetTriple$exe"
+Copy-Item ./target/$TargetTriple/optimized/$bin$exe ./target/$BinaryName
+$hash = (Get-FileHash -Algorithm SHA256 ./target/$BinaryName).Hash.ToLower()
+"$hash  $BinaryName" | Out-File -Encoding ascii -NoNewline ./target/$BinaryName.sha256
@@ -0,0 +1,281 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, Write},
+    path::Path,
+};
+
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+use color_eyre::{
+    Section,
+    eyre::{Context, Result},
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use zip::{AesMode, CompressionMethod, ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+/// Extension appended to backups stored as zip archive.
+pub const ZIP_EXTENSION: &str = "zip";
+
+/// Archive comment telling zip backups apart from backups of zip files.
+const ZIP_COMMENT: &str = "staggered-file-backup zip backup v1";
+
+/// Environment variable the password of encrypted zip backups is read from when restoring.
+pub const ZIP_PASSWORD_ENV: &str = "SFB_ZIP_PASSWORD";
+
+/// Extensions of formats which are already compressed and are stored without deflating them.
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "aac", "apk", "avif", "br", "bz2", "cab", "docx", "epub", "flac", "gif", "gz", "heic",
+    "jar", "jpeg", "jpg", "lz", "lz4", "lzma", "m4a", "mkv", "mov", "mp3", "mp4", "odt", "ogg",
+    "opus", "png", "pptx", "rar", "tgz", "txz", "webm", "webp", "xlsx", "xz", "zip", "zst",
+];
+
+/// Size of the sample at the start of the source its entropy is measured on.
+const ENTROPY_SAMPLE_SIZE: u64 = 1024 * 1024;
+
+/// Entropy in bits per byte above which the source is deemed compressed or encrypted.
+const ENTROPY_THRESHOLD: f64 = 7.5;
+
+/// How the backup is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Copy of the source file
+    Plain,
+    /// Zip archive containing the source file, openable without this tool
+    Zip,
+}
+
+/// Checks if the file is a zip backup, not a plain backup of a zip file.
+pub fn is_zip(path: impl AsRef<Path>) -> bool {
+    path.as_ref()
+        .extension()
+        .is_some_and(|ext| ext == ZIP_EXTENSION)
+        && File::open(path.as_ref())
+            .ok()
+            .and_then(|file| ZipArchive::new(BufReader::new(file)).ok())
+            .is_some_and(|archive| archive.comment() == ZIP_COMMENT.as_bytes())
+}
+
+/// Shannon entropy of the bytes in bits per byte.
+fn entropy(bytes: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for byte in bytes {
+        counts[usize::from(*byte)] += 1;
+    }
+
+    let len = bytes.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Chooses how the source is stored in the zip archive.
+///
+/// Deflating already compressed files costs time without saving space, so sources with a known
+/// compressed extension or a high entropy start are stored as is, unless compression is forced.
+fn compression_method(
+    source: &mut File,
+    path: &Path,
+    compress_force: bool,
+) -> Result<CompressionMethod> {
+    if compress_force {
+        return Ok(CompressionMethod::Deflated);
+    }
+
+    if path.extension().is_some_and(|ext| {
+        COMPRESSED_EXTENSIONS
+            .iter()
+            .any(|compressed| ext.eq_ignore_ascii_case(compressed))
+    }) {
+        return Ok(CompressionMethod::Stored);
+    }
+
+    let mut sample = vec![];
+    source
+        .take(ENTROPY_SAMPLE_SIZE)
+        .read_to_end(&mut sample)
+        .wrap_err("Failed to read source file.")?;
+    source.rewind().wrap_err("Failed to read source file.")?;
+
+    if !sample.is_empty() && entropy(&sample) > ENTROPY_THRESHOLD {
+        Ok(CompressionMethod::Stored)
+    } else {
+        Ok(CompressionMethod::Deflated)
+    }
+}
+
+/// Writes a zip archive containing the source file under the given name.
+///
+/// With a password the content is encrypted with AES-256, which Windows Explorer cannot open,
+/// but 7-Zip and most other archive tools can. Already compressed sources are stored without
+/// deflating them, unless `compress_force` is set.
+pub fn write_zip(
+    source: impl AsRef<Path>,
+    zip_path: impl AsRef<Path>,
+    entry_name: &str,
+    password: Option<&str>,
+    compress_force: bool,
+) -> Result<()> {
+    let mut source_file = File::open(source.as_ref()).wrap_err("Failed to open source file.")?;
+    let metadata = source_file
+        .metadata()
+        .wrap_err("Failed to read metadata of source file.")?;
+
+    let mut options = SimpleFileOptions::default()
+        .compression_method(compression_method(
+            &mut source_file,
+            source.as_ref(),
+            compress_force,
+        )?)
+        .large_file(metadata.len() >= u64::from(u32::MAX));
+    if let Ok(modified) = metadata.modified()
+        && let Ok(modified) = DateTime::<Local>::from(modified).naive_local().try_into()
+    {
+        options = options.last_modified_time(modified);
+    }
+    if let Some(password) = password {
+        options = options.with_aes_encryption(AesMode::Aes256, password);
+    }
+
+    let zip_file = File::create(zip_path.as_ref()).wrap_err("Failed to create zip backup.")?;
+    let mut writer = ZipWriter::new(BufWriter::new(zip_file));
+    writer
+        .start_file(entry_name, options)
+        .wrap_err("Failed to add source file to zip backup.")?;
+    io::copy(&mut source_file, &mut writer).wrap_err("Failed to write zip backup.")?;
+    writer.set_comment(ZIP_COMMENT)?;
+    writer
+        .finish()
+        .wrap_err("Failed to write zip backup.")?
+        .flush()
+        .wrap_err("Failed to write zip backup.")?;
+
+    Ok(())
+}
+
+/// Writes the content of the zip backup into the writer, decrypting it with the password.
+///
+/// Without password, the password of encrypted backups is read from `SFB_ZIP_PASSWORD`.
+pub fn write_zip_content(
+    zip_path: impl AsRef<Path>,
+    writer: &mut impl Write,
+    password: Option<&str>,
+) -> Result<()> {
+    let zip_file = File::open(zip_path.as_ref()).wrap_err("Failed to open zip backup.")?;
+    let mut archive =
+        ZipArchive::new(BufReader::new(zip_file)).wrap_err("Failed to read zip backup.")?;
+
+    let env_password = std::env::var(ZIP_PASSWORD_ENV).ok();
+    let entry = match password.or(env_password.as_deref()) {
+        Some(password) => archive.by_index_decrypt(0, password.as_bytes()),
+        None => archive.by_index(0),
+    };
+    let mut entry = entry
+        .wrap_err("Failed to read content of zip backup.")
+        .suggestion(format!(
+            "Set {} to the password of encrypted zip backups.",
+            ZIP_PASSWORD_ENV
+        ))?;
+
+    io::copy(&mut entry, writer).wrap_err("Failed to read content of zip backup.")?;
+
+    Ok(())
+}
+
+/// Hashes the content of the zip backup.
+pub fn hash_zip(zip_path: impl AsRef<Path>, password: Option<&str>) -> Result<String> {
+    let mut hasher = Sha256::new();
+    write_zip_content(zip_path, &mut hasher, password)?;
+    Ok(hex::encode_upper(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backup::test_util::TempDir;
+
+    #[test]
+    fn test_write_zip() {
+        let dir = std::env::temp_dir().join(format!("sfb-zip-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("db.sql");
+        std::fs::write(&source, b"CREATE TABLE backups;").unwrap();
+
+        let plain = dir.join("2025-01-01_00_db.sql.zip");
+        write_zip(&source, &plain, "db.sql", None, false).unwrap();
+        let encrypted = dir.join("2025-01-02_00_db.sql.zip");
+        write_zip(&source, &encrypted, "db.sql", Some("secret"), false).unwrap();
+        let mut content = vec![];
+        write_zip_content(&plain, &mut content, None).unwrap();
+        let mut decrypted = vec![];
+        write_zip_content(&encrypted, &mut decrypted, Some("secret")).unwrap();
+        let wrong_password = write_zip_content(&encrypted, &mut vec![], Some("wrong"));
+
+        let zip_of_zip = dir.join("2025-01-03_00_archive.zip");
+        let mut archive = ZipWriter::new(File::create(&zip_of_zip).unwrap());
+        archive
+            .start_file("db.sql", SimpleFileOptions::default())
+            .unwrap();
+        archive.finish().unwrap();
+        let is_zip_backup = is_zip(&plain);
+        let is_zip_of_zip = is_zip(&zip_of_zip);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(content, b"CREATE TABLE backups;");
+        assert_eq!(decrypted, b"CREATE TABLE backups;");
+        assert!(wrong_password.is_err());
+        assert!(is_zip_backup);
+        assert!(!is_zip_of_zip);
+    }
+
+    #[test]
+    fn test_write_zip_compression() {
+        let dir = TempDir::new("zip-compression");
+        let text = dir.join("notes.txt");
+        std::fs::write(&text, "backup ".repeat(1000)).unwrap();
+        let photo = dir.join("photo.JPG");
+        std::fs::write(&photo, "backup ".repeat(1000)).unwrap();
+        let random = dir.join("random.bin");
+        let mut random_bytes = vec![];
+        let mut block = Sha256::digest(b"seed");
+        for _ in 0..1024 {
+            random_bytes.extend_from_slice(&block);
+            block = Sha256::digest(block);
+        }
+        std::fs::write(&random, &random_bytes).unwrap();
+
+        let method = |source: &Path, compress_force: bool| {
+            let zip_path = dir.join("backup.zip");
+            write_zip(source, &zip_path, "entry", None, compress_force).unwrap();
+            let mut archive = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+            let method = archive.by_index(0).unwrap().compression();
+            let mut content = vec![];
+            write_zip_content(&zip_path, &mut content, None).unwrap();
+            assert_eq!(content, std::fs::read(source).unwrap());
+            method
+        };
+        let text_method = method(&text, false);
+        let photo_method = method(&photo, false);
+        let random_method = method(&random, false);
+        let forced_photo_method = method(&photo, true);
+        let forced_random_method = method(&random, true);
+
+        assert_eq!(text_method, CompressionMethod::Deflated);
+        assert_eq!(photo_method, CompressionMethod::Stored);
+        assert_eq!(random_method, CompressionMethod::Stored);
+        assert_eq!(forced_photo_method, CompressionMethod::Deflated);
+        assert_eq!(forced_random_method, CompressionMethod::Deflated);
+    }
+}
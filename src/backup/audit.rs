@@ -0,0 +1,200 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Local, Utc};
+use color_eyre::eyre::{Context, Result};
+use diesel::SqliteConnection;
+
+use crate::{
+    backup::{
+        cold::relative_backup_path,
+        db::{audit_entries, backup_file_with_relative_path, insert_audit_entries, open_db},
+        hash::hash_file,
+        list::format_size,
+    },
+    model::{AuditEntry, PathBufSql, UuidSQL},
+};
+
+/// Name of the audit log written into the target folder with `--audit-log`.
+pub const AUDIT_LOG_NAME: &str = "audit.log";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Moved into the recycle bin.
+    Trash,
+    /// Removed for good.
+    Delete,
+    /// Restored from the recycle bin.
+    Restore,
+    /// Moved into the quarantine folder after failing verification.
+    Quarantine,
+    /// Moved into the archive folder, as the recycle bin is unavailable.
+    Archive,
+}
+
+impl Operation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Trash => "trash",
+            Self::Delete => "delete",
+            Self::Restore => "restore",
+            Self::Quarantine => "quarantine",
+            Self::Archive => "archive",
+        }
+    }
+}
+
+/// Entry of the file with its current size, recorded at the current time.
+///
+/// Entries of files about to be removed have to be created before removing them.
+pub fn audit_entry(
+    operation: Operation,
+    reason: impl Into<String>,
+    path: &Path,
+    hash: Option<String>,
+) -> AuditEntry {
+    AuditEntry {
+        uuid: UuidSQL::new(),
+        recorded_at: Utc::now().timestamp_millis(),
+        operation: operation.as_str().to_owned(),
+        reason: reason.into(),
+        path: PathBufSql {
+            path: std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+        },
+        size: std::fs::metadata(path)
+            .ok()
+            .and_then(|metadata| i64::try_from(metadata.len()).ok()),
+        hash,
+    }
+}
+
+/// Entry of a file of the target folder, with the hash stored in the tracking database or else
+/// the hash of its content.
+pub fn file_audit_entry(
+    conn: &mut SqliteConnection,
+    target: &Path,
+    operation: Operation,
+    reason: impl Into<String>,
+    path: &Path,
+) -> Result<AuditEntry> {
+    let relative_path = relative_backup_path(conn, target, path)?;
+    let hash = match backup_file_with_relative_path(conn, relative_path)?.and_then(|row| row.hash) {
+        Some(hash) => hash,
+        None => hash_file(
+            &mut File::open(path)
+                .wrap_err_with(|| format!("Failed to open {} for hashing", path.display()))?,
+        )?,
+    };
+
+    Ok(audit_entry(operation, reason, path, Some(hash)))
+}
+
+fn format_entry(entry: &AuditEntry) -> String {
+    let recorded_at = DateTime::from_timestamp_millis(entry.recorded_at)
+        .map(|time| {
+            time.with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_default();
+    format!(
+        "{}  {:<10}  {:>10}  {:<64}  {}  ({})",
+        recorded_at,
+        entry.operation,
+        entry
+            .size
+            .map(|size| format_size(size as u64))
+            .unwrap_or_default(),
+        entry.hash.as_deref().unwrap_or("-"),
+        entry.path.display(),
+        entry.reason
+    )
+}
+
+/// Appends the entries to the audit log of the tracking database, and to `audit.log` in the target
+/// folder if `write_log` is set or the file exists from earlier runs.
+pub fn record_audit(
+    conn: &mut SqliteConnection,
+    target: &Path,
+    entries: &[AuditEntry],
+    write_log: bool,
+) -> Result<()> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+    insert_audit_entries(conn, entries)?;
+
+    let log_path = target.join(AUDIT_LOG_NAME);
+    if write_log || log_path.is_file() {
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)
+            .wrap_err_with(|| format!("Failed to open audit log {}", log_path.display()))?;
+        let lines: String = entries
+            .iter()
+            .map(|entry| format_entry(entry) + "\n")
+            .collect();
+        log.write_all(lines.as_bytes())
+            .wrap_err_with(|| format!("Failed to write audit log {}", log_path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Prints the audit log of the target folder, oldest first.
+pub fn list_audit(target: PathBuf) -> Result<()> {
+    let mut conn = open_db(&target)?;
+    for entry in audit_entries(&mut conn)? {
+        println!("{}", format_entry(&entry));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use diesel::prelude::*;
+
+    use super::*;
+    use crate::schema::audit_log;
+
+    #[test]
+    fn test_record_audit() {
+        let dir = std::env::temp_dir().join(format!("sfb-audit-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let backup_path = dir.join("2025-01-01_00_db.sql");
+        std::fs::write(&backup_path, b"backup").unwrap();
+
+        let mut conn = open_db(&dir).unwrap();
+        let entry =
+            file_audit_entry(&mut conn, &dir, Operation::Trash, "test", &backup_path).unwrap();
+        record_audit(&mut conn, &dir, &[entry], true).unwrap();
+
+        let entries = audit_entries(&mut conn).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].size, Some(6));
+        assert_eq!(
+            entries[0].hash.as_deref(),
+            Some(&*hash_file(&mut &b"backup"[..]).unwrap())
+        );
+        assert!(
+            std::fs::read_to_string(dir.join(AUDIT_LOG_NAME))
+                .unwrap()
+                .contains("trash")
+        );
+        assert!(diesel::delete(audit_log::table).execute(&mut conn).is_err());
+
+        drop(conn);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Context, Result};
+use serde::Serialize;
+
+use crate::backup::{
+    cleanup::{BackupFile, Tiers},
+    file::relative_path_string,
+    sidecar::sidecar_hash,
+};
+
+/// Name of the file listing all backups, kept in the target folder.
+pub const CATALOG_FILE_NAME: &str = "manifest.json";
+
+const CATALOG_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize)]
+struct Catalog {
+    version: u32,
+    backups: Vec<CatalogEntry>,
+}
+
+#[derive(Debug, Serialize)]
+struct CatalogEntry {
+    /// Path relative to the target folder, separated by `/`.
+    path: String,
+    /// Date of the backup as `YYYY-MM-DD`.
+    date: String,
+    counter: u32,
+    /// SHA-256 of the original content, as recorded in the hash file.
+    hash: Option<String>,
+    /// Size of the backup on disk in bytes.
+    size: u64,
+    /// Creation time of the backup in RFC 3339.
+    created: String,
+    tiers: Tiers,
+}
+
+fn catalog_entry(target_dir: &Path, file: &BackupFile, tiers: Tiers) -> Result<CatalogEntry> {
+    let metadata = std::fs::metadata(&file.path)
+        .wrap_err_with(|| format!("Failed reading metadata of {}", file.path.display()))?;
+    let created = metadata
+        .created()
+        .or_else(|_| metadata.modified())
+        .wrap_err_with(|| format!("Failed reading creation date of {}", file.path.display()))?;
+
+    Ok(CatalogEntry {
+        path: relative_path_string(target_dir, &file.path)?,
+        date: format!(
+            "{:04}-{:02}-{:02}",
+            file.metadata.year, file.metadata.month, file.metadata.day
+        ),
+        counter: file.metadata.counter,
+        hash: sidecar_hash(&file.path),
+        size: metadata.len(),
+        created: DateTime::<Utc>::from(created).to_rfc3339(),
+        tiers,
+    })
+}
+
+/// Writes the `manifest.json` listing all backups, so that the target folder stays
+/// self-describing without the tracking database.
+pub fn write_catalog(target_dir: impl AsRef<Path>, files: &[(BackupFile, Tiers)]) -> Result<()> {
+    let catalog = Catalog {
+        version: CATALOG_VERSION,
+        backups: files
+            .iter()
+            .map(|(file, tiers)| catalog_entry(target_dir.as_ref(), file, *tiers))
+            .collect::<Result<_>>()?,
+    };
+
+    let catalog_path = target_dir.as_ref().join(CATALOG_FILE_NAME);
+    let tmp_path = target_dir
+        .as_ref()
+        .join(format!("{}.tmp", CATALOG_FILE_NAME));
+
+    let mut writer = BufWriter::new(
+        File::create(&tmp_path)
+            .wrap_err_with(|| format!("Failed to create {}", CATALOG_FILE_NAME))?,
+    );
+    serde_json::to_writer_pretty(&mut writer, &catalog)?;
+    writer.write_all(b"\n")?;
+    writer
+        .flush()
+        .wrap_err_with(|| format!("Failed to write {}", CATALOG_FILE_NAME))?;
+    drop(writer);
+
+    std::fs::rename(&tmp_path, &catalog_path)
+        .wrap_err_with(|| format!("Failed to replace {}", CATALOG_FILE_NAME))?;
+
+    Ok(())
+}
@@ -0,0 +1,375 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Rolling manifest of the backups added to and removed from the target folder, kept with
+//! `--chain`.
+//!
+//! Each entry contains the hash of the previous entry, so that entries cannot be removed or
+//! rewritten without breaking the chain. Runs with `--sign-key` sign the chain, so that only the
+//! holder of the key can extend it. `verify --chain` replays the chain and detects backups deleted
+//! or replaced by anyone with write access to the target folder but without the key.
+
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use color_eyre::{
+    Section,
+    eyre::{Context, Result},
+};
+use diesel::SqliteConnection;
+use log::{error, info, warn};
+use minisign::{PublicKey, SecretKey, SignatureBox};
+use serde::{Deserialize, Serialize};
+
+use crate::backup::{
+    db::backup_file_with_relative_path,
+    hash::hash_bytes,
+    sidecar::{Integrity, check_backup_hash},
+    signature::signature_path,
+};
+
+/// Name of the chain in the target folder. Its signature is written next to it.
+pub const CHAIN_FILE_NAME: &str = "chain.jsonl";
+
+/// Previous hash of the first entry.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChainOperation {
+    /// Backup created, restored or moved to this path.
+    Add,
+    /// Backup pruned, quarantined or moved away from this path.
+    Remove,
+}
+
+/// Backup added to or removed from the target folder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainEvent {
+    pub operation: ChainOperation,
+    /// Path relative to the target folder, separated by `/`.
+    pub path: String,
+    /// SHA-256 of the original content, as recorded in the hash file.
+    pub hash: String,
+}
+
+impl ChainEvent {
+    pub fn new(operation: ChainOperation, path: impl AsRef<Path>, hash: impl Into<String>) -> Self {
+        Self {
+            operation,
+            path: path.as_ref().to_string_lossy().replace('\\', "/"),
+            hash: hash.into(),
+        }
+    }
+}
+
+/// Line of the chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ChainEntry {
+    seq: u64,
+    /// Unix time in milliseconds.
+    recorded_at: i64,
+    operation: ChainOperation,
+    path: String,
+    hash: String,
+    /// SHA-256 of the previous line.
+    previous: String,
+}
+
+fn chain_path(target: &Path) -> PathBuf {
+    target.join(CHAIN_FILE_NAME)
+}
+
+/// Sequence number and hash of the last line of the chain.
+fn chain_head(content: &str) -> Result<(u64, String)> {
+    match content.lines().last() {
+        Some(line) => {
+            let entry: ChainEntry =
+                serde_json::from_str(line).wrap_err("Failed to parse last entry of the chain.")?;
+            Ok((entry.seq + 1, hash_bytes(line.as_bytes())))
+        }
+        None => Ok((0, GENESIS_HASH.to_owned())),
+    }
+}
+
+/// Appends the events to the chain of the target folder, if `start` is set or the chain exists
+/// from earlier runs.
+pub fn append_chain(target: &Path, events: &[ChainEvent], start: bool) -> Result<()> {
+    let path = chain_path(target);
+    if events.is_empty() || !(start || path.is_file()) {
+        return Ok(());
+    }
+
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).wrap_err("Failed to read chain."),
+    };
+    let (mut seq, mut previous) = chain_head(&content)?;
+
+    let mut lines = String::new();
+    for event in events {
+        let line = serde_json::to_string(&ChainEntry {
+            seq,
+            recorded_at: Utc::now().timestamp_millis(),
+            operation: event.operation,
+            path: event.path.clone(),
+            hash: event.hash.clone(),
+            previous,
+        })?;
+        previous = hash_bytes(line.as_bytes());
+        seq += 1;
+        lines.push_str(&line);
+        lines.push('\n');
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .wrap_err("Failed to open chain.")?;
+    file.write_all(lines.as_bytes())
+        .and_then(|()| file.sync_all())
+        .wrap_err("Failed to append to chain.")
+}
+
+/// Signs all entries of the chain of the target folder, if it exists.
+///
+/// The trusted comment names the number of signed entries, so that entries appended later by
+/// commands without the key are told apart from tampered ones.
+pub fn sign_chain(target: &Path, secret_key: &SecretKey) -> Result<()> {
+    let path = chain_path(target);
+    if !path.is_file() {
+        return Ok(());
+    }
+    let content = std::fs::read(&path).wrap_err("Failed to read chain.")?;
+    let entry_count = content.iter().filter(|byte| **byte == b'\n').count();
+    let trusted_comment = format!(
+        "timestamp:{}\tentries:{}",
+        Utc::now().timestamp(),
+        entry_count
+    );
+
+    let signature = minisign::sign(
+        None,
+        secret_key,
+        Cursor::new(&content),
+        Some(&trusted_comment),
+        None,
+    )
+    .wrap_err("Failed to sign chain.")?;
+    std::fs::write(signature_path(&path), signature.to_string())
+        .wrap_err("Failed to write signature of chain.")
+}
+
+/// Number of entries the signature covers, if it is valid.
+fn signed_entry_count(public_key: &PublicKey, chain_path: &Path, content: &str) -> Option<usize> {
+    let signature = SignatureBox::from_file(signature_path(chain_path)).ok()?;
+    let entry_count: usize = signature
+        .trusted_comment()
+        .ok()?
+        .split('\t')
+        .find_map(|field| field.strip_prefix("entries:"))?
+        .parse()
+        .ok()?;
+    let signed_len: usize = content
+        .split_inclusive('\n')
+        .take(entry_count)
+        .map(str::len)
+        .sum();
+
+    minisign::verify(
+        public_key,
+        &signature,
+        Cursor::new(&content.as_bytes()[..signed_len]),
+        true,
+        false,
+        false,
+    )
+    .is_ok()
+    .then_some(entry_count)
+}
+
+/// Checks that the entries link to each other and returns the backups they leave behind, by path.
+///
+/// Logs each problem and counts it in `problem_count`.
+fn replay(content: &str, problem_count: &mut usize) -> HashMap<String, String> {
+    let mut backups = HashMap::new();
+    let mut previous = GENESIS_HASH.to_owned();
+    let mut seq = 0;
+
+    for (index, line) in content.lines().enumerate() {
+        let entry: ChainEntry = match serde_json::from_str(line) {
+            Ok(entry) => entry,
+            Err(err) => {
+                error!("Entry {} of the chain is unreadable: {}", index, err);
+                *problem_count += 1;
+                previous = hash_bytes(line.as_bytes());
+                continue;
+            }
+        };
+        if entry.seq != seq || entry.previous != previous {
+            error!(
+                "Entry {} of the chain does not follow the previous entry. Entries were removed, inserted or rewritten.",
+                index
+            );
+            *problem_count += 1;
+        }
+        previous = hash_bytes(line.as_bytes());
+        seq = entry.seq + 1;
+
+        match entry.operation {
+            ChainOperation::Add => {
+                backups.insert(entry.path, entry.hash);
+            }
+            ChainOperation::Remove => {
+                backups.remove(&entry.path);
+            }
+        }
+    }
+
+    backups
+}
+
+/// Path of the backup in the target folder, or in the cold storage folder it was moved to.
+fn locate_backup(conn: &mut SqliteConnection, target: &Path, relative_path: &str) -> PathBuf {
+    let path = target.join(relative_path);
+    if path.exists() {
+        return path;
+    }
+
+    backup_file_with_relative_path(conn, Path::new(relative_path))
+        .ok()
+        .flatten()
+        .and_then(|row| row.cold_target)
+        .map(|cold_target| cold_target.join(relative_path))
+        .unwrap_or(path)
+}
+
+/// Checks the chain of the target folder and that every backup it leaves behind exists with its
+/// recorded content.
+///
+/// Returns the number of problems found, each of them logged.
+pub fn check_chain(
+    conn: &mut SqliteConnection,
+    target: &Path,
+    public_key: Option<&PublicKey>,
+) -> Result<usize> {
+    let path = chain_path(target);
+    let content = std::fs::read_to_string(&path)
+        .wrap_err("Failed to read chain.")
+        .suggestion("Start the chain by backing up with `--chain`.")?;
+    let mut problem_count = 0;
+
+    let backups = replay(&content, &mut problem_count);
+    let entry_count = content.lines().count();
+    info!("Replayed {} entries of the chain.", entry_count);
+
+    if let Some(public_key) = public_key {
+        match signed_entry_count(public_key, &path, &content) {
+            Some(signed_count) if signed_count < entry_count => warn!(
+                "The last {} entries of the chain are not signed yet. They are signed by the next backup with `--sign-key`.",
+                entry_count - signed_count
+            ),
+            Some(_) => info!("Signature of the chain is valid."),
+            None => {
+                error!("Signature of the chain is missing or invalid!");
+                problem_count += 1;
+            }
+        }
+    }
+
+    let mut backups: Vec<_> = backups.into_iter().collect();
+    backups.sort();
+    for (relative_path, hash) in backups {
+        let backup_path = locate_backup(conn, target, &relative_path);
+        if !backup_path.exists() {
+            error!(
+                "Backup {} recorded in the chain is missing!",
+                backup_path.display()
+            );
+            problem_count += 1;
+        } else if check_backup_hash(target, &backup_path, &hash) != Integrity::Intact {
+            error!(
+                "Backup {} differs from the backup recorded in the chain!",
+                backup_path.display()
+            );
+            problem_count += 1;
+        }
+    }
+
+    Ok(problem_count)
+}
+
+#[cfg(test)]
+mod test {
+    use minisign::KeyPair;
+
+    use super::*;
+    use crate::backup::{
+        db::open_db,
+        sidecar::{SidecarFormat, write_sidecar},
+    };
+
+    #[test]
+    fn test_chain() {
+        let dir = std::env::temp_dir().join(format!("sfb-chain-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut conn = open_db(&dir).unwrap();
+        let KeyPair { pk, sk } = KeyPair::generate_unencrypted_keypair().unwrap();
+        let hash = hash_bytes(b"content");
+        for name in ["2025-01-01_00_a.txt", "2025-01-02_00_a.txt"] {
+            std::fs::write(dir.join(name), b"content").unwrap();
+            write_sidecar(dir.join(name), &hash, SidecarFormat::Gnu).unwrap();
+        }
+
+        append_chain(
+            &dir,
+            &[
+                ChainEvent::new(ChainOperation::Add, "2025-01-01_00_a.txt", &hash),
+                ChainEvent::new(ChainOperation::Add, "2025-01-02_00_a.txt", &hash),
+                ChainEvent::new(ChainOperation::Add, "2025-01-03_00_a.txt", &hash),
+                ChainEvent::new(ChainOperation::Remove, "2025-01-03_00_a.txt", &hash),
+            ],
+            true,
+        )
+        .unwrap();
+        sign_chain(&dir, &sk).unwrap();
+        assert_eq!(check_chain(&mut conn, &dir, Some(&pk)).unwrap(), 0);
+
+        // Entries appended without the key are not signed yet, but not tampered with.
+        std::fs::remove_file(dir.join("2025-01-02_00_a.txt")).unwrap();
+        append_chain(
+            &dir,
+            &[ChainEvent::new(
+                ChainOperation::Remove,
+                "2025-01-02_00_a.txt",
+                &hash,
+            )],
+            false,
+        )
+        .unwrap();
+        assert_eq!(check_chain(&mut conn, &dir, Some(&pk)).unwrap(), 0);
+
+        std::fs::write(dir.join("2025-01-01_00_a.txt"), b"replaced").unwrap();
+        assert_eq!(check_chain(&mut conn, &dir, Some(&pk)).unwrap(), 1);
+        std::fs::write(dir.join("2025-01-01_00_a.txt"), b"content").unwrap();
+
+        // Dropping the first entry breaks the chain and its signature.
+        let content = std::fs::read_to_string(chain_path(&dir)).unwrap();
+        let (_, rest) = content.split_once('\n').unwrap();
+        std::fs::write(chain_path(&dir), rest).unwrap();
+        assert!(check_chain(&mut conn, &dir, Some(&pk)).unwrap() >= 2);
+
+        drop(conn);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
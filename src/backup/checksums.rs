@@ -0,0 +1,97 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{fs::File, path::PathBuf};
+
+use color_eyre::{
+    Section,
+    eyre::{Context, Result, eyre},
+};
+use log::info;
+
+use crate::{
+    backup::{
+        archive::is_zip,
+        cold::relative_backup_path,
+        db::{backup_file_with_relative_path, open_db},
+        delta::is_delta,
+        file::relative_path_string,
+        hash::hash_file,
+        parsing::metadata_from_directory,
+        sidecar::{SidecarFormat, read_sidecar},
+        store::is_manifest,
+        template::NameTemplate,
+    },
+    exit_code::{ExitCode, WithExitCode},
+};
+
+/// Name of the file listing the hashes of all backups, written into the target folder by default.
+pub const CHECKSUMS_FILE_NAME: &str = "SHA256SUMS";
+
+/// Writes the hashes of all backups of the target folder into one checksum file, with paths
+/// relative to the target folder.
+///
+/// Plain backups are listed with the hash recorded when they were created, so that corruption
+/// since then is detected. Chunked, incremental and zip backups differ from the source and are
+/// listed with the hash of the file as it is now.
+pub fn export_checksums(
+    target: PathBuf,
+    name_template: &NameTemplate,
+    format: SidecarFormat,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    if format == SidecarFormat::None {
+        return Err(eyre!("Checksums cannot be exported without format."))
+            .suggestion("Use `--format gnu` or `--format bsd`.")
+            .exit_code(ExitCode::Usage);
+    }
+    let output = output.unwrap_or_else(|| target.join(CHECKSUMS_FILE_NAME));
+    let mut conn = open_db(&target)?;
+
+    let mut backup_files = metadata_from_directory(&target, name_template)?;
+    backup_files.sort();
+
+    let mut content = String::new();
+    for file in &backup_files {
+        let recorded_hash = if is_manifest(&file.path) || is_delta(&file.path) || is_zip(&file.path)
+        {
+            None
+        } else {
+            match read_sidecar(&file.path) {
+                Some(sidecar) if sidecar.references(&file.path) => Some(sidecar.hash),
+                _ => {
+                    let relative_path = relative_backup_path(&mut conn, &target, &file.path)?;
+                    backup_file_with_relative_path(&mut conn, relative_path)?
+                        .and_then(|row| row.hash)
+                }
+            }
+        };
+        let hash = match recorded_hash {
+            Some(hash) => hash,
+            None => {
+                info!("Hashing {}", file.path.display());
+                hash_file(&mut File::open(&file.path).wrap_err_with(|| {
+                    format!("Failed to open {} for hashing", file.path.display())
+                })?)?
+            }
+        };
+
+        if let Some(line) = format.line(&hash, &relative_path_string(&target, &file.path)?) {
+            content.push_str(&line);
+        }
+    }
+
+    std::fs::write(&output, content)
+        .wrap_err_with(|| format!("Failed to write {}", output.display()))?;
+    info!(
+        "Wrote hashes of {} backups to {}.",
+        backup_files.len(),
+        output.display()
+    );
+    info!("DONE!");
+
+    Ok(())
+}
@@ -4,13 +4,185 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::{cmp::Ordering, path::PathBuf};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+};
 
+use chrono::Datelike;
+use clap::ValueEnum;
 use color_eyre::eyre::{Ok, Result};
 use log::warn;
+use serde::{Deserialize, Serialize};
 
 use crate::backup::parsing::FileNameMetadata;
 
+/// Which backups of a target folder the retention periods apply to together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RetentionScope {
+    /// Backups of each source file of this host separately, leaving backups of other hosts sharing
+    /// the target folder to them
+    PerHost,
+    /// All backups of the target folder together
+    Global,
+}
+
+/// Algorithm selecting the backups to keep when pruning.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Keep the newest backups, and the first or last backup of each of the last days, months and
+    /// years
+    #[default]
+    Tiered,
+    /// Keep the newest backups, and one backup per age range, each range twice as long as the
+    /// previous one
+    Exponential,
+}
+
+/// Which backup of each period the tiered strategy keeps.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PeriodAnchor {
+    /// The first backup of the period
+    #[default]
+    First,
+    /// The last backup of the period, i.e. the state at its end
+    Last,
+}
+
+/// Decides which backups of a source are kept when pruning.
+pub trait RetentionStrategy {
+    /// Backups of the list to keep, oldest first.
+    fn files_to_keep(&self, file_list: &[BackupFile]) -> Result<Vec<BackupFile>>;
+}
+
+/// Calendar period backups are grouped by, keeping one backup of each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Day,
+    /// ISO week, starting on Monday and belonging to the year its Thursday is in.
+    Week,
+    Month,
+    /// Calendar quarter, starting in January, April, July and October.
+    Quarter,
+    Year,
+}
+
+impl Period {
+    /// Key shared by the backups of the same period.
+    fn key(self, metadata: &FileNameMetadata) -> (u32, u32, u32) {
+        match self {
+            Self::Day => (metadata.year, metadata.month, metadata.day),
+            Self::Week => match metadata.date() {
+                Some(date) => {
+                    let week = date.iso_week();
+                    (
+                        u32::try_from(week.year()).unwrap_or_default(),
+                        0,
+                        week.week(),
+                    )
+                }
+                // Backups with invalid dates form a period of their own.
+                None => (metadata.year, metadata.month, metadata.day),
+            },
+            Self::Month => (metadata.year, metadata.month, 0),
+            Self::Quarter => (metadata.year, metadata.month.saturating_sub(1) / 3, 0),
+            Self::Year => (metadata.year, 0, 0),
+        }
+    }
+}
+
+/// Strategy keeping the newest `keep_latest` backups, and the first or last backup of each of the
+/// last `keep_daily` days, `keep_weekly` weeks, `keep_monthly` months, `keep_quarterly` quarters
+/// and `keep_yearly` years with backups.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Tiered {
+    pub keep_latest: Option<u32>,
+    pub keep_daily: Option<u32>,
+    pub keep_weekly: Option<u32>,
+    pub keep_monthly: Option<u32>,
+    pub keep_quarterly: Option<u32>,
+    pub keep_yearly: Option<u32>,
+    pub anchor: PeriodAnchor,
+}
+
+impl Tiered {
+    /// Number of periods to keep a backup of, for each period.
+    fn periods(&self) -> [(Period, Option<u32>); 5] {
+        [
+            (Period::Day, self.keep_daily),
+            (Period::Week, self.keep_weekly),
+            (Period::Month, self.keep_monthly),
+            (Period::Quarter, self.keep_quarterly),
+            (Period::Year, self.keep_yearly),
+        ]
+    }
+}
+
+impl RetentionStrategy for Tiered {
+    fn files_to_keep(&self, file_list: &[BackupFile]) -> Result<Vec<BackupFile>> {
+        identify_files_to_keep(file_list, self)
+    }
+}
+
+/// Strategy keeping the newest `keep_latest` backups, and the first backup of each age range in
+/// days before the newest backup: 0, 1, 2 to 3, 4 to 7, 8 to 15 and so on.
+///
+/// The kept backups thin out smoothly with age, about one per doubling of the history.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Exponential {
+    pub keep_latest: Option<u32>,
+}
+
+impl RetentionStrategy for Exponential {
+    fn files_to_keep(&self, file_list: &[BackupFile]) -> Result<Vec<BackupFile>> {
+        let mut keep = identify_files_to_keep(
+            file_list,
+            &Tiered {
+                keep_latest: self.keep_latest,
+                ..Default::default()
+            },
+        )?;
+
+        let mut file_list = file_list.to_vec();
+        file_list.sort();
+        let Some(newest) = file_list.last().and_then(|file| file.metadata.date()) else {
+            return Ok(keep);
+        };
+
+        let mut ranges: BTreeMap<u32, &BackupFile> = BTreeMap::new();
+        for file in &file_list {
+            let Some(date) = file.metadata.date() else {
+                warn!("Keeping {} with invalid date.", file.path.display());
+                keep.push(file.clone());
+                continue;
+            };
+            let age = u64::try_from((newest - date).num_days()).unwrap_or_default();
+            // Index of the highest set bit, 0 for backups of the day of the newest backup.
+            let range = u64::BITS - age.leading_zeros();
+            ranges.entry(range).or_insert(file);
+        }
+        keep.extend(ranges.into_values().cloned());
+
+        let mut keep = dedup_by_path(keep);
+        keep.sort();
+
+        Ok(keep)
+    }
+}
+
+/// What a backup does once `--max-per-day` backups of the source were created that day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CapMode {
+    /// Skip the backup
+    Skip,
+    /// Replace the newest backup of the day
+    Replace,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct BackupFile {
     pub metadata: FileNameMetadata,
@@ -29,123 +201,312 @@ impl PartialOrd for BackupFile {
     }
 }
 
+/// Paths of the files, to look up whether a file is among them in constant time.
+fn path_set(files: &[BackupFile]) -> HashSet<&Path> {
+    files.iter().map(|file| file.path.as_path()).collect()
+}
+
+/// Removes files listed more than once, keeping the first occurrence.
+pub fn dedup_by_path(files: Vec<BackupFile>) -> Vec<BackupFile> {
+    let mut seen = HashSet::new();
+    files
+        .into_iter()
+        .filter(|file| seen.insert(file.path.clone()))
+        .collect()
+}
+
+/// Picks the first or last backup of each period from the sorted backups, oldest first.
+fn one_per_period(
+    file_list: &[BackupFile],
+    period: Period,
+    anchor: PeriodAnchor,
+) -> Vec<&BackupFile> {
+    let mut filtered: Vec<&BackupFile> = vec![];
+    for file in file_list {
+        match filtered.last_mut() {
+            Some(last) if period.key(&last.metadata) == period.key(&file.metadata) => {
+                if anchor == PeriodAnchor::Last {
+                    *last = file;
+                }
+            }
+            _ => filtered.push(file),
+        }
+    }
+
+    filtered
+}
+
+/// Backups of the newest `count` periods with backups, one of each, from the sorted backups.
+fn keep_periods(
+    file_list: &[BackupFile],
+    period: Period,
+    count: u32,
+    anchor: PeriodAnchor,
+) -> Vec<BackupFile> {
+    let mut filtered = one_per_period(file_list, period, anchor);
+    let start_index = filtered.len().saturating_sub(count as usize);
+
+    filtered.drain(start_index..).cloned().collect()
+}
+
+/// The newest `keep_latest` backups of the sorted backups, none if unset.
+pub fn newest_files(file_list: &[BackupFile], keep_latest: Option<u32>) -> Result<Vec<BackupFile>> {
+    let Some(keep_latest) = keep_latest else {
+        return Ok(vec![]);
+    };
+    let start_index = file_list
+        .len()
+        .saturating_sub(usize::try_from(keep_latest)?);
+
+    Ok(file_list[start_index..].to_vec())
+}
+
+/// First backup of each content epoch, a run of consecutive backups with the same hash, oldest
+/// first.
+///
+/// Backups without a known hash start an epoch of their own.
+pub fn content_epochs(
+    file_list: &[BackupFile],
+    mut hash_of: impl FnMut(&BackupFile) -> Option<String>,
+) -> Vec<BackupFile> {
+    let mut file_list = file_list.to_vec();
+    file_list.sort();
+
+    let mut previous_hash = None;
+    file_list
+        .into_iter()
+        .filter(|file| {
+            let hash = hash_of(file);
+            let changed = hash.is_none() || hash != previous_hash;
+            previous_hash = hash;
+            changed
+        })
+        .collect()
+}
+
 pub fn identify_files_to_keep(
-    file_list: &Vec<BackupFile>,
-    keep_latest: Option<u32>,
-    keep_daily: Option<u32>,
-    keep_monthly: Option<u32>,
-    keep_yearly: Option<u32>,
+    file_list: &[BackupFile],
+    retention: &Tiered,
 ) -> Result<Vec<BackupFile>> {
     if file_list.is_empty() {
         warn!("No files are backed up! Cleanup skipped.");
         return Ok(vec![]);
     }
 
-    let mut file_list = file_list.clone();
+    let mut file_list = file_list.to_vec();
     file_list.sort();
     let file_list = file_list;
 
-    let mut keep = vec![];
+    let mut keep = newest_files(&file_list, retention.keep_latest)?;
 
-    if let Some(keep_latest) = keep_latest {
-        let keep_latest = usize::try_from(keep_latest)?;
-        let start_index = if file_list.len() >= keep_latest {
-            file_list.len() - keep_latest
-        } else {
-            0
-        };
-        keep.extend_from_slice(&file_list[start_index..]);
+    for (period, count) in retention.periods() {
+        if let Some(count) = count {
+            keep.extend(keep_periods(&file_list, period, count, retention.anchor));
+        }
     }
 
-    if let Some(keep_daily) = keep_daily {
-        let mut filtered = vec![];
-        filtered.push(file_list.first().unwrap());
-        for file in file_list.iter() {
-            if filtered.last().unwrap().metadata.year != file.metadata.year
-                || filtered.last().unwrap().metadata.month != file.metadata.month
-                || filtered.last().unwrap().metadata.day != file.metadata.day
-            {
-                filtered.push(file);
-            }
-        }
+    let mut keep_dedup = dedup_by_path(keep);
+    keep_dedup.sort();
 
-        let mut count = 0;
-        while let Some(file) = filtered.pop() {
-            if count == keep_daily {
-                break;
-            }
+    Ok(keep_dedup)
+}
 
-            keep.push(file.clone());
-            count += 1;
-        }
+/// Extends the files to keep by the newest intact backup of each source without any kept intact
+/// backup.
+///
+/// If no backup of a source is intact, its newest backup is kept regardless.
+pub fn with_last_backups(
+    file_list: &[BackupFile],
+    mut files_to_keep: Vec<BackupFile>,
+    source_of: impl Fn(&BackupFile) -> Option<String>,
+    mut is_intact: impl FnMut(&BackupFile) -> bool,
+) -> Vec<BackupFile> {
+    let mut sources: BTreeMap<Option<String>, Vec<&BackupFile>> = BTreeMap::new();
+    for file in file_list {
+        sources.entry(source_of(file)).or_default().push(file);
     }
 
-    if let Some(keep_monthly) = keep_monthly {
-        let mut filtered = vec![];
-        filtered.push(file_list.first().unwrap());
-        for file in file_list.iter() {
-            if filtered.last().unwrap().metadata.year != file.metadata.year
-                || filtered.last().unwrap().metadata.month != file.metadata.month
-            {
-                filtered.push(file);
-            }
+    let kept: HashSet<PathBuf> = files_to_keep.iter().map(|file| file.path.clone()).collect();
+    for (source, mut files) in sources {
+        if files
+            .iter()
+            .any(|file| kept.contains(&file.path) && is_intact(file))
+        {
+            continue;
         }
 
-        let mut count = 0;
-        while let Some(file) = filtered.pop() {
-            if count == keep_monthly {
-                break;
+        files.sort();
+        let last = match files.iter().rev().find(|file| is_intact(file)) {
+            Some(file) => file,
+            None => {
+                warn!(
+                    "No intact backup of {} found. Keeping the newest one regardless.",
+                    source.as_deref().unwrap_or("unknown source")
+                );
+                files.last().expect("Source without backups")
             }
+        };
+        if !kept.contains(&last.path) {
+            files_to_keep.push((*last).clone());
+        }
+    }
+
+    files_to_keep.sort();
 
-            keep.push(file.clone());
-            count += 1;
+    files_to_keep
+}
+
+/// Retention tiers protecting a backup.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Tiers {
+    pub latest: bool,
+    pub daily: bool,
+    pub weekly: bool,
+    pub monthly: bool,
+    pub quarterly: bool,
+    pub yearly: bool,
+}
+
+impl Tiers {
+    /// Checks if the tier protects the backup.
+    pub fn contains(self, tier: Tier) -> bool {
+        match tier {
+            Tier::Latest => self.latest,
+            Tier::Daily => self.daily,
+            Tier::Weekly => self.weekly,
+            Tier::Monthly => self.monthly,
+            Tier::Quarterly => self.quarterly,
+            Tier::Yearly => self.yearly,
         }
     }
 
-    if let Some(keep_yearly) = keep_yearly {
-        let mut filtered = vec![];
-        filtered.push(file_list.first().unwrap());
-        for file in file_list.iter() {
-            if filtered.last().unwrap().metadata.year != file.metadata.year {
-                filtered.push(file);
-            }
+    /// Sets whether the tier protects the backup.
+    pub fn set(&mut self, tier: Tier, protected: bool) {
+        match tier {
+            Tier::Latest => self.latest = protected,
+            Tier::Daily => self.daily = protected,
+            Tier::Weekly => self.weekly = protected,
+            Tier::Monthly => self.monthly = protected,
+            Tier::Quarterly => self.quarterly = protected,
+            Tier::Yearly => self.yearly = protected,
         }
+    }
+}
 
-        let mut count = 0;
-        while let Some(file) = filtered.pop() {
-            if count == keep_yearly {
-                break;
-            }
+/// Retention tier, which backups can be promoted into by hand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Tier {
+    Latest,
+    Daily,
+    Weekly,
+    Monthly,
+    Quarterly,
+    Yearly,
+}
 
-            keep.push(file.clone());
-            count += 1;
+impl Tier {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Latest => "latest",
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+            Self::Monthly => "monthly",
+            Self::Quarterly => "quarterly",
+            Self::Yearly => "yearly",
         }
     }
 
-    let mut keep_dedup = vec![];
-    for file in keep.into_iter() {
-        if !keep_dedup.contains(&file) {
-            keep_dedup.push(file);
-        }
+    /// Tier with the name returned by [`Tier::as_str`].
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::value_variants()
+            .iter()
+            .copied()
+            .find(|tier| tier.as_str() == name)
     }
+}
 
-    keep_dedup.sort();
+/// Flags of the tiers protecting a backup, e.g. `L··M··` for the latest and monthly tiers.
+pub fn tiers_flags(tiers: Tiers) -> String {
+    [
+        (tiers.latest, 'L'),
+        (tiers.daily, 'D'),
+        (tiers.weekly, 'W'),
+        (tiers.monthly, 'M'),
+        (tiers.quarterly, 'Q'),
+        (tiers.yearly, 'Y'),
+    ]
+    .iter()
+    .map(|(set, flag)| if *set { *flag } else { '·' })
+    .collect()
+}
 
-    Ok(keep_dedup)
+/// Determines which retention tiers protect each of the files.
+pub fn identify_tiers(
+    file_list: &[BackupFile],
+    retention: &Tiered,
+) -> Result<Vec<(BackupFile, Tiers)>> {
+    if file_list.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut file_list = file_list.to_vec();
+    file_list.sort();
+
+    let latest = identify_files_to_keep(
+        &file_list,
+        &Tiered {
+            keep_latest: retention.keep_latest,
+            ..Default::default()
+        },
+    )?;
+    let [daily, weekly, monthly, quarterly, yearly] = retention.periods().map(|(period, count)| {
+        count.map_or_else(Vec::new, |count| {
+            keep_periods(&file_list, period, count, retention.anchor)
+        })
+    });
+    let (latest, daily, weekly, monthly, quarterly, yearly) = (
+        path_set(&latest),
+        path_set(&daily),
+        path_set(&weekly),
+        path_set(&monthly),
+        path_set(&quarterly),
+        path_set(&yearly),
+    );
+
+    Ok(file_list
+        .iter()
+        .map(|file| {
+            let path = file.path.as_path();
+            let tiers = Tiers {
+                latest: latest.contains(path),
+                daily: daily.contains(path),
+                weekly: weekly.contains(path),
+                monthly: monthly.contains(path),
+                quarterly: quarterly.contains(path),
+                yearly: yearly.contains(path),
+            };
+            (file.clone(), tiers)
+        })
+        .collect())
 }
 
 pub fn identify_files_to_delete(
     file_list: Vec<BackupFile>,
-    files_to_keep: &Vec<BackupFile>,
+    files_to_keep: &[BackupFile],
 ) -> Vec<BackupFile> {
+    let files_to_keep = path_set(files_to_keep);
     file_list
         .into_iter()
-        .filter(|file| !files_to_keep.contains(file))
+        .filter(|file| !files_to_keep.contains(file.path.as_path()))
         .collect()
 }
 
 #[cfg(test)]
 mod test {
+    use std::path::Path;
+
+    use chrono::{Datelike, NaiveDate, TimeDelta};
+
     use super::*;
     use crate::backup::parsing::FileNameMetadata;
 
@@ -227,7 +588,14 @@ mod test {
         ];
 
         assert_eq!(
-            identify_files_to_keep(&files, Some(3), None, None, None).unwrap(),
+            identify_files_to_keep(
+                &files,
+                &Tiered {
+                    keep_latest: Some(3),
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
             vec![
                 BackupFile {
                     metadata: FileNameMetadata {
@@ -338,7 +706,14 @@ mod test {
         ];
 
         assert_eq!(
-            identify_files_to_keep(&files, None, Some(4), None, None).unwrap(),
+            identify_files_to_keep(
+                &files,
+                &Tiered {
+                    keep_daily: Some(4),
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
             vec![
                 BackupFile {
                     metadata: FileNameMetadata {
@@ -458,7 +833,14 @@ mod test {
         ];
 
         assert_eq!(
-            identify_files_to_keep(&files, None, None, Some(3), None).unwrap(),
+            identify_files_to_keep(
+                &files,
+                &Tiered {
+                    keep_monthly: Some(3),
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
             vec![
                 BackupFile {
                     metadata: FileNameMetadata {
@@ -569,7 +951,14 @@ mod test {
         ];
 
         assert_eq!(
-            identify_files_to_keep(&files, None, None, None, Some(2)).unwrap(),
+            identify_files_to_keep(
+                &files,
+                &Tiered {
+                    keep_yearly: Some(2),
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
             vec![
                 BackupFile {
                     metadata: FileNameMetadata {
@@ -671,7 +1060,17 @@ mod test {
         ];
 
         assert_eq!(
-            identify_files_to_keep(&files, Some(3), Some(4), Some(3), Some(2)).unwrap(),
+            identify_files_to_keep(
+                &files,
+                &Tiered {
+                    keep_latest: Some(3),
+                    keep_daily: Some(4),
+                    keep_monthly: Some(3),
+                    keep_yearly: Some(2),
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
             vec![
                 BackupFile {
                     metadata: FileNameMetadata {
@@ -898,4 +1297,278 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn test_with_last_backups() {
+        let file = |day, path: &str| BackupFile {
+            metadata: FileNameMetadata {
+                year: 2025,
+                month: 10,
+                day,
+                counter: 1,
+            },
+            path: PathBuf::from(path),
+        };
+        let files = vec![
+            file(1, "a_1"),
+            file(2, "a_2"),
+            file(3, "a_3"),
+            file(4, "b_4"),
+            file(5, "b_5"),
+        ];
+        let source_of = |file: &BackupFile| {
+            file.path
+                .to_string_lossy()
+                .split_once('_')
+                .map(|(source, _)| source.to_owned())
+        };
+
+        let keep = with_last_backups(&files, vec![file(5, "b_5")], source_of, |file| {
+            file.path != Path::new("a_3")
+        });
+
+        assert_eq!(keep, vec![file(2, "a_2"), file(5, "b_5")]);
+
+        // The kept newest backup is corrupted, so the newest intact one is kept as well.
+        let keep = with_last_backups(&files, vec![file(5, "b_5")], source_of, |file| {
+            file.path != Path::new("b_5")
+        });
+
+        assert_eq!(keep, vec![file(3, "a_3"), file(4, "b_4"), file(5, "b_5")]);
+
+        let keep = with_last_backups(&files, vec![file(5, "b_5")], source_of, |file| {
+            file.path.starts_with("a")
+        });
+
+        assert_eq!(keep, vec![file(3, "a_3"), file(5, "b_5")]);
+    }
+
+    #[test]
+    fn test_tiers_flags() {
+        let tiers = Tiers {
+            latest: true,
+            monthly: true,
+            ..Default::default()
+        };
+
+        assert_eq!(tiers_flags(tiers), "L··M··");
+    }
+
+    #[test]
+    fn test_tiered_strategy() {
+        let file = |month, day, path: &str| BackupFile {
+            metadata: FileNameMetadata {
+                year: 2025,
+                month,
+                day,
+                counter: 1,
+            },
+            path: PathBuf::from(path),
+        };
+        let files = vec![file(10, 2, "c"), file(9, 1, "a"), file(10, 1, "b")];
+        let strategy: Box<dyn RetentionStrategy> = Box::new(Tiered {
+            keep_latest: Some(1),
+            keep_monthly: Some(2),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            strategy.files_to_keep(&files).unwrap(),
+            vec![file(9, 1, "a"), file(10, 1, "b"), file(10, 2, "c")]
+        );
+        assert!(strategy.files_to_keep(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_period_anchor() {
+        let file = |month, day, counter| BackupFile {
+            metadata: FileNameMetadata {
+                year: 2025,
+                month,
+                day,
+                counter,
+            },
+            path: PathBuf::from(format!("2025-{:02}-{:02}_{:02}", month, day, counter)),
+        };
+        let files = vec![
+            file(9, 30, 0),
+            file(9, 30, 1),
+            file(10, 1, 0),
+            file(10, 1, 1),
+            file(10, 2, 0),
+        ];
+        let keep = |keep_daily, keep_monthly, anchor| {
+            identify_files_to_keep(
+                &files,
+                &Tiered {
+                    keep_daily,
+                    keep_monthly,
+                    anchor,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            keep(Some(2), None, PeriodAnchor::First),
+            vec![file(10, 1, 0), file(10, 2, 0)]
+        );
+        assert_eq!(
+            keep(Some(2), None, PeriodAnchor::Last),
+            vec![file(10, 1, 1), file(10, 2, 0)]
+        );
+        assert_eq!(
+            keep(None, Some(2), PeriodAnchor::Last),
+            vec![file(9, 30, 1), file(10, 2, 0)]
+        );
+    }
+
+    #[test]
+    fn test_content_epochs() {
+        let file = |day| BackupFile {
+            metadata: FileNameMetadata {
+                year: 2025,
+                month: 3,
+                day,
+                counter: 0,
+            },
+            path: PathBuf::from(format!("2025-03-{:02}", day)),
+        };
+        let hashes = [(1, "a"), (2, "a"), (3, "b"), (5, "a"), (6, "a")];
+        let files: Vec<BackupFile> = [6, 1, 2, 3, 4, 5].into_iter().map(file).collect();
+
+        let epochs = content_epochs(&files, |backup| {
+            hashes
+                .iter()
+                .find(|(day, _)| *day == backup.metadata.day)
+                .map(|(_, hash)| hash.to_string())
+        });
+
+        assert_eq!(epochs, vec![file(1), file(3), file(4), file(5)]);
+    }
+
+    #[test]
+    fn test_weekly_and_quarterly() {
+        let file = |year, month, day| BackupFile {
+            metadata: FileNameMetadata {
+                year,
+                month,
+                day,
+                counter: 0,
+            },
+            path: PathBuf::from(format!("{}-{:02}-{:02}", year, month, day)),
+        };
+        // 2024-12-30 already belongs to the first ISO week of 2025.
+        let files = vec![
+            file(2024, 12, 29),
+            file(2024, 12, 30),
+            file(2025, 1, 5),
+            file(2025, 1, 6),
+            file(2025, 4, 1),
+        ];
+
+        let weekly = Tiered {
+            keep_weekly: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(
+            identify_files_to_keep(&files, &weekly).unwrap(),
+            vec![file(2024, 12, 30), file(2025, 1, 6), file(2025, 4, 1)]
+        );
+
+        let quarterly = Tiered {
+            keep_quarterly: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            identify_files_to_keep(&files, &quarterly).unwrap(),
+            vec![file(2025, 1, 5), file(2025, 4, 1)]
+        );
+    }
+
+    #[test]
+    fn test_exponential_strategy() {
+        let newest = NaiveDate::from_ymd_opt(2025, 3, 31).unwrap();
+        let file = |age| {
+            let date = newest - TimeDelta::days(age);
+            BackupFile {
+                metadata: FileNameMetadata {
+                    year: date.year() as u32,
+                    month: date.month(),
+                    day: date.day(),
+                    counter: 1,
+                },
+                path: PathBuf::from(age.to_string()),
+            }
+        };
+        let files: Vec<BackupFile> = [0, 1, 2, 3, 5, 6, 9, 20, 40]
+            .into_iter()
+            .map(file)
+            .collect();
+        let strategy = Exponential {
+            keep_latest: Some(1),
+        };
+
+        assert_eq!(
+            strategy.files_to_keep(&files).unwrap(),
+            [40, 20, 9, 6, 3, 1, 0]
+                .into_iter()
+                .map(file)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_identify_tiers() {
+        let file = |month, day, path: &str| BackupFile {
+            metadata: FileNameMetadata {
+                year: 2025,
+                month,
+                day,
+                counter: 1,
+            },
+            path: PathBuf::from(path),
+        };
+        let files = vec![file(9, 1, "a"), file(10, 1, "b"), file(10, 2, "c")];
+
+        let tiers = identify_tiers(
+            &files,
+            &Tiered {
+                keep_latest: Some(1),
+                keep_daily: Some(1),
+                keep_monthly: Some(2),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            tiers,
+            vec![
+                (
+                    file(9, 1, "a"),
+                    Tiers {
+                        monthly: true,
+                        ..Default::default()
+                    }
+                ),
+                (
+                    file(10, 1, "b"),
+                    Tiers {
+                        monthly: true,
+                        ..Default::default()
+                    }
+                ),
+                (
+                    file(10, 2, "c"),
+                    Tiers {
+                        latest: true,
+                        daily: true,
+                        ..Default::default()
+                    }
+                ),
+            ]
+        );
+    }
 }
@@ -0,0 +1,388 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local, NaiveDate};
+use color_eyre::eyre::{Context, ContextCompat, Result, bail, eyre};
+use diesel::SqliteConnection;
+use log::{info, warn};
+
+use crate::{
+    backup::{
+        BackupOptions,
+        cleanup::{BackupFile, Strategy, Tiered, Tiers, identify_tiers},
+        db::{backup_file_with_relative_path, cold_backup_files, set_cold_target},
+        delta::{is_delta, with_delta_bases},
+        file::remove_empty_layout_dirs,
+        parsing::{ScanFilter, metadata_from_directory, metadata_from_file_name, scan_directory},
+        protect::{protect, unprotect},
+        restore::hash_backup_content,
+        retention_groups,
+        sidecar::companion_paths,
+        store::is_manifest,
+        template::{NameTemplate, hostname},
+    },
+    error_code::{ErrorCode, WithErrorCode},
+};
+
+/// Parses the backups of the target folder, including those moved to cold storage.
+pub fn backups_of_target(
+    conn: &mut SqliteConnection,
+    target: impl AsRef<Path>,
+    template: &NameTemplate,
+) -> Result<Vec<BackupFile>> {
+    scan_target(conn, target, template, ScanFilter::default())
+}
+
+/// Parses the backups of the target folder matching the filter, including those moved to cold
+/// storage.
+pub fn scan_target(
+    conn: &mut SqliteConnection,
+    target: impl AsRef<Path>,
+    template: &NameTemplate,
+    filter: ScanFilter,
+) -> Result<Vec<BackupFile>> {
+    let mut backup_files: Vec<BackupFile> =
+        scan_directory(target.as_ref(), template, filter.clone())?.collect();
+
+    for row in cold_backup_files(conn)? {
+        let Some(cold_target) = row.cold_target else {
+            continue;
+        };
+        if row.quarantined_at.is_some() {
+            continue;
+        }
+        let path = cold_target.join(&*row.relative_path);
+        // Pruned backups stay in the database.
+        if !path.is_file() || backup_files.iter().any(|file| file.path == path) {
+            continue;
+        }
+
+        match path
+            .file_name()
+            .and_then(|file_name| metadata_from_file_name(file_name, template))
+        {
+            Some(metadata) => {
+                let file = BackupFile { metadata, path };
+                if filter.matches(&file, template) {
+                    backup_files.push(file);
+                }
+            }
+            None => warn!(
+                "Failed parsing date of file {} with name template {}",
+                path.display(),
+                template.as_str()
+            ),
+        }
+    }
+
+    Ok(backup_files)
+}
+
+/// Path of the backup relative to the target folder, or to the cold storage folder it was moved to.
+pub fn relative_backup_path(
+    conn: &mut SqliteConnection,
+    target: impl AsRef<Path>,
+    backup_path: impl AsRef<Path>,
+) -> Result<PathBuf> {
+    let backup_path = backup_path.as_ref();
+    if let Ok(relative_path) = backup_path.strip_prefix(target.as_ref()) {
+        return Ok(relative_path.to_path_buf());
+    }
+
+    cold_backup_files(conn)?
+        .into_iter()
+        .find_map(|row| {
+            let relative_path = backup_path.strip_prefix(&*row.cold_target?).ok()?;
+            (relative_path == *row.relative_path).then(|| relative_path.to_path_buf())
+        })
+        .wrap_err_with(|| {
+            format!(
+                "Backup {} is neither located in the target folder nor in cold storage.",
+                backup_path.display()
+            )
+        })
+}
+
+/// Checks if the backup belongs into cold storage: with `cold_after` if it is older than that many
+/// days, otherwise if it is only kept by the monthly, quarterly or yearly retention period.
+fn belongs_into_cold_storage(
+    file: &BackupFile,
+    tiers: Tiers,
+    today: NaiveDate,
+    cold_after: Option<u32>,
+) -> bool {
+    match cold_after {
+        Some(days) => NaiveDate::from_ymd_opt(
+            i32::try_from(file.metadata.year).unwrap_or(i32::MAX),
+            file.metadata.month,
+            file.metadata.day,
+        )
+        .is_some_and(|date| (today - date).num_days() > i64::from(days)),
+        None => {
+            !tiers.latest
+                && !tiers.daily
+                && !tiers.weekly
+                && (tiers.monthly || tiers.quarterly || tiers.yearly)
+        }
+    }
+}
+
+/// Retention periods the backups of a retention group are kept by.
+///
+/// With [`Strategy::Exponential`], backups kept for their age range rather than as one of the
+/// newest count as kept by the monthly period.
+fn retention_tiers(
+    group_files: &[BackupFile],
+    options: &BackupOptions,
+) -> Result<Vec<(BackupFile, Tiers)>> {
+    match options.strategy {
+        Strategy::Tiered => identify_tiers(group_files, &options.tiered()),
+        Strategy::Exponential => {
+            let kept = options.retention_strategy().files_to_keep(group_files)?;
+            let latest = Tiered {
+                keep_latest: options.keep_latest,
+                ..Default::default()
+            };
+            Ok(identify_tiers(group_files, &latest)?
+                .into_iter()
+                .map(|(file, mut tiers)| {
+                    tiers.monthly = !tiers.latest && kept.iter().any(|kept| kept.path == file.path);
+                    (file, tiers)
+                })
+                .collect())
+        }
+    }
+}
+
+/// Moves the backup with its hash, parity and signature files into the cold storage folder.
+///
+/// Copies and verifies the backup if the cold storage folder is on another file system.
+fn move_backup(
+    target: &Path,
+    cold_target: &Path,
+    backup_path: &Path,
+    options: &BackupOptions,
+) -> Result<PathBuf> {
+    let paths = std::iter::once(backup_path.to_path_buf())
+        .chain(companion_paths(backup_path))
+        .filter(|path| path.exists())
+        .map(|from| {
+            let to = cold_target.join(from.strip_prefix(target)?);
+            Ok((from, to))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let cold_path = paths[0].1.clone();
+
+    if cold_path.exists() {
+        bail!("{} already exists in cold storage.", cold_path.display());
+    }
+    if let Some(parent) = cold_path.parent() {
+        std::fs::create_dir_all(parent).wrap_err("Failed to create cold storage folder.")?;
+    }
+    unprotect(backup_path)?;
+
+    if std::fs::rename(backup_path, &cold_path).is_ok() {
+        for (from, to) in &paths[1..] {
+            std::fs::rename(from, to)
+                .wrap_err_with(|| format!("Failed to move {} to cold storage", from.display()))?;
+        }
+    } else {
+        for (from, to) in &paths {
+            options
+                .retry
+                .run("Copying backup to cold storage", || std::fs::copy(from, to))
+                .wrap_err_with(|| format!("Failed to copy {} to cold storage", from.display()))?;
+        }
+
+        if hash_backup_content(target, backup_path)?
+            != hash_backup_content(cold_target, &cold_path)?
+        {
+            for (_, to) in &paths {
+                std::fs::remove_file(to)?;
+            }
+            return Err(eyre!(
+                "Copy of {} in cold storage does not match the backup.",
+                backup_path.display()
+            ))
+            .error_code(ErrorCode::CopyMismatch);
+        }
+
+        for (from, _) in &paths {
+            std::fs::remove_file(from)
+                .wrap_err_with(|| format!("Failed to remove {}", from.display()))?;
+        }
+    }
+
+    if options.protect {
+        protect(&cold_path, options.immutable)?;
+    }
+
+    Ok(cold_path)
+}
+
+/// Moves the backups selected by the cold storage policy of the options out of the target folder.
+///
+/// Chunked and incremental backups and the full backups incremental ones are based on stay, as
+/// they depend on the target folder.
+pub fn move_to_cold_storage(
+    target: &Path,
+    conn: &mut SqliteConnection,
+    options: &BackupOptions,
+) -> Result<()> {
+    let Some(cold_target) = &options.cold_target else {
+        return Ok(());
+    };
+    let cold_target =
+        std::fs::canonicalize(cold_target).wrap_err("Failed to resolve cold storage path.")?;
+    info!("Cold storage folder: {}", cold_target.display());
+
+    let local_files = metadata_from_directory(target, &options.name_template)?;
+    let deltas = local_files
+        .iter()
+        .filter(|file| is_delta(&file.path))
+        .cloned()
+        .collect();
+    let dependencies = with_delta_bases(target, &local_files, deltas)?;
+
+    let local_host = hostname().to_string_lossy().into_owned();
+    let backup_files = backups_of_target(conn, target, &options.name_template)?;
+    let mut tiers = vec![];
+    for (group, group_files) in retention_groups(conn, target, &backup_files, options)? {
+        // Other hosts prune their backups themselves, and might not find them in cold storage.
+        if group.is_some_and(|(host, _)| host != local_host) {
+            continue;
+        }
+        tiers.extend(retention_tiers(&group_files, options)?);
+    }
+    let today = DateTime::<Local>::from(options.now()).date_naive();
+
+    let mut moved_count = 0;
+    for (file, tiers) in tiers {
+        if !file.path.starts_with(target)
+            || !belongs_into_cold_storage(&file, tiers, today, options.cold_after)
+        {
+            continue;
+        }
+        if is_manifest(&file.path)
+            || dependencies
+                .iter()
+                .any(|dependency| dependency.path == file.path)
+        {
+            info!(
+                "Keeping {} in target folder, as it depends on it.",
+                file.path.display()
+            );
+            continue;
+        }
+        let relative_path = file.path.strip_prefix(target)?;
+        if backup_file_with_relative_path(conn, relative_path)?.is_none() {
+            warn!(
+                "Not moving {} to cold storage, as it is not tracked.",
+                file.path.display()
+            );
+            continue;
+        }
+
+        info!("COLD: {}", file.path.display());
+        move_backup(target, &cold_target, &file.path, options)?;
+        set_cold_target(conn, relative_path, &cold_target)?;
+        moved_count += 1;
+    }
+
+    if moved_count > 0 {
+        info!("Moved {} backups to cold storage.", moved_count);
+        remove_empty_layout_dirs(target).wrap_err("Failed to remove empty backup folders.")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::backup::parsing::FileNameMetadata;
+
+    use super::*;
+
+    fn file(year: u32, month: u32, day: u32) -> BackupFile {
+        BackupFile {
+            metadata: FileNameMetadata {
+                year,
+                month,
+                day,
+                counter: 0,
+            },
+            path: PathBuf::from(format!("{:04}-{:02}-{:02}_00_db.sql", year, month, day)),
+        }
+    }
+
+    #[test]
+    fn test_retention_tiers() {
+        let files: Vec<BackupFile> = (1..=31).map(|day| file(2025, 1, day)).collect();
+        let options = BackupOptions {
+            keep_latest: Some(2),
+            keep_daily: Some(0),
+            keep_monthly: Some(12),
+            ..Default::default()
+        };
+        let monthly = |options: &BackupOptions| -> Vec<u32> {
+            retention_tiers(&files, options)
+                .unwrap()
+                .into_iter()
+                .filter(|(_, tiers)| tiers.monthly && !tiers.latest)
+                .map(|(file, _)| file.metadata.day)
+                .collect()
+        };
+
+        assert_eq!(monthly(&options), vec![1]);
+        let exponential = BackupOptions {
+            strategy: Strategy::Exponential,
+            ..options
+        };
+        // Kept for their age ranges of 16-31, 8-15, 4-7 and 2-3 days.
+        assert_eq!(monthly(&exponential), vec![1, 16, 24, 28]);
+    }
+
+    #[test]
+    fn test_belongs_into_cold_storage() {
+        let today = NaiveDate::from_ymd_opt(2025, 6, 30).unwrap();
+        let monthly = Tiers {
+            monthly: true,
+            ..Tiers::default()
+        };
+        let daily_and_monthly = Tiers {
+            daily: true,
+            monthly: true,
+            ..Tiers::default()
+        };
+
+        assert!(belongs_into_cold_storage(
+            &file(2025, 1, 31),
+            monthly,
+            today,
+            None
+        ));
+        assert!(!belongs_into_cold_storage(
+            &file(2025, 6, 1),
+            daily_and_monthly,
+            today,
+            None
+        ));
+        assert!(belongs_into_cold_storage(
+            &file(2025, 3, 1),
+            daily_and_monthly,
+            today,
+            Some(90)
+        ));
+        assert!(!belongs_into_cold_storage(
+            &file(2025, 4, 1),
+            monthly,
+            today,
+            Some(90)
+        ));
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use color_eyre::eyre::{Context, ContextCompat, Result};
+use log::{info, warn};
+
+use crate::backup::{
+    archive::is_zip,
+    cold::{backups_of_target, relative_backup_path},
+    db::{backup_file_with_relative_path, open_db},
+    delta::is_delta,
+    list::format_size,
+    lock::TargetLock,
+    sidecar::{Integrity, sidecar_hash},
+    store::is_manifest,
+    template::NameTemplate,
+    verify::check_tracked_backup,
+};
+
+/// Device and inode of the file, to recognize hardlinks of the same file. Unknown on other
+/// platforms than Unix.
+fn file_id(path: &Path) -> Result<Option<(u64, u64)>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = std::fs::metadata(path)
+            .wrap_err_with(|| format!("Failed reading metadata of {}", path.display()))?;
+        Ok(Some((metadata.dev(), metadata.ino())))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        Ok(None)
+    }
+}
+
+/// Replaces the duplicate by a hardlink to the original.
+///
+/// The hardlink is created next to the duplicate first and renamed over it, so the duplicate is
+/// never missing.
+fn link_duplicate(original: &Path, duplicate: &Path) -> Result<()> {
+    let file_name = duplicate
+        .file_name()
+        .wrap_err_with(|| format!("{} has no file name", duplicate.display()))?;
+    let link_path = duplicate.with_file_name(format!(".{}.compact", file_name.to_string_lossy()));
+    if link_path.exists() {
+        std::fs::remove_file(&link_path)
+            .wrap_err_with(|| format!("Failed to remove leftover {}", link_path.display()))?;
+    }
+
+    std::fs::hard_link(original, &link_path).wrap_err_with(|| {
+        format!(
+            "Failed to hardlink {} to {}",
+            original.display(),
+            link_path.display()
+        )
+    })?;
+    if let Err(err) = std::fs::rename(&link_path, duplicate) {
+        let _ = std::fs::remove_file(&link_path);
+        return Err(err).wrap_err_with(|| format!("Failed to replace {}", duplicate.display()));
+    }
+
+    Ok(())
+}
+
+/// Replaces plain backups identical to an older backup of the target folder by hardlinks to it,
+/// reporting the space reclaimed.
+///
+/// Backups are grouped by their hash in the tracking database, or in their hash file if untracked.
+/// Each backup is verified against its hash before it is linked or linked to. With `dry_run` the
+/// backups that would be linked are only listed.
+pub fn compact(target: PathBuf, dry_run: bool, name_template: &NameTemplate) -> Result<()> {
+    let _lock = TargetLock::acquire(&target, Duration::ZERO)?;
+    let mut conn = open_db(&target)?;
+
+    let mut backup_files = backups_of_target(&mut conn, &target, name_template)?;
+    backup_files.sort();
+
+    let mut by_hash: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for file in backup_files {
+        // Only plain backups hold the source content as is.
+        if is_zip(&file.path) || is_manifest(&file.path) || is_delta(&file.path) {
+            continue;
+        }
+        let relative_path = relative_backup_path(&mut conn, &target, &file.path)?;
+        let hash = backup_file_with_relative_path(&mut conn, relative_path)?
+            .and_then(|row| row.hash)
+            .or_else(|| sidecar_hash(&file.path));
+        match hash {
+            Some(hash) => by_hash.entry(hash).or_default().push(file.path),
+            None => warn!("Skipping {}, as its hash is unknown.", file.path.display()),
+        }
+    }
+
+    let mut linked_count = 0;
+    let mut reclaimed_bytes = 0;
+    let mut reclaimed_ids = HashSet::new();
+    for paths in by_hash.into_values().filter(|paths| paths.len() > 1) {
+        let mut original: Option<(&PathBuf, Option<(u64, u64)>)> = None;
+        for path in &paths {
+            let id = file_id(path)?;
+            if let Some((_, original_id)) = original
+                && id.is_some()
+                && id == original_id
+            {
+                continue;
+            }
+            if check_tracked_backup(&mut conn, &target, path)? != Integrity::Intact {
+                warn!(
+                    "Skipping {}, as it does not match its hash.",
+                    path.display()
+                );
+                continue;
+            }
+            let Some((original_path, _)) = original else {
+                original = Some((path, id));
+                continue;
+            };
+
+            let size = std::fs::metadata(path)
+                .wrap_err_with(|| format!("Failed reading metadata of {}", path.display()))?
+                .len();
+            if dry_run {
+                info!(
+                    "Would hardlink {} to {}",
+                    original_path.display(),
+                    path.display()
+                );
+            } else {
+                info!(
+                    "Hardlinking {} to {}",
+                    original_path.display(),
+                    path.display()
+                );
+                if let Err(err) = link_duplicate(original_path, path) {
+                    warn!("{:#}", err);
+                    continue;
+                }
+            }
+
+            linked_count += 1;
+            // Files already hardlinked to each other take up their space once.
+            if id.is_none_or(|id| reclaimed_ids.insert(id)) {
+                reclaimed_bytes += size;
+            }
+        }
+    }
+
+    if dry_run {
+        info!(
+            "Would hardlink {} identical backups, reclaiming {}.",
+            linked_count,
+            format_size(reclaimed_bytes)
+        );
+    } else {
+        info!(
+            "Hardlinked {} identical backups, reclaiming {}.",
+            linked_count,
+            format_size(reclaimed_bytes)
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_link_duplicate() {
+        let dir = std::env::temp_dir().join(format!("sfb-compact-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let original = dir.join("2025-01-01_00_notes.txt");
+        let duplicate = dir.join("2025-02-01_00_notes.txt");
+        std::fs::write(&original, "notes").unwrap();
+        std::fs::write(&duplicate, "notes").unwrap();
+
+        link_duplicate(&original, &duplicate).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&duplicate).unwrap(), "notes");
+        assert!(!dir.join(".2025-02-01_00_notes.txt.compact").exists());
+        #[cfg(unix)]
+        assert_eq!(file_id(&original).unwrap(), file_id(&duplicate).unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
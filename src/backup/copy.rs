@@ -0,0 +1,592 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Instant, SystemTime},
+};
+
+use color_eyre::eyre::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const DEFAULT_BUFFER_SIZE: usize = 1024 * 1024;
+/// Alignment of buffers, sizes and offsets required by direct IO.
+const DIRECT_IO_ALIGNMENT: usize = 4096;
+/// Size of the chunks hashed in parallel by the tree hash.
+const TREE_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+/// Files at least this large are copied resumably, saving the progress after each part this size.
+pub const RESUME_PART_SIZE: u64 = 64 * 1024 * 1024;
+/// Folder in the target folder holding partial copies of large files until they are complete.
+pub const PARTIAL_DIR_NAME: &str = ".partial";
+
+/// How the content of files is read and written when copying and hashing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IoOptions {
+    pub buffer_size: Option<usize>,
+    /// Bypass the page cache with `O_DIRECT` (Linux only).
+    pub direct_io: bool,
+}
+
+impl IoOptions {
+    /// Checks if files are copied by cloning or by the OS, instead of a copy loop.
+    pub fn is_default(&self) -> bool {
+        self.buffer_size.is_none() && !self.direct_io
+    }
+
+    /// Size of the buffer files are read and written with.
+    pub fn buffer_size(&self) -> usize {
+        let size = self.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE).max(1);
+        if self.direct_io {
+            size.next_multiple_of(DIRECT_IO_ALIGNMENT)
+        } else {
+            size
+        }
+    }
+}
+
+/// Buffer whose start is aligned for direct IO.
+struct AlignedBuffer {
+    data: Vec<u8>,
+    offset: usize,
+    len: usize,
+}
+
+impl AlignedBuffer {
+    fn new(len: usize) -> Self {
+        let data = vec![0; len + DIRECT_IO_ALIGNMENT];
+        let offset = data.as_ptr().align_offset(DIRECT_IO_ALIGNMENT);
+        Self { data, offset, len }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.data[self.offset..self.offset + self.len]
+    }
+}
+
+/// Opens the file, with direct IO if requested and supported.
+///
+/// Returns if direct IO is used.
+fn open(path: &Path, write: bool, direct_io: bool) -> io::Result<(File, bool)> {
+    let mut options = File::options();
+    if write {
+        options.write(true).create(true).truncate(true);
+    } else {
+        options.read(true);
+    }
+    open_with(path, &options, direct_io)
+}
+
+/// Opens the file with the options, adding direct IO if requested and supported.
+///
+/// Returns if direct IO is used.
+fn open_with(path: &Path, options: &OpenOptions, direct_io: bool) -> io::Result<(File, bool)> {
+    #[cfg(target_os = "linux")]
+    if direct_io {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let mut direct_options = options.clone();
+        direct_options.custom_flags(libc::O_DIRECT);
+        match direct_options.open(path) {
+            Ok(file) => return Ok((file, true)),
+            Err(err) if err.kind() == io::ErrorKind::InvalidInput => warn!(
+                "Direct IO is not supported for {}, falling back to buffered IO.",
+                path.display()
+            ),
+            Err(err) => return Err(err),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    if direct_io {
+        warn!("Direct IO is only supported on Linux, falling back to buffered IO.");
+    }
+
+    Ok((options.open(path)?, false))
+}
+
+/// Reads until the buffer is full or the end of the file is reached.
+fn read_full(file: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(filled)
+}
+
+/// Writes the first `read` bytes of the buffer, padded to whole blocks for direct IO.
+///
+/// The padding has to be truncated after the last write.
+fn write_block(
+    writer: &mut File,
+    buffer: &mut [u8],
+    read: usize,
+    direct_write: bool,
+) -> io::Result<()> {
+    if direct_write && !read.is_multiple_of(DIRECT_IO_ALIGNMENT) {
+        // Direct IO only writes whole blocks.
+        let padded = read.next_multiple_of(DIRECT_IO_ALIGNMENT);
+        buffer[read..padded].fill(0);
+        writer.write_all(&buffer[..padded])
+    } else {
+        writer.write_all(&buffer[..read])
+    }
+}
+
+/// Copies the file with a copy loop using the buffer size and direct IO of the options.
+///
+/// Returns the number of bytes copied.
+pub fn copy_file(source: &Path, target: &Path, options: &IoOptions) -> io::Result<u64> {
+    let (mut reader, _) = open(source, false, options.direct_io)?;
+    let (mut writer, direct_write) = open(target, true, options.direct_io)?;
+    let mut buffer = AlignedBuffer::new(options.buffer_size());
+    let buffer = buffer.as_mut_slice();
+
+    let mut len = 0;
+    loop {
+        let read = read_full(&mut reader, buffer)?;
+        if read == 0 {
+            break;
+        }
+        len += read as u64;
+        write_block(&mut writer, buffer, read, direct_write)?;
+
+        if read < buffer.len() {
+            break;
+        }
+    }
+
+    writer.set_len(len)?;
+
+    Ok(len)
+}
+
+/// Progress of a resumable copy, saved next to its partial file.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct CopyProgress {
+    source: PathBuf,
+    source_len: u64,
+    source_modified: SystemTime,
+    /// Bytes written to the partial file.
+    copied: u64,
+    /// SHA-256 of the copied bytes, to detect partial files altered since, e.g. by a crash.
+    hash: String,
+}
+
+impl CopyProgress {
+    fn is_same_source(&self, other: &Self) -> bool {
+        self.source == other.source
+            && self.source_len == other.source_len
+            && self.source_modified == other.source_modified
+    }
+}
+
+/// Hashes the copied part of the partial file into the hasher and checks it against the saved
+/// progress of an interrupted copy of the same unchanged source.
+///
+/// Returns the offset to resume from, or 0 if the copy has to start over.
+fn resume_offset(
+    progress: &CopyProgress,
+    partial_path: &Path,
+    progress_path: &Path,
+    options: &IoOptions,
+    hasher: &mut Sha256,
+) -> io::Result<u64> {
+    let Some(saved) = std::fs::read(progress_path)
+        .ok()
+        .and_then(|saved| serde_json::from_slice::<CopyProgress>(&saved).ok())
+        .filter(|saved| saved.is_same_source(progress))
+    else {
+        return Ok(0);
+    };
+    let Ok((mut file, _)) = open(partial_path, false, options.direct_io) else {
+        return Ok(0);
+    };
+
+    let mut buffer = AlignedBuffer::new(options.buffer_size());
+    let buffer = buffer.as_mut_slice();
+    let mut remaining = saved.copied;
+    while remaining > 0 {
+        let read = read_full(&mut file, buffer)?;
+        let used = remaining.min(read as u64);
+        hasher.update(&buffer[..used as usize]);
+        remaining -= used;
+        if read < buffer.len() {
+            break;
+        }
+    }
+
+    if remaining > 0 || hex::encode_upper(hasher.clone().finalize()) != saved.hash {
+        warn!("Partial copy does not match its saved progress. Copying from the start.");
+        hasher.reset();
+        return Ok(0);
+    }
+    Ok(saved.copied)
+}
+
+fn copy_resumable(
+    source: &Path,
+    target: &Path,
+    partial_dir: &Path,
+    options: &IoOptions,
+    part_size: u64,
+) -> io::Result<u64> {
+    std::fs::create_dir_all(partial_dir)?;
+    let metadata = std::fs::metadata(source)?;
+    // Named after the source, so that the copy resumes into a backup of another name, e.g. a day
+    // later.
+    let partial_name =
+        hex::encode_upper(&Sha256::digest(source.as_os_str().as_encoded_bytes())[..8]);
+    let partial_path = partial_dir.join(&partial_name);
+    let progress_path = partial_dir.join(format!("{}.json", partial_name));
+
+    let mut progress = CopyProgress {
+        source: source.to_owned(),
+        source_len: metadata.len(),
+        source_modified: metadata.modified()?,
+        copied: 0,
+        hash: String::new(),
+    };
+    let mut hasher = Sha256::new();
+    let offset = resume_offset(
+        &progress,
+        &partial_path,
+        &progress_path,
+        options,
+        &mut hasher,
+    )?;
+    progress.copied = offset;
+    if offset > 0 {
+        info!(
+            "Resuming interrupted copy at {} of {} bytes.",
+            offset, progress.source_len
+        );
+    }
+
+    let (mut reader, _) = open(source, false, options.direct_io)?;
+    reader.seek(SeekFrom::Start(offset))?;
+    let (mut writer, direct_write) = open_with(
+        &partial_path,
+        File::options().write(true).create(true).truncate(false),
+        options.direct_io,
+    )?;
+    writer.set_len(offset)?;
+    writer.seek(SeekFrom::Start(offset))?;
+    let mut buffer = AlignedBuffer::new(options.buffer_size());
+    let buffer = buffer.as_mut_slice();
+
+    let mut len = offset;
+    loop {
+        let read = read_full(&mut reader, buffer)?;
+        if read == 0 {
+            break;
+        }
+        len += read as u64;
+        write_block(&mut writer, buffer, read, direct_write)?;
+        hasher.update(&buffer[..read]);
+
+        if read < buffer.len() {
+            break;
+        }
+        // Saved after whole buffers only, which keeps the offset aligned for direct IO.
+        if len - progress.copied >= part_size {
+            writer.sync_data()?;
+            progress.copied = len;
+            progress.hash = hex::encode_upper(hasher.clone().finalize());
+            std::fs::write(&progress_path, serde_json::to_vec(&progress)?)?;
+        }
+    }
+
+    writer.set_len(len)?;
+    drop(writer);
+    std::fs::rename(&partial_path, target)?;
+    if progress_path.exists() {
+        std::fs::remove_file(&progress_path)?;
+    }
+    // Fails if partial copies of other sources remain.
+    let _ = std::fs::remove_dir(partial_dir);
+
+    Ok(len - offset)
+}
+
+/// Copies the file through a partial file in `partial_dir` with a copy loop, saving the progress
+/// after each [`RESUME_PART_SIZE`] bytes.
+///
+/// A copy of the same unchanged source interrupted before, e.g. by a crash or cancellation,
+/// resumes from its last saved progress. Returns the number of bytes copied by this call.
+pub fn copy_file_resumable(
+    source: &Path,
+    target: &Path,
+    partial_dir: &Path,
+    options: &IoOptions,
+) -> io::Result<u64> {
+    copy_resumable(source, target, partial_dir, options, RESUME_PART_SIZE)
+}
+
+/// Hashes the file using the buffer size and direct IO of the options.
+///
+/// Errors are not wrapped, so that failures can be retried.
+pub fn hash_path(path: impl AsRef<Path>, options: &IoOptions) -> io::Result<String> {
+    let (mut reader, _) = open(path.as_ref(), false, options.direct_io)?;
+    let mut buffer = AlignedBuffer::new(options.buffer_size());
+    let buffer = buffer.as_mut_slice();
+    let mut hasher = Sha256::new();
+
+    loop {
+        let read = read_full(&mut reader, buffer)?;
+        hasher.update(&buffer[..read]);
+        if read < buffer.len() {
+            break;
+        }
+    }
+
+    Ok(hex::encode_upper(hasher.finalize()))
+}
+
+/// Hashes the file in chunks of `chunk_size` bytes, one thread per core.
+fn tree_hash(path: &Path, options: &IoOptions, chunk_size: u64) -> io::Result<String> {
+    let len = std::fs::metadata(path)?.len();
+    let chunk_count = usize::try_from(len.div_ceil(chunk_size).max(1)).map_err(io::Error::other)?;
+    let thread_count = std::thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(chunk_count);
+
+    let next_chunk = AtomicUsize::new(0);
+    let chunk_hashes = Mutex::new(vec![[0; 32]; chunk_count]);
+
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..thread_count)
+            .map(|_| {
+                scope.spawn(|| -> io::Result<()> {
+                    let (mut file, _) = open(path, false, options.direct_io)?;
+                    let mut buffer = AlignedBuffer::new(options.buffer_size());
+                    let buffer = buffer.as_mut_slice();
+
+                    loop {
+                        let index = next_chunk.fetch_add(1, Ordering::Relaxed);
+                        if index >= chunk_count {
+                            return Ok(());
+                        }
+
+                        let offset = index as u64 * chunk_size;
+                        file.seek(SeekFrom::Start(offset))?;
+                        let mut remaining = chunk_size.min(len - offset);
+                        let mut hasher = Sha256::new();
+                        while remaining > 0 {
+                            // Whole buffers are read, as direct IO requires aligned sizes.
+                            let read = read_full(&mut file, buffer)?;
+                            let used = remaining.min(read as u64);
+                            hasher.update(&buffer[..used as usize]);
+                            remaining -= used;
+                            if read < buffer.len() {
+                                break;
+                            }
+                        }
+
+                        chunk_hashes.lock().unwrap_or_else(PoisonError::into_inner)[index] =
+                            hasher.finalize().into();
+                    }
+                })
+            })
+            .collect();
+
+        workers.into_iter().try_for_each(|worker| {
+            worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    })?;
+
+    let mut hasher = Sha256::new();
+    for chunk_hash in chunk_hashes
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner)
+    {
+        hasher.update(chunk_hash);
+    }
+    hasher.update(len.to_le_bytes());
+
+    Ok(hex::encode_upper(hasher.finalize()))
+}
+
+/// Hashes the file in parallel chunks using all cores.
+///
+/// The result is the SHA-256 of the hashes of the chunks, so it is only comparable to other tree
+/// hashes and not to the SHA-256 of the file. Errors are not wrapped, so that failures can be
+/// retried.
+pub fn tree_hash_path(path: impl AsRef<Path>, options: &IoOptions) -> io::Result<String> {
+    tree_hash(path.as_ref(), options, TREE_CHUNK_SIZE)
+}
+
+/// Copies the file with different IO settings and prints the throughput of each.
+pub fn bench(source: PathBuf, dir: Option<PathBuf>) -> Result<()> {
+    let dir = dir.unwrap_or_else(std::env::temp_dir);
+    let target = dir.join(format!(
+        ".staggered-file-backup-bench-{}",
+        std::process::id()
+    ));
+    let len = std::fs::metadata(&source)
+        .wrap_err("Failed reading metadata of source file.")?
+        .len();
+    info!("Copying {} bytes to {}", len, dir.display());
+
+    let settings = [
+        ("default", None),
+        ("64K", Some((64 * 1024, false))),
+        ("1M", Some((1024 * 1024, false))),
+        ("8M", Some((8 * 1024 * 1024, false))),
+        ("1M direct", Some((1024 * 1024, true))),
+        ("8M direct", Some((8 * 1024 * 1024, true))),
+    ];
+
+    for (name, setting) in settings {
+        let start = Instant::now();
+        let copied = match setting {
+            None => reflink_copy::reflink_or_copy(&source, &target).map(|_| ()),
+            Some((buffer_size, direct_io)) => copy_file(
+                &source,
+                &target,
+                &IoOptions {
+                    buffer_size: Some(buffer_size),
+                    direct_io,
+                },
+            )
+            .map(|_| ()),
+        };
+        let elapsed = start.elapsed();
+        let removed = std::fs::remove_file(&target);
+
+        copied.wrap_err_with(|| format!("Failed to copy with {} settings.", name))?;
+        removed.wrap_err("Failed to remove copied file.")?;
+
+        println!(
+            "{:<10} {:>10.1} MB/s",
+            name,
+            len as f64 / 1_000_000.0 / elapsed.as_secs_f64()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_copy_file() {
+        let dir = std::env::temp_dir().join(format!("sfb-copy-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("source.bin");
+        let target = dir.join("target.bin");
+
+        let content: Vec<u8> = (0..DIRECT_IO_ALIGNMENT * 5 + 123)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        std::fs::write(&source, &content).unwrap();
+
+        let options = IoOptions {
+            buffer_size: Some(DIRECT_IO_ALIGNMENT * 2),
+            direct_io: false,
+        };
+        let len = copy_file(&source, &target, &options).unwrap();
+        let copied = std::fs::read(&target).unwrap();
+        let hash = hash_path(&target, &options).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(len, content.len() as u64);
+        assert!(copied == content);
+        assert_eq!(hash, hex::encode_upper(Sha256::digest(&content)));
+    }
+
+    #[test]
+    fn test_copy_resumable() {
+        let dir = std::env::temp_dir().join(format!("sfb-resume-test-{}", std::process::id()));
+        let partial_dir = dir.join(PARTIAL_DIR_NAME);
+        std::fs::create_dir_all(&partial_dir).unwrap();
+        let source = dir.join("source.bin");
+        let target = dir.join("target.bin");
+        let content: Vec<u8> = (0..DIRECT_IO_ALIGNMENT * 10 + 7)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        std::fs::write(&source, &content).unwrap();
+        let options = IoOptions {
+            buffer_size: Some(DIRECT_IO_ALIGNMENT),
+            direct_io: false,
+        };
+
+        let partial_name =
+            hex::encode_upper(&Sha256::digest(source.as_os_str().as_encoded_bytes())[..8]);
+        let copied = DIRECT_IO_ALIGNMENT * 4;
+        let metadata = std::fs::metadata(&source).unwrap();
+        let progress = CopyProgress {
+            source: source.clone(),
+            source_len: metadata.len(),
+            source_modified: metadata.modified().unwrap(),
+            copied: copied as u64,
+            hash: hex::encode_upper(Sha256::digest(&content[..copied])),
+        };
+        std::fs::write(
+            partial_dir.join(format!("{}.json", partial_name)),
+            serde_json::to_vec(&progress).unwrap(),
+        )
+        .unwrap();
+        // Bytes written after the saved progress are copied again.
+        std::fs::write(partial_dir.join(&partial_name), &content[..copied + 100]).unwrap();
+
+        let resumed = copy_resumable(&source, &target, &partial_dir, &options, 4096).unwrap();
+        let resumed_copy = std::fs::read(&target).unwrap();
+        let partial_dir_removed = !partial_dir.exists();
+        let restarted = copy_resumable(&source, &target, &partial_dir, &options, 4096).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(resumed, (content.len() - copied) as u64);
+        assert!(resumed_copy == content);
+        assert!(partial_dir_removed);
+        assert_eq!(restarted, content.len() as u64);
+    }
+
+    #[test]
+    fn test_tree_hash() {
+        let dir = std::env::temp_dir().join(format!("sfb-tree-hash-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let a = dir.join("a.bin");
+        let b = dir.join("b.bin");
+
+        let mut content: Vec<u8> = (0..DIRECT_IO_ALIGNMENT * 10 + 7)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        std::fs::write(&a, &content).unwrap();
+        std::fs::write(&b, &content).unwrap();
+        let options = IoOptions::default();
+        let chunk_size = DIRECT_IO_ALIGNMENT as u64 * 3;
+        let equal = tree_hash(&a, &options, chunk_size).unwrap()
+            == tree_hash(&b, &options, chunk_size).unwrap();
+
+        content[DIRECT_IO_ALIGNMENT * 7] ^= 0xff;
+        std::fs::write(&b, &content).unwrap();
+        let different = tree_hash(&a, &options, chunk_size).unwrap()
+            != tree_hash(&b, &options, chunk_size).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(equal);
+        assert!(different);
+    }
+}
@@ -4,29 +4,266 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::path::Path;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use color_eyre::{
     Section,
-    eyre::{Context, ContextCompat, Result, eyre},
+    eyre::{Context, Result, eyre},
 };
-use diesel::{Connection, SqliteConnection, sqlite::Sqlite};
+use diesel::{prelude::*, sqlite::Sqlite};
 use diesel_migrations::{EmbeddedMigrations, MigrationHarness, embed_migrations};
+use log::info;
+#[cfg(unix)]
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_encode};
+use serde::Deserialize;
+
+use crate::{
+    backup::{
+        audit::Operation,
+        cleanup::{Tier, Tiers},
+        cold::{backups_of_target, relative_backup_path},
+        list::format_size,
+        lock::{LOCK_FILE_NAME, TargetLock},
+        normalize::path_forms,
+        template::NameTemplate,
+    },
+    error_code::{ErrorCode, WithErrorCode},
+    model::{AuditEntry, BackupFile, JournalEntry, PathBufSql, PrunedFile, SourceHash, UuidSQL},
+    schema::{audit_log, backup_files, journal, pruned_files, source_hashes},
+};
+use uuid::Uuid;
 
-const DB_NAME: &'static str = "staggered-file-backup.keepme";
+pub const DB_NAME: &str = "staggered-file-backup.keepme";
 
 const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
+/// Version of the tracking database in `PRAGMA user_version` since paths are stored as raw bytes
+/// instead of serialized with bitcode.
+const RAW_PATHS_VERSION: i32 = 1;
+
+/// Version of the tracking database in `PRAGMA user_version` since UUIDs are stored as their 16
+/// bytes instead of serialized with bitcode.
+const RAW_UUIDS_VERSION: i32 = 2;
+
+const AUDIT_LOG_NO_UPDATE_TRIGGER: &str =
+    "CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+  SELECT RAISE(ABORT, 'audit_log is append-only');
+END";
+
+/// Characters of a path percent encoded in a `file:` URI.
+#[cfg(unix)]
+const URI_PATH: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Checks if the folder is used by this tool, which is the case if it contains the tracking
+/// database or is empty.
+pub fn is_managed_dir(backup_dir: impl AsRef<Path>) -> Result<bool> {
+    // Another machine sharing the target folder might hold the lock of its first backup.
+    Ok(backup_dir.as_ref().join(DB_NAME).try_exists()?
+        || std::fs::read_dir(backup_dir.as_ref())?
+            .filter_map(|entry| entry.ok())
+            .all(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with(LOCK_FILE_NAME)
+            }))
+}
+
+/// Database URL of the tracking database, a percent encoded `file:` URI if the path is not valid
+/// UTF-8.
+fn database_url(path: &Path) -> Result<String> {
+    if let Some(path) = path.to_str() {
+        return Ok(path.to_string());
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let bytes = path.as_os_str().as_bytes();
+        let prefix = if bytes.starts_with(b"/") {
+            "file://"
+        } else {
+            "file:"
+        };
+        Ok(format!("{}{}", prefix, percent_encode(bytes, URI_PATH)))
+    }
+    #[cfg(not(unix))]
+    {
+        Err(eyre!("Backup tracking database expects a unicode path."))
+            .suggestion("Check if your backup directory path entails invalid unicode characters.")
+    }
+}
+
 fn connect_db(backup_dir: impl AsRef<Path>) -> Result<SqliteConnection> {
-    SqliteConnection::establish(
-        backup_dir
-            .as_ref()
-            .join(DB_NAME)
-            .to_str()
-            .wrap_err("Backup tracking database expects a utf-8 compatible path.")
-            .suggestion("Check if your backup directory path entails non utf-8 characters.")?,
-    )
-    .wrap_err("Failed to connect to backup tracking database located in backup folder.")
+    SqliteConnection::establish(&database_url(&backup_dir.as_ref().join(DB_NAME))?)
+        .wrap_err("Failed to connect to backup tracking database located in backup folder.")
+}
+
+#[derive(QueryableByName)]
+struct UserVersion {
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    user_version: i32,
+}
+
+fn user_version(conn: &mut SqliteConnection) -> Result<i32> {
+    Ok(diesel::sql_query("PRAGMA user_version")
+        .get_result::<UserVersion>(conn)
+        .wrap_err("Failed to read version of backup tracking database.")?
+        .user_version)
+}
+
+/// Re-encodes a path serialized with bitcode by older versions as raw bytes.
+fn convert_legacy_path(bytes: &[u8]) -> Result<Vec<u8>> {
+    bitcode::deserialize::<PathBufSql>(bytes)
+        .wrap_err("Failed to decode path stored in backup tracking database.")?
+        .to_bytes()
+        .map_err(|err| eyre!(err))
+}
+
+fn convert_legacy_optional_path(bytes: Option<Vec<u8>>) -> Result<Option<Vec<u8>>> {
+    bytes.map(|bytes| convert_legacy_path(&bytes)).transpose()
+}
+
+/// Re-encodes the paths stored by older versions, which required paths to be valid UTF-8, as raw
+/// bytes and records the conversion in the version of the database.
+///
+/// The triggers keeping the audit log append-only are recreated after its paths are converted.
+fn convert_legacy_paths(conn: &mut SqliteConnection) -> Result<()> {
+    if user_version(conn)? >= RAW_PATHS_VERSION {
+        return Ok(());
+    }
+
+    type Bytes = Vec<u8>;
+    conn.immediate_transaction(|conn| -> Result<()> {
+        let rows: Vec<(Bytes, Bytes, Option<Bytes>, Option<Bytes>)> = backup_files::table
+            .select((
+                backup_files::uuid,
+                backup_files::relative_path,
+                backup_files::cold_target,
+                backup_files::source_path,
+            ))
+            .load(conn)?;
+        for (uuid, relative_path, cold_target, source_path) in rows {
+            diesel::update(backup_files::table.filter(backup_files::uuid.eq(uuid)))
+                .set((
+                    backup_files::relative_path.eq(convert_legacy_path(&relative_path)?),
+                    backup_files::cold_target.eq(convert_legacy_optional_path(cold_target)?),
+                    backup_files::source_path.eq(convert_legacy_optional_path(source_path)?),
+                ))
+                .execute(conn)?;
+        }
+
+        let rows: Vec<(Bytes, Bytes, Option<Bytes>)> = pruned_files::table
+            .select((
+                pruned_files::uuid,
+                pruned_files::original_path,
+                pruned_files::trash_id,
+            ))
+            .load(conn)?;
+        for (uuid, original_path, trash_id) in rows {
+            diesel::update(pruned_files::table.filter(pruned_files::uuid.eq(uuid)))
+                .set((
+                    pruned_files::original_path.eq(convert_legacy_path(&original_path)?),
+                    pruned_files::trash_id.eq(convert_legacy_optional_path(trash_id)?),
+                ))
+                .execute(conn)?;
+        }
+
+        let paths: Vec<Vec<u8>> = source_hashes::table
+            .select(source_hashes::path)
+            .load(conn)?;
+        for path in paths {
+            diesel::update(source_hashes::table.filter(source_hashes::path.eq(&path)))
+                .set(source_hashes::path.eq(convert_legacy_path(&path)?))
+                .execute(conn)?;
+        }
+
+        diesel::sql_query("DROP TRIGGER audit_log_no_update").execute(conn)?;
+        let rows: Vec<(Vec<u8>, Vec<u8>)> = audit_log::table
+            .select((audit_log::uuid, audit_log::path))
+            .load(conn)?;
+        for (uuid, path) in rows {
+            diesel::update(audit_log::table.filter(audit_log::uuid.eq(uuid)))
+                .set(audit_log::path.eq(convert_legacy_path(&path)?))
+                .execute(conn)?;
+        }
+        diesel::sql_query(AUDIT_LOG_NO_UPDATE_TRIGGER).execute(conn)?;
+
+        diesel::sql_query(format!("PRAGMA user_version = {}", RAW_PATHS_VERSION)).execute(conn)?;
+        Ok(())
+    })
+    .wrap_err("Failed to convert paths stored in backup tracking database.")
+}
+
+/// UUID as serialized with bitcode by older versions.
+#[derive(Deserialize)]
+struct LegacyUuid {
+    uuid: Uuid,
+}
+
+/// Re-encodes a UUID serialized with bitcode by older versions as its 16 bytes. UUIDs already
+/// stored as 16 bytes are kept, as the serialized form is longer.
+fn convert_legacy_uuid(bytes: Vec<u8>) -> Result<Vec<u8>> {
+    if bytes.len() == 16 {
+        return Ok(bytes);
+    }
+    Ok(bitcode::deserialize::<LegacyUuid>(&bytes)
+        .wrap_err("Failed to decode UUID stored in backup tracking database.")?
+        .uuid
+        .as_bytes()
+        .to_vec())
+}
+
+/// Re-encodes the UUIDs stored by older versions as their 16 bytes and records the conversion in
+/// the version of the database.
+fn convert_legacy_uuids(conn: &mut SqliteConnection) -> Result<()> {
+    if user_version(conn)? >= RAW_UUIDS_VERSION {
+        return Ok(());
+    }
+
+    conn.immediate_transaction(|conn| -> Result<()> {
+        let uuids: Vec<Vec<u8>> = backup_files::table.select(backup_files::uuid).load(conn)?;
+        for uuid in uuids {
+            diesel::update(backup_files::table.filter(backup_files::uuid.eq(&uuid)))
+                .set(backup_files::uuid.eq(convert_legacy_uuid(uuid.clone())?))
+                .execute(conn)?;
+        }
+
+        let rows: Vec<(Vec<u8>, Vec<u8>)> = pruned_files::table
+            .select((pruned_files::uuid, pruned_files::prune_uuid))
+            .load(conn)?;
+        for (uuid, prune_uuid) in rows {
+            diesel::update(pruned_files::table.filter(pruned_files::uuid.eq(&uuid)))
+                .set((
+                    pruned_files::uuid.eq(convert_legacy_uuid(uuid.clone())?),
+                    pruned_files::prune_uuid.eq(convert_legacy_uuid(prune_uuid)?),
+                ))
+                .execute(conn)?;
+        }
+
+        diesel::sql_query("DROP TRIGGER audit_log_no_update").execute(conn)?;
+        let uuids: Vec<Vec<u8>> = audit_log::table.select(audit_log::uuid).load(conn)?;
+        for uuid in uuids {
+            diesel::update(audit_log::table.filter(audit_log::uuid.eq(&uuid)))
+                .set(audit_log::uuid.eq(convert_legacy_uuid(uuid.clone())?))
+                .execute(conn)?;
+        }
+        diesel::sql_query(AUDIT_LOG_NO_UPDATE_TRIGGER).execute(conn)?;
+
+        diesel::sql_query(format!("PRAGMA user_version = {}", RAW_UUIDS_VERSION)).execute(conn)?;
+        Ok(())
+    })
+    .wrap_err("Failed to convert UUIDs stored in backup tracking database.")
 }
 
 fn run_pending_migrations(conn: &mut impl MigrationHarness<Sqlite>) -> Result<()> {
@@ -35,3 +272,799 @@ fn run_pending_migrations(conn: &mut impl MigrationHarness<Sqlite>) -> Result<()
         .wrap_err("Failed to run database migrations.")?;
     Ok(())
 }
+
+#[derive(QueryableByName)]
+struct IntegrityCheck {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    integrity_check: String,
+}
+
+/// Checks the tracking database without migrating it.
+///
+/// Returns the problems SQLite found and the number of pending migrations.
+pub fn check_db(backup_dir: impl AsRef<Path>) -> Result<(Vec<String>, usize)> {
+    let mut conn = connect_db(backup_dir)?;
+    let problems = diesel::sql_query("PRAGMA integrity_check")
+        .load::<IntegrityCheck>(&mut conn)
+        .wrap_err("Failed to check integrity of backup tracking database.")?
+        .into_iter()
+        .map(|row| row.integrity_check)
+        .filter(|result| result != "ok")
+        .collect();
+    let pending_count = pending_upgrades(&mut conn)?.len();
+
+    Ok((problems, pending_count))
+}
+
+/// Newest migration applied to the tracking database, like `202610151800000000`, if any.
+pub fn schema_version(conn: &mut SqliteConnection) -> Result<Option<String>> {
+    Ok(conn
+        .applied_migrations()
+        .map_err(|err| eyre!(err))
+        .wrap_err("Failed to list applied database migrations.")?
+        .into_iter()
+        .max()
+        .map(|version| version.to_string()))
+}
+
+/// Names of the upgrades opening the tracking database applies: the pending migrations, and the
+/// conversions of paths and UUIDs stored by older versions.
+fn pending_upgrades(conn: &mut SqliteConnection) -> Result<Vec<String>> {
+    let mut upgrades: Vec<String> = conn
+        .pending_migrations(MIGRATIONS)
+        .map_err(|err| eyre!(err))
+        .wrap_err("Failed to list pending database migrations.")?
+        .iter()
+        .map(|migration| migration.name().to_string())
+        .collect();
+    if user_version(conn)? < RAW_PATHS_VERSION {
+        upgrades.push("store paths as raw bytes".to_owned());
+    }
+    if user_version(conn)? < RAW_UUIDS_VERSION {
+        upgrades.push("store UUIDs as 16 bytes".to_owned());
+    }
+
+    Ok(upgrades)
+}
+
+/// Applies the pending upgrades to the tracking database.
+///
+/// A database created by an earlier version is copied next to it first, named after its schema
+/// version like `staggered-file-backup.keepme.202610151800000000.bak`, so that it can be restored
+/// if the upgrade fails or the earlier version is needed again. Returns the path of the copy.
+fn upgrade_db(conn: &mut SqliteConnection, backup_dir: &Path) -> Result<Option<PathBuf>> {
+    if pending_upgrades(conn)?.is_empty() {
+        return Ok(None);
+    }
+
+    // A new database has nothing to lose.
+    let copy_path = match schema_version(conn)? {
+        Some(version) => {
+            let copy_path = backup_dir.join(format!("{}.{}.bak", DB_NAME, version));
+            std::fs::copy(backup_dir.join(DB_NAME), &copy_path).wrap_err_with(|| {
+                format!(
+                    "Failed to copy backup tracking database to {} before upgrading it.",
+                    copy_path.display()
+                )
+            })?;
+            Some(copy_path)
+        }
+        None => None,
+    };
+    run_pending_migrations(conn)?;
+    convert_legacy_paths(conn)?;
+    convert_legacy_uuids(conn)?;
+
+    Ok(copy_path)
+}
+
+pub fn open_db(backup_dir: impl AsRef<Path>) -> Result<SqliteConnection> {
+    let mut conn = connect_db(&backup_dir)?;
+    if let Some(copy_path) = upgrade_db(&mut conn, backup_dir.as_ref())? {
+        info!(
+            "Upgraded backup tracking database, keeping the previous one as {}",
+            copy_path.display()
+        );
+    }
+    Ok(conn)
+}
+
+fn ensure_db_exists(backup_dir: &Path) -> Result<()> {
+    if backup_dir.join(DB_NAME).is_file() {
+        return Ok(());
+    }
+    Err(eyre!(
+        "{} contains no backup tracking database.",
+        backup_dir.display()
+    ))
+    .suggestion("The database is created by the first backup into the target folder.")
+}
+
+/// Prints the schema version of the tracking database of the target folder and the upgrades the
+/// next backup or `db upgrade` applies.
+pub fn db_status(target: PathBuf) -> Result<()> {
+    ensure_db_exists(&target)?;
+    let mut conn = connect_db(&target)?;
+    let version = schema_version(&mut conn)?;
+    let pending = pending_upgrades(&mut conn)?;
+
+    println!("Database:        {}", target.join(DB_NAME).display());
+    println!("Schema version:  {}", version.as_deref().unwrap_or("none"));
+    if pending.is_empty() {
+        println!("Pending:         none");
+    } else {
+        println!("Pending:         {}", pending.len());
+        for name in pending {
+            println!("  {}", name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Applies the pending upgrades to the tracking database of the target folder, after copying it
+/// next to it.
+pub fn db_upgrade(target: PathBuf) -> Result<()> {
+    ensure_db_exists(&target)?;
+    let _lock = TargetLock::acquire(&target, Duration::ZERO)?;
+    let mut conn = connect_db(&target)?;
+
+    let pending = pending_upgrades(&mut conn)?;
+    if pending.is_empty() {
+        info!("Backup tracking database is up to date.");
+        return Ok(());
+    }
+    for name in &pending {
+        info!("Applying {}", name);
+    }
+    if let Some(copy_path) = upgrade_db(&mut conn, &target)? {
+        info!(
+            "Kept the database before the upgrade as {}",
+            copy_path.display()
+        );
+    }
+    info!(
+        "Upgraded backup tracking database to schema version {}.",
+        schema_version(&mut conn)?.unwrap_or_default()
+    );
+
+    Ok(())
+}
+
+/// Backups recorded in the tracking database that are neither found nor were removed by a prune,
+/// and backups of the target folder missing from it.
+#[derive(Debug, Default)]
+struct Drift {
+    tracked_count: usize,
+    missing: Vec<PathBuf>,
+    untracked: Vec<PathBuf>,
+}
+
+/// Compares the backups recorded in the tracking database to the backups of the target folder.
+///
+/// Pruned backups stay in the database, so recorded backups are only missing if the audit log
+/// does not record them as removed.
+fn find_drift(
+    conn: &mut SqliteConnection,
+    target: &Path,
+    name_template: &NameTemplate,
+) -> Result<Drift> {
+    let mut removed = HashMap::new();
+    for entry in audit_entries(conn)? {
+        removed.insert(
+            entry.path.path,
+            entry.operation != Operation::Restore.as_str(),
+        );
+    }
+
+    let rows = backup_files::table
+        .select(BackupFile::as_select())
+        .load(conn)
+        .wrap_err("Failed to query tracking database for backups.")?;
+    let mut drift = Drift {
+        tracked_count: rows.len(),
+        ..Default::default()
+    };
+    for row in rows {
+        // The target folder, or the cold storage folder the backup was moved to.
+        let root = row
+            .cold_target
+            .map_or_else(|| target.to_path_buf(), |path| path.path);
+        let path = root.join(&*row.relative_path);
+        if path.exists() {
+            continue;
+        }
+        let absolute_path = std::path::absolute(&path).unwrap_or_else(|_| path.clone());
+        if !removed.get(&absolute_path).copied().unwrap_or(false) {
+            drift.missing.push(path);
+        }
+    }
+
+    for file in backups_of_target(conn, target, name_template)? {
+        let relative_path = relative_backup_path(conn, target, &file.path)?;
+        if backup_file_with_relative_path(conn, relative_path)?.is_none() {
+            drift.untracked.push(file.path);
+        }
+    }
+    drift.missing.sort();
+    drift.untracked.sort();
+
+    Ok(drift)
+}
+
+/// Checks the tracking database of the target folder with SQLite and against the backups of the
+/// target folder, listing backups it records that are missing and backups missing from it.
+pub fn db_check(target: PathBuf, name_template: &NameTemplate) -> Result<()> {
+    ensure_db_exists(&target)?;
+    let _lock = TargetLock::acquire(&target, Duration::ZERO)?;
+
+    let (problems, _) = check_db(&target)?;
+    if problems.is_empty() {
+        println!("Integrity:  ok");
+    } else {
+        println!("Integrity:  {} problems", problems.len());
+        for problem in &problems {
+            println!("  {}", problem);
+        }
+        return Err(eyre!("SQLite found problems in the backup tracking database."))
+            .error_code(ErrorCode::DatabaseCorrupted)
+            .suggestion(
+                "Restore the database from a copy, e.g. on a mirror, or remove it to track the backups anew.",
+            );
+    }
+
+    let mut conn = connect_db(&target)?;
+    let pending_count = pending_upgrades(&mut conn)?.len();
+    if pending_count > 0 {
+        return Err(eyre!(
+            "Backup tracking database has {} pending migrations.",
+            pending_count
+        ))
+        .suggestion("Apply them with `db upgrade` first.");
+    }
+
+    let drift = find_drift(&mut conn, &target, name_template)?;
+    println!("Tracked:    {} backups", drift.tracked_count);
+    println!(
+        "Missing:    {} tracked backups are neither found nor were pruned",
+        drift.missing.len()
+    );
+    for path in &drift.missing {
+        println!("  {}", path.display());
+    }
+    println!(
+        "Untracked:  {} backups are not in the database",
+        drift.untracked.len()
+    );
+    for path in &drift.untracked {
+        println!("  {}", path.display());
+    }
+
+    if !drift.missing.is_empty() || !drift.untracked.is_empty() {
+        return Err(eyre!(
+            "Backup tracking database does not match the target folder."
+        ))
+        .error_code(ErrorCode::DatabaseMismatch)
+        .suggestion("Check whether backups were moved, removed or copied into the target folder by other tools, as untracked backups lack verification times and tags.");
+    }
+
+    Ok(())
+}
+
+/// Rebuilds the tracking database of the target folder, returning the space of removed rows to
+/// the file system.
+pub fn db_vacuum(target: PathBuf) -> Result<()> {
+    ensure_db_exists(&target)?;
+    let _lock = TargetLock::acquire(&target, Duration::ZERO)?;
+    let mut conn = open_db(&target)?;
+    let db_path = target.join(DB_NAME);
+    let size = |path: &Path| {
+        std::fs::metadata(path)
+            .map(|metadata| metadata.len())
+            .wrap_err("Failed reading size of backup tracking database.")
+    };
+
+    let size_before = size(&db_path)?;
+    diesel::sql_query("VACUUM")
+        .execute(&mut conn)
+        .wrap_err("Failed to vacuum backup tracking database.")?;
+    info!(
+        "Vacuumed backup tracking database from {} to {}.",
+        format_size(size_before),
+        format_size(size(&db_path)?)
+    );
+
+    Ok(())
+}
+
+/// Forms of the relative path to look up backups by, as target folders on macOS or SMB shares may
+/// return names in another unicode form than the backup was recorded with.
+fn relative_path_forms(relative_path: impl AsRef<Path>) -> Vec<PathBufSql> {
+    path_forms(relative_path.as_ref())
+        .into_iter()
+        .map(|path| PathBufSql { path })
+        .collect()
+}
+
+pub fn insert_backup_file(conn: &mut SqliteConnection, file: &BackupFile) -> Result<()> {
+    diesel::insert_into(backup_files::table)
+        .values(file)
+        .execute(conn)
+        .wrap_err("Failed to insert backup into tracking database.")?;
+    Ok(())
+}
+
+pub fn backup_files_with_hash(
+    conn: &mut SqliteConnection,
+    hash: impl AsRef<str>,
+) -> Result<Vec<BackupFile>> {
+    backup_files::table
+        .filter(backup_files::hash.eq(hash.as_ref()))
+        .select(BackupFile::as_select())
+        .load(conn)
+        .wrap_err("Failed to query tracking database for backups with equal hash.")
+}
+
+pub fn update_relative_path(
+    conn: &mut SqliteConnection,
+    old_relative_path: impl AsRef<Path>,
+    new_relative_path: impl AsRef<Path>,
+) -> Result<()> {
+    diesel::update(
+        backup_files::table
+            .filter(backup_files::relative_path.eq_any(relative_path_forms(old_relative_path))),
+    )
+    .set(backup_files::relative_path.eq(PathBufSql {
+        path: new_relative_path.as_ref().to_path_buf(),
+    }))
+    .execute(conn)
+    .wrap_err("Failed to update path of backup in tracking database.")?;
+    Ok(())
+}
+
+pub fn backup_file_with_relative_path(
+    conn: &mut SqliteConnection,
+    relative_path: impl AsRef<Path>,
+) -> Result<Option<BackupFile>> {
+    backup_files::table
+        .filter(backup_files::relative_path.eq_any(relative_path_forms(relative_path)))
+        .select(BackupFile::as_select())
+        .first(conn)
+        .optional()
+        .wrap_err("Failed to query tracking database for backup.")
+}
+
+/// Moves the backup to the new path in the quarantine folder and records when it was quarantined.
+pub fn set_quarantined(
+    conn: &mut SqliteConnection,
+    old_relative_path: impl AsRef<Path>,
+    new_relative_path: impl AsRef<Path>,
+    timestamp: i64,
+) -> Result<()> {
+    diesel::update(
+        backup_files::table
+            .filter(backup_files::relative_path.eq_any(relative_path_forms(old_relative_path))),
+    )
+    .set((
+        backup_files::relative_path.eq(PathBufSql {
+            path: new_relative_path.as_ref().to_path_buf(),
+        }),
+        backup_files::quarantined_at.eq(timestamp),
+    ))
+    .execute(conn)
+    .wrap_err("Failed to mark backup as quarantined in tracking database.")?;
+    Ok(())
+}
+
+pub fn set_last_verified(
+    conn: &mut SqliteConnection,
+    relative_path: impl AsRef<Path>,
+    timestamp: i64,
+) -> Result<()> {
+    diesel::update(
+        backup_files::table
+            .filter(backup_files::relative_path.eq_any(relative_path_forms(relative_path))),
+    )
+    .set(backup_files::last_verified.eq(timestamp))
+    .execute(conn)
+    .wrap_err("Failed to update verification time of backup in tracking database.")?;
+    Ok(())
+}
+
+/// Returns the cached hash of the source file if its size and modification time are unchanged.
+pub fn cached_source_hash(
+    conn: &mut SqliteConnection,
+    path: impl AsRef<Path>,
+    size: i64,
+    mtime: i64,
+) -> Result<Option<String>> {
+    source_hashes::table
+        .filter(source_hashes::path.eq(PathBufSql {
+            path: path.as_ref().to_path_buf(),
+        }))
+        .filter(source_hashes::size.eq(size))
+        .filter(source_hashes::mtime.eq(mtime))
+        .select(source_hashes::hash)
+        .first(conn)
+        .optional()
+        .wrap_err("Failed to query tracking database for cached source hash.")
+}
+
+pub fn store_source_hash(conn: &mut SqliteConnection, source_hash: &SourceHash) -> Result<()> {
+    diesel::replace_into(source_hashes::table)
+        .values(source_hash)
+        .execute(conn)
+        .wrap_err("Failed to store source hash in tracking database.")?;
+    Ok(())
+}
+
+pub fn insert_pruned_files(conn: &mut SqliteConnection, files: &[PrunedFile]) -> Result<()> {
+    diesel::insert_into(pruned_files::table)
+        .values(files)
+        .execute(conn)
+        .wrap_err("Failed to record pruned files in tracking database.")?;
+    Ok(())
+}
+
+/// Returns the files moved into the recycle bin by the most recent prune.
+pub fn last_pruned_files(conn: &mut SqliteConnection) -> Result<Vec<PrunedFile>> {
+    let Some(prune_uuid) = pruned_files::table
+        .order(pruned_files::pruned_at.desc())
+        .select(pruned_files::prune_uuid)
+        .first::<UuidSQL>(conn)
+        .optional()
+        .wrap_err("Failed to query tracking database for the last prune.")?
+    else {
+        return Ok(vec![]);
+    };
+
+    pruned_files::table
+        .filter(pruned_files::prune_uuid.eq(prune_uuid))
+        .select(PrunedFile::as_select())
+        .load(conn)
+        .wrap_err("Failed to query tracking database for pruned files.")
+}
+
+/// Returns all files moved into the recycle bin by prunes, oldest first.
+pub fn all_pruned_files(conn: &mut SqliteConnection) -> Result<Vec<PrunedFile>> {
+    pruned_files::table
+        .order(pruned_files::pruned_at.asc())
+        .select(PrunedFile::as_select())
+        .load(conn)
+        .wrap_err("Failed to query tracking database for pruned files.")
+}
+
+/// Forgets the pruned files, e.g. after purging them from the recycle bin.
+pub fn forget_pruned_files(conn: &mut SqliteConnection, uuids: &[UuidSQL]) -> Result<()> {
+    diesel::delete(pruned_files::table.filter(pruned_files::uuid.eq_any(uuids)))
+        .execute(conn)
+        .wrap_err("Failed to remove purged files from tracking database.")?;
+    Ok(())
+}
+
+pub fn delete_pruned_files(conn: &mut SqliteConnection, prune_uuid: &UuidSQL) -> Result<()> {
+    diesel::delete(pruned_files::table.filter(pruned_files::prune_uuid.eq(prune_uuid)))
+        .execute(conn)
+        .wrap_err("Failed to remove undone prune from tracking database.")?;
+    Ok(())
+}
+
+pub fn insert_audit_entries(conn: &mut SqliteConnection, entries: &[AuditEntry]) -> Result<()> {
+    diesel::insert_into(audit_log::table)
+        .values(entries)
+        .execute(conn)
+        .wrap_err("Failed to record audit log entries in tracking database.")?;
+    Ok(())
+}
+
+/// Returns the entries of the audit log, oldest first.
+pub fn audit_entries(conn: &mut SqliteConnection) -> Result<Vec<AuditEntry>> {
+    audit_log::table
+        .order(audit_log::recorded_at.asc())
+        .select(AuditEntry::as_select())
+        .load(conn)
+        .wrap_err("Failed to query tracking database for audit log.")
+}
+
+pub fn insert_journal_entries(conn: &mut SqliteConnection, entries: &[JournalEntry]) -> Result<()> {
+    diesel::insert_into(journal::table)
+        .values(entries)
+        .execute(conn)
+        .wrap_err("Failed to record operation in journal of tracking database.")?;
+    Ok(())
+}
+
+/// Records the hash of the verified backup of the journal entry.
+pub fn set_journal_hash(
+    conn: &mut SqliteConnection,
+    entry: &JournalEntry,
+    hash: impl AsRef<str>,
+) -> Result<()> {
+    diesel::update(journal::table.filter(journal::uuid.eq(&entry.uuid)))
+        .set(journal::hash.eq(hash.as_ref()))
+        .execute(conn)
+        .wrap_err("Failed to update journal of tracking database.")?;
+    Ok(())
+}
+
+/// Removes the entries of finished operations from the journal.
+pub fn remove_journal_entries(conn: &mut SqliteConnection, entries: &[JournalEntry]) -> Result<()> {
+    diesel::delete(
+        journal::table.filter(journal::uuid.eq_any(entries.iter().map(|entry| entry.uuid.clone()))),
+    )
+    .execute(conn)
+    .wrap_err("Failed to remove finished operations from journal of tracking database.")?;
+    Ok(())
+}
+
+/// Returns the entries of the journal, which are the unfinished operations, oldest first.
+pub fn journal_entries(conn: &mut SqliteConnection) -> Result<Vec<JournalEntry>> {
+    journal::table
+        .order(journal::started_at.asc())
+        .select(JournalEntry::as_select())
+        .load(conn)
+        .wrap_err("Failed to query journal of tracking database.")
+}
+
+/// Checks if the audit log has an entry of the file since the Unix timestamp in milliseconds.
+pub fn is_audited_since(
+    conn: &mut SqliteConnection,
+    path: impl AsRef<Path>,
+    since: i64,
+) -> Result<bool> {
+    let path = PathBufSql {
+        path: path.as_ref().to_path_buf(),
+    };
+    Ok(audit_log::table
+        .filter(audit_log::path.eq(path))
+        .filter(audit_log::recorded_at.ge(since))
+        .count()
+        .get_result::<i64>(conn)
+        .wrap_err("Failed to query tracking database for audit log.")?
+        > 0)
+}
+
+/// Records the cold storage folder the backup was moved to.
+pub fn set_cold_target(
+    conn: &mut SqliteConnection,
+    relative_path: impl AsRef<Path>,
+    cold_target: impl AsRef<Path>,
+) -> Result<()> {
+    diesel::update(
+        backup_files::table
+            .filter(backup_files::relative_path.eq_any(relative_path_forms(relative_path))),
+    )
+    .set(backup_files::cold_target.eq(Some(PathBufSql {
+        path: cold_target.as_ref().to_path_buf(),
+    })))
+    .execute(conn)
+    .wrap_err("Failed to update location of backup in tracking database.")?;
+    Ok(())
+}
+
+/// Returns the backups moved to cold storage.
+pub fn cold_backup_files(conn: &mut SqliteConnection) -> Result<Vec<BackupFile>> {
+    backup_files::table
+        .filter(backup_files::cold_target.is_not_null())
+        .select(BackupFile::as_select())
+        .load(conn)
+        .wrap_err("Failed to query tracking database for backups in cold storage.")
+}
+
+/// Marks all backups as located in the target folder again.
+pub fn clear_cold_targets(conn: &mut SqliteConnection) -> Result<()> {
+    diesel::update(backup_files::table)
+        .set(backup_files::cold_target.eq(None::<PathBufSql>))
+        .execute(conn)
+        .wrap_err("Failed to update location of backups in tracking database.")?;
+    Ok(())
+}
+
+pub fn set_tags(
+    conn: &mut SqliteConnection,
+    relative_path: impl AsRef<Path>,
+    tags: Option<String>,
+) -> Result<()> {
+    diesel::update(
+        backup_files::table
+            .filter(backup_files::relative_path.eq_any(relative_path_forms(relative_path))),
+    )
+    .set(backup_files::tags.eq(tags))
+    .execute(conn)
+    .wrap_err("Failed to update tags of backup in tracking database.")?;
+    Ok(())
+}
+
+/// Retention tiers protecting the backup as recorded by the last prune or promotion.
+pub fn tiers_of(row: &BackupFile) -> Tiers {
+    Tiers {
+        latest: row.keep_latest,
+        daily: row.keep_daily,
+        weekly: row.keep_weekly,
+        monthly: row.keep_monthly,
+        quarterly: row.keep_quarterly,
+        yearly: row.keep_yearly,
+    }
+}
+
+pub fn set_tiers(
+    conn: &mut SqliteConnection,
+    relative_path: impl AsRef<Path>,
+    tiers: Tiers,
+) -> Result<()> {
+    diesel::update(
+        backup_files::table
+            .filter(backup_files::relative_path.eq_any(relative_path_forms(relative_path))),
+    )
+    .set((
+        backup_files::keep_latest.eq(tiers.latest),
+        backup_files::keep_daily.eq(tiers.daily),
+        backup_files::keep_weekly.eq(tiers.weekly),
+        backup_files::keep_monthly.eq(tiers.monthly),
+        backup_files::keep_quarterly.eq(tiers.quarterly),
+        backup_files::keep_yearly.eq(tiers.yearly),
+    ))
+    .execute(conn)
+    .wrap_err("Failed to update retention tiers of backup in tracking database.")?;
+    Ok(())
+}
+
+pub fn set_promoted_tier(
+    conn: &mut SqliteConnection,
+    relative_path: impl AsRef<Path>,
+    tier: Option<Tier>,
+) -> Result<()> {
+    diesel::update(
+        backup_files::table
+            .filter(backup_files::relative_path.eq_any(relative_path_forms(relative_path))),
+    )
+    .set(backup_files::promoted_tier.eq(tier.map(|tier| tier.as_str())))
+    .execute(conn)
+    .wrap_err("Failed to update promoted tier of backup in tracking database.")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backup::test_util::{TempDir, tracked_row_in_all_tiers};
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_paths() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let dir = std::env::temp_dir()
+            .join(format!("sfb-db-test-{}", std::process::id()))
+            .join(OsStr::from_bytes(b"caf\xe9"));
+        std::fs::create_dir_all(&dir).unwrap();
+        let legacy_path =
+            |path: &str| bitcode::serialize(&PathBufSql { path: path.into() }).unwrap();
+
+        let legacy_uuid = UuidSQL::new();
+
+        // Rows written before paths and UUIDs were stored as raw bytes.
+        let mut conn = open_db(&dir).unwrap();
+        diesel::insert_into(source_hashes::table)
+            .values((
+                source_hashes::path.eq(legacy_path("notes.txt")),
+                source_hashes::size.eq(5),
+                source_hashes::mtime.eq(1),
+                source_hashes::hash.eq("hash"),
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        diesel::insert_into(audit_log::table)
+            .values((
+                audit_log::uuid.eq(bitcode::serialize(&legacy_uuid).unwrap()),
+                audit_log::recorded_at.eq(1),
+                audit_log::operation.eq("trash"),
+                audit_log::reason.eq("pruned"),
+                audit_log::path.eq(legacy_path("/backups/notes.txt")),
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        diesel::sql_query("PRAGMA user_version = 0")
+            .execute(&mut conn)
+            .unwrap();
+        drop(conn);
+        assert_eq!(check_db(&dir).unwrap().1, 2);
+
+        let mut conn = open_db(&dir).unwrap();
+        assert_eq!(check_db(&dir).unwrap().1, 0);
+        assert_eq!(
+            cached_source_hash(&mut conn, "notes.txt", 5, 1).unwrap(),
+            Some("hash".to_string())
+        );
+        let entry = &audit_entries(&mut conn).unwrap()[0];
+        assert_eq!(entry.path.path, Path::new("/backups/notes.txt"));
+        assert_eq!(*entry.uuid, *legacy_uuid);
+        assert!(
+            diesel::update(audit_log::table)
+                .set(audit_log::reason.eq("rewritten"))
+                .execute(&mut conn)
+                .is_err()
+        );
+
+        let latin1_path = Path::new(OsStr::from_bytes(b"/home/r\xe9sum\xe9.txt"));
+        store_source_hash(
+            &mut conn,
+            &SourceHash {
+                path: PathBufSql {
+                    path: latin1_path.to_path_buf(),
+                },
+                size: 7,
+                mtime: 2,
+                hash: "latin1".to_string(),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            cached_source_hash(&mut conn, latin1_path, 7, 2).unwrap(),
+            Some("latin1".to_string())
+        );
+
+        drop(conn);
+        std::fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_db() {
+        let dir = std::env::temp_dir().join(format!("sfb-upgrade-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut conn = open_db(&dir).unwrap();
+        let version = schema_version(&mut conn).unwrap().unwrap();
+        assert!(pending_upgrades(&mut conn).unwrap().is_empty());
+        assert!(!dir.join(format!("{}.{}.bak", DB_NAME, version)).exists());
+        assert_eq!(upgrade_db(&mut conn, &dir).unwrap(), None);
+
+        diesel::sql_query("PRAGMA user_version = 0")
+            .execute(&mut conn)
+            .unwrap();
+        assert_eq!(pending_upgrades(&mut conn).unwrap().len(), 2);
+        let copy_path = upgrade_db(&mut conn, &dir).unwrap().unwrap();
+        assert_eq!(copy_path, dir.join(format!("{}.{}.bak", DB_NAME, version)));
+        assert!(copy_path.is_file());
+        assert!(pending_upgrades(&mut conn).unwrap().is_empty());
+
+        drop(conn);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_find_drift() {
+        let dir = TempDir::new("drift");
+        let mut conn = open_db(&dir).unwrap();
+        let mut track = |name: &str| {
+            insert_backup_file(&mut conn, &tracked_row_in_all_tiers(name)).unwrap();
+        };
+        track("2025-01-01_00_db.sql");
+        track("2025-01-02_00_db.sql");
+        track("2025-01-03_00_db.sql");
+        std::fs::write(dir.join("2025-01-03_00_db.sql"), b"kept").unwrap();
+        std::fs::write(dir.join("2025-01-04_00_db.sql"), b"copied").unwrap();
+        // Pruned backups stay in the database.
+        insert_audit_entries(
+            &mut conn,
+            &[AuditEntry {
+                uuid: UuidSQL::new(),
+                recorded_at: 1,
+                operation: Operation::Trash.as_str().to_owned(),
+                reason: "pruned".to_owned(),
+                path: PathBufSql {
+                    path: std::path::absolute(dir.join("2025-01-01_00_db.sql")).unwrap(),
+                },
+                size: None,
+                hash: None,
+            }],
+        )
+        .unwrap();
+
+        let drift = find_drift(&mut conn, &dir, &NameTemplate::default()).unwrap();
+
+        assert_eq!(drift.tracked_count, 3);
+        assert_eq!(drift.missing, vec![dir.join("2025-01-02_00_db.sql")]);
+        assert_eq!(drift.untracked, vec![dir.join("2025-01-04_00_db.sql")]);
+    }
+}
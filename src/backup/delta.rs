@@ -0,0 +1,550 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    Section,
+    eyre::{Context, Result, bail, ensure},
+};
+use sha2::{Digest, Sha256};
+
+use crate::backup::{
+    archive::is_zip, cleanup::BackupFile, file::relative_path_string, store::is_manifest,
+};
+
+/// Extension appended to backups stored as delta against a full backup.
+pub const DELTA_EXTENSION: &str = "delta";
+
+const MAGIC: &[u8] = b"SFBDELTA\x01";
+
+const BLOCK_SIZE: usize = 16 * 1024;
+const READ_SIZE: usize = 1024 * 1024;
+
+const OP_END: u8 = 0;
+const OP_COPY: u8 = 1;
+const OP_INSERT: u8 = 2;
+
+/// Checks if the file is a delta backup, not a plain backup of a file with the delta extension.
+pub fn is_delta(path: impl AsRef<Path>) -> bool {
+    let mut magic = [0; MAGIC.len()];
+    path.as_ref()
+        .extension()
+        .is_some_and(|ext| ext == DELTA_EXTENSION)
+        && File::open(path.as_ref())
+            .and_then(|mut file| file.read_exact(&mut magic))
+            .is_ok()
+        && magic == MAGIC
+}
+
+/// Weak rolling checksum as used by rsync.
+#[derive(Debug, Clone, Copy)]
+struct RollingChecksum {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl RollingChecksum {
+    fn new(window: &[u8]) -> Self {
+        let len = window.len() as u32;
+        let mut a: u32 = 0;
+        let mut b: u32 = 0;
+        for (i, byte) in window.iter().enumerate() {
+            a = a.wrapping_add(*byte as u32);
+            b = b.wrapping_add((len - i as u32).wrapping_mul(*byte as u32));
+        }
+        Self { a, b, len }
+    }
+
+    fn roll(&mut self, out: u8, input: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(input as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+
+    fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+struct BlockIndex {
+    blocks: HashMap<u32, Vec<(u64, [u8; 32])>>,
+}
+
+impl BlockIndex {
+    fn find(&self, weak: u32, window: &[u8]) -> Option<u64> {
+        let candidates = self.blocks.get(&weak)?;
+        let strong: [u8; 32] = Sha256::digest(window).into();
+        candidates
+            .iter()
+            .find(|(_, candidate)| *candidate == strong)
+            .map(|(offset, _)| *offset)
+    }
+}
+
+/// Indexes all full blocks of the base and hashes the base as a whole.
+fn build_index(base: &mut impl Read) -> Result<(BlockIndex, String)> {
+    let mut blocks: HashMap<u32, Vec<(u64, [u8; 32])>> = HashMap::new();
+    let mut hasher = Sha256::new();
+    let mut block = vec![0; BLOCK_SIZE];
+    let mut offset = 0;
+
+    loop {
+        let mut filled = 0;
+        while filled < BLOCK_SIZE {
+            let read = base.read(&mut block[filled..])?;
+            if read == 0 {
+                break;
+            }
+            filled += read;
+        }
+
+        hasher.update(&block[..filled]);
+
+        if filled < BLOCK_SIZE {
+            break;
+        }
+
+        let weak = RollingChecksum::new(&block).digest();
+        let strong: [u8; 32] = Sha256::digest(&block).into();
+        blocks.entry(weak).or_default().push((offset, strong));
+        offset += BLOCK_SIZE as u64;
+    }
+
+    Ok((BlockIndex { blocks }, hex::encode_upper(hasher.finalize())))
+}
+
+struct DeltaWriter<W: Write> {
+    out: W,
+    pending_copy: Option<(u64, u64)>,
+}
+
+impl<W: Write> DeltaWriter<W> {
+    fn copy(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        if let Some((pending_offset, pending_len)) = &mut self.pending_copy
+            && *pending_offset + *pending_len == offset
+        {
+            *pending_len += len;
+            return Ok(());
+        }
+        self.flush_copy()?;
+        self.pending_copy = Some((offset, len));
+        Ok(())
+    }
+
+    fn insert(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.flush_copy()?;
+        self.out.write_all(&[OP_INSERT])?;
+        self.out.write_all(&(data.len() as u64).to_le_bytes())?;
+        self.out.write_all(data)
+    }
+
+    fn flush_copy(&mut self) -> io::Result<()> {
+        if let Some((offset, len)) = self.pending_copy.take() {
+            self.out.write_all(&[OP_COPY])?;
+            self.out.write_all(&offset.to_le_bytes())?;
+            self.out.write_all(&len.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        self.flush_copy()?;
+        self.out.write_all(&[OP_END])?;
+        Ok(self.out)
+    }
+}
+
+/// Encodes the source as copy and insert operations against the indexed base.
+fn encode_ops(index: &BlockIndex, source: &mut impl Read, out: impl Write) -> io::Result<()> {
+    let mut writer = DeltaWriter {
+        out,
+        pending_copy: None,
+    };
+    let mut buf: Vec<u8> = Vec::new();
+    let mut pos = 0;
+    let mut literal_start = 0;
+    let mut checksum: Option<RollingChecksum> = None;
+    let mut eof = false;
+
+    loop {
+        if buf.len() - pos < BLOCK_SIZE && !eof {
+            if pos - literal_start >= READ_SIZE {
+                writer.insert(&buf[literal_start..pos])?;
+                literal_start = pos;
+            }
+            buf.drain(..literal_start);
+            pos -= literal_start;
+            literal_start = 0;
+
+            let old_len = buf.len();
+            buf.resize(old_len + READ_SIZE, 0);
+            let read = source.read(&mut buf[old_len..])?;
+            buf.truncate(old_len + read);
+            eof = read == 0;
+            continue;
+        }
+
+        if buf.len() - pos < BLOCK_SIZE {
+            break;
+        }
+
+        let window = &buf[pos..pos + BLOCK_SIZE];
+        let weak = *checksum.get_or_insert_with(|| RollingChecksum::new(window));
+
+        if let Some(offset) = index.find(weak.digest(), window) {
+            writer.insert(&buf[literal_start..pos])?;
+            writer.copy(offset, BLOCK_SIZE as u64)?;
+            pos += BLOCK_SIZE;
+            literal_start = pos;
+            checksum = None;
+        } else if buf.len() > pos + BLOCK_SIZE {
+            let mut weak = weak;
+            weak.roll(buf[pos], buf[pos + BLOCK_SIZE]);
+            checksum = Some(weak);
+            pos += 1;
+        } else {
+            pos += 1;
+            checksum = None;
+        }
+    }
+
+    writer.insert(&buf[literal_start..])?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Rebuilds the original content from the base and the delta operations.
+fn apply_ops(
+    base: &mut (impl Read + Seek),
+    ops: &mut impl Read,
+    out: &mut impl Write,
+) -> Result<()> {
+    loop {
+        let mut op = [0];
+        ops.read_exact(&mut op)
+            .wrap_err("Delta ended unexpectedly.")?;
+
+        match op[0] {
+            OP_END => return Ok(()),
+            OP_COPY => {
+                let offset = read_u64(ops)?;
+                let len = read_u64(ops)?;
+                base.seek(SeekFrom::Start(offset))?;
+                let copied = io::copy(&mut base.by_ref().take(len), out)?;
+                ensure!(
+                    copied == len,
+                    "Delta references data beyond the end of its base."
+                );
+            }
+            OP_INSERT => {
+                let len = read_u64(ops)?;
+                let copied = io::copy(&mut ops.by_ref().take(len), out)?;
+                ensure!(copied == len, "Delta ended unexpectedly.");
+            }
+            other => bail!("Delta contains an unknown operation: {}", other),
+        }
+    }
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader
+        .read_exact(&mut bytes)
+        .wrap_err("Delta ended unexpectedly.")?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub struct DeltaHeader {
+    /// Path of the base relative to the target folder, separated by `/`.
+    pub base_name: String,
+    /// Hash of the base, only recorded for manual inspection.
+    pub base_hash: String,
+}
+
+fn read_header(reader: &mut impl Read) -> Result<DeltaHeader> {
+    let mut magic = [0; MAGIC.len()];
+    reader
+        .read_exact(&mut magic)
+        .wrap_err("Failed to read delta header.")?;
+    ensure!(magic == MAGIC, "File is not a delta backup.");
+
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let mut base_name = vec![0; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut base_name)?;
+    let mut base_hash = vec![0; 64];
+    reader.read_exact(&mut base_hash)?;
+
+    Ok(DeltaHeader {
+        base_name: String::from_utf8(base_name).wrap_err("Delta base name is not utf-8.")?,
+        base_hash: String::from_utf8(base_hash).wrap_err("Delta base hash is not utf-8.")?,
+    })
+}
+
+fn write_header(writer: &mut impl Write, header: &DeltaHeader) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&(header.base_name.len() as u32).to_le_bytes())?;
+    writer.write_all(header.base_name.as_bytes())?;
+    writer.write_all(header.base_hash.as_bytes())
+}
+
+pub fn read_delta_header(delta_path: impl AsRef<Path>) -> Result<DeltaHeader> {
+    let mut file = File::open(delta_path.as_ref()).wrap_err("Failed to open delta backup.")?;
+    read_header(&mut file)
+}
+
+/// Writes a delta of the source against the full backup at `base_path`.
+pub fn write_delta(
+    target_dir: impl AsRef<Path>,
+    base_path: impl AsRef<Path>,
+    source: impl AsRef<Path>,
+    delta_path: impl AsRef<Path>,
+) -> Result<()> {
+    let base_name = relative_path_string(target_dir.as_ref(), base_path.as_ref())
+        .wrap_err("Delta base is not located in the target folder.")?;
+
+    let mut base =
+        BufReader::new(File::open(base_path.as_ref()).wrap_err("Failed to open delta base.")?);
+    let (index, base_hash) = build_index(&mut base).wrap_err("Failed to index delta base.")?;
+
+    let mut source =
+        BufReader::new(File::open(source.as_ref()).wrap_err("Failed to open source file.")?);
+    let mut out = BufWriter::new(
+        File::create(delta_path.as_ref())
+            .wrap_err("Failed to create delta backup.")
+            .suggestion("Check if you have permissions to write to the target dir.")?,
+    );
+
+    write_header(
+        &mut out,
+        &DeltaHeader {
+            base_name,
+            base_hash,
+        },
+    )?;
+
+    encode_ops(&index, &mut source, &mut out).wrap_err("Failed to write delta backup.")?;
+    out.flush().wrap_err("Failed to write delta backup.")?;
+
+    Ok(())
+}
+
+/// Copies the delta to `new_delta_path`, pointing it to the base at `base_name` instead.
+pub fn rewrite_delta_base(
+    delta_path: impl AsRef<Path>,
+    new_delta_path: impl AsRef<Path>,
+    base_name: impl Into<String>,
+) -> Result<()> {
+    let mut delta =
+        BufReader::new(File::open(delta_path.as_ref()).wrap_err("Failed to open delta backup.")?);
+    let header = read_header(&mut delta)?;
+
+    let mut out = BufWriter::new(
+        File::create_new(new_delta_path.as_ref()).wrap_err("Failed to create delta backup.")?,
+    );
+    write_header(
+        &mut out,
+        &DeltaHeader {
+            base_name: base_name.into(),
+            base_hash: header.base_hash,
+        },
+    )?;
+    io::copy(&mut delta, &mut out).wrap_err("Failed to write delta backup.")?;
+    out.flush().wrap_err("Failed to write delta backup.")?;
+
+    Ok(())
+}
+
+/// Reassembles the file described by the delta into the writer.
+pub fn write_delta_content(
+    target_dir: impl AsRef<Path>,
+    delta_path: impl AsRef<Path>,
+    writer: &mut impl Write,
+) -> Result<()> {
+    let mut delta =
+        BufReader::new(File::open(delta_path.as_ref()).wrap_err("Failed to open delta backup.")?);
+    let header = read_header(&mut delta)?;
+
+    let base_path = target_dir.as_ref().join(&header.base_name);
+    let mut base = File::open(&base_path)
+        .wrap_err_with(|| format!("Failed to open delta base {}", base_path.display()))
+        .suggestion("The full backup this delta is based on might have been removed.")?;
+
+    apply_ops(&mut base, &mut delta, writer)
+}
+
+/// Returns the newest full backup, unless `full_every` backups have passed since it.
+///
+/// Only plain copies are full backups, as zip archives and chunk manifests differ from the source.
+pub fn find_delta_base(file_list: &[BackupFile], full_every: u32) -> Option<BackupFile> {
+    let latest_full = file_list
+        .iter()
+        .filter(|file| !is_delta(&file.path) && !is_manifest(&file.path) && !is_zip(&file.path))
+        .max()?;
+
+    let deltas_since_full = file_list
+        .iter()
+        .filter(|file| is_delta(&file.path) && *file > latest_full)
+        .count();
+
+    if deltas_since_full + 1 < usize::try_from(full_every).ok()? {
+        Some(latest_full.clone())
+    } else {
+        None
+    }
+}
+
+/// Extends the files to keep by the full backups the kept deltas are based on.
+pub fn with_delta_bases(
+    target_dir: impl AsRef<Path>,
+    file_list: &[BackupFile],
+    mut files_to_keep: Vec<BackupFile>,
+) -> Result<Vec<BackupFile>> {
+    let base_paths = files_to_keep
+        .iter()
+        .filter(|file| is_delta(&file.path))
+        .map(|file| {
+            read_delta_header(&file.path).map(|header| target_dir.as_ref().join(header.base_name))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let files_by_path: HashMap<&Path, &BackupFile> = file_list
+        .iter()
+        .map(|file| (file.path.as_path(), file))
+        .collect();
+    let mut kept: HashSet<PathBuf> = files_to_keep.iter().map(|file| file.path.clone()).collect();
+    for base_path in base_paths {
+        if let Some(base) = files_by_path.get(base_path.as_path())
+            && kept.insert(base_path)
+        {
+            files_to_keep.push((*base).clone());
+        }
+    }
+
+    files_to_keep.sort();
+
+    Ok(files_to_keep)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::backup::test_util::TempDir;
+
+    fn pseudo_random_bytes(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (state >> 33) as u8
+            })
+            .collect()
+    }
+
+    fn round_trip(base: &[u8], source: &[u8]) -> (Vec<u8>, usize) {
+        let (index, _) = build_index(&mut Cursor::new(base)).unwrap();
+        let mut ops = vec![];
+        encode_ops(&index, &mut Cursor::new(source), &mut ops).unwrap();
+
+        let mut restored = vec![];
+        apply_ops(
+            &mut Cursor::new(base),
+            &mut Cursor::new(&ops),
+            &mut restored,
+        )
+        .unwrap();
+
+        (restored, ops.len())
+    }
+
+    #[test]
+    fn test_rolling_checksum_roll() {
+        let data = pseudo_random_bytes(100, 1);
+        let mut rolling = RollingChecksum::new(&data[0..32]);
+        for i in 0..50 {
+            rolling.roll(data[i], data[i + 32]);
+            assert_eq!(
+                rolling.digest(),
+                RollingChecksum::new(&data[i + 1..i + 33]).digest()
+            );
+        }
+    }
+
+    #[test]
+    fn test_delta_identical() {
+        let base = pseudo_random_bytes(BLOCK_SIZE * 20 + 123, 2);
+
+        let (restored, delta_len) = round_trip(&base, &base);
+
+        assert_eq!(restored, base);
+        assert!(delta_len < 1024);
+    }
+
+    #[test]
+    fn test_delta_shifted_and_changed() {
+        let base = pseudo_random_bytes(BLOCK_SIZE * 20, 3);
+        let mut source = b"inserted prefix".to_vec();
+        source.extend_from_slice(&base[..BLOCK_SIZE * 10]);
+        source.extend_from_slice(&pseudo_random_bytes(500, 4));
+        source.extend_from_slice(&base[BLOCK_SIZE * 12..]);
+
+        let (restored, delta_len) = round_trip(&base, &source);
+
+        assert_eq!(restored, source);
+        assert!(delta_len < BLOCK_SIZE * 2);
+    }
+
+    #[test]
+    fn test_delta_unrelated() {
+        let base = pseudo_random_bytes(BLOCK_SIZE * 3, 5);
+        let source = pseudo_random_bytes(BLOCK_SIZE * 5 + 7, 6);
+
+        let (restored, _) = round_trip(&base, &source);
+
+        assert_eq!(restored, source);
+    }
+
+    #[test]
+    fn test_delta_empty() {
+        let (restored, _) = round_trip(&[], &[]);
+
+        assert!(restored.is_empty());
+    }
+
+    #[test]
+    fn test_is_delta() {
+        let dir = TempDir::new("is-delta");
+        let base = dir.join("2025-01-01_00_notes.txt");
+        std::fs::write(&base, "notes").unwrap();
+        let delta = dir.join("2025-01-02_00_notes.txt.delta");
+        write_delta(&dir, &base, &base, &delta).unwrap();
+        let plain = dir.join("2025-01-03_00_foo.delta");
+        std::fs::write(&plain, "SFBDELTA").unwrap();
+
+        let is_delta_backup = is_delta(&delta);
+        let is_plain_delta = is_delta(&plain);
+        let is_missing_delta = is_delta(dir.join("2025-01-04_00_foo.delta"));
+
+        assert!(is_delta_backup);
+        assert!(!is_plain_delta);
+        assert!(!is_missing_delta);
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use color_eyre::eyre::{Context, Result};
+use log::info;
+use sha2::{Digest, Sha256};
+
+use crate::backup::{
+    hash::hash_file, parsing::metadata_from_directory, restore::write_backup_content,
+    template::NameTemplate,
+};
+
+/// Writer hashing and counting everything written to it.
+#[derive(Default)]
+struct HashingWriter {
+    hasher: Sha256,
+    len: u64,
+}
+
+impl Write for HashingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.update(buf);
+        self.len += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn modified(path: &Path) -> Result<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .wrap_err_with(|| format!("Failed reading modification date of {}", path.display()))
+}
+
+/// Signed difference in seconds from `earlier` to `later`.
+fn seconds_between(earlier: SystemTime, later: SystemTime) -> i64 {
+    match later.duration_since(earlier) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(err) => -(err.duration().as_secs() as i64),
+    }
+}
+
+/// Compares the source to the newest backup in the target folder.
+///
+/// Returns `true` if the source is identical to the newest backup.
+pub fn diff(source: PathBuf, target: PathBuf, name_template: &NameTemplate) -> Result<bool> {
+    let Some(backup) = metadata_from_directory(&target, name_template)?
+        .into_iter()
+        .max()
+    else {
+        info!("No backups found in target folder.");
+        return Ok(false);
+    };
+    info!("Newest backup: {}", backup.path.display());
+
+    info!("Hashing source file.");
+    let source_hash =
+        hash_file(&mut File::open(&source).wrap_err("Failed to open source file for hashing.")?)?;
+    let source_len = std::fs::metadata(&source)
+        .wrap_err("Failed reading metadata of source file.")?
+        .len();
+
+    info!("Hashing newest backup.");
+    let mut backup_content = HashingWriter::default();
+    write_backup_content(&target, &backup.path, &mut backup_content)?;
+    let backup_hash = hex::encode_upper(backup_content.hasher.finalize());
+
+    info!("Source file sh256: {}", source_hash);
+    info!("Backup sh256: {}", backup_hash);
+    info!(
+        "Size delta: {:+} bytes",
+        source_len as i64 - backup_content.len as i64
+    );
+    info!(
+        "Modification time delta: {:+} seconds",
+        seconds_between(modified(&backup.path)?, modified(&source)?)
+    );
+
+    let identical = source_hash == backup_hash;
+    if identical {
+        info!("Source is identical to the newest backup.");
+    } else {
+        info!("Source differs from the newest backup.");
+    }
+
+    Ok(identical)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_seconds_between() {
+        let earlier = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let later = SystemTime::UNIX_EPOCH + Duration::from_secs(160);
+
+        assert_eq!(seconds_between(earlier, later), 60);
+        assert_eq!(seconds_between(later, earlier), -60);
+    }
+}
@@ -0,0 +1,344 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{collections::HashSet, fmt::Display, path::Path};
+
+use chrono::{Local, NaiveDate, TimeDelta};
+use color_eyre::{
+    Section,
+    eyre::{Result, eyre},
+};
+
+use crate::backup::{
+    cleanup::BackupFile,
+    db::{DB_NAME, check_db},
+    fs_limits::available_space,
+    list::format_size,
+    normalize::composed,
+    parsing::{basename_from_file_name, metadata_from_directory, unmatched_file_paths},
+    quarantine::quarantined_backups,
+    template::NameTemplate,
+    undo::check_trash,
+};
+
+/// Name of the file created and removed to check that the target folder is writable.
+const WRITE_PROBE_NAME: &str = ".sfb-write-probe";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pass => write!(f, "PASS"),
+            Self::Warn => write!(f, "WARN"),
+            Self::Fail => write!(f, "FAIL"),
+        }
+    }
+}
+
+/// Outcome of a single check of the target folder.
+#[derive(Debug)]
+struct Check {
+    name: &'static str,
+    status: Status,
+    message: String,
+    suggestion: Option<&'static str>,
+}
+
+impl Check {
+    fn pass(name: &'static str, message: String) -> Self {
+        Self {
+            name,
+            status: Status::Pass,
+            message,
+            suggestion: None,
+        }
+    }
+
+    fn warn(name: &'static str, message: String, suggestion: &'static str) -> Self {
+        Self {
+            name,
+            status: Status::Warn,
+            message,
+            suggestion: Some(suggestion),
+        }
+    }
+
+    fn fail(name: &'static str, message: String, suggestion: &'static str) -> Self {
+        Self {
+            name,
+            status: Status::Fail,
+            message,
+            suggestion: Some(suggestion),
+        }
+    }
+}
+
+fn check_writable(target: &Path) -> Check {
+    const NAME: &str = "Target writable";
+
+    let probe_path = target.join(WRITE_PROBE_NAME);
+    match std::fs::write(&probe_path, b"").and_then(|()| std::fs::remove_file(&probe_path)) {
+        Ok(()) => Check::pass(
+            NAME,
+            format!("Created and removed a file in {}.", target.display()),
+        ),
+        Err(err) => Check::fail(
+            NAME,
+            format!("Failed to write into {}: {}", target.display(), err),
+            "Check the permissions of the target folder and that its drive is not mounted read-only.",
+        ),
+    }
+}
+
+fn check_recycle_bin(target: &Path) -> Check {
+    const NAME: &str = "Recycle bin";
+
+    match check_trash(target) {
+        Ok(()) => Check::pass(NAME, "Moved a file into the recycle bin.".to_owned()),
+        Err(err) => Check::fail(
+            NAME,
+            format!("{:#}", err),
+            "Pruned backups are moved into the recycle bin. Use a drive with a recycle bin, e.g. not a network share, or pass `--on-no-trash`.",
+        ),
+    }
+}
+
+fn check_database(target: &Path) -> Check {
+    const NAME: &str = "Tracking database";
+
+    if !target.join(DB_NAME).is_file() {
+        return Check::warn(
+            NAME,
+            format!("{} does not exist yet.", DB_NAME),
+            "The database is created by the first backup into the target folder.",
+        );
+    }
+    match check_db(target) {
+        Ok((problems, _)) if !problems.is_empty() => Check::fail(
+            NAME,
+            format!("SQLite found problems: {}", problems.join("; ")),
+            "Restore the database from a copy, e.g. on a mirror, or remove it to track the backups anew.",
+        ),
+        Ok((_, 0)) => Check::pass(NAME, "Intact and up to date.".to_owned()),
+        Ok((_, pending_count)) => Check::warn(
+            NAME,
+            format!("Intact with {} pending migrations.", pending_count),
+            "Migrations are applied by the next backup or `db upgrade`. Older versions cannot open the database afterwards.",
+        ),
+        Err(err) => Check::fail(
+            NAME,
+            format!("{:#}", err),
+            "Restore the database from a copy, e.g. on a mirror, or remove it to track the backups anew.",
+        ),
+    }
+}
+
+fn check_quarantine(target: &Path) -> Check {
+    const NAME: &str = "Quarantine";
+
+    match quarantined_backups(target) {
+        Ok(paths) if paths.is_empty() => Check::pass(NAME, "No corrupted backups.".to_owned()),
+        Ok(paths) => Check::warn(
+            NAME,
+            format!(
+                "{} corrupted backups were quarantined, e.g. {}",
+                paths.len(),
+                paths[0].strip_prefix(target).unwrap_or(&paths[0]).display()
+            ),
+            "Restore a healthy copy, e.g. from a mirror, or remove the quarantined backups after checking them.",
+        ),
+        Err(err) => Check::fail(
+            NAME,
+            format!("{:#}", err),
+            "Check the permissions of the quarantine folder.",
+        ),
+    }
+}
+
+/// Compares the date of the newest backup with today, as backups dated in the future are kept
+/// before any new backup.
+fn check_clock(newest: Option<NaiveDate>, today: NaiveDate) -> Check {
+    const NAME: &str = "Clock";
+
+    match newest {
+        None => Check::pass(NAME, format!("Today is {}, no backups yet.", today)),
+        // Local time and UTC can be a day apart, see `--timezone`.
+        Some(newest) if newest > today + TimeDelta::days(1) => Check::fail(
+            NAME,
+            format!(
+                "Newest backup of {} is dated after today, {}.",
+                newest, today
+            ),
+            "Check the date, time and time zone of the system.",
+        ),
+        Some(newest) if newest > today => Check::warn(
+            NAME,
+            format!("Newest backup of {} is dated tomorrow.", newest),
+            "Use the same `--timezone` for all backups into the target folder.",
+        ),
+        Some(newest) => Check::pass(
+            NAME,
+            format!("Today is {}, newest backup is of {}.", today, newest),
+        ),
+    }
+}
+
+fn check_file_names(target: &Path, name_template: &NameTemplate, backups: &[BackupFile]) -> Check {
+    const NAME: &str = "File names";
+
+    let unmatched = match unmatched_file_paths(target, name_template) {
+        Ok(unmatched) => unmatched,
+        Err(err) => {
+            return Check::fail(
+                NAME,
+                format!("{:#}", err),
+                "Check the permissions of the target folder.",
+            );
+        }
+    };
+    if let Some(path) = unmatched.first() {
+        return Check::warn(
+            NAME,
+            format!(
+                "{} files do not follow the name template {}, e.g. {}, and are never pruned.",
+                unmatched.len(),
+                name_template.as_str(),
+                path.display()
+            ),
+            "Pass the `--name-template` the backups were named by, or move other files out of the target folder.",
+        );
+    }
+
+    // Pruned separately, unless `--retention-scope global` is used.
+    let basenames: HashSet<String> = backups
+        .iter()
+        .filter_map(|file| basename_from_file_name(file.path.file_name()?, name_template))
+        .map(|basename| composed(&basename).into_owned())
+        .collect();
+    Check::pass(
+        NAME,
+        format!(
+            "{} backups of {} sources follow the name template {}.",
+            backups.len(),
+            basenames.len().max(1),
+            name_template.as_str()
+        ),
+    )
+}
+
+/// Compares the free space of the target drive with the size of the newest backup, which the next
+/// backup likely has as well.
+fn check_space(available: Option<u64>, newest_size: Option<u64>) -> Check {
+    const NAME: &str = "Free space";
+    const SUGGESTION: &str = "Free space on the drive or lower the retention periods.";
+
+    match (available, newest_size) {
+        (None, _) => Check::warn(
+            NAME,
+            "Failed to determine the free space of the drive.".to_owned(),
+            "Check the free space of the drive yourself.",
+        ),
+        (Some(available), Some(size)) if available < size => Check::fail(
+            NAME,
+            format!(
+                "{} free, less than the newest backup of {}.",
+                format_size(available),
+                format_size(size)
+            ),
+            SUGGESTION,
+        ),
+        (Some(available), Some(size)) if available < size.saturating_mul(2) => Check::warn(
+            NAME,
+            format!(
+                "{} free, room for one more backup of {}.",
+                format_size(available),
+                format_size(size)
+            ),
+            SUGGESTION,
+        ),
+        (Some(available), _) => Check::pass(NAME, format!("{} free.", format_size(available))),
+    }
+}
+
+/// Checks the target folder for common problems and prints PASS, WARN or FAIL for each check.
+///
+/// Fails if any check fails.
+pub fn doctor(target: impl AsRef<Path>, name_template: &NameTemplate) -> Result<()> {
+    let target = target.as_ref();
+    let backups = metadata_from_directory(target, name_template).unwrap_or_default();
+    let newest = backups.iter().max();
+
+    let checks = [
+        check_writable(target),
+        check_recycle_bin(target),
+        check_database(target),
+        check_quarantine(target),
+        check_clock(
+            newest.and_then(|file| {
+                NaiveDate::from_ymd_opt(
+                    file.metadata.year as i32,
+                    file.metadata.month,
+                    file.metadata.day,
+                )
+            }),
+            Local::now().date_naive(),
+        ),
+        check_file_names(target, name_template, &backups),
+        check_space(
+            available_space(target),
+            newest.and_then(|file| std::fs::metadata(&file.path).ok().map(|m| m.len())),
+        ),
+    ];
+
+    for check in &checks {
+        println!("{}  {:<17}  {}", check.status, check.name, check.message);
+        if let Some(suggestion) = check.suggestion {
+            println!("      {:<17}  {}", "", suggestion);
+        }
+    }
+
+    let failed_count = checks
+        .iter()
+        .filter(|check| check.status == Status::Fail)
+        .count();
+    if failed_count > 0 {
+        return Err(eyre!("{} of {} checks failed.", failed_count, checks.len()))
+            .suggestion("Follow the suggestions printed with the failed checks.");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_clock() {
+        let today = NaiveDate::from_ymd_opt(2025, 3, 10).unwrap();
+        let status = |newest| check_clock(newest, today).status;
+
+        assert_eq!(status(None), Status::Pass);
+        assert_eq!(status(NaiveDate::from_ymd_opt(2025, 3, 10)), Status::Pass);
+        assert_eq!(status(NaiveDate::from_ymd_opt(2025, 3, 11)), Status::Warn);
+        assert_eq!(status(NaiveDate::from_ymd_opt(2026, 1, 1)), Status::Fail);
+    }
+
+    #[test]
+    fn test_check_space() {
+        assert_eq!(check_space(None, Some(10)).status, Status::Warn);
+        assert_eq!(check_space(Some(5), Some(10)).status, Status::Fail);
+        assert_eq!(check_space(Some(15), Some(10)).status, Status::Warn);
+        assert_eq!(check_space(Some(25), Some(10)).status, Status::Pass);
+        assert_eq!(check_space(Some(25), None).status, Status::Pass);
+    }
+}
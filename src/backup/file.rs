@@ -4,23 +4,560 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::ffi::{OsStr, OsString};
+use std::{
+    collections::HashSet,
+    ffi::{OsStr, OsString},
+    fs::{File, FileTimes},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
-use color_eyre::eyre::{Ok, Result};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use clap::ValueEnum;
+use color_eyre::{
+    Section,
+    eyre::{Context, ContextCompat, Result, bail, ensure, eyre},
+};
+use log::warn;
 
-pub fn target_file_name(
-    uuid: impl AsRef<str>,
+use crate::backup::{
+    copy::PARTIAL_DIR_NAME,
+    normalize::composed,
+    parsing::{metadata_from_directory, metadata_from_file_name},
+    template::{NameTemplate, hostname},
+};
+
+/// Point in time a backup is dated by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TimestampSource {
+    /// Modification time of the source file
+    Mtime,
+    /// Time the backup is created
+    Now,
+}
+
+/// Timezone the date of a backup is expressed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Timezone {
+    Local,
+    Utc,
+}
+
+/// How backups are arranged inside the target folder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Layout {
+    /// All backups are placed directly in the target folder
+    Flat,
+    /// Backups are placed in `<year>/<month>/` subfolders of the target folder
+    Nested,
+}
+
+/// Metadata of the source file carried over to the backup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Preserve {
+    /// Modification time, and creation time on Windows
+    Mtime,
+    /// Permissions, like the read-only flag
+    Permissions,
+    /// Extended attributes (Unix only)
+    Xattrs,
+}
+
+/// Folder a backup of the given date (`YYYY-MM-DD`) is placed in.
+pub fn layout_dir(target_dir: impl AsRef<Path>, layout: Layout, date: &str) -> PathBuf {
+    match layout {
+        Layout::Flat => target_dir.as_ref().to_path_buf(),
+        Layout::Nested => {
+            let mut parts = date.split('-');
+            let year = parts.next().unwrap_or_default();
+            let month = parts.next().unwrap_or_default();
+            target_dir.as_ref().join(year).join(month)
+        }
+    }
+}
+
+/// Removes empty `<year>/<month>/` subfolders left behind after cleanup.
+pub fn remove_empty_layout_dirs(target_dir: impl AsRef<Path>) -> Result<()> {
+    for year_entry in std::fs::read_dir(target_dir.as_ref())? {
+        let year_path = year_entry?.path();
+        if !year_path.is_dir() || !is_layout_dir_name(&year_path, 4) {
+            continue;
+        }
+
+        for month_entry in std::fs::read_dir(&year_path)? {
+            let month_path = month_entry?.path();
+            if month_path.is_dir()
+                && is_layout_dir_name(&month_path, 2)
+                && std::fs::read_dir(&month_path)?.next().is_none()
+            {
+                std::fs::remove_dir(&month_path)?;
+            }
+        }
+
+        if std::fs::read_dir(&year_path)?.next().is_none() {
+            std::fs::remove_dir(&year_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks if the folder name consists of exactly `digits` digits, like the year and month folders.
+pub fn is_layout_dir_name(path: impl AsRef<Path>, digits: usize) -> bool {
+    path.as_ref().file_name().is_some_and(|name| {
+        let name = name.to_string_lossy();
+        name.len() == digits && name.chars().all(|c| c.is_ascii_digit())
+    })
+}
+
+/// Size of all files in the folder and its subfolders in bytes, without following symlinks.
+///
+/// Hardlinked files, like identical backups, are counted once on Unix.
+pub fn folder_size(dir: impl AsRef<Path>) -> Result<u64> {
+    fn add_sizes(dir: &Path, seen: &mut HashSet<(u64, u64)>) -> Result<u64> {
+        let mut size = 0;
+        for entry in std::fs::read_dir(dir)
+            .wrap_err_with(|| format!("Failed to read folder {}", dir.display()))?
+        {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                size += add_sizes(&entry.path(), seen)?;
+                continue;
+            }
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                if metadata.nlink() > 1 && !seen.insert((metadata.dev(), metadata.ino())) {
+                    continue;
+                }
+            }
+            size += metadata.len();
+        }
+
+        Ok(size)
+    }
+
+    add_sizes(dir.as_ref(), &mut HashSet::new())
+}
+
+/// Path relative to the target folder, separated by `/` regardless of the platform.
+pub fn relative_path_string(target_dir: &Path, path: &Path) -> Result<String> {
+    let relative = path.strip_prefix(target_dir).wrap_err_with(|| {
+        format!(
+            "{} is not located in the target folder {}.",
+            path.display(),
+            target_dir.display()
+        )
+    })?;
+
+    let parts = relative
+        .components()
+        .map(|component| {
+            component
+                .as_os_str()
+                .to_str()
+                .wrap_err_with(|| format!("Path {} is not utf-8.", path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(parts.join("/"))
+}
+
+/// Refuses source and target combinations where backups could overwrite or trash the source.
+pub fn validate_source_and_target(
+    source: impl AsRef<Path>,
+    target_dir: impl AsRef<Path>,
+) -> Result<()> {
+    let source =
+        std::fs::canonicalize(source.as_ref()).wrap_err("Failed to resolve source path.")?;
+    let target_dir =
+        std::fs::canonicalize(target_dir.as_ref()).wrap_err("Failed to resolve target path.")?;
+
+    ensure!(
+        source != target_dir,
+        "Source and target folder resolve to the same path {}.",
+        source.display()
+    );
+
+    if source.parent() == Some(target_dir.as_path()) {
+        return Err(eyre!(
+            "Target folder {} is the folder the source file is located in.",
+            target_dir.display()
+        ))
+        .suggestion("Use a dedicated folder for backups.");
+    }
+
+    if target_dir.starts_with(&source) {
+        return Err(eyre!(
+            "Target folder {} is located inside the source {}.",
+            target_dir.display(),
+            source.display()
+        ))
+        .suggestion("Use a target folder outside of the source.");
+    }
+
+    if source.starts_with(&target_dir) {
+        return Err(eyre!(
+            "Source {} is located inside the target folder {}.",
+            source.display(),
+            target_dir.display()
+        ))
+        .suggestion(
+            "The source might be a symlink to a backup. Back up the original file instead.",
+        );
+    }
+
+    Ok(())
+}
+
+/// Copies the requested metadata of the source file to the backup.
+pub fn preserve_metadata(
+    source: &Path,
+    target_file_path: &Path,
+    preserve: &[Preserve],
+) -> Result<()> {
+    let metadata = std::fs::metadata(source).wrap_err("Failed reading metadata of source file.")?;
+
+    if preserve.contains(&Preserve::Xattrs) {
+        if xattr::SUPPORTED_PLATFORM {
+            for name in xattr::list(source).wrap_err("Failed reading extended attributes.")? {
+                if let Some(value) =
+                    xattr::get(source, &name).wrap_err("Failed reading extended attributes.")?
+                {
+                    xattr::set(target_file_path, &name, &value)
+                        .wrap_err("Failed writing extended attributes.")?;
+                }
+            }
+        } else {
+            warn!("Extended attributes are not supported on this platform.");
+        }
+    }
+
+    if preserve.contains(&Preserve::Mtime) {
+        let times = FileTimes::new().set_modified(
+            metadata
+                .modified()
+                .wrap_err("Failed reading modification date of source file.")?,
+        );
+        #[cfg(windows)]
+        let times = {
+            use std::os::windows::fs::FileTimesExt;
+            match metadata.created() {
+                Ok(created) => times.set_created(created),
+                Err(_) => times,
+            }
+        };
+
+        // Opened read-only, as hardlinked backups might be protected already.
+        let mut open_options = File::options();
+        open_options.read(true);
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::OpenOptionsExt;
+            // FILE_WRITE_ATTRIBUTES, granted for read-only files as well.
+            open_options.access_mode(0x100);
+        }
+
+        open_options
+            .open(target_file_path)
+            .and_then(|file| file.set_times(times))
+            .wrap_err("Failed setting modification date of backup.")?;
+    }
+
+    // Last, as the permissions might make the backup read-only.
+    if preserve.contains(&Preserve::Permissions) {
+        std::fs::set_permissions(target_file_path, metadata.permissions())
+            .wrap_err("Failed setting permissions of backup.")?;
+    }
+
+    Ok(())
+}
+
+/// Flushes the file or folder to disk, so that it survives a power loss.
+///
+/// Folders are only flushed on Unix, as Windows journals their metadata.
+pub fn sync_path(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    if cfg!(windows) && path.is_dir() {
+        return Ok(());
+    }
+
+    // Flushing requires write access on Windows.
+    let file = if cfg!(windows) {
+        File::options().write(true).open(path)
+    } else {
+        File::open(path)
+    };
+    file.and_then(|file| file.sync_all())
+        .wrap_err_with(|| format!("Failed to flush {} to disk", path.display()))
+}
+
+/// Date of the backup of the file, with `now` as the time the backup is created.
+pub fn date_string_from_path(
+    path: impl AsRef<Path>,
+    timestamp: TimestampSource,
+    timezone: Timezone,
+    now: SystemTime,
+) -> Result<String> {
+    let time = match timestamp {
+        TimestampSource::Mtime => std::fs::metadata(path.as_ref())
+            .wrap_err("Failed reading metadata of source file.")?
+            .modified()
+            .wrap_err("Failed reading modification date of source file.")?,
+        TimestampSource::Now => now,
+    };
+
+    Ok(date_string_from_time(time, timezone))
+}
+
+pub fn date_string_from_time(time: SystemTime, timezone: Timezone) -> String {
+    let date = match timezone {
+        Timezone::Local => DateTime::<Local>::from(time).format("%Y-%m-%d"),
+        Timezone::Utc => DateTime::<Utc>::from(time).format("%Y-%m-%d"),
+    };
+
+    date.to_string()
+}
+
+/// Point in time of the date and time in the timezone.
+pub fn system_time_from_naive(time: NaiveDateTime, timezone: Timezone) -> Result<SystemTime> {
+    let time = match timezone {
+        Timezone::Local => Local
+            .from_local_datetime(&time)
+            .earliest()
+            .wrap_err_with(|| format!("{} does not exist in the local timezone.", time))?
+            .into(),
+        Timezone::Utc => Utc.from_utc_datetime(&time).into(),
+    };
+    Ok(time)
+}
+
+/// Next free counter of the date, after the highest counter of any backup of the date in the
+/// target folder, including its year and month folders.
+pub fn next_counter(
+    target_dir: impl AsRef<Path>,
+    template: &NameTemplate,
+    date: impl AsRef<str>,
+) -> Result<u32> {
+    Ok(metadata_from_directory(target_dir, template)
+        .wrap_err("Failed to read target directory.")?
+        .into_iter()
+        .filter(|file| file.metadata.date_string() == date.as_ref())
+        .map(|file| file.metadata.counter)
+        .max()
+        .map_or(0, |counter| counter + 1))
+}
+
+/// Backup file name claimed by an empty placeholder file, until the backup written to the staging
+/// path replaces it.
+///
+/// Dropping an uncommitted claim removes the placeholder and the staging file.
+#[derive(Debug)]
+pub struct ClaimedTarget {
+    pub file_name: OsString,
+    pub path: PathBuf,
+    /// Path in the partial folder of the target folder the backup is written to.
+    pub staging_path: PathBuf,
+    committed: bool,
+}
+
+impl ClaimedTarget {
+    /// Moves the backup from the staging path over the placeholder.
+    pub fn commit(mut self) -> Result<PathBuf> {
+        std::fs::rename(&self.staging_path, &self.path)
+            .wrap_err_with(|| format!("Failed to move backup to {}", self.path.display()))?;
+        self.committed = true;
+        if let Some(partial_dir) = self.staging_path.parent() {
+            // Fails if partial copies of other sources remain.
+            let _ = std::fs::remove_dir(partial_dir);
+        }
+
+        Ok(self.path.clone())
+    }
+}
+
+impl Drop for ClaimedTarget {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        let _ = std::fs::remove_file(&self.staging_path);
+        if std::fs::metadata(&self.path).is_ok_and(|metadata| metadata.len() == 0)
+            && let Err(err) = std::fs::remove_file(&self.path)
+        {
+            warn!(
+                "Failed to remove placeholder {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Name in lowercase and composed unicode form, equal for names denoting the same file on
+/// case-insensitive volumes like those of Windows and macOS.
+fn case_folded(name: &OsStr) -> String {
+    composed(&name.to_string_lossy()).to_lowercase()
+}
+
+/// Names of the files in the folder.
+fn file_names(dir: &Path) -> Result<HashSet<OsString>> {
+    match std::fs::read_dir(dir) {
+        Ok(entries) => Ok(entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name())
+            .collect()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(err) => Err(err).wrap_err_with(|| format!("Failed to read {}", dir.display())),
+    }
+}
+
+/// Claims the backup file name with the next free counter of the date by exclusively creating an
+/// empty placeholder, so that no earlier backup is ever overwritten.
+///
+/// Counters taken in the meantime, e.g. by another machine backing up into the same network
+/// share, are skipped, as are names differing from an existing file only in case, which would
+/// collide on case-insensitive volumes or when copying the target folder to one.
+pub fn claim_target_file(
+    target_dir: impl AsRef<Path>,
+    backup_dir: impl AsRef<Path>,
+    template: &NameTemplate,
+    date: impl AsRef<str>,
     base_name: impl AsRef<OsStr>,
     extension: Option<impl AsRef<OsStr>>,
-) -> Result<OsString> {
-    let mut file_name = OsString::new();
-    file_name.push(uuid.as_ref());
-    file_name.push(base_name.as_ref());
+) -> Result<ClaimedTarget> {
+    let target_dir = target_dir.as_ref();
+    let existing_names = file_names(backup_dir.as_ref())?;
+    let folded_names: HashSet<String> = existing_names
+        .iter()
+        .map(|name| case_folded(name))
+        .collect();
+    for counter in next_counter(target_dir, template, &date)?..=99 {
+        let file_name = template.render(
+            date.as_ref(),
+            counter,
+            &hostname(),
+            base_name.as_ref(),
+            extension.as_ref().map(|ext| ext.as_ref()),
+        );
+        let path = backup_dir.as_ref().join(&file_name);
+        if !existing_names.contains(&file_name) && folded_names.contains(&case_folded(&file_name)) {
+            warn!(
+                "{} differs from an existing file only in case. Trying the next counter.",
+                path.display()
+            );
+            continue;
+        }
+        match File::create_new(&path) {
+            Ok(_) => {
+                let partial_dir = target_dir.join(PARTIAL_DIR_NAME);
+                std::fs::create_dir_all(&partial_dir)
+                    .wrap_err("Failed to create partial folder.")?;
+                return Ok(ClaimedTarget {
+                    staging_path: partial_dir.join(&file_name),
+                    file_name,
+                    path,
+                    committed: false,
+                });
+            }
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                warn!(
+                    "Counter {} was taken meanwhile. Trying the next one.",
+                    counter
+                );
+            }
+            Err(err) => {
+                return Err(err)
+                    .wrap_err_with(|| format!("Failed to create backup {}", path.display()));
+            }
+        }
+    }
+
+    bail!("Exhausted all backup counters for date {}.", date.as_ref());
+}
 
-    if let Some(ext) = extension.as_ref() {
-        file_name.push(".");
-        file_name.push(ext.as_ref());
+/// Backups other than the one at `path` of the same date and counter, created concurrently without
+/// the lock of the target folder.
+pub fn counter_collisions(
+    target_dir: impl AsRef<Path>,
+    template: &NameTemplate,
+    path: &Path,
+) -> Result<Vec<PathBuf>> {
+    let Some(claimed) = path
+        .file_name()
+        .and_then(|file_name| metadata_from_file_name(file_name, template))
+    else {
+        return Ok(vec![]);
+    };
+
+    Ok(metadata_from_directory(target_dir, template)?
+        .into_iter()
+        .filter(|file| file.metadata == claimed && file.path != path)
+        .map(|file| file.path)
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_claim_target_file() {
+        let dir = std::env::temp_dir().join(format!("sfb-claim-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("2025").join("01")).unwrap();
+        std::fs::write(
+            dir.join("2025").join("01").join("2025-01-01_03_db.sql"),
+            b"",
+        )
+        .unwrap();
+        let template = NameTemplate::default();
+        let claim = |backup_dir: &Path| {
+            claim_target_file(&dir, backup_dir, &template, "2025-01-01", "db", Some("sql")).unwrap()
+        };
+
+        let first = claim(&dir);
+        assert_eq!(first.file_name, "2025-01-01_04_db.sql");
+        let second = claim(&dir);
+        assert_eq!(second.file_name, "2025-01-01_05_db.sql");
+
+        std::fs::write(&second.staging_path, b"backup").unwrap();
+        let second_path = second.commit().unwrap();
+        assert_eq!(std::fs::read(&second_path).unwrap(), b"backup");
+        drop(first);
+        assert!(!dir.join("2025-01-01_04_db.sql").exists());
+        assert!(
+            counter_collisions(&dir, &template, &second_path)
+                .unwrap()
+                .is_empty()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
-    Ok(file_name)
+    #[test]
+    fn test_claim_target_file_case_collision() {
+        let dir = std::env::temp_dir().join(format!("sfb-case-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Named by a template differing in case, and therefore not counted as a backup.
+        std::fs::write(dir.join("Backup_2025-01-01_00_db.sql"), b"old").unwrap();
+        let template = NameTemplate::parse("backup_{date}_{counter}_{basename}.{ext}").unwrap();
+
+        let claim =
+            claim_target_file(&dir, &dir, &template, "2025-01-01", "db", Some("sql")).unwrap();
+
+        assert_eq!(claim.file_name, "backup_2025-01-01_01_db.sql");
+        assert_eq!(
+            std::fs::read(dir.join("Backup_2025-01-01_00_db.sql")).unwrap(),
+            b"old"
+        );
+        assert_eq!(
+            case_folded(OsStr::new("R\u{c9}SUME\u{301}.txt")),
+            case_folded(OsStr::new("re\u{301}sum\u{e9}.TXT"))
+        );
+
+        drop(claim);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
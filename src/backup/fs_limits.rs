@@ -0,0 +1,240 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{ffi::OsStr, fmt::Display, path::Path};
+
+use color_eyre::{
+    Section,
+    eyre::{Result, eyre},
+};
+use log::warn;
+
+/// File systems with limits on file sizes and names, as found on USB drives and SD cards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileSystem {
+    Fat32,
+    ExFat,
+}
+
+impl Display for FileSystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fat32 => write!(f, "FAT32"),
+            Self::ExFat => write!(f, "exFAT"),
+        }
+    }
+}
+
+impl FileSystem {
+    /// Largest file the file system can store in bytes.
+    pub fn max_file_size(self) -> Option<u64> {
+        match self {
+            Self::Fat32 => Some(u64::from(u32::MAX)),
+            Self::ExFat => None,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn detect(path: &Path) -> Option<FileSystem> {
+    use std::os::unix::ffi::OsStrExt;
+
+    const EXFAT_SUPER_MAGIC: i64 = 0x2011_BAB0;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    match stat.f_type as i64 {
+        magic if magic == libc::MSDOS_SUPER_MAGIC as i64 => Some(FileSystem::Fat32),
+        EXFAT_SUPER_MAGIC => Some(FileSystem::ExFat),
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn detect(path: &Path) -> Option<FileSystem> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    let name = unsafe { std::ffi::CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+    match name.to_bytes() {
+        b"msdos" => Some(FileSystem::Fat32),
+        b"exfat" => Some(FileSystem::ExFat),
+        _ => None,
+    }
+}
+
+#[cfg(windows)]
+fn detect(path: &Path) -> Option<FileSystem> {
+    let path = std::fs::canonicalize(path).ok()?;
+    let script = format!(
+        "([System.IO.DriveInfo]'{}').DriveFormat",
+        path.display().to_string().replace('\'', "''")
+    );
+    match crate::backup::vss::powershell(&script).ok()?.trim() {
+        "FAT32" | "FAT" => Some(FileSystem::Fat32),
+        "exFAT" => Some(FileSystem::ExFat),
+        _ => None,
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn detect(_path: &Path) -> Option<FileSystem> {
+    None
+}
+
+/// Detects if the folder is on a FAT32 or exFAT file system.
+pub fn detect_file_system(path: impl AsRef<Path>) -> Option<FileSystem> {
+    detect(path.as_ref())
+}
+
+#[cfg(unix)]
+fn available(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(windows)]
+fn available(path: &Path) -> Option<u64> {
+    let path = std::fs::canonicalize(path).ok()?;
+    let script = format!(
+        "([System.IO.DriveInfo]'{}').AvailableFreeSpace",
+        path.display().to_string().replace('\'', "''")
+    );
+    crate::backup::vss::powershell(&script)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(not(any(unix, windows)))]
+fn available(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Free space of the file system of the folder in bytes, as available to the current user.
+pub fn available_space(path: impl AsRef<Path>) -> Option<u64> {
+    available(path.as_ref())
+}
+
+/// Characters FAT32 and exFAT cannot represent in file names.
+fn invalid_name_char(char: char) -> bool {
+    char.is_control() || matches!(char, '"' | '*' | '/' | ':' | '<' | '>' | '?' | '\\' | '|')
+}
+
+/// Describes why the file system cannot represent the file name, if it cannot.
+fn name_problem(file_name: &OsStr) -> Option<String> {
+    let name = file_name.to_string_lossy();
+    if let Some(char) = name.chars().find(|char| invalid_name_char(*char)) {
+        return Some(format!("contains the character {:?}", char));
+    }
+    if name.ends_with([' ', '.']) {
+        return Some("ends with a space or dot".to_owned());
+    }
+    if name.encode_utf16().count() > 255 {
+        return Some("is longer than 255 characters".to_owned());
+    }
+
+    None
+}
+
+/// How the backup is written, as the size on disk only equals the source size for copies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Storage {
+    Copy,
+    /// Compressed or incremental backups, likely smaller than the source.
+    Smaller,
+    /// Small chunks, which never exceed file size limits.
+    Chunks,
+}
+
+/// Checks up front that the target file system can store the backup, instead of failing mid-copy.
+///
+/// The source size is unknown for streamed sources.
+pub fn check_target_limits(
+    target_dir: impl AsRef<Path>,
+    file_name: &OsStr,
+    source_size: Option<u64>,
+    storage: Storage,
+) -> Result<()> {
+    let Some(file_system) = detect_file_system(target_dir.as_ref()) else {
+        return Ok(());
+    };
+
+    if let Some(problem) = name_problem(file_name) {
+        return Err(eyre!(
+            "The {} file system of the target folder cannot store the backup {}, as its name {}.",
+            file_system,
+            file_name.display(),
+            problem
+        ))
+        .suggestion("Change `--name-template` or rename the source file.");
+    }
+
+    let Some(max_size) = file_system.max_file_size() else {
+        return Ok(());
+    };
+    match (source_size, storage) {
+        (_, Storage::Chunks) => {}
+        (None, _) => warn!(
+            "Target folder is on {}, where backups larger than 4 GiB fail.",
+            file_system
+        ),
+        (Some(size), Storage::Copy) if size > max_size => {
+            return Err(eyre!(
+                "The source file of {} bytes exceeds the maximum file size of the {} file system of the target folder.",
+                size,
+                file_system
+            ))
+            .suggestion("Use `--dedup-store`, which stores the source in small chunks.")
+            .suggestion("Use a target folder formatted with exFAT or NTFS.");
+        }
+        (Some(size), Storage::Smaller) if size > max_size => warn!(
+            "Source file exceeds the maximum file size of {}. The backup fails if it is not smaller than 4 GiB.",
+            file_system
+        ),
+        _ => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_name_problem() {
+        assert_eq!(name_problem(OsStr::new("2025-01-01_00_db.sql")), None);
+        assert_eq!(
+            name_problem(OsStr::new("2025-01-01_00_host:db.sql")),
+            Some("contains the character ':'".to_owned())
+        );
+        assert_eq!(
+            name_problem(OsStr::new("2025-01-01_00_db.")),
+            Some("ends with a space or dot".to_owned())
+        );
+        assert!(name_problem(OsStr::new(&"a".repeat(256))).is_some());
+    }
+}
@@ -4,12 +4,12 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::{ffi::OsStr, fs::File, io};
+use std::io::{self, Read};
 
 use color_eyre::eyre::{Context, Result};
 use sha2::{Digest, Sha256};
 
-pub fn hash_file(file: &mut File) -> Result<String> {
+pub fn hash_file(file: &mut impl Read) -> Result<String> {
     let mut hasher = Sha256::new();
 
     io::copy(file, &mut hasher).wrap_err("Failed to hash file.")?;
@@ -19,10 +19,6 @@ pub fn hash_file(file: &mut File) -> Result<String> {
     Ok(hex::encode_upper(hash))
 }
 
-pub fn generate_sha256_file_content<S, S2>(hash: S, file_name: S2) -> String
-where
-    S: AsRef<str>,
-    S2: AsRef<OsStr>,
-{
-    format!("{} *{}\n", hash.as_ref(), file_name.as_ref().display())
+pub fn hash_bytes(bytes: &[u8]) -> String {
+    hex::encode_upper(Sha256::digest(bytes))
 }
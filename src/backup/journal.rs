@@ -0,0 +1,318 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Journal of the operations on the target folder.
+//!
+//! Backups and prunes record their intent in the tracking database before touching any file and
+//! remove it once done. Entries left over were interrupted, e.g. by a crash or power loss, and are
+//! reconciled with the target folder by the next backup.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Context, Result};
+use diesel::SqliteConnection;
+use log::{info, warn};
+
+use crate::{
+    backup::{
+        BackupOptions,
+        audit::{Operation, audit_entry, record_audit},
+        chain::{ChainEvent, ChainOperation, append_chain},
+        cleanup::Tiers,
+        copy::PARTIAL_DIR_NAME,
+        db::{
+            backup_file_with_relative_path, insert_backup_file, is_audited_since, journal_entries,
+            remove_journal_entries, set_tiers,
+        },
+        protect::protect,
+        quarantine::quarantine,
+        restore::hash_backup_content,
+        sidecar::{SidecarFormat, sidecar_path, write_sidecar},
+        template::hostname,
+        undo::record_prune,
+    },
+    model::{BackupFile, JournalEntry, PathBufSql, UuidSQL},
+};
+
+/// Operation recorded in the journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Intent {
+    /// Writing a new backup, from claiming its name until it is tracked in the database.
+    Backup,
+    /// Moving a pruned backup or its companion file into the recycle bin.
+    Trash,
+}
+
+impl Intent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Backup => "backup",
+            Self::Trash => "trash",
+        }
+    }
+}
+
+/// Journal entry of the operation on the file at the path relative to the target folder, started
+/// now.
+pub fn journal_entry(intent: Intent, relative_path: impl Into<PathBuf>) -> JournalEntry {
+    JournalEntry {
+        uuid: UuidSQL::new(),
+        started_at: Utc::now().timestamp_millis(),
+        operation: intent.as_str().to_owned(),
+        path: PathBufSql {
+            path: relative_path.into(),
+        },
+        hash: None,
+    }
+}
+
+/// Completes or rolls back a backup interrupted before it was tracked in the database.
+///
+/// Backups not moved to their name yet are removed. Moved backups matching the hash they were
+/// verified with get their hash file and are tracked, others are quarantined.
+///
+/// Backups are moved to their name only after their hash was recorded in the journal, replacing
+/// the empty placeholder claiming the name, so this tells them apart even from empty backups.
+fn recover_backup(
+    conn: &mut SqliteConnection,
+    target: &Path,
+    entry: &JournalEntry,
+    options: &BackupOptions,
+) -> Result<()> {
+    let backup_path = target.join(&*entry.path);
+    if backup_file_with_relative_path(conn, &*entry.path)?.is_some() {
+        return Ok(());
+    }
+
+    let staging_path = target
+        .join(PARTIAL_DIR_NAME)
+        .join(backup_path.file_name().unwrap_or_default());
+    let committed = entry.hash.is_some() && !staging_path.exists() && backup_path.is_file();
+    if !committed {
+        // Failed backups remove these themselves, interrupted ones leave them behind.
+        let leftovers: Vec<&Path> = [staging_path.as_path(), backup_path.as_path()]
+            .into_iter()
+            .filter(|path| path.is_file())
+            .collect();
+        for path in &leftovers {
+            std::fs::remove_file(path)
+                .wrap_err_with(|| format!("Failed to remove {}", path.display()))?;
+        }
+        if !leftovers.is_empty() {
+            info!(
+                "Removed backup {} interrupted while it was written.",
+                backup_path.display()
+            );
+        }
+        return Ok(());
+    }
+
+    // Encrypted zip archives cannot be hashed without their password, and are quarantined as well.
+    let hash = hash_backup_content(target, &backup_path).ok();
+    let Some(hash) = hash.filter(|hash| entry.hash.as_ref() == Some(hash)) else {
+        let quarantine_path = quarantine(conn, target, &backup_path)?;
+        warn!(
+            "Backup {} was interrupted and does not match the source it was verified against. Moved it to {}.",
+            backup_path.display(),
+            quarantine_path.display()
+        );
+        return Ok(());
+    };
+
+    if options.sidecar_format != SidecarFormat::None && !sidecar_path(&backup_path).exists() {
+        write_sidecar(&backup_path, &hash, options.sidecar_format)?;
+    }
+    if options.protect {
+        protect(&backup_path, options.immutable)?;
+    }
+    insert_backup_file(
+        conn,
+        &BackupFile {
+            uuid: UuidSQL::new(),
+            relative_path: entry.path.clone(),
+            keep_yearly: false,
+            keep_monthly: false,
+            keep_daily: false,
+            keep_latest: false,
+            hash: Some(hash.clone()),
+            last_verified: Some(Utc::now().timestamp()),
+            tags: None,
+            comment: None,
+            cold_target: None,
+            source_path: None,
+            hostname: Some(hostname().to_string_lossy().into_owned()),
+            tool_version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+            created_at: Some(entry.started_at),
+            quarantined_at: None,
+            keep_weekly: false,
+            keep_quarterly: false,
+            promoted_tier: None,
+        },
+    )?;
+    append_chain(
+        target,
+        &[ChainEvent::new(ChainOperation::Add, &*entry.path, hash)],
+        false,
+    )?;
+    info!(
+        "Completed backup {} interrupted after it was copied.",
+        backup_path.display()
+    );
+
+    Ok(())
+}
+
+/// Records the removal of a file by a prune interrupted before it was recorded.
+///
+/// Files still present were not moved, and are left to the next prune.
+fn recover_trash(
+    conn: &mut SqliteConnection,
+    target: &Path,
+    entry: &JournalEntry,
+    options: &BackupOptions,
+) -> Result<()> {
+    let path = target.join(&*entry.path);
+    let absolute_path = std::path::absolute(&path).unwrap_or_else(|_| path.clone());
+    if path.exists() || is_audited_since(conn, &absolute_path, entry.started_at)? {
+        return Ok(());
+    }
+
+    let hash = backup_file_with_relative_path(conn, &*entry.path)?.and_then(|row| row.hash);
+    let started_at = DateTime::<Utc>::from_timestamp_millis(entry.started_at).unwrap_or_default();
+    record_audit(
+        conn,
+        target,
+        &[audit_entry(
+            Operation::Trash,
+            format!(
+                "Pruned by a prune interrupted at {}",
+                started_at.format("%Y-%m-%d %H:%M:%S UTC")
+            ),
+            &path,
+            hash,
+        )],
+        options.audit_log,
+    )?;
+    record_prune(
+        conn,
+        &[std::fs::canonicalize(target)
+            .wrap_err("Failed to resolve path of target folder.")?
+            .join(&*entry.path)],
+    )?;
+    set_tiers(conn, &*entry.path, Tiers::default())?;
+    info!(
+        "Recorded {} as pruned by an interrupted prune.",
+        path.display()
+    );
+
+    Ok(())
+}
+
+/// Reconciles the operations of the journal left over by interrupted runs with the target folder,
+/// removing them from the journal once done.
+///
+/// Returns the number of operations reconciled.
+pub fn recover(
+    conn: &mut SqliteConnection,
+    target: &Path,
+    options: &BackupOptions,
+) -> Result<usize> {
+    let entries = journal_entries(conn)?;
+    if entries.is_empty() {
+        return Ok(0);
+    }
+
+    warn!(
+        "Found {} operations on the target folder that did not finish. Reconciling them.",
+        entries.len()
+    );
+    for entry in &entries {
+        match entry.operation.as_str() {
+            "backup" => recover_backup(conn, target, entry, options)?,
+            "trash" => recover_trash(conn, target, entry, options)?,
+            operation => warn!(
+                "Unknown operation {} in journal, recorded by a newer version. Ignoring it.",
+                operation
+            ),
+        }
+        remove_journal_entries(conn, std::slice::from_ref(entry))?;
+    }
+
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backup::{
+        db::{audit_entries, insert_journal_entries, open_db, set_journal_hash},
+        test_util::TempDir,
+    };
+
+    #[test]
+    fn test_recover() {
+        let dir = TempDir::new("journal");
+        std::fs::create_dir_all(dir.join(PARTIAL_DIR_NAME)).unwrap();
+        let mut conn = open_db(&dir).unwrap();
+
+        // Copied and verified, but interrupted before writing the hash file.
+        std::fs::write(dir.join("2025-01-01_00_notes.txt"), b"notes").unwrap();
+        let copied = journal_entry(Intent::Backup, "2025-01-01_00_notes.txt");
+        // Interrupted while copying into the partial folder.
+        std::fs::write(dir.join("2025-01-02_00_notes.txt"), b"").unwrap();
+        std::fs::write(
+            dir.join(PARTIAL_DIR_NAME).join("2025-01-02_00_notes.txt"),
+            b"no",
+        )
+        .unwrap();
+        let copying = journal_entry(Intent::Backup, "2025-01-02_00_notes.txt");
+        // Backup of an empty source, interrupted like the first one.
+        std::fs::write(dir.join("2025-01-03_00_empty.txt"), b"").unwrap();
+        let empty = journal_entry(Intent::Backup, "2025-01-03_00_empty.txt");
+        // Moved into the recycle bin, but interrupted before recording it.
+        let trashed = journal_entry(Intent::Trash, "2024-12-01_00_notes.txt");
+        // Not moved yet.
+        std::fs::write(dir.join("2024-12-02_00_notes.txt"), b"kept").unwrap();
+        let untouched = journal_entry(Intent::Trash, "2024-12-02_00_notes.txt");
+        insert_journal_entries(
+            &mut conn,
+            &[copied.clone(), copying, empty.clone(), trashed, untouched],
+        )
+        .unwrap();
+        let hash = "AB5AA97074C454A0632057E704220D9A6678FBF773A0A5806FC09B8173B07309";
+        set_journal_hash(&mut conn, &copied, hash).unwrap();
+        let empty_hash = "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855";
+        set_journal_hash(&mut conn, &empty, empty_hash).unwrap();
+
+        let options = BackupOptions::default();
+        assert_eq!(recover(&mut conn, &dir, &options).unwrap(), 5);
+        assert!(journal_entries(&mut conn).unwrap().is_empty());
+
+        let row = backup_file_with_relative_path(&mut conn, "2025-01-01_00_notes.txt")
+            .unwrap()
+            .unwrap();
+        assert_eq!(row.hash.as_deref(), Some(hash));
+        assert!(sidecar_path(dir.join("2025-01-01_00_notes.txt")).is_file());
+        let row = backup_file_with_relative_path(&mut conn, "2025-01-03_00_empty.txt")
+            .unwrap()
+            .unwrap();
+        assert_eq!(row.hash.as_deref(), Some(empty_hash));
+        assert!(dir.join("2025-01-03_00_empty.txt").is_file());
+        assert!(!dir.join("2025-01-02_00_notes.txt").exists());
+        assert!(
+            !dir.join(PARTIAL_DIR_NAME)
+                .join("2025-01-02_00_notes.txt")
+                .exists()
+        );
+        assert!(dir.join("2024-12-02_00_notes.txt").is_file());
+        let entries = audit_entries(&mut conn).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].path.ends_with("2024-12-01_00_notes.txt"));
+
+        assert_eq!(recover(&mut conn, &dir, &options).unwrap(), 0);
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{Context, Result};
+use log::{info, warn};
+
+use crate::backup::{
+    archive::is_zip, delta::is_delta, restore::write_backup_content, store::is_manifest,
+};
+
+const LATEST_PREFIX: &str = "latest_";
+
+/// Path of the stable `latest_<file name>` entry in the target folder.
+pub fn latest_path(target_dir: impl AsRef<Path>, file_name: impl AsRef<OsStr>) -> PathBuf {
+    let mut latest_name = LATEST_PREFIX.to_owned();
+    latest_name.push_str(&file_name.as_ref().to_string_lossy());
+    target_dir.as_ref().join(latest_name)
+}
+
+#[cfg(unix)]
+pub fn symlink(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+pub fn symlink(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(original, link)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn symlink(_original: &Path, _link: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Points `latest_<file name>` in the target folder at the backup.
+///
+/// Uses a relative symlink, or a copy where symlinks are not supported, e.g. on Windows without
+/// developer mode. Chunked, incremental and zip backups are reassembled into a copy.
+pub fn update_latest(
+    target_dir: impl AsRef<Path>,
+    backup_path: impl AsRef<Path>,
+    file_name: impl AsRef<OsStr>,
+) -> Result<PathBuf> {
+    let latest_path = latest_path(target_dir.as_ref(), file_name);
+    let mut tmp_path = latest_path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    if tmp_path.symlink_metadata().is_ok() {
+        std::fs::remove_file(&tmp_path).wrap_err("Failed to remove leftover temporary file.")?;
+    }
+
+    let reassemble = is_manifest(backup_path.as_ref())
+        || is_delta(backup_path.as_ref())
+        || is_zip(backup_path.as_ref());
+    let relative_backup_path = backup_path.as_ref().strip_prefix(target_dir.as_ref())?;
+    let linked = !reassemble
+        && match symlink(relative_backup_path, &tmp_path) {
+            Ok(()) => true,
+            Err(err) => {
+                warn!("Failed to create symlink, falling back to copying: {}", err);
+                false
+            }
+        };
+
+    if !linked {
+        let mut writer = BufWriter::new(
+            File::create(&tmp_path).wrap_err("Failed to create copy of newest backup.")?,
+        );
+        write_backup_content(target_dir.as_ref(), backup_path.as_ref(), &mut writer)?;
+        writer
+            .flush()
+            .wrap_err("Failed to write copy of newest backup.")?;
+    }
+
+    std::fs::rename(&tmp_path, &latest_path)
+        .wrap_err_with(|| format!("Failed to replace {}", latest_path.display()))?;
+    info!(
+        "{} points at {}",
+        latest_path.display(),
+        relative_backup_path.display()
+    );
+
+    Ok(latest_path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_update_latest() {
+        let dir = std::env::temp_dir().join(format!("sfb-latest-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("2025")).unwrap();
+        let first = dir.join("2025").join("2025-01-01_00_db.sql");
+        let second = dir.join("2025").join("2025-01-02_00_db.sql");
+        std::fs::write(&first, b"first").unwrap();
+        std::fs::write(&second, b"second").unwrap();
+
+        update_latest(&dir, &first, "db.sql").unwrap();
+        let latest = update_latest(&dir, &second, "db.sql").unwrap();
+        let content = std::fs::read(&latest).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(latest, dir.join("latest_db.sql"));
+        assert_eq!(content, b"second");
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use color_eyre::eyre::{Context, ContextCompat, Result};
+use log::warn;
+
+use crate::backup::{
+    cleanup::{BackupFile, Tier, Tiers},
+    cold::{relative_backup_path, scan_target},
+    db::{backup_file_with_relative_path, open_db, set_tags, tiers_of},
+    parsing::ScanFilter,
+    sidecar::{read_sidecar, sidecar_path},
+    template::NameTemplate,
+};
+
+/// Formats the size in bytes with a binary unit, e.g. `1.5 MiB`.
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", size, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Formats the duration with its two largest units, e.g. `2d 5h` or `45s`.
+pub fn format_duration(duration: Duration) -> String {
+    const UNITS: [(&str, u64); 4] = [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)];
+
+    let seconds = duration.as_secs();
+    let Some(index) = UNITS
+        .iter()
+        .position(|(_, unit_seconds)| seconds >= *unit_seconds)
+    else {
+        return "0s".to_owned();
+    };
+    let (unit, unit_seconds) = UNITS[index];
+    let mut formatted = format!("{}{}", seconds / unit_seconds, unit);
+    if let Some((next_unit, next_unit_seconds)) = UNITS.get(index + 1) {
+        let rest = seconds % unit_seconds / next_unit_seconds;
+        if rest > 0 {
+            formatted.push_str(&format!(" {}{}", rest, next_unit));
+        }
+    }
+
+    formatted
+}
+
+/// Backup with the details stored in the tracking database.
+#[derive(Debug, Clone)]
+pub struct BackupDetails {
+    pub file: BackupFile,
+    /// Size of the backup on disk in bytes.
+    pub size: u64,
+    pub tags: Vec<String>,
+    pub comment: Option<String>,
+    /// Unix timestamp of the last time the backup matched its hash.
+    pub last_verified: Option<i64>,
+    /// Hash recorded in the tracking database.
+    pub hash: Option<String>,
+    /// Retention tiers protecting the backup as of the last prune.
+    pub tiers: Tiers,
+}
+
+impl BackupDetails {
+    /// Date and counter of the backup, e.g. `2025-09-27_03`.
+    pub fn date(&self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}_{:02}",
+            self.file.metadata.year,
+            self.file.metadata.month,
+            self.file.metadata.day,
+            self.file.metadata.counter
+        )
+    }
+
+    /// Describes the backup with date, counter, size, tags and comment in one line.
+    pub fn describe(&self) -> String {
+        let line = format!(
+            "{}  {:>10}  {:<16}  {}",
+            self.date(),
+            format_size(self.size),
+            self.tags.join(","),
+            self.comment.as_deref().unwrap_or_default()
+        );
+        line.trim_end().to_owned()
+    }
+}
+
+/// Lists the backups of the target folder, oldest first.
+pub fn list_backups(
+    target: impl AsRef<Path>,
+    name_template: &NameTemplate,
+) -> Result<Vec<BackupDetails>> {
+    list_matching_backups(target, name_template, ScanFilter::default())
+}
+
+/// Lists the backups of the target folder matching the filter, oldest first.
+pub fn list_matching_backups(
+    target: impl AsRef<Path>,
+    name_template: &NameTemplate,
+    filter: ScanFilter,
+) -> Result<Vec<BackupDetails>> {
+    let target = target.as_ref();
+    let mut conn = open_db(target)?;
+    let mut backup_files = scan_target(&mut conn, target, name_template, filter)?;
+    backup_files.sort();
+
+    backup_files
+        .into_iter()
+        .map(|file| {
+            let size = std::fs::metadata(&file.path)
+                .wrap_err_with(|| format!("Failed reading metadata of {}", file.path.display()))?
+                .len();
+            let relative_path = relative_backup_path(&mut conn, target, &file.path)?;
+            let row = backup_file_with_relative_path(&mut conn, relative_path)?;
+
+            Ok(BackupDetails {
+                file,
+                size,
+                tags: row
+                    .as_ref()
+                    .and_then(|row| row.tags.as_deref())
+                    .map(|tags| tags.split(',').map(str::to_owned).collect())
+                    .unwrap_or_default(),
+                comment: row.as_ref().and_then(|row| row.comment.clone()),
+                last_verified: row.as_ref().and_then(|row| row.last_verified),
+                tiers: row.as_ref().map(tiers_of).unwrap_or_default(),
+                hash: row.and_then(|row| row.hash),
+            })
+        })
+        .collect()
+}
+
+/// Adds or removes the tag of a tracked backup.
+pub fn set_tag(
+    target: impl AsRef<Path>,
+    backup_path: impl AsRef<Path>,
+    tag: &str,
+    present: bool,
+) -> Result<()> {
+    let mut conn = open_db(target.as_ref())?;
+    let relative_path = relative_backup_path(&mut conn, target.as_ref(), backup_path.as_ref())?;
+    let row = backup_file_with_relative_path(&mut conn, &relative_path)?
+        .wrap_err("Backup is not tracked in the database.")?;
+
+    let mut tags: Vec<&str> = row
+        .tags
+        .as_deref()
+        .map(|tags| tags.split(',').filter(|other| *other != tag).collect())
+        .unwrap_or_default();
+    if present {
+        tags.push(tag);
+    }
+
+    set_tags(
+        &mut conn,
+        &relative_path,
+        (!tags.is_empty()).then(|| tags.join(",")),
+    )
+}
+
+/// Prints the backups of the target folder matching the filter with date, counter, size, tags and
+/// comment, only those protected by the retention tier if given.
+///
+/// Warns about backups whose hash file is missing or references another file.
+pub fn list(
+    target: PathBuf,
+    name_template: &NameTemplate,
+    filter: ScanFilter,
+    tier: Option<Tier>,
+) -> Result<()> {
+    for backup in list_matching_backups(&target, name_template, filter)? {
+        if tier.is_some_and(|tier| !backup.tiers.contains(tier)) {
+            continue;
+        }
+        println!("{}", backup.describe());
+
+        match read_sidecar(&backup.file.path) {
+            Some(sidecar) if sidecar.references(&backup.file.path) => {}
+            Some(sidecar) => warn!(
+                "Hash file of {} references {}. Use `repair-sidecars` to regenerate it.",
+                backup.file.path.display(),
+                sidecar.file_name
+            ),
+            // Backups written with `--sidecar-format none` are verified against the database.
+            None if backup.hash.is_some() && !sidecar_path(&backup.file.path).exists() => {}
+            None => warn!(
+                "Hash file of {} is missing or unreadable.",
+                backup.file.path.display()
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_millis(500)), "0s");
+        assert_eq!(format_duration(Duration::from_secs(45)), "45s");
+        assert_eq!(format_duration(Duration::from_secs(3600)), "1h");
+        assert_eq!(
+            format_duration(Duration::from_secs(2 * 86400 + 5 * 3600 + 7)),
+            "2d 5h"
+        );
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Cooperative lock of a target folder, so that several machines backing up into the same network
+//! share do not allocate the same counter or prune each others files.
+//!
+//! `flock` and friends are unreliable on SMB and NFS, so the lock is a file created exclusively,
+//! naming its holder and a heartbeat refreshed while the lock is held. Locks without heartbeat for
+//! [`STALE_AFTER`] are left over by crashed runs and taken over.
+
+use std::{
+    fs::OpenOptions,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use color_eyre::{
+    Section,
+    eyre::{Context, Result, eyre},
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    backup::template::hostname,
+    error_code::{ErrorCode, WithErrorCode},
+};
+
+/// Name of the lock file in the target folder. Heartbeats are written to files starting with it.
+pub const LOCK_FILE_NAME: &str = "staggered-file-backup.lock";
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+/// Generous compared to the heartbeat, as the clocks of the machines sharing a folder differ.
+const STALE_AFTER: Duration = Duration::from_secs(5 * 60);
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Content of the lock file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct LockInfo {
+    id: String,
+    host: String,
+    pid: u32,
+    /// Unix time of the last heartbeat in seconds.
+    heartbeat: i64,
+}
+
+impl LockInfo {
+    fn new() -> Self {
+        Self {
+            id: Uuid::now_v7().to_string(),
+            host: hostname().to_string_lossy().into_owned(),
+            pid: std::process::id(),
+            heartbeat: Utc::now().timestamp(),
+        }
+    }
+
+    /// Whether the holder stopped refreshing the lock, or is known to be gone.
+    fn is_stale(&self, now: i64) -> bool {
+        let crashed = self.host == hostname().to_string_lossy() && !process_exists(self.pid);
+        crashed || now - self.heartbeat > STALE_AFTER.as_secs() as i64
+    }
+}
+
+#[cfg(unix)]
+fn process_exists(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks whether the process exists.
+    let exists = unsafe { libc::kill(pid, 0) } == 0;
+    exists || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_exists(_pid: u32) -> bool {
+    true
+}
+
+fn read_lock(path: &Path) -> Option<LockInfo> {
+    let content = std::fs::read(path).ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+/// Whether the unreadable lock file was left over, rather than just created and not written yet.
+fn is_stale_file(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age > STALE_AFTER)
+}
+
+/// Creates the lock file, if no other lock file exists.
+fn try_create(path: &Path, info: &LockInfo) -> Result<bool> {
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => return Ok(false),
+        Err(err) => {
+            return Err(err).wrap_err_with(|| format!("Failed to create lock {}", path.display()));
+        }
+    };
+    file.write_all(&serde_json::to_vec(info)?)
+        .and_then(|()| file.sync_all())
+        .wrap_err_with(|| format!("Failed to write lock {}", path.display()))?;
+    Ok(true)
+}
+
+/// Replaces the lock file atomically, so that others never read a partially written lock.
+fn write_heartbeat(path: &Path, info: &LockInfo) -> Result<()> {
+    let heartbeat_path = path.with_extension(format!("lock.{}", info.id));
+    std::fs::write(&heartbeat_path, serde_json::to_vec(info)?)
+        .and_then(|()| std::fs::rename(&heartbeat_path, path))
+        .wrap_err_with(|| format!("Failed to refresh lock {}", path.display()))
+}
+
+/// Removes the stale lock, unless another process took it over in the meantime.
+fn remove_stale(path: &Path, stale: Option<&LockInfo>, own_id: &str) -> Result<()> {
+    // Renaming is atomic, so only one of several processes finding the stale lock removes it.
+    let claimed_path = path.with_extension(format!("lock.{}", own_id));
+    match std::fs::rename(path, &claimed_path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => {
+            return Err(err)
+                .wrap_err_with(|| format!("Failed to remove stale lock {}", path.display()));
+        }
+    }
+
+    let claimed = read_lock(&claimed_path);
+    if claimed.is_some() && claimed.as_ref() != stale {
+        // Another process took over the lock between reading and renaming it.
+        std::fs::rename(&claimed_path, path)
+            .wrap_err_with(|| format!("Failed to put back lock {}", path.display()))?;
+        return Ok(());
+    }
+    std::fs::remove_file(&claimed_path)
+        .wrap_err_with(|| format!("Failed to remove stale lock {}", claimed_path.display()))
+}
+
+/// Lock of a target folder, released when dropped.
+#[derive(Debug)]
+pub struct TargetLock {
+    path: PathBuf,
+    id: String,
+    stop: Option<Sender<()>>,
+    heartbeat: Option<JoinHandle<()>>,
+}
+
+impl TargetLock {
+    /// Locks the target folder, waiting up to `timeout` for other processes to release it.
+    ///
+    /// Fails with [`ExitCode::LockContention`] if the lock is still held afterwards.
+    pub fn acquire(target: &Path, timeout: Duration) -> Result<Self> {
+        let path = target.join(LOCK_FILE_NAME);
+        let info = LockInfo::new();
+        let deadline = Instant::now() + timeout;
+        let mut waiting = false;
+
+        while !try_create(&path, &info)? {
+            let holder = read_lock(&path);
+            let (holder_name, stale) = match &holder {
+                Some(holder) => (
+                    format!("process {} on {}", holder.pid, holder.host),
+                    holder.is_stale(Utc::now().timestamp()),
+                ),
+                None => ("an unknown process".to_owned(), is_stale_file(&path)),
+            };
+
+            if stale {
+                warn!("Taking over stale lock of {}.", holder_name);
+                remove_stale(&path, holder.as_ref(), &info.id)?;
+                continue;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(eyre!(
+                    "Target folder {} is locked by {}.",
+                    target.display(),
+                    holder_name
+                ))
+                .suggestion(
+                    "Wait for the other backup to finish, or use `--lock-timeout` to wait for it.",
+                )
+                .suggestion(format!(
+                    "Remove {} if no backup is running, e.g. after a crash.",
+                    path.display()
+                ))
+                .error_code(ErrorCode::TargetLocked);
+            }
+            if !waiting {
+                info!("Waiting for {} to release the target folder.", holder_name);
+                waiting = true;
+            }
+            std::thread::sleep(POLL_INTERVAL.min(deadline - now));
+        }
+        info!("Locked target folder {}.", target.display());
+
+        let (stop, stopped) = mpsc::channel();
+        let heartbeat = {
+            let path = path.clone();
+            let mut info = info.clone();
+            std::thread::spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(HEARTBEAT_INTERVAL)
+                {
+                    if read_lock(&path).is_none_or(|holder| holder.id != info.id) {
+                        error!(
+                            "Lock {} was taken over by another process. Check that the clocks of all machines backing up into the folder are right.",
+                            path.display()
+                        );
+                        return;
+                    }
+                    info.heartbeat = Utc::now().timestamp();
+                    if let Err(err) = write_heartbeat(&path, &info) {
+                        warn!("{:#}", err);
+                    }
+                }
+            })
+        };
+
+        Ok(Self {
+            path,
+            id: info.id,
+            stop: Some(stop),
+            heartbeat: Some(heartbeat),
+        })
+    }
+}
+
+impl Drop for TargetLock {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(heartbeat) = self.heartbeat.take() {
+            let _ = heartbeat.join();
+        }
+        if read_lock(&self.path).is_some_and(|holder| holder.id == self.id)
+            && let Err(err) = std::fs::remove_file(&self.path)
+        {
+            error!("Failed to release lock {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::exit_code::{ExitCode, exit_code_of};
+
+    #[test]
+    fn test_target_lock() {
+        let dir = std::env::temp_dir().join(format!("sfb-lock-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(LOCK_FILE_NAME);
+
+        let lock = TargetLock::acquire(&dir, Duration::ZERO).unwrap();
+        let err = TargetLock::acquire(&dir, Duration::ZERO).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ErrorCode>(),
+            Some(&ErrorCode::TargetLocked)
+        );
+        assert_eq!(exit_code_of(&err), ExitCode::LockContention);
+        drop(lock);
+        assert!(!path.exists());
+
+        let mut stale = LockInfo::new();
+        stale.host = "other-host".to_owned();
+        stale.heartbeat -= STALE_AFTER.as_secs() as i64 + 1;
+        assert!(stale.is_stale(Utc::now().timestamp()));
+        assert!(try_create(&path, &stale).unwrap());
+        let lock = TargetLock::acquire(&dir, Duration::ZERO).unwrap();
+        assert_eq!(read_lock(&path).unwrap().id, lock.id);
+
+        drop(lock);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
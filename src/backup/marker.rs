@@ -0,0 +1,200 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Marker file identifying a target folder as managed by staggered-file-backup.
+//!
+//! Backups into a non-empty folder without marker are refused unless it is adopted with
+//! `--adopt`, so that a mistyped target folder full of date-prefixed files is never pruned.
+
+use std::{
+    io::{ErrorKind, Write},
+    path::Path,
+};
+
+use chrono::Utc;
+use color_eyre::{
+    Section,
+    eyre::{Context, Result, eyre},
+};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::backup::{db::is_managed_dir, stream::Source, template::hostname};
+
+/// Name of the marker file in the target folder.
+pub const MARKER_FILE_NAME: &str = ".staggered-file-backup.json";
+
+const MARKER_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Marker {
+    pub version: u32,
+    /// Version of staggered-file-backup that marked the folder.
+    pub created_by: String,
+    /// Time the folder was marked in RFC 3339.
+    pub created: String,
+    /// Newest migration applied to the tracking database, like `202610151800000000`.
+    pub schema_version: Option<String>,
+    /// Sources backed up into the folder.
+    #[serde(default)]
+    pub sources: Vec<RegisteredSource>,
+}
+
+/// Source backed up into the target folder.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegisteredSource {
+    /// Name of the machine backing up the source.
+    pub host: String,
+    /// File name of the source.
+    pub name: String,
+    /// Absolute path of the source file, unknown for streamed sources.
+    pub path: Option<String>,
+}
+
+impl RegisteredSource {
+    /// The source as backed up by this host.
+    pub fn of(source: &Source) -> Result<Self> {
+        Ok(Self {
+            host: hostname().to_string_lossy().into_owned(),
+            name: source
+                .name()
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            path: match source {
+                Source::File(path) => Some(
+                    std::fs::canonicalize(path)
+                        .wrap_err("Failed to resolve absolute path of source file.")?
+                        .to_string_lossy()
+                        .into_owned(),
+                ),
+                Source::Stream { .. } => None,
+            },
+        })
+    }
+}
+
+impl Marker {
+    fn new() -> Self {
+        Self {
+            version: MARKER_VERSION,
+            created_by: env!("CARGO_PKG_VERSION").to_owned(),
+            created: Utc::now().to_rfc3339(),
+            schema_version: None,
+            sources: vec![],
+        }
+    }
+
+    /// Adds the source to the registry unless it is registered already.
+    pub fn register(&mut self, source: RegisteredSource) {
+        if !self.sources.contains(&source) {
+            self.sources.push(source);
+        }
+    }
+}
+
+/// Reads the marker of the target folder, `None` if it has none.
+pub fn read_marker(target: impl AsRef<Path>) -> Result<Option<Marker>> {
+    let path = target.as_ref().join(MARKER_FILE_NAME);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).wrap_err_with(|| format!("Failed to read {}", path.display())),
+    };
+    let marker: Marker = serde_json::from_str(&content)
+        .wrap_err_with(|| format!("Failed to parse {}", path.display()))
+        .suggestion("Remove the damaged marker and back up with `--adopt` to recreate it.")?;
+    if marker.version > MARKER_VERSION {
+        return Err(eyre!(
+            "Target folder was marked by a newer version of staggered-file-backup ({}).",
+            marker.created_by
+        ))
+        .suggestion("Update staggered-file-backup with `self-update`.");
+    }
+
+    Ok(Some(marker))
+}
+
+/// Writes the marker of the target folder, replacing the previous one at once.
+pub fn write_marker(target: impl AsRef<Path>, marker: &Marker) -> Result<()> {
+    let path = target.as_ref().join(MARKER_FILE_NAME);
+    let tmp_path = target.as_ref().join(format!("{}.tmp", MARKER_FILE_NAME));
+
+    let mut file = std::fs::File::create(&tmp_path)
+        .wrap_err_with(|| format!("Failed to create {}", MARKER_FILE_NAME))?;
+    serde_json::to_writer_pretty(&mut file, marker)?;
+    file.write_all(b"\n")
+        .wrap_err_with(|| format!("Failed to write {}", MARKER_FILE_NAME))?;
+    drop(file);
+
+    std::fs::rename(&tmp_path, &path)
+        .wrap_err_with(|| format!("Failed to replace {}", MARKER_FILE_NAME))
+}
+
+/// Returns the marker of the target folder, marking the folder first if it is empty, was used by
+/// an older version without marker, or is adopted. Other folders are refused.
+pub fn ensure_marked(target: impl AsRef<Path>, adopt: bool) -> Result<Marker> {
+    let target = target.as_ref();
+    if let Some(marker) = read_marker(target)? {
+        return Ok(marker);
+    }
+
+    if !adopt && !is_managed_dir(target)? {
+        return Err(eyre!(
+            "Target folder {} is not empty and is not marked as managed by staggered-file-backup.",
+            target.display()
+        ))
+        .suggestion("Check if the target folder is correct. Files in it might be moved into the recycle bin.")
+        .suggestion("Use `--adopt` to manage it from now on.");
+    }
+
+    let marker = Marker::new();
+    write_marker(target, &marker)?;
+    info!("Marked {} as target folder.", target.display());
+
+    Ok(marker)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_ensure_marked() {
+        let dir = std::env::temp_dir().join(format!("sfb-marker-test-{}", std::process::id()));
+        let empty = dir.join("empty");
+        let foreign = dir.join("foreign");
+        std::fs::create_dir_all(&empty).unwrap();
+        std::fs::create_dir_all(&foreign).unwrap();
+        std::fs::write(foreign.join("2025-01-01_00_photo.jpg"), b"photo").unwrap();
+
+        let mut marker = ensure_marked(&empty, false).unwrap();
+        assert_eq!(read_marker(&empty).unwrap(), Some(marker.clone()));
+
+        assert!(ensure_marked(&foreign, false).is_err());
+        assert_eq!(read_marker(&foreign).unwrap(), None);
+        ensure_marked(&foreign, true).unwrap();
+        assert!(ensure_marked(&foreign, false).is_ok());
+
+        let source = RegisteredSource::of(&Source::File(PathBuf::from("Cargo.toml"))).unwrap();
+        marker.register(source.clone());
+        marker.register(source);
+        write_marker(&empty, &marker).unwrap();
+        assert_eq!(read_marker(&empty).unwrap().unwrap().sources.len(), 1);
+
+        std::fs::write(
+            empty.join(MARKER_FILE_NAME),
+            r#"{"version": 2, "created_by": "9.0.0", "created": "", "schema_version": null}"#,
+        )
+        .unwrap();
+        assert!(read_marker(&empty).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
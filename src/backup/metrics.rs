@@ -0,0 +1,339 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::Utc;
+use color_eyre::eyre::{Context, Report, Result};
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    backup::{cleanup::Tiers, list::format_size},
+    error_code::error_code_of,
+};
+
+/// Metrics with their type and help text, in the order they are written.
+const METRICS: [(&str, &str, &str); 6] = [
+    (
+        "sfb_last_run_timestamp",
+        "gauge",
+        "Unix time of the last backup run.",
+    ),
+    (
+        "sfb_last_run_success",
+        "gauge",
+        "1 if the last backup run succeeded, otherwise 0.",
+    ),
+    (
+        "sfb_last_success_timestamp",
+        "gauge",
+        "Unix time of the last successful backup run.",
+    ),
+    (
+        "sfb_backup_size_bytes",
+        "gauge",
+        "Size of the newest backup in bytes.",
+    ),
+    (
+        "sfb_backups_total",
+        "gauge",
+        "Number of backups in the target folder.",
+    ),
+    (
+        "sfb_pruned_total",
+        "counter",
+        "Number of backups moved into the recycle bin by all runs.",
+    ),
+];
+
+/// Number of backups kept by each retention tier. Backups kept by several tiers count for each.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TierCounts {
+    pub latest: usize,
+    pub daily: usize,
+    pub weekly: usize,
+    pub monthly: usize,
+    pub quarterly: usize,
+    pub yearly: usize,
+}
+
+impl TierCounts {
+    pub fn count<'a>(tiers: impl IntoIterator<Item = &'a Tiers>) -> Self {
+        tiers
+            .into_iter()
+            .fold(Self::default(), |counts, tiers| Self {
+                latest: counts.latest + usize::from(tiers.latest),
+                daily: counts.daily + usize::from(tiers.daily),
+                weekly: counts.weekly + usize::from(tiers.weekly),
+                monthly: counts.monthly + usize::from(tiers.monthly),
+                quarterly: counts.quarterly + usize::from(tiers.quarterly),
+                yearly: counts.yearly + usize::from(tiers.yearly),
+            })
+    }
+}
+
+/// Outcome of a successful backup run.
+#[derive(Debug, Default, Clone)]
+pub struct RunMetrics {
+    pub backup_path: PathBuf,
+    pub backup_size: u64,
+    /// Bytes written into the target folder, none if the backup was hardlinked to an identical one.
+    pub bytes_copied: u64,
+    /// Time spent hashing, copying and verifying the backup.
+    pub transfer_duration: Duration,
+    /// Time spent hashing the source and the backup.
+    pub hash_duration: Duration,
+    pub backup_count: usize,
+    pub kept: TierCounts,
+    pub pruned_count: usize,
+    /// Size of all files in the target folder in bytes.
+    pub target_size_before: u64,
+    pub target_size_after: u64,
+}
+
+/// Summary of a run written with `--summary-file`.
+#[derive(Debug, Serialize)]
+struct Summary<'a> {
+    backup: &'a Path,
+    backup_size: u64,
+    bytes_copied: u64,
+    transfer_seconds: f64,
+    /// Bytes copied per second of the transfer.
+    throughput: Option<f64>,
+    hash_seconds: f64,
+    backups_total: usize,
+    kept: TierCounts,
+    pruned: usize,
+    target_size_before: u64,
+    target_size_after: u64,
+}
+
+/// Summary of a failed run written with `--summary-file`.
+#[derive(Debug, Serialize)]
+struct ErrorSummary {
+    error: ErrorDetails,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorDetails {
+    /// Stable identifier like `E0007`, none for failures without one.
+    code: Option<&'static str>,
+    message: String,
+}
+
+impl RunMetrics {
+    fn throughput(&self) -> Option<f64> {
+        let seconds = self.transfer_duration.as_secs_f64();
+        (seconds > 0.0 && self.bytes_copied > 0).then(|| self.bytes_copied as f64 / seconds)
+    }
+
+    /// Logs the summary of the run.
+    pub fn log_summary(&self) {
+        info!(
+            "Copied {} of {} in {:.1?}{}, hashing took {:.1?}.",
+            format_size(self.bytes_copied),
+            format_size(self.backup_size),
+            self.transfer_duration,
+            self.throughput()
+                .map(|throughput| format!(" ({}/s)", format_size(throughput as u64)))
+                .unwrap_or_default(),
+            self.hash_duration
+        );
+        info!(
+            "Kept {} backups: {} newest, {} daily, {} weekly, {} monthly, {} quarterly and {} yearly. Pruned {}.",
+            self.backup_count,
+            self.kept.latest,
+            self.kept.daily,
+            self.kept.weekly,
+            self.kept.monthly,
+            self.kept.quarterly,
+            self.kept.yearly,
+            self.pruned_count
+        );
+        info!(
+            "Target folder size: {} before, {} after.",
+            format_size(self.target_size_before),
+            format_size(self.target_size_after)
+        );
+    }
+
+    fn summary(&self) -> Summary<'_> {
+        Summary {
+            backup: &self.backup_path,
+            backup_size: self.backup_size,
+            bytes_copied: self.bytes_copied,
+            transfer_seconds: self.transfer_duration.as_secs_f64(),
+            throughput: self.throughput(),
+            hash_seconds: self.hash_duration.as_secs_f64(),
+            backups_total: self.backup_count,
+            kept: self.kept,
+            pruned: self.pruned_count,
+            target_size_before: self.target_size_before,
+            target_size_after: self.target_size_after,
+        }
+    }
+}
+
+/// Writes the summary of the run as JSON, replacing the summary of the last run.
+pub fn write_summary(path: &Path, run: &RunMetrics) -> Result<()> {
+    write_summary_json(path, &run.summary())
+}
+
+/// Writes the error code and message of the failed run as JSON, replacing the summary of the last
+/// run.
+pub fn write_error_summary(path: &Path, report: &Report) -> Result<()> {
+    write_summary_json(
+        path,
+        &ErrorSummary {
+            error: ErrorDetails {
+                code: error_code_of(report).map(|code| code.id()),
+                message: format!("{:#}", report),
+            },
+        },
+    )
+}
+
+fn write_summary_json(path: &Path, summary: &impl Serialize) -> Result<()> {
+    let mut content = serde_json::to_string_pretty(summary)?;
+    content.push('\n');
+    std::fs::write(path, content)
+        .wrap_err_with(|| format!("Failed to write summary file {}", path.display()))
+}
+
+/// Escapes a label value of the Prometheus text format.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+/// Parses the values of the metrics in a file written before, ignoring their labels.
+fn parse_metrics(content: &str) -> HashMap<String, f64> {
+    content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let (series, value) = line.rsplit_once(' ')?;
+            let name = series.split('{').next()?;
+            Some((name.to_owned(), value.parse().ok()?))
+        })
+        .collect()
+}
+
+fn render_metrics(target: &Path, values: &HashMap<String, f64>) -> String {
+    let label = escape_label(&target.to_string_lossy());
+    let mut content = String::new();
+    for (name, kind, help) in METRICS {
+        let Some(value) = values.get(name) else {
+            continue;
+        };
+        let _ = writeln!(content, "# HELP {} {}", name, help);
+        let _ = writeln!(content, "# TYPE {} {}", name, kind);
+        let _ = writeln!(content, "{}{{target=\"{}\"}} {}", name, label, value);
+    }
+    content
+}
+
+/// Values of the metrics after the run, keeping those of earlier runs the run did not update.
+fn updated_metrics(previous: &str, now: i64, run: Option<&RunMetrics>) -> HashMap<String, f64> {
+    let mut values = parse_metrics(previous);
+    values.insert("sfb_last_run_timestamp".to_owned(), now as f64);
+    values.insert(
+        "sfb_last_run_success".to_owned(),
+        if run.is_some() { 1.0 } else { 0.0 },
+    );
+    if let Some(run) = run {
+        values.insert("sfb_last_success_timestamp".to_owned(), now as f64);
+        values.insert("sfb_backup_size_bytes".to_owned(), run.backup_size as f64);
+        values.insert("sfb_backups_total".to_owned(), run.backup_count as f64);
+        *values.entry("sfb_pruned_total".to_owned()).or_default() += run.pruned_count as f64;
+    }
+    values
+}
+
+/// Writes the metrics of the run in the text format of Prometheus, to be collected by the
+/// textfile collector of node_exporter.
+///
+/// Failed runs are passed as `None` and keep the values of the last successful run, so that alerts
+/// can fire when `sfb_last_success_timestamp` gets too old.
+pub fn write_metrics(path: &Path, target: &Path, run: Option<&RunMetrics>) -> Result<()> {
+    let previous = std::fs::read_to_string(path).unwrap_or_default();
+    let values = updated_metrics(&previous, Utc::now().timestamp(), run);
+
+    // Replaced at once, so that node_exporter never reads a partially written file.
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    std::fs::write(&tmp_path, render_metrics(target, &values))
+        .wrap_err_with(|| format!("Failed to write metrics file {}", path.display()))?;
+    std::fs::rename(&tmp_path, path)
+        .wrap_err_with(|| format!("Failed to replace metrics file {}", path.display()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        let run = RunMetrics {
+            backup_size: 1024,
+            backup_count: 8,
+            pruned_count: 2,
+            ..Default::default()
+        };
+        let target = Path::new("/backups/\"db\"");
+        let success = render_metrics(target, &updated_metrics("", 100, Some(&run)));
+        let again = render_metrics(target, &updated_metrics(&success, 200, Some(&run)));
+        let failure = render_metrics(target, &updated_metrics(&again, 300, None));
+
+        assert!(success.contains("# TYPE sfb_pruned_total counter\n"));
+        assert!(success.contains("sfb_backup_size_bytes{target=\"/backups/\\\"db\\\"\"} 1024\n"));
+        assert_eq!(parse_metrics(&again)["sfb_pruned_total"], 4.0);
+        let failure = parse_metrics(&failure);
+        assert_eq!(failure["sfb_last_run_success"], 0.0);
+        assert_eq!(failure["sfb_last_run_timestamp"], 300.0);
+        assert_eq!(failure["sfb_last_success_timestamp"], 200.0);
+        assert_eq!(failure["sfb_backups_total"], 8.0);
+    }
+
+    #[test]
+    fn test_summary() {
+        let tiers = [
+            Tiers {
+                latest: true,
+                daily: true,
+                ..Default::default()
+            },
+            Tiers {
+                daily: true,
+                yearly: true,
+                ..Default::default()
+            },
+        ];
+        let run = RunMetrics {
+            backup_size: 2048,
+            bytes_copied: 2048,
+            transfer_duration: Duration::from_secs(2),
+            kept: TierCounts::count(&tiers),
+            ..Default::default()
+        };
+
+        let summary = serde_json::to_value(run.summary()).unwrap();
+        assert_eq!(summary["throughput"], 1024.0);
+        assert_eq!(summary["kept"]["daily"], 2);
+        assert_eq!(summary["kept"]["monthly"], 0);
+        assert_eq!(summary["kept"]["yearly"], 1);
+    }
+}
@@ -0,0 +1,347 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use color_eyre::{
+    Report, Section,
+    eyre::{Context, ContextCompat, Result, bail, eyre},
+};
+use diesel::{Connection, SqliteConnection};
+use log::{error, info, warn};
+
+use crate::backup::{
+    chain::{ChainEvent, ChainOperation, append_chain},
+    cleanup::BackupFile,
+    db::{backup_file_with_relative_path, open_db, update_relative_path},
+    delta::{is_delta, read_delta_header, rewrite_delta_base},
+    file::{Layout, layout_dir, relative_path_string, remove_empty_layout_dirs},
+    lock::TargetLock,
+    parity::parity_path,
+    parsing::metadata_from_directory,
+    protect::{is_immutable, is_protected, protect, unprotect},
+    sidecar::{companion_paths, read_sidecar, sidecar_path, write_sidecar},
+    signature::signature_path,
+    template::{NameTemplate, hostname},
+};
+
+/// A backup to be renamed or moved.
+struct Move {
+    from: PathBuf,
+    to: PathBuf,
+    /// New base of a delta backup whose base is moved as well.
+    base_name: Option<String>,
+}
+
+impl Move {
+    /// Path the rewritten delta is written to before it replaces the original.
+    fn rewritten_path(&self) -> PathBuf {
+        if self.from == self.to {
+            with_suffix(&self.to, ".migrating")
+        } else {
+            self.to.clone()
+        }
+    }
+}
+
+/// Change done to the target folder, recorded so that it can be reverted.
+enum Step {
+    Renamed { from: PathBuf, to: PathBuf },
+    Created(PathBuf),
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path_str = path.to_path_buf().into_os_string();
+    path_str.push(suffix);
+    PathBuf::from(path_str)
+}
+
+fn plan_move(
+    target: &Path,
+    file: &BackupFile,
+    from_template: &NameTemplate,
+    name_template: &NameTemplate,
+    layout: Layout,
+) -> Result<Move> {
+    let file_name = file
+        .path
+        .file_name()
+        .and_then(OsStr::to_str)
+        .wrap_err_with(|| format!("File name of {} is not utf-8.", file.path.display()))?;
+    let captures = from_template
+        .regex()
+        .captures(file_name)
+        .wrap_err_with(|| format!("Failed parsing file name {}", file_name))?;
+
+    let base_name = captures
+        .name("basename")
+        .wrap_err("Cannot determine the base name of backups.")
+        .suggestion("The name template the backups were named by has to contain {basename}.")?
+        .as_str();
+    let host = captures
+        .name("hostname")
+        .map_or_else(hostname, |host| host.as_str().into());
+    let extension = captures
+        .name("ext")
+        .map(|ext| ext.as_str())
+        .filter(|ext| !ext.is_empty());
+
+    let date = format!(
+        "{:04}-{:02}-{:02}",
+        file.metadata.year, file.metadata.month, file.metadata.day
+    );
+    let to = layout_dir(target, layout, &date).join(name_template.render(
+        &date,
+        file.metadata.counter,
+        &host,
+        OsStr::new(base_name),
+        extension.map(OsStr::new),
+    ));
+
+    Ok(Move {
+        from: file.path.clone(),
+        to,
+        base_name: None,
+    })
+}
+
+fn check_destinations(moves: &[Move]) -> Result<()> {
+    let mut destinations = HashSet::new();
+
+    for planned in moves {
+        if !destinations.insert(&planned.to) {
+            bail!(
+                "Multiple backups would be moved to {}",
+                planned.to.display()
+            );
+        }
+
+        if is_immutable(&planned.from) {
+            return Err(eyre!("{} is immutable", planned.from.display()))
+                .suggestion("Clear the immutable attribute with `chattr -i` before migrating.");
+        }
+
+        if planned.from != planned.to
+            && (planned.to.try_exists()?
+                || companion_paths(&planned.to)
+                    .iter()
+                    .any(|companion_path| companion_path.exists()))
+        {
+            bail!("Refusing to overwrite {}", planned.to.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_move(
+    target: &Path,
+    planned: &Move,
+    conn: &mut SqliteConnection,
+    journal: &mut Vec<Step>,
+) -> Result<()> {
+    if let Some(dir) = planned.to.parent() {
+        std::fs::create_dir_all(dir).wrap_err("Failed to create backup folder.")?;
+    }
+
+    match &planned.base_name {
+        Some(base_name) => {
+            let rewritten_path = planned.rewritten_path();
+            rewrite_delta_base(&planned.from, &rewritten_path, base_name)?;
+            journal.push(Step::Created(rewritten_path));
+        }
+        None => {
+            std::fs::rename(&planned.from, &planned.to)
+                .wrap_err_with(|| format!("Failed to move {}", planned.from.display()))?;
+            journal.push(Step::Renamed {
+                from: planned.from.clone(),
+                to: planned.to.clone(),
+            });
+        }
+    }
+
+    if planned.from == planned.to {
+        return Ok(());
+    }
+
+    for companion_path in [parity_path, signature_path] {
+        let old_path = companion_path(&planned.from);
+        if old_path.exists() {
+            let new_path = companion_path(&planned.to);
+            std::fs::rename(&old_path, &new_path)
+                .wrap_err_with(|| format!("Failed to move {}", old_path.display()))?;
+            journal.push(Step::Renamed {
+                from: old_path,
+                to: new_path,
+            });
+        }
+    }
+
+    if let Some(sidecar) = read_sidecar(&planned.from) {
+        write_sidecar(&planned.to, &sidecar.hash, sidecar.format)?;
+        journal.push(Step::Created(sidecar_path(&planned.to)));
+    }
+
+    update_relative_path(
+        conn,
+        planned.from.strip_prefix(target)?,
+        planned.to.strip_prefix(target)?,
+    )
+}
+
+fn revert(journal: Vec<Step>) {
+    for step in journal.into_iter().rev() {
+        let result = match &step {
+            Step::Renamed { from, to } => std::fs::rename(to, from),
+            Step::Created(path) => std::fs::remove_file(path),
+        };
+        if let Err(err) = result {
+            let path = match &step {
+                Step::Renamed { to, .. } => to,
+                Step::Created(path) => path,
+            };
+            error!("Failed to revert {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// Removes the originals replaced by rewritten copies.
+fn finish_move(planned: &Move) -> Result<()> {
+    if planned.base_name.is_some() {
+        let protected = is_protected(&planned.from)?;
+        unprotect(&planned.from)?;
+        if planned.from == planned.to {
+            std::fs::rename(planned.rewritten_path(), &planned.to)?;
+        } else {
+            std::fs::remove_file(&planned.from)?;
+        }
+        if protected {
+            protect(&planned.to, false)?;
+        }
+    }
+
+    let old_sidecar_path = sidecar_path(&planned.from);
+    if planned.from != planned.to && old_sidecar_path.try_exists()? {
+        std::fs::remove_file(old_sidecar_path)?;
+    }
+
+    Ok(())
+}
+
+/// Renames and moves all backups of the target folder to the given name template and layout.
+///
+/// Delta backups are pointed to the new location of their base, hash files are rewritten and the
+/// tracking database is updated. If any backup fails to be moved, all changes are reverted.
+pub fn migrate(
+    target: PathBuf,
+    from_template: &NameTemplate,
+    name_template: &NameTemplate,
+    layout: Layout,
+) -> Result<()> {
+    let _lock = TargetLock::acquire(&target, Duration::ZERO)?;
+    info!(
+        "Parsing files of target directory with name template {}",
+        from_template.as_str()
+    );
+    let mut backup_files = metadata_from_directory(&target, from_template)?;
+    backup_files.sort();
+
+    let mut moves = backup_files
+        .iter()
+        .map(|file| plan_move(&target, file, from_template, name_template, layout))
+        .collect::<Result<Vec<_>>>()?;
+
+    let new_base_names = moves
+        .iter()
+        .map(|planned| {
+            Ok((
+                relative_path_string(&target, &planned.from)?,
+                relative_path_string(&target, &planned.to)?,
+            ))
+        })
+        .collect::<Result<HashMap<_, _>>>()?;
+
+    for planned in &mut moves {
+        if !is_delta(&planned.from) {
+            continue;
+        }
+        let header = read_delta_header(&planned.from)?;
+        match new_base_names.get(&header.base_name) {
+            Some(base_name) if *base_name != header.base_name => {
+                planned.base_name = Some(base_name.clone())
+            }
+            Some(_) => {}
+            None => warn!(
+                "Base {} of delta {} is not a known backup.",
+                header.base_name,
+                planned.from.display()
+            ),
+        }
+    }
+
+    moves.retain(|planned| planned.from != planned.to || planned.base_name.is_some());
+    if moves.is_empty() {
+        info!("All backups already follow the name template and layout.");
+        return Ok(());
+    }
+
+    check_destinations(&moves)?;
+
+    for planned in &moves {
+        info!(
+            "MOVE: {} -> {}",
+            planned.from.display(),
+            planned.to.display()
+        );
+    }
+
+    let mut conn = open_db(&target)?;
+    let mut journal = vec![];
+    let result = conn.transaction::<_, Report, _>(|conn| {
+        for planned in &moves {
+            apply_move(&target, planned, conn, &mut journal)?;
+        }
+        Ok(())
+    });
+
+    if let Err(err) = result {
+        error!("Migration failed, reverting all changes.");
+        revert(journal);
+        remove_empty_layout_dirs(&target).wrap_err("Failed to remove empty backup folders.")?;
+        return Err(err);
+    }
+
+    for planned in &moves {
+        finish_move(planned)
+            .wrap_err_with(|| format!("Failed to clean up {}", planned.from.display()))?;
+    }
+    remove_empty_layout_dirs(&target).wrap_err("Failed to remove empty backup folders.")?;
+
+    let mut chain_events = vec![];
+    for planned in moves.iter().filter(|planned| planned.from != planned.to) {
+        let relative_path = planned.to.strip_prefix(&target)?;
+        let hash = backup_file_with_relative_path(&mut conn, relative_path)?
+            .and_then(|row| row.hash)
+            .unwrap_or_default();
+        chain_events.push(ChainEvent::new(
+            ChainOperation::Remove,
+            planned.from.strip_prefix(&target)?,
+            hash.clone(),
+        ));
+        chain_events.push(ChainEvent::new(ChainOperation::Add, relative_path, hash));
+    }
+    append_chain(&target, &chain_events, false)?;
+
+    info!("Migrated {} backups.", moves.len());
+    info!("DONE!");
+
+    Ok(())
+}
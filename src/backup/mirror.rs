@@ -0,0 +1,161 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    Section,
+    eyre::{Result, eyre},
+};
+use log::info;
+
+use crate::{
+    backup::{
+        catalog::CATALOG_FILE_NAME, db::DB_NAME, hash::hash_file, marker::MARKER_FILE_NAME,
+        retry::RetryPolicy, transfer::relative_file_paths,
+    },
+    error_code::{ErrorCode, WithErrorCode},
+};
+
+/// Folder on a remote storage the target folder is mirrored onto.
+///
+/// Paths are relative to the folder and separated by `/`.
+pub trait RemoteFolder {
+    /// Name of the folder shown in logs.
+    fn name(&self) -> &str;
+
+    /// Lists the files of the folder with their sizes.
+    fn list(&self) -> Result<HashMap<String, u64>>;
+
+    fn upload(&self, local_path: &Path, remote_path: &str) -> Result<()>;
+
+    /// Hashes the uploaded file with SHA-256, on the server if supported or by downloading it.
+    fn hash(&self, remote_path: &str) -> Result<String>;
+
+    /// Removes the file, e.g. by moving it into a trash folder on the remote.
+    fn remove(&self, remote_path: &str) -> Result<()>;
+
+    /// Checks that all files of the target folder were uploaded unaltered.
+    ///
+    /// Compares the sizes of the files, if the remote offers no better check.
+    fn check(&self, _target: &Path, local_files: &[(PathBuf, String)]) -> Result<()> {
+        let remote_files = self.list()?;
+        for (local_path, remote_path) in local_files {
+            if remote_files.get(remote_path) != Some(&std::fs::metadata(local_path)?.len()) {
+                return Err(eyre!(
+                    "{} on {} does not match the target folder.",
+                    remote_path,
+                    self.name()
+                ))
+                .error_code(ErrorCode::RemoteMismatch);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Path with `/` separators, as used by remotes on all platforms.
+pub fn slash_path(relative_path: &Path) -> String {
+    relative_path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Checks that the uploaded file has the same SHA-256 hash as the local file.
+fn verify_upload(remote: &impl RemoteFolder, local_path: &Path, remote_path: &str) -> Result<()> {
+    let local_hash = hash_file(&mut File::open(local_path)?)?;
+    let remote_hash = remote.hash(remote_path)?;
+    if !local_hash.eq_ignore_ascii_case(&remote_hash) {
+        return Err(eyre!(
+            "{} on {} does not match the uploaded file.",
+            remote_path,
+            remote.name()
+        ))
+        .suggestion("Check the connection to the remote and back up again.")
+        .error_code(ErrorCode::RemoteMismatch);
+    }
+    Ok(())
+}
+
+/// Mirrors the target folder onto the remote: uploads new and changed files and removes files that
+/// were pruned from the target folder.
+///
+/// With `verify_uploads` each uploaded file is read back and compared with the local file.
+///
+/// The target folder stays the working copy, as the tracking database and hardlinked backups need
+/// a local file system.
+pub fn mirror(
+    target: &Path,
+    remote: &impl RemoteFolder,
+    retry: &RetryPolicy,
+    verify_uploads: bool,
+) -> Result<()> {
+    info!("Mirroring target directory onto {}", remote.name());
+    let remote_files = retry.run("Listing files of remote", || remote.list())?;
+
+    let mut local_files: Vec<(PathBuf, String)> = relative_file_paths(target, Path::new(""))?
+        .into_iter()
+        .filter(|relative_path| {
+            // Symlinks, e.g. the link to the newest backup, point at files uploaded anyway.
+            !target
+                .join(relative_path)
+                .symlink_metadata()
+                .is_ok_and(|metadata| metadata.is_symlink())
+        })
+        .map(|relative_path| {
+            let remote_path = slash_path(&relative_path);
+            (target.join(relative_path), remote_path)
+        })
+        .collect();
+    // Uploaded last, so the remote never tracks backups it does not contain.
+    local_files.sort_by_key(|(_, remote_path)| remote_path == DB_NAME);
+
+    let mut uploaded_count = 0;
+    for (local_path, remote_path) in &local_files {
+        let size = std::fs::metadata(local_path)?.len();
+        let changing = remote_path == DB_NAME
+            || remote_path == CATALOG_FILE_NAME
+            || remote_path == MARKER_FILE_NAME;
+        if !changing && remote_files.get(remote_path) == Some(&size) {
+            continue;
+        }
+        info!("UPLOAD: {}", remote_path);
+        retry.run("Uploading file to remote", || {
+            remote.upload(local_path, remote_path)
+        })?;
+        if verify_uploads {
+            verify_upload(remote, local_path, remote_path)?;
+        }
+        uploaded_count += 1;
+    }
+
+    let mut removed_count = 0;
+    for remote_path in remote_files.keys() {
+        if local_files.iter().any(|(_, local)| local == remote_path) {
+            continue;
+        }
+        info!("REMOVE REMOTE: {}", remote_path);
+        retry.run("Removing file of remote", || remote.remove(remote_path))?;
+        removed_count += 1;
+    }
+
+    info!("Verifying files of remote.");
+    remote.check(target, &local_files)?;
+    info!(
+        "Uploaded {} and removed {} files on {}.",
+        uploaded_count,
+        removed_count,
+        remote.name()
+    );
+
+    Ok(())
+}
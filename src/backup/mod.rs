@@ -4,158 +4,2139 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::{ffi::OsString, path::PathBuf, process::exit};
+use std::{
+    collections::{BTreeMap, HashSet},
+    ffi::{OsStr, OsString},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
+use chrono::{DateTime, Utc};
 use color_eyre::{
     Result, Section,
-    eyre::{Context, ContextCompat},
+    eyre::{Context, ContextCompat, eyre},
 };
-use log::{error, info};
+use diesel::SqliteConnection;
+use log::{error, info, warn};
 
-use crate::backup::{
-    cleanup::{identify_files_to_delete, identify_files_to_keep},
-    file::{modified_date_string_from_path, target_file_name},
-    hash::{generate_sha256_file_content, hash_file},
-    parsing::metadata_from_directory,
+use crate::{
+    backup::{
+        archive::{Format, ZIP_EXTENSION, hash_zip, is_zip, write_zip},
+        audit::{Operation, file_audit_entry, record_audit},
+        catalog::write_catalog,
+        chain::{ChainEvent, ChainOperation, append_chain, sign_chain},
+        cleanup::{
+            CapMode, Exponential, PeriodAnchor, RetentionScope, RetentionStrategy, Strategy, Tier,
+            Tiered, Tiers, content_epochs, dedup_by_path, identify_files_to_delete, identify_tiers,
+            newest_files, with_last_backups,
+        },
+        cold::{backups_of_target, move_to_cold_storage, relative_backup_path, scan_target},
+        copy::{
+            IoOptions, PARTIAL_DIR_NAME, RESUME_PART_SIZE, copy_file, copy_file_resumable,
+            hash_path, tree_hash_path,
+        },
+        db::{
+            backup_file_with_relative_path, backup_files_with_hash, cached_source_hash,
+            insert_backup_file, insert_journal_entries, open_db, remove_journal_entries,
+            schema_version, set_journal_hash, set_tiers, store_source_hash,
+        },
+        delta::{DELTA_EXTENSION, find_delta_base, is_delta, with_delta_bases, write_delta},
+        file::{
+            Layout, Preserve, TimestampSource, Timezone, claim_target_file, counter_collisions,
+            date_string_from_path, date_string_from_time, folder_size, layout_dir,
+            preserve_metadata, remove_empty_layout_dirs, sync_path, validate_source_and_target,
+        },
+        fs_limits::{Storage, check_target_limits},
+        journal::{Intent, journal_entry, recover},
+        latest::update_latest,
+        list::format_duration,
+        lock::TargetLock,
+        marker::{RegisteredSource, ensure_marked, write_marker},
+        metrics::{RunMetrics, TierCounts, write_error_summary, write_metrics, write_summary},
+        mirror::mirror,
+        normalize::{NameNormalization, composed},
+        parity::write_parity,
+        parsing::{
+            FileNameMetadata, IGNORE_FILE_NAME, ScanFilter, basename_from_file_name,
+            metadata_from_directory, orphaned_sidecars, source_name_from_path,
+            unmatched_file_paths,
+        },
+        protect::{protect, unprotect},
+        quarantine::quarantine,
+        rclone::Remote,
+        restore::hash_backup_content,
+        retry::RetryPolicy,
+        sidecar::{Integrity, SidecarFormat, companion_paths, sidecar_path, write_sidecar},
+        signature::{load_secret_key, sign_backup},
+        store::{MANIFEST_EXTENSION, hash_manifest, is_manifest, store_chunked},
+        stream::{Source, StreamInput, store_stream},
+        template::{NameTemplate, hostname},
+        undo::{OnNoTrash, record_prune, trash_files},
+        verify::{VerifyMode, check_tracked_backup, compare_backup_content, scrub},
+        vss::ShadowCopy,
+        webdav::{WebDav, WebDavTarget},
+    },
+    error_code::{ErrorCode, WithErrorCode},
+    exit_code::{ExitCode, WithExitCode, exit_code_of},
+    model::{BackupFile, PathBufSql, SourceHash, UuidSQL},
 };
 
-mod backup;
+pub mod archive;
+pub mod audit;
+pub mod catalog;
+pub mod chain;
+pub mod checksums;
 pub mod cleanup;
-mod db;
+pub mod cold;
+pub mod compact;
+pub mod copy;
+pub mod db;
+pub mod delta;
+pub mod diff;
+pub mod doctor;
 pub mod file;
+pub mod fs_limits;
 pub mod hash;
+pub mod journal;
+pub mod latest;
+pub mod list;
+pub mod lock;
+pub mod marker;
+pub mod metrics;
+pub mod migrate;
+pub mod mirror;
+pub mod normalize;
+pub mod parity;
 pub mod parsing;
+pub mod promote;
+pub mod protect;
+pub mod provenance;
+pub mod quarantine;
+pub mod rclone;
+pub mod restore;
+pub mod retry;
+pub mod sidecar;
+pub mod signature;
+pub mod simulate;
+pub mod stats;
+pub mod store;
+pub mod stream;
+pub mod template;
+#[cfg(test)]
+pub mod test_util;
+pub mod throttle;
+pub mod transfer;
+pub mod undo;
+pub mod verify;
+pub mod vss;
+pub mod webdav;
 
-pub fn backup(
-    source: PathBuf,
-    target: PathBuf,
-    keep_latest: Option<u32>,
-    keep_daily: Option<u32>,
-    keep_monthly: Option<u32>,
-    keep_yearly: Option<u32>,
-) -> Result<()> {
-    info!("Source file path: {}", source.display());
+#[derive(Debug, Clone)]
+pub struct BackupOptions {
+    pub keep_latest: Option<u32>,
+    pub keep_daily: Option<u32>,
+    pub keep_weekly: Option<u32>,
+    pub keep_monthly: Option<u32>,
+    pub keep_quarterly: Option<u32>,
+    pub keep_yearly: Option<u32>,
+    pub dedup_store: bool,
+    pub incremental: bool,
+    pub full_every: u32,
+    pub timestamp: TimestampSource,
+    pub timezone: Timezone,
+    pub name_template: NameTemplate,
+    pub layout: Layout,
+    pub allow_unmanaged_dir: bool,
+    /// Mark a non-empty target folder without marker as managed, instead of refusing it.
+    pub adopt: bool,
+    /// Fail if the target folder contains files neither following the name template nor ignored.
+    pub strict: bool,
+    pub verify_before_prune: bool,
+    pub allow_empty: bool,
+    /// Skip the retention, keeping all backups including the new one.
+    pub no_prune: bool,
+    pub on_no_trash: OnNoTrash,
+    /// Folder pruned files are moved into with [`OnNoTrash::ArchiveDir`].
+    pub archive_dir: Option<PathBuf>,
+    pub scrub: Option<u32>,
+    pub parity: Option<u8>,
+    pub sign_key: Option<PathBuf>,
+    pub retry: RetryPolicy,
+    pub change_retries: u32,
+    pub use_vss: bool,
+    pub preserve: Vec<Preserve>,
+    pub protect: bool,
+    pub immutable: bool,
+    pub fsync: bool,
+    pub io: IoOptions,
+    /// Verify copies of source files of at least this size with the parallel tree hash.
+    pub parallel_hash_above: Option<u64>,
+    /// Reuse the cached source hash if size and modification time are unchanged.
+    pub trust_mtime: bool,
+    /// Maintain `latest_<file name>` in the target folder pointing at the newest backup.
+    pub latest_link: bool,
+    /// Ask for confirmation on the terminal before trashing more than `confirm_above` backups.
+    pub interactive: bool,
+    pub confirm_above: usize,
+    pub tags: Vec<String>,
+    pub comment: Option<String>,
+    /// Exclude tagged backups from the retention periods.
+    pub keep_tagged: bool,
+    pub format: Format,
+    /// Password the content of zip backups is encrypted with.
+    pub zip_password: Option<String>,
+    /// Deflate zip backups even if the source looks already compressed.
+    pub compress_force: bool,
+    /// Folder old backups are moved to, e.g. on slower or cheaper storage.
+    pub cold_target: Option<PathBuf>,
+    /// Move backups older than this many days, instead of those only kept by the monthly and
+    /// yearly retention periods.
+    pub cold_after: Option<u32>,
+    /// rclone remote the target folder is mirrored onto after each backup.
+    pub rclone_remote: Option<Remote>,
+    /// WebDAV folder the target folder is mirrored onto after each backup.
+    pub webdav: Option<WebDavTarget>,
+    /// Limit of uploads to rclone remotes and WebDAV folders in bytes per second.
+    pub bwlimit: Option<u64>,
+    /// Read back files uploaded to rclone remotes and WebDAV folders and compare their hashes.
+    pub verify_uploads: bool,
+    /// File the metrics of each run are written to for the textfile collector of node_exporter.
+    pub metrics_file: Option<PathBuf>,
+    /// File the summary of each successful run is written to as JSON.
+    pub summary_file: Option<PathBuf>,
+    /// Simulated time backups are created at, to test the retention over many runs.
+    pub now: Option<SystemTime>,
+    /// Also append the audit log of trashed and restored files to `audit.log` in the target folder.
+    pub audit_log: bool,
+    /// Start the hash chain of added and removed backups, see [`chain`].
+    pub chain: bool,
+    pub retention_scope: RetentionScope,
+    pub strategy: Strategy,
+    /// Keep the first or last backup of each day, month and year with the tiered strategy.
+    pub period_anchor: PeriodAnchor,
+    /// Apply the retention periods only to backups whose content differs from the previous backup.
+    pub content_epochs: bool,
+    /// Unicode form the names of new backups are written in, or the form of the source name.
+    pub normalize_names: Option<NameNormalization>,
+    /// Wait this long for other processes to release the lock of the target folder.
+    pub lock_timeout: Duration,
+    /// Skip the backup if the newest backup of the source is younger than this.
+    pub min_interval: Option<Duration>,
+    /// Skip or replace backups once this many backups of the source were created that day.
+    pub max_per_day: Option<u32>,
+    pub cap_mode: CapMode,
+    pub verify_mode: VerifyMode,
+    /// Back up regardless of `min_interval` and `max_per_day`.
+    pub force: bool,
+    /// Verify the newest backup of the source before backing up, and back up in full if it is
+    /// corrupted.
+    pub verify_newest: bool,
+    pub sidecar_format: SidecarFormat,
+}
+
+impl BackupOptions {
+    /// Retention periods of the options.
+    pub fn tiered(&self) -> Tiered {
+        Tiered {
+            keep_latest: self.keep_latest,
+            keep_daily: self.keep_daily,
+            keep_weekly: self.keep_weekly,
+            keep_monthly: self.keep_monthly,
+            keep_quarterly: self.keep_quarterly,
+            keep_yearly: self.keep_yearly,
+            anchor: self.period_anchor,
+        }
+    }
+
+    /// Retention strategy selected by `strategy`, with the retention periods of the options.
+    pub fn retention_strategy(&self) -> Box<dyn RetentionStrategy> {
+        match self.strategy {
+            Strategy::Tiered => Box::new(self.tiered()),
+            Strategy::Exponential => Box::new(Exponential {
+                keep_latest: self.keep_latest,
+            }),
+        }
+    }
+
+    /// Time the backup is created at, simulated if set.
+    pub fn now(&self) -> SystemTime {
+        self.now.unwrap_or_else(SystemTime::now)
+    }
+}
+
+/// Same defaults as the command line flags.
+impl Default for BackupOptions {
+    fn default() -> Self {
+        Self {
+            keep_latest: Some(8),
+            keep_daily: Some(32),
+            keep_weekly: None,
+            keep_monthly: Some(12),
+            keep_quarterly: None,
+            keep_yearly: None,
+            dedup_store: false,
+            incremental: false,
+            full_every: 7,
+            timestamp: TimestampSource::Mtime,
+            timezone: Timezone::Local,
+            name_template: NameTemplate::default(),
+            layout: Layout::Flat,
+            allow_unmanaged_dir: false,
+            adopt: false,
+            strict: false,
+            verify_before_prune: false,
+            allow_empty: false,
+            no_prune: false,
+            on_no_trash: OnNoTrash::Fail,
+            archive_dir: None,
+            scrub: None,
+            parity: None,
+            sign_key: None,
+            retry: RetryPolicy {
+                retries: 0,
+                delay: Duration::from_secs(5),
+            },
+            change_retries: 3,
+            use_vss: false,
+            preserve: vec![Preserve::Mtime],
+            protect: false,
+            immutable: false,
+            fsync: false,
+            io: IoOptions::default(),
+            parallel_hash_above: Some(1024 * 1024 * 1024),
+            trust_mtime: false,
+            latest_link: false,
+            interactive: false,
+            confirm_above: 3,
+            tags: vec![],
+            comment: None,
+            keep_tagged: false,
+            format: Format::Plain,
+            zip_password: None,
+            compress_force: false,
+            cold_target: None,
+            cold_after: None,
+            rclone_remote: None,
+            webdav: None,
+            bwlimit: None,
+            verify_uploads: false,
+            metrics_file: None,
+            summary_file: None,
+            now: None,
+            audit_log: false,
+            chain: false,
+            retention_scope: RetentionScope::PerHost,
+            strategy: Strategy::Tiered,
+            period_anchor: PeriodAnchor::First,
+            content_epochs: false,
+            normalize_names: None,
+            lock_timeout: Duration::ZERO,
+            min_interval: None,
+            max_per_day: None,
+            cap_mode: CapMode::Skip,
+            verify_mode: VerifyMode::Hash,
+            force: false,
+            verify_newest: false,
+            sidecar_format: SidecarFormat::Gnu,
+        }
+    }
+}
+
+/// Backs up the source into the target folder and cleans up old backups.
+///
+/// Returns the path of the created backup.
+pub fn backup(source: Source, target: PathBuf, options: &BackupOptions) -> Result<PathBuf> {
+    let result = run_backup(source, &target, options);
 
-    let source_basename = source
-        .file_stem()
-        .wrap_err("Failed extracting the basename (file stem) from source path.")?
-        .to_os_string();
+    if let Some(metrics_file) = &options.metrics_file
+        && let Err(err) = write_metrics(metrics_file, &target, result.as_ref().ok())
+    {
+        // The outcome of the backup is more important than its metrics.
+        error!("{:#}", err);
+    }
+    if let Some(summary_file) = &options.summary_file {
+        let written = match &result {
+            Ok(run) => write_summary(summary_file, run),
+            // Skipped backups keep the summary of the last run.
+            Err(err) if exit_code_of(err) == ExitCode::Skipped => Ok(()),
+            Err(err) => write_error_summary(summary_file, err),
+        };
+        if let Err(err) = written {
+            error!("{:#}", err);
+        }
+    }
+
+    result.map(|run| run.backup_path)
+}
+
+fn run_backup(source: Source, target: &Path, options: &BackupOptions) -> Result<RunMetrics> {
+    let target = target.to_path_buf();
+    if options.format == Format::Zip && (options.incremental || options.dedup_store) {
+        return Err(eyre!(
+            "Zip backups cannot be stored incrementally or in a chunk store."
+        ))
+        .suggestion("Remove `--incremental` and `--dedup-store`, or `--format zip`.");
+    }
+
+    match &source {
+        Source::File(path) => {
+            info!("Source file path: {}", path.display());
+            validate_source_and_target(path, &target)?;
+        }
+        Source::Stream { input, name } => {
+            match input {
+                StreamInput::Stdin => info!("Source: stdin"),
+                StreamInput::Command(command) => info!("Source command: {}", command),
+            }
+            info!("Source name: {}", name.display());
+
+            if options.incremental || options.dedup_store || options.format != Format::Plain {
+                return Err(eyre!(
+                    "Streamed sources cannot be backed up incrementally, into a chunk store or as zip archive."
+                ))
+                .suggestion("Remove `--incremental`, `--dedup-store` and `--format`.");
+            }
+            if options.verify_mode.compares_bytes() {
+                return Err(eyre!(
+                    "Streamed sources cannot be compared byte by byte, as they are read only once."
+                ))
+                .suggestion("Use `--verify-mode hash`.");
+            }
+        }
+    }
+
+    // Held until the backup is mirrored, so that other machines sharing the target folder neither
+    // allocate the same counter nor prune files in flight.
+    let _lock = TargetLock::acquire(&target, options.lock_timeout)?;
+    let target_size_before = folder_size(&target)?;
+
+    let mut marker = ensure_marked(&target, options.adopt || options.allow_unmanaged_dir)?;
+
+    if options.strict {
+        let unknown_paths = unmatched_file_paths(&target, &options.name_template)?;
+        if let Some(path) = unknown_paths.first() {
+            return Err(eyre!(
+                "Target folder {} contains {} files not following the name template, e.g. {}.",
+                target.display(),
+                unknown_paths.len(),
+                path.display()
+            ))
+            .suggestion(format!(
+                "Add globs matching them to {} in the target folder, or move them elsewhere.",
+                IGNORE_FILE_NAME
+            ));
+        }
+    }
+
+    let normalize = |name: &OsStr| match options.normalize_names {
+        Some(normalization) => normalization.apply(name),
+        None => name.to_os_string(),
+    };
+    let source_basename = normalize(
+        source
+            .name()
+            .file_stem()
+            .wrap_err("Failed extracting the basename (file stem) from source path.")?,
+    );
     info!("Source basename: {}", source_basename.display());
 
-    let extension_option = source.extension().map(|ext| ext.to_os_string());
+    let extension_option = source.name().extension().map(normalize);
     match &extension_option {
         Some(ext) => info!("Source file extension: {}", ext.display()),
         None => log::warn!("Source file has no file extension."),
     }
 
-    info!("Reading modification date of source file.");
-    let modified_string = modified_date_string_from_path(&source)?;
-    info!("Source file last modified: {}", &modified_string);
+    let secret_key = options.sign_key.as_ref().map(load_secret_key).transpose()?;
 
-    info!("Hashing source file.");
-    let source_hash = hash_file(&source)?;
-    info!("Source file sh256: {}", &source_hash);
+    info!("Reading date of backup.");
+    let date_string = match &source {
+        Source::File(path) => {
+            date_string_from_path(path, options.timestamp, options.timezone, options.now())?
+        }
+        Source::Stream { .. } => date_string_from_time(options.now(), options.timezone),
+    };
+    info!("Backup date: {}", &date_string);
 
     info!("Target directory: {}", target.display());
 
-    let target_file = target_file_name(
+    info!("Name template: {}", options.name_template.as_str());
+
+    info!("Opening backup tracking database.");
+    let mut conn = open_db(&target)?;
+    recover(&mut conn, &target, options)
+        .wrap_err("Failed to reconcile operations interrupted before.")?;
+
+    let registered = marker.clone();
+    marker.schema_version = schema_version(&mut conn)?;
+    marker.register(RegisteredSource::of(&source)?);
+    if marker != registered {
+        write_marker(&target, &marker).wrap_err("Failed to update marker of target folder.")?;
+    }
+
+    let mut newest_corrupted = false;
+    if options.verify_newest
+        && let Some(newest) = source_backups(
+            &mut conn,
+            &target,
+            options,
+            &date_string,
+            &source_basename,
+            extension_option.as_ref(),
+        )?
+        .into_iter()
+        .max()
+    {
+        info!("Verifying newest backup {}", newest.path.display());
+        if check_tracked_backup(&mut conn, &target, &newest.path)? == Integrity::Corrupted {
+            error!(
+                "Newest backup {} is corrupted! Creating a full backup.",
+                newest.path.display()
+            );
+            quarantine(&mut conn, &target, &newest.path)?;
+            newest_corrupted = true;
+        }
+    }
+
+    let source_backups = if options.force
+        || newest_corrupted
+        || (options.min_interval.is_none() && options.max_per_day.is_none())
+    {
+        vec![]
+    } else {
+        source_backups(
+            &mut conn,
+            &target,
+            options,
+            &date_string,
+            &source_basename,
+            extension_option.as_ref(),
+        )?
+    };
+
+    if let Some(min_interval) = options.min_interval
+        && let Some((newest_path, age)) =
+            newest_backup_age(&mut conn, &target, options, &source_backups)?
+        && age < min_interval
+    {
+        return Err(eyre!(
+            "Newest backup {} is {} old, younger than the minimum interval of {}.",
+            newest_path.display(),
+            format_duration(age),
+            format_duration(min_interval)
+        ))
+        .suggestion("Use `--force` to back up regardless.")
+        .exit_code(ExitCode::Skipped);
+    }
+
+    let mut replaced = None;
+    if let Some(max_per_day) = options.max_per_day {
+        let same_day: Vec<&cleanup::BackupFile> = source_backups
+            .iter()
+            .filter(|file| file.metadata.date_string() == date_string)
+            .collect();
+        if same_day.len() >= max_per_day as usize {
+            match options.cap_mode {
+                CapMode::Skip => {
+                    return Err(eyre!(
+                        "{} backups of the source were created on {}, the maximum per day.",
+                        same_day.len(),
+                        date_string
+                    ))
+                    .suggestion("Use `--cap-mode replace` to replace the newest backup of the day, or `--force` to back up regardless.")
+                    .exit_code(ExitCode::Skipped);
+                }
+                CapMode::Replace if options.no_prune => info!(
+                    "Reached the maximum of {} backups per day. Keeping them, as `--no-prune` is set.",
+                    max_per_day
+                ),
+                CapMode::Replace => {
+                    replaced = same_day.into_iter().max().map(|file| file.path.clone());
+                    if let Some(replaced) = &replaced {
+                        info!(
+                            "Reached the maximum of {} backups per day. Replacing {}.",
+                            max_per_day,
+                            replaced.display()
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    let delta_base = if options.incremental && !newest_corrupted {
+        info!("Searching for full backup to base incremental backup on.");
+        let backup_files = delta_base_candidates(
+            &mut conn,
+            &target,
+            options,
+            &date_string,
+            &source_basename,
+            extension_option.as_ref(),
+        )?;
+        let delta_base = find_delta_base(&backup_files, options.full_every);
+        match &delta_base {
+            Some(base) => info!("Incremental backup based on: {}", base.path.display()),
+            None => info!("Creating a full backup."),
+        }
+        delta_base
+    } else {
+        None
+    };
+
+    let suffix = if options.dedup_store {
+        Some(MANIFEST_EXTENSION)
+    } else if delta_base.is_some() {
+        Some(DELTA_EXTENSION)
+    } else if options.format == Format::Zip {
+        Some(ZIP_EXTENSION)
+    } else {
+        None
+    };
+
+    let extension_option = match suffix {
+        Some(suffix) => {
+            let mut ext = extension_option
+                .map(|mut ext| {
+                    ext.push(".");
+                    ext
+                })
+                .unwrap_or_default();
+            ext.push(suffix);
+            Some(ext)
+        }
+        None => extension_option,
+    };
+
+    let backup_dir = layout_dir(&target, options.layout, &date_string);
+    std::fs::create_dir_all(&backup_dir).wrap_err("Failed to create backup folder.")?;
+
+    let claim = claim_target_file(
         &target,
-        &modified_string,
+        &backup_dir,
+        &options.name_template,
+        &date_string,
         &source_basename,
         extension_option,
     )?;
+    let target_file = claim.file_name.clone();
+    let intent = journal_entry(Intent::Backup, claim.path.strip_prefix(&target)?);
+    insert_journal_entries(&mut conn, std::slice::from_ref(&intent))?;
 
     info!("Target file: {}", target_file.display());
 
-    let target_file_path = target.join(&target_file);
-    info!("Target file path: {}", target_file_path.display());
+    let source_size = match &source {
+        Source::File(path) => Some(source_state(path)?.1),
+        Source::Stream { .. } => None,
+    };
+    let storage = if options.dedup_store {
+        Storage::Chunks
+    } else if delta_base.is_some() || options.format == Format::Zip {
+        Storage::Smaller
+    } else {
+        Storage::Copy
+    };
+    check_target_limits(&backup_dir, &target_file, source_size, storage)?;
 
-    info!(
-        "Copying file '{}' to '{}'",
-        source.display(),
-        target_file_path.display()
-    );
+    info!("Target file path: {}", claim.path.display());
+    // Written in the partial folder and moved over the claimed name once verified.
+    let staging_path = &claim.staging_path;
 
-    std::fs::copy(source, &target_file_path)
-        .wrap_err("Failed to copy source file to target dir.")
-        .suggestion("Check if the target dir exists and if you have permissions to access it.")?;
+    let transfer_start = Instant::now();
+    let mut transfer = TransferStats::default();
+    // The hashes compared are tree hashes for large files, otherwise the SHA-256 of the source.
+    let (source_hash, expected_hash, target_hash) = match &source {
+        Source::File(source_path) => {
+            let shadow_copy = if options.use_vss {
+                Some(ShadowCopy::create_for(source_path)?)
+            } else {
+                None
+            };
+            let path = &match &shadow_copy {
+                Some(shadow_copy) => shadow_copy.path_of(source_path)?,
+                None => source_path.clone(),
+            };
+            if shadow_copy.is_some() {
+                info!("Reading source file from: {}", path.display());
+            }
+            let cache_path =
+                std::fs::canonicalize(source_path).wrap_err("Failed to resolve source path.")?;
 
-    info!("Finished copying.");
+            let mut attempt = 0;
+            let hashes = loop {
+                let state_before = source_state(path)?;
+                let (size, mtime) = (i64::try_from(state_before.1)?, unix_nanos(state_before.0));
+                let parallel_hash = !options.dedup_store
+                    && delta_base.is_none()
+                    && options.format == Format::Plain
+                    && options
+                        .parallel_hash_above
+                        .is_some_and(|size| state_before.1 >= size);
 
-    info!("Hashing target file.");
-    let target_hash = hash_file(&target_file_path)?;
-    info!("Target file sh256: {}", &target_hash);
+                let cached_hash = if options.trust_mtime && !newest_corrupted {
+                    cached_source_hash(&mut conn, &cache_path, size, mtime)?
+                } else {
+                    None
+                };
+                let cached = cached_hash.is_some();
+                let (source_hash, source_tree_hash) = match cached_hash {
+                    Some(source_hash) => {
+                        info!("Source file unchanged since last backup. Skipping hashing.");
+                        (source_hash, None)
+                    }
+                    None => {
+                        info!("Hashing source file.");
+                        timed(&mut transfer.hash_duration, || {
+                            hash_source_file(path, parallel_hash, options)
+                        })?
+                    }
+                };
+                info!("Source file sh256: {}", &source_hash);
+                if let Some(source_tree_hash) = &source_tree_hash {
+                    info!("Source file tree hash: {}", source_tree_hash);
+                }
+
+                let target_hash = if options.dedup_store {
+                    store_source_chunked(path, &target, staging_path)?
+                } else if let Some(delta_base) = &delta_base {
+                    store_source_delta(path, &target, &delta_base.path, staging_path)?
+                } else if options.format == Format::Zip {
+                    store_source_zip(path, source.name(), staging_path, options)?
+                } else {
+                    link_or_copy_source_to_target(
+                        path,
+                        &target,
+                        staging_path,
+                        &SourceHashes {
+                            sha256: &source_hash,
+                            tree: source_tree_hash.as_deref(),
+                            cached,
+                        },
+                        &mut conn,
+                        &mut transfer,
+                        options,
+                    )?
+                };
+                let expected_hash = source_tree_hash.unwrap_or_else(|| source_hash.clone());
+
+                if source_state(path)? == state_before {
+                    if !cached {
+                        store_source_hash(
+                            &mut conn,
+                            &SourceHash {
+                                path: PathBufSql {
+                                    path: cache_path.clone(),
+                                },
+                                size,
+                                mtime,
+                                hash: source_hash.clone(),
+                            },
+                        )?;
+                    }
+                    break (source_hash, expected_hash, target_hash);
+                }
+                if attempt == options.change_retries {
+                    warn!("Source file changed during backup. Giving up retrying.");
+                    break (source_hash, expected_hash, target_hash);
+                }
+
+                attempt += 1;
+                warn!(
+                    "Source file changed during backup. Retrying ({}/{}).",
+                    attempt, options.change_retries
+                );
+                std::fs::remove_file(staging_path)
+                    .wrap_err("Failed to remove backup of changed source file.")?;
+            };
+
+            if options.verify_mode.compares_bytes() {
+                info!("Comparing target file with source file byte by byte.");
+                compare_backup_content(
+                    &target,
+                    staging_path,
+                    path,
+                    options.zip_password.as_deref(),
+                    options.io.buffer_size(),
+                )?;
+                info!("Target and source file are identical.");
+            }
+            hashes
+        }
+        Source::Stream { input, .. } => {
+            let source_hash = store_stream(input, staging_path)?;
+            info!("Source sh256: {}", &source_hash);
+
+            info!("Hashing target file.");
+            let target_hash = timed(&mut transfer.hash_duration, || {
+                hash_target_file(staging_path, false, options)
+            })?;
+            info!("Target file sh256: {}", &target_hash);
+            (source_hash.clone(), source_hash, target_hash)
+        }
+    };
 
-    if target_hash == source_hash {
+    if options.verify_mode.compares_hashes() {
+        if target_hash != expected_hash {
+            return Err(eyre!("Target and source file hash are NOT equal!"))
+                .error_code(ErrorCode::CopyMismatch);
+        }
         info!("Target and source file hash are equal.");
+    }
+    let transfer_duration = transfer_start.elapsed();
+
+    set_journal_hash(&mut conn, &intent, &source_hash)?;
+    let target_file_path = claim.commit()?;
+    let collisions = counter_collisions(&target, &options.name_template, &target_file_path)?;
+    if !collisions.is_empty() {
+        warn!(
+            "Backups of the same date and counter exist besides the new backup, e.g. {}. Another process backed up into the target folder without locking it.",
+            collisions[0].display()
+        );
+    }
+
+    if options.protect {
+        // Hardlinked backups might be protected already.
+        unprotect(&target_file_path)?;
+    }
+
+    if let Source::File(path) = &source
+        && !options.preserve.is_empty()
+    {
+        info!("Preserving metadata of source file.");
+        preserve_metadata(path, &target_file_path, &options.preserve)?;
+    }
+
+    if options.sidecar_format != SidecarFormat::None {
+        info!(
+            "Write hash to file: {}",
+            sidecar_path(&target_file_path).display()
+        );
+        write_sidecar(&target_file_path, &source_hash, options.sidecar_format)?;
+        info!("Write success!");
+    }
+
+    if let Some(percent) = options.parity {
+        info!("Writing {}% parity data.", percent);
+        write_parity(&target_file_path, percent)?;
+    }
+
+    if let Some(secret_key) = &secret_key {
+        info!("Signing backup.");
+        sign_backup(secret_key, &target_file_path)?;
+    }
+
+    if options.fsync {
+        info!("Flushing backup to disk.");
+        for path in std::iter::once(target_file_path.clone())
+            .chain(companion_paths(&target_file_path))
+            .filter(|path| path.exists())
+        {
+            sync_path(path)?;
+        }
+        for dir in backup_dir.ancestors() {
+            sync_path(dir)?;
+            if dir == target {
+                break;
+            }
+        }
+    }
+
+    if options.protect {
+        info!("Protecting backup against modification.");
+        protect(&target_file_path, options.immutable)?;
+    }
+
+    info!("Tracking backup in database.");
+    insert_backup_file(
+        &mut conn,
+        &BackupFile {
+            uuid: UuidSQL::new(),
+            relative_path: PathBufSql {
+                path: target_file_path.strip_prefix(&target)?.to_path_buf(),
+            },
+            keep_yearly: false,
+            keep_monthly: false,
+            keep_daily: false,
+            keep_latest: false,
+            hash: Some(source_hash.clone()),
+            last_verified: Some(DateTime::<Utc>::from(options.now()).timestamp()),
+            tags: (!options.tags.is_empty()).then(|| options.tags.join(",")),
+            comment: options.comment.clone(),
+            cold_target: None,
+            source_path: match &source {
+                Source::File(path) => Some(PathBufSql {
+                    path: std::fs::canonicalize(path)
+                        .wrap_err("Failed to resolve absolute path of source file.")?,
+                }),
+                Source::Stream { .. } => None,
+            },
+            hostname: Some(hostname().to_string_lossy().into_owned()),
+            tool_version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+            created_at: Some(DateTime::<Utc>::from(options.now()).timestamp_millis()),
+            quarantined_at: None,
+            keep_weekly: false,
+            keep_quarterly: false,
+            promoted_tier: None,
+        },
+    )?;
+
+    append_chain(
+        &target,
+        &[ChainEvent::new(
+            ChainOperation::Add,
+            target_file_path.strip_prefix(&target)?,
+            source_hash,
+        )],
+        options.chain,
+    )?;
+    remove_journal_entries(&mut conn, &[intent])?;
+
+    let pruned_count = if options.no_prune {
+        info!("Skipping cleanup.");
+        0
     } else {
-        error!("Target and source file hash are NOT equal! Exiting...");
-        exit(1);
+        prune(&target, &mut conn, options, replaced.as_deref())
+            .error_code(ErrorCode::PruneFailed)?
+    };
+
+    if let Some(secret_key) = &secret_key {
+        sign_chain(&target, secret_key)?;
     }
 
-    let mut hash_file_name = OsString::from(&target_file);
-    hash_file_name.push(".sha256");
-    let hash_file_path = &target.join(hash_file_name);
+    if options.cold_target.is_some() {
+        move_to_cold_storage(&target, &mut conn, options)
+            .wrap_err("Failed to move backups to cold storage.")?;
+    }
 
-    info!("Write hash to file: {}", hash_file_path.display());
+    if options.latest_link {
+        let file_name = source
+            .name()
+            .file_name()
+            .wrap_err("Failed extracting the file name from source path.")?;
+        update_latest(&target, &target_file_path, file_name)
+            .wrap_err("Failed to update link to newest backup.")?;
+    }
 
-    std::fs::write(
-        hash_file_path,
-        generate_sha256_file_content(source_hash, target_file.to_string_lossy()),
-    )
-    .wrap_err("Failed to write hash file.")?;
-    info!("Write success!");
+    info!("Updating list of backups in target directory.");
+    let backup_tiers = identify_tiers(
+        &metadata_from_directory(&target, &options.name_template)?,
+        &options.tiered(),
+    )?;
+    write_catalog(&target, &backup_tiers).wrap_err("Failed to write list of backups.")?;
+    let backup_count = backup_tiers.len();
+    let kept = TierCounts::count(backup_tiers.iter().map(|(_, tiers)| tiers));
 
-    info!("Starting cleanup.");
+    if let Some(count) = options.scrub {
+        info!("Scrubbing {} least recently verified backups.", count);
+        let backup_files: Vec<_> = backup_tiers.into_iter().map(|(file, _)| file).collect();
+        scrub(&mut conn, &target, &backup_files, count)?;
+    }
+
+    if let Some(remote) = &options.rclone_remote {
+        let remote = remote.clone().with_bwlimit(options.bwlimit);
+        mirror(&target, &remote, &options.retry, options.verify_uploads)
+            .wrap_err("Failed to mirror backups onto rclone remote.")?;
+    }
+
+    if let Some(webdav) = &options.webdav {
+        let webdav = WebDav::connect(webdav, options.bwlimit)?;
+        mirror(&target, &webdav, &options.retry, options.verify_uploads)
+            .wrap_err("Failed to mirror backups onto WebDAV folder.")?;
+    }
+
+    let backup_size = std::fs::metadata(&target_file_path)?.len();
+    let run = RunMetrics {
+        backup_path: target_file_path,
+        backup_size,
+        bytes_copied: if transfer.linked { 0 } else { backup_size },
+        transfer_duration,
+        hash_duration: transfer.hash_duration,
+        backup_count,
+        kept,
+        pruned_count,
+        target_size_before,
+        target_size_after: folder_size(&target)?,
+    };
+    run.log_summary();
+    info!("DONE!");
+
+    Ok(run)
+}
+
+/// Modification date and size of the source, to detect it changing during the backup.
+/// Nanoseconds since the Unix epoch, negative for earlier times.
+fn unix_nanos(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => i64::try_from(duration.as_nanos()).unwrap_or(i64::MAX),
+        Err(err) => i64::try_from(err.duration().as_nanos()).map_or(i64::MIN, |nanos| -nanos),
+    }
+}
+
+fn source_state(source: &Path) -> Result<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(source).wrap_err("Failed reading metadata of source file.")?;
+    let modified = metadata
+        .modified()
+        .wrap_err("Failed reading modification date of source file.")?;
+
+    Ok((modified, metadata.len()))
+}
 
+/// Asks on the terminal whether the listed backups should be moved into the recycle bin.
+fn confirm_trash(count: usize) -> Result<bool> {
+    eprint!(
+        "Move the {} backups listed above into the recycle bin? [y/N] ",
+        count
+    );
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .wrap_err("Failed to read confirmation.")?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Host and file name of a source, whose backups are pruned together.
+type RetentionGroup = (String, Option<String>);
+
+/// Host and file name of the source of the backup, whose backups the retention periods apply to
+/// separately with [`RetentionScope::PerHost`].
+///
+/// Sources are told apart by their whole file name, as `report.2024.xlsx` and `report.2025.xlsx`
+/// share their base name.
+/// The host is taken from the file name if the name template contains `{hostname}`, otherwise from
+/// the tracking database. Backups without recorded host, e.g. of older versions, count as backups
+/// of this host.
+fn retention_group(
+    conn: &mut SqliteConnection,
+    target: &Path,
+    file: &cleanup::BackupFile,
+    name_template: &NameTemplate,
+) -> Result<RetentionGroup> {
+    let file_name = file
+        .path
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let captures = name_template.regex().captures(&file_name);
+    let capture = |name| {
+        captures
+            .as_ref()
+            .and_then(|captures| captures.name(name))
+            .map(|capture| capture.as_str().to_owned())
+    };
+
+    let host = match capture("hostname") {
+        Some(host) => Some(host),
+        None => {
+            let relative_path = relative_backup_path(conn, target, &file.path)?;
+            backup_file_with_relative_path(conn, relative_path)?.and_then(|row| row.hostname)
+        }
+    };
+
+    // Names are compared in their composed form, which target folders on macOS or SMB shares may
+    // not return them in.
+    Ok((
+        composed(&host.unwrap_or_else(|| hostname().to_string_lossy().into_owned())).into_owned(),
+        source_name_from_path(&file.path, name_template)
+            .map(|source_name| composed(&source_name).into_owned()),
+    ))
+}
+
+/// Next backup of the source by this host, named with counter 0.
+fn next_backup(
+    target: &Path,
+    options: &BackupOptions,
+    date: &str,
+    source_basename: &OsStr,
+    extension: Option<&OsString>,
+) -> cleanup::BackupFile {
+    cleanup::BackupFile {
+        path: target.join(options.name_template.render(
+            date,
+            0,
+            &hostname(),
+            source_basename,
+            extension.map(|ext| ext.as_os_str()),
+        )),
+        metadata: FileNameMetadata {
+            year: 0,
+            month: 0,
+            day: 0,
+            counter: 0,
+        },
+    }
+}
+
+/// Backups in the target folder an incremental backup of the source could be based on, and the
+/// incremental backups of the source based on them.
+///
+/// With [`RetentionScope::PerHost`] only backups of this host are considered, as backups of other
+/// hosts might be pruned by them at any time.
+fn delta_base_candidates(
+    conn: &mut SqliteConnection,
+    target: &Path,
+    options: &BackupOptions,
+    date: &str,
+    source_basename: &OsStr,
+    extension: Option<&OsString>,
+) -> Result<Vec<cleanup::BackupFile>> {
+    let next_backup = next_backup(target, options, date, source_basename, extension);
+    let (host, source_name) = retention_group(conn, target, &next_backup, &options.name_template)?;
+
+    let mut candidates = vec![];
+    for file in metadata_from_directory(target, &options.name_template)? {
+        let group = retention_group(conn, target, &file, &options.name_template)?;
+        if group.1 == source_name
+            && (options.retention_scope == RetentionScope::Global || group.0 == host)
+        {
+            candidates.push(file);
+        }
+    }
+
+    Ok(candidates)
+}
+
+/// Backups of the source by this host, which are those in the retention group of its next backup.
+fn source_backups(
+    conn: &mut SqliteConnection,
+    target: &Path,
+    options: &BackupOptions,
+    date: &str,
+    source_basename: &OsStr,
+    extension: Option<&OsString>,
+) -> Result<Vec<cleanup::BackupFile>> {
+    let next_backup = next_backup(target, options, date, source_basename, extension);
+    let group = retention_group(conn, target, &next_backup, &options.name_template)?;
+
+    // Skips parsing backups of other sources and hosts named by the template early.
+    let filter = ScanFilter {
+        basename: basename_from_file_name(
+            next_backup.path.file_name().unwrap_or_default(),
+            &options.name_template,
+        ),
+        host: Some(group.0.clone()),
+        ..Default::default()
+    };
+    let mut backups = vec![];
+    for file in scan_target(conn, target, &options.name_template, filter)? {
+        if retention_group(conn, target, &file, &options.name_template)? == group {
+            backups.push(file);
+        }
+    }
+
+    Ok(backups)
+}
+
+/// Newest of the backups and its age, judged by the creation time recorded in the tracking
+/// database.
+///
+/// Backups created before the creation time was recorded are ignored.
+fn newest_backup_age(
+    conn: &mut SqliteConnection,
+    target: &Path,
+    options: &BackupOptions,
+    backups: &[cleanup::BackupFile],
+) -> Result<Option<(PathBuf, Duration)>> {
+    let mut newest: Option<(&Path, i64)> = None;
+    for file in backups {
+        let relative_path = relative_backup_path(conn, target, &file.path)?;
+        let Some(created_at) =
+            backup_file_with_relative_path(conn, relative_path)?.and_then(|row| row.created_at)
+        else {
+            continue;
+        };
+        if newest
+            .as_ref()
+            .is_none_or(|(_, newest_created_at)| created_at > *newest_created_at)
+        {
+            newest = Some((&file.path, created_at));
+        }
+    }
+
+    let now = DateTime::<Utc>::from(options.now()).timestamp_millis();
+    Ok(newest.map(|(path, created_at)| {
+        let age = u64::try_from(now - created_at).unwrap_or(0);
+        (path.to_path_buf(), Duration::from_millis(age))
+    }))
+}
+
+/// Backups grouped by the retention group they are pruned in, all in the `None` group with
+/// [`RetentionScope::Global`].
+fn retention_groups(
+    conn: &mut SqliteConnection,
+    target: &Path,
+    backup_files: &[cleanup::BackupFile],
+    options: &BackupOptions,
+) -> Result<BTreeMap<Option<RetentionGroup>, Vec<cleanup::BackupFile>>> {
+    let mut groups: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for file in backup_files {
+        let group = match options.retention_scope {
+            RetentionScope::PerHost => {
+                Some(retention_group(conn, target, file, &options.name_template)?)
+            }
+            RetentionScope::Global => None,
+        };
+        groups.entry(group).or_default().push(file.clone());
+    }
+
+    Ok(groups)
+}
+
+/// Determines which backups of the target folder are kept and which are moved into the recycle
+/// bin by the retention periods of the options.
+///
+/// The `replaced` backup is moved into the recycle bin as well, unless it is the base of a kept
+/// incremental backup.
+pub fn plan_prune(
+    target: &Path,
+    conn: &mut SqliteConnection,
+    options: &BackupOptions,
+    replaced: Option<&Path>,
+) -> Result<(Vec<cleanup::BackupFile>, Vec<cleanup::BackupFile>)> {
     info!("Parsing files of target directory for dates.");
-    let backup_files = metadata_from_directory(&target)?;
+    let backup_files = backups_of_target(conn, target, &options.name_template)?;
+
+    let mut pinned_files = vec![];
+    // Oldest tracked backup, from which on backups are tracked in the database.
+    let mut tracked_since: Option<&FileNameMetadata> = None;
+    for file in &backup_files {
+        let relative_path = relative_backup_path(conn, target, &file.path)?;
+        let Some(row) = backup_file_with_relative_path(conn, relative_path)? else {
+            continue;
+        };
+        if tracked_since.is_none_or(|since| file.metadata < *since) {
+            tracked_since = Some(&file.metadata);
+        }
+        if let Some(tier) = row.promoted_tier {
+            info!("KEEP PROMOTED ({}): {}", tier, file.path.display());
+            pinned_files.push(file.clone());
+        } else if let Some(tags) = row.tags.filter(|_| options.keep_tagged) {
+            info!("KEEP TAGGED ({}): {}", tags, file.path.display());
+            pinned_files.push(file.clone());
+        }
+    }
+
+    let groups = retention_groups(conn, target, &backup_files, options)?;
 
     info!("Determine which files to keep...");
 
-    let backup_files_to_keep = identify_files_to_keep(
-        &backup_files,
-        keep_latest,
-        keep_daily,
-        keep_monthly,
-        keep_yearly,
-    )
-    .wrap_err("Failed to determine which files to keep.")?;
+    let local_host = hostname().to_string_lossy().into_owned();
+    let strategy = options.retention_strategy();
+    let mut backup_files_to_keep = vec![];
+    for (group, group_files) in &groups {
+        if let Some((host, source_name)) = group {
+            let source_name = source_name.as_deref().unwrap_or("unknown source");
+            // Other hosts prune their backups with their own retention periods.
+            if *host != local_host {
+                info!(
+                    "Leaving {} backups of {} to host {}.",
+                    group_files.len(),
+                    source_name,
+                    host
+                );
+                backup_files_to_keep.extend(group_files.iter().cloned());
+                continue;
+            }
+            if groups.len() > 1 {
+                info!("Applying retention periods to backups of {}.", source_name);
+            }
+        }
+        let keep = if options.content_epochs {
+            let epochs = content_epochs(group_files, |file| {
+                relative_backup_path(conn, target, &file.path)
+                    .and_then(|relative_path| backup_file_with_relative_path(conn, relative_path))
+                    .ok()
+                    .flatten()
+                    .and_then(|row| row.hash)
+            });
+            info!(
+                "{} of {} backups start a content epoch.",
+                epochs.len(),
+                group_files.len()
+            );
+            // The newest backups are kept whether their content changed or not.
+            let mut group_files = group_files.clone();
+            group_files.sort();
+            let mut keep = newest_files(&group_files, options.keep_latest)?;
+            keep.extend(
+                strategy
+                    .files_to_keep(&epochs)
+                    .wrap_err("Failed to determine which files to keep.")?,
+            );
+            let mut keep = dedup_by_path(keep);
+            keep.sort();
+            keep
+        } else {
+            strategy
+                .files_to_keep(group_files)
+                .wrap_err("Failed to determine which files to keep.")?
+        };
+        backup_files_to_keep.extend(if options.allow_empty {
+            keep
+        } else {
+            with_last_backups(
+                group_files,
+                keep,
+                |file| {
+                    source_name_from_path(&file.path, &options.name_template)
+                        .map(|source_name| composed(&source_name).into_owned())
+                },
+                |file| {
+                    check_tracked_backup(conn, target, &file.path)
+                        .is_ok_and(|integrity| integrity == Integrity::Intact)
+                },
+            )
+        });
+    }
+    let kept: HashSet<PathBuf> = backup_files_to_keep
+        .iter()
+        .map(|file| file.path.clone())
+        .collect();
+    for file in pinned_files {
+        if !kept.contains(&file.path) {
+            backup_files_to_keep.push(file);
+        }
+    }
+    if let Some(replaced) = replaced {
+        backup_files_to_keep.retain(|file| file.path != replaced);
+    }
+    backup_files_to_keep.sort();
+    let backup_files_to_keep = with_delta_bases(target, &backup_files, backup_files_to_keep)
+        .wrap_err("Failed to determine which files to keep.")?;
 
     backup_files_to_keep
         .iter()
         .for_each(|file| info!("KEEP: {}", file.path.display()));
 
+    let tracked_since = tracked_since.cloned();
     info!("Determine which files to move into recycle bin...");
-    let files_to_trash = identify_files_to_delete(backup_files, &backup_files_to_keep);
+    let mut files_to_trash = identify_files_to_delete(backup_files, &backup_files_to_keep);
+
+    if !options.allow_unmanaged_dir {
+        let mut managed_files = vec![];
+        for file in files_to_trash {
+            if is_managed_file(conn, target, &file, tracked_since.as_ref())? {
+                managed_files.push(file);
+            } else {
+                warn!(
+                    "Not trashing {}, as it is newer than the oldest tracked backup but not tracked.",
+                    file.path.display()
+                );
+            }
+        }
+        files_to_trash = managed_files;
+    }
+
+    Ok((backup_files_to_keep, files_to_trash))
+}
+
+/// Lists the backups of the target folder that the next backup would move into the recycle bin.
+pub fn preview_prune(target: &Path, options: &BackupOptions) -> Result<Vec<cleanup::BackupFile>> {
+    let mut conn = open_db(target)?;
+    let (_, files_to_trash) = plan_prune(target, &mut conn, options, None)?;
+    Ok(files_to_trash)
+}
+
+/// Describes the retention periods like the command line flags, e.g. `-n 8 -d 32 -m 12 -y -1`.
+///
+/// The weekly and quarterly periods are only mentioned if set.
+fn retention_description(options: &BackupOptions) -> String {
+    let count = |count: Option<u32>| count.map_or("-1".to_owned(), |count| count.to_string());
+    let mut description = format!(
+        "-n {} -d {} -m {} -y {}",
+        count(options.keep_latest),
+        count(options.keep_daily),
+        count(options.keep_monthly),
+        count(options.keep_yearly)
+    );
+    if let Some(keep_weekly) = options.keep_weekly {
+        description.push_str(&format!(" --keep-weekly {}", keep_weekly));
+    }
+    if let Some(keep_quarterly) = options.keep_quarterly {
+        description.push_str(&format!(" --keep-quarterly {}", keep_quarterly));
+    }
+
+    description
+}
+
+/// Records which retention tiers protect the backups of this host in the tracking database, and
+/// the tier they were promoted into.
+///
+/// With the exponential strategy, only the newest backups are in a tier.
+fn record_tiers(target: &Path, conn: &mut SqliteConnection, options: &BackupOptions) -> Result<()> {
+    let retention = match options.strategy {
+        Strategy::Tiered => options.tiered(),
+        Strategy::Exponential => Tiered {
+            keep_latest: options.keep_latest,
+            ..Default::default()
+        },
+    };
+    let local_host = hostname().to_string_lossy().into_owned();
+    let backup_files = backups_of_target(conn, target, &options.name_template)?;
+    for (group, group_files) in retention_groups(conn, target, &backup_files, options)? {
+        if group.is_some_and(|(host, _)| host != local_host) {
+            continue;
+        }
+        for (file, mut tiers) in identify_tiers(&group_files, &retention)? {
+            let relative_path = relative_backup_path(conn, target, &file.path)?;
+            let Some(row) = backup_file_with_relative_path(conn, &relative_path)? else {
+                continue;
+            };
+            if let Some(tier) = row.promoted_tier.as_deref().and_then(Tier::parse) {
+                tiers.set(tier, true);
+            }
+            set_tiers(conn, relative_path, tiers)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves backups outside the retention periods into the recycle bin.
+///
+/// Returns the number of backups moved.
+fn prune(
+    target: &Path,
+    conn: &mut SqliteConnection,
+    options: &BackupOptions,
+    replaced: Option<&Path>,
+) -> Result<usize> {
+    info!("Starting cleanup.");
+    let (backup_files_to_keep, mut files_to_trash) = plan_prune(target, conn, options, replaced)?;
+
+    if options.verify_before_prune && !files_to_trash.is_empty() {
+        info!("Verifying backups to keep before pruning...");
+        let mut intact_count = 0;
+        for file in &backup_files_to_keep {
+            if check_tracked_backup(conn, target, &file.path)? == Integrity::Intact {
+                intact_count += 1;
+            } else {
+                error!("Backup {} failed verification!", file.path.display());
+            }
+        }
+
+        if intact_count == 0 {
+            return Err(eyre!(
+                "None of the backups to keep passed verification. Refusing to prune."
+            ))
+            .suggestion("Check the integrity of the backups in the target folder.")
+            .error_code(ErrorCode::NoIntactBackup);
+        }
+        info!(
+            "{} of {} backups to keep passed verification.",
+            intact_count,
+            backup_files_to_keep.len()
+        );
+    }
 
     files_to_trash
         .iter()
         .for_each(|file| info!("TRASH: {}", file.path.display()));
 
+    if options.interactive
+        && files_to_trash.len() > options.confirm_above
+        && !confirm_trash(files_to_trash.len())?
+    {
+        warn!("Moving backups into recycle bin was not confirmed. Keeping all backups.");
+        files_to_trash.clear();
+    }
+
     let files_to_trash_count = files_to_trash.len();
     let mut files_to_trash_paths: Vec<PathBuf> =
         files_to_trash.into_iter().map(|file| file.path).collect();
     let files_to_trash_paths_sum_files: Vec<PathBuf> = files_to_trash_paths
         .iter()
-        .map(|path| {
-            let mut path_str = path.clone().into_os_string();
-            path_str.push(".sha256");
-            PathBuf::from(path_str)
-        })
+        .flat_map(companion_paths)
+        .filter(|path| path.exists())
         .collect();
     files_to_trash_paths.extend_from_slice(&files_to_trash_paths_sum_files);
 
     if files_to_trash_count > 0 {
+        let intents = files_to_trash_paths
+            .iter()
+            .map(|path| {
+                Ok(journal_entry(
+                    Intent::Trash,
+                    relative_backup_path(conn, target, path)?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        insert_journal_entries(conn, &intents)?;
+
+        for file in &files_to_trash_paths[..files_to_trash_count] {
+            unprotect(file)?;
+        }
+
+        let original_paths = files_to_trash_paths
+            .iter()
+            .map(std::fs::canonicalize)
+            .collect::<Result<Vec<_>, _>>()
+            .wrap_err("Failed to resolve paths of files to move into recycle bin.")?;
+        let reason_of = |path: &Path| {
+            if Some(path) == replaced {
+                format!(
+                    "Replaced by a newer backup of the day, --max-per-day {}",
+                    options.max_per_day.unwrap_or_default()
+                )
+            } else {
+                format!(
+                    "Outside retention periods {}",
+                    retention_description(options)
+                )
+            }
+        };
+        let backup_paths = &files_to_trash_paths[..files_to_trash_count];
+        let mut audit_entries = files_to_trash_paths
+            .iter()
+            .enumerate()
+            .map(|(index, path)| {
+                let reason = if index < files_to_trash_count {
+                    reason_of(path)
+                } else {
+                    let backup_path = backup_paths
+                        .iter()
+                        .find(|backup_path| companion_paths(backup_path).contains(path));
+                    format!(
+                        "Companion of backup {}",
+                        reason_of(backup_path.unwrap_or(path)).to_lowercase()
+                    )
+                };
+                file_audit_entry(conn, target, Operation::Trash, reason, path)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let chain_events = backup_paths
+            .iter()
+            .zip(&audit_entries)
+            .map(|(path, entry)| {
+                Ok(ChainEvent::new(
+                    ChainOperation::Remove,
+                    relative_backup_path(conn, target, path)?,
+                    entry.hash.clone().unwrap_or_default(),
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
         info!("Moving files into recycle bin...");
-        trash::delete_all(files_to_trash_paths)?;
+        let operation = trash_files(
+            target,
+            &files_to_trash_paths,
+            "Moving files into recycle bin",
+            options,
+        )?;
 
-        info!("Moved {} files into recycle bin.", files_to_trash_count);
+        if operation == Operation::Trash {
+            info!("Moved {} files into recycle bin.", files_to_trash_count);
+            record_prune(conn, &original_paths)?;
+        } else {
+            audit_entries
+                .iter_mut()
+                .for_each(|entry| entry.operation = operation.as_str().to_owned());
+        }
+        record_audit(conn, target, &audit_entries, options.audit_log)?;
+        append_chain(target, &chain_events, false)?;
+        // Removed backups are no longer protected by any tier.
+        for path in backup_paths {
+            let relative_path = relative_backup_path(conn, target, path)?;
+            set_tiers(conn, relative_path, Tiers::default())?;
+        }
+        remove_journal_entries(conn, &intents)?;
+
+        if options.protect {
+            // Kept backups hardlinked to trashed ones lost their protection.
+            for file in &backup_files_to_keep {
+                protect(&file.path, options.immutable)?;
+            }
+        }
     } else {
         info!("No files where determined to be moved into recycle bin.");
     }
 
-    info!("DONE!");
+    let orphaned_sidecar_paths = orphaned_sidecars(target, &options.name_template)?;
+    if !orphaned_sidecar_paths.is_empty() {
+        orphaned_sidecar_paths
+            .iter()
+            .for_each(|path| info!("TRASH ORPHANED: {}", path.display()));
+        let mut audit_entries = orphaned_sidecar_paths
+            .iter()
+            .map(|path| {
+                file_audit_entry(
+                    conn,
+                    target,
+                    Operation::Trash,
+                    "Hash, parity or signature file without backup",
+                    path,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let operation = trash_files(
+            target,
+            &orphaned_sidecar_paths,
+            "Moving orphaned files into recycle bin",
+            options,
+        )?;
+        if operation == Operation::Trash {
+            info!(
+                "Moved {} orphaned hash, parity and signature files into recycle bin.",
+                orphaned_sidecar_paths.len()
+            );
+        } else {
+            audit_entries
+                .iter_mut()
+                .for_each(|entry| entry.operation = operation.as_str().to_owned());
+        }
+        record_audit(conn, target, &audit_entries, options.audit_log)?;
+    }
+
+    remove_empty_layout_dirs(target).wrap_err("Failed to remove empty backup folders.")?;
+
+    record_tiers(target, conn, options).wrap_err("Failed to record retention tiers of backups.")?;
+
+    Ok(files_to_trash_count)
+}
+
+/// Checks if the backup is tracked in the database, or predates the oldest tracked backup.
+///
+/// Backups are tracked from the first backup with tracking database on, so untracked files named
+/// like newer backups were put into the target folder by someone else.
+fn is_managed_file(
+    conn: &mut SqliteConnection,
+    target: &Path,
+    file: &cleanup::BackupFile,
+    tracked_since: Option<&FileNameMetadata>,
+) -> Result<bool> {
+    if tracked_since.is_none_or(|since| file.metadata < *since) {
+        return Ok(true);
+    }
+    let relative_path = relative_backup_path(conn, target, &file.path)?;
+    Ok(backup_file_with_relative_path(conn, relative_path)?.is_some())
+}
+
+fn store_source_chunked(source: &Path, target: &Path, manifest_path: &Path) -> Result<String> {
+    info!(
+        "Storing file '{}' as chunks referenced by '{}'",
+        source.display(),
+        manifest_path.display()
+    );
+
+    let stats = store_chunked(source, target, manifest_path)?;
+    info!(
+        "Stored {} chunks of which {} were new ({} bytes).",
+        stats.chunks, stats.new_chunks, stats.new_bytes
+    );
+
+    info!("Hashing reassembled target file.");
+    let target_hash = hash_manifest(target, manifest_path)?;
+    info!("Target file sh256: {}", &target_hash);
+
+    Ok(target_hash)
+}
+
+fn store_source_delta(
+    source: &Path,
+    target: &Path,
+    base_path: &Path,
+    delta_path: &Path,
+) -> Result<String> {
+    info!(
+        "Storing file '{}' as delta against '{}' in '{}'",
+        source.display(),
+        base_path.display(),
+        delta_path.display()
+    );
+
+    write_delta(target, base_path, source, delta_path)?;
+
+    let delta_size = std::fs::metadata(delta_path)?.len();
+    info!("Finished writing delta ({} bytes).", delta_size);
+
+    info!("Hashing reassembled target file.");
+    let target_hash = hash_backup_content(target, delta_path)?;
+    info!("Target file sh256: {}", &target_hash);
+
+    Ok(target_hash)
+}
+
+fn store_source_zip(
+    source: &Path,
+    source_name: &Path,
+    zip_path: &Path,
+    options: &BackupOptions,
+) -> Result<String> {
+    info!(
+        "Storing file '{}' as zip archive '{}'",
+        source.display(),
+        zip_path.display()
+    );
+    if options.zip_password.is_some() {
+        info!("Encrypting zip archive with AES-256.");
+    }
+
+    let entry_name = source_name
+        .file_name()
+        .wrap_err("Failed extracting the file name from source path.")?
+        .to_string_lossy();
+    options
+        .retry
+        .run("Writing zip archive", || {
+            write_zip(
+                source,
+                zip_path,
+                &entry_name,
+                options.zip_password.as_deref(),
+                options.compress_force,
+            )
+        })
+        .wrap_err("Failed to store source file as zip archive.")?;
+
+    let zip_size = std::fs::metadata(zip_path)?.len();
+    info!("Finished writing zip archive ({} bytes).", zip_size);
 
-    return Ok(());
+    info!("Hashing content of zip archive.");
+    let target_hash = hash_zip(zip_path, options.zip_password.as_deref())?;
+    info!("Target file sh256: {}", &target_hash);
+
+    Ok(target_hash)
+}
+
+/// Hashes of the source file a copy is verified against.
+struct SourceHashes<'a> {
+    sha256: &'a str,
+    /// Parallel tree hash of large files, used instead of the SHA-256 to verify the copy.
+    tree: Option<&'a str>,
+    /// The SHA-256 was read from the cache, so hardlinks to backups with equal hash are trusted.
+    cached: bool,
+}
+
+/// Statistics of the transfer of the source, for the summary of the run.
+#[derive(Debug, Default)]
+struct TransferStats {
+    hash_duration: Duration,
+    /// Whether the backup was hardlinked to an identical backup instead of copied.
+    linked: bool,
+}
+
+/// Runs the function, adding the time it took to `duration`.
+fn timed<T>(duration: &mut Duration, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    *duration += start.elapsed();
+    result
+}
+
+fn link_or_copy_source_to_target(
+    source: &Path,
+    target: &Path,
+    target_file_path: &Path,
+    source_hashes: &SourceHashes,
+    conn: &mut SqliteConnection,
+    transfer: &mut TransferStats,
+    options: &BackupOptions,
+) -> Result<String> {
+    let expected_hash = source_hashes.tree.unwrap_or(source_hashes.sha256);
+    let tree_hash = source_hashes.tree.is_some();
+    let hash_name = if tree_hash { "tree hash" } else { "sh256" };
+
+    let identical_backup_path = backup_files_with_hash(conn, source_hashes.sha256)?
+        .into_iter()
+        .filter(|file| file.quarantined_at.is_none())
+        .map(|file| target.join(&*file.relative_path))
+        .find(|path| path.is_file() && !is_manifest(path) && !is_delta(path) && !is_zip(path));
+
+    let mut linked = false;
+    if let Some(identical_backup_path) = &identical_backup_path {
+        info!(
+            "Identical backup found. Hardlinking '{}' to '{}'",
+            identical_backup_path.display(),
+            target_file_path.display()
+        );
+
+        match std::fs::hard_link(identical_backup_path, target_file_path) {
+            Ok(()) => linked = true,
+            Err(err) => warn!(
+                "Failed to create hardlink, falling back to copying: {}",
+                err
+            ),
+        }
+    }
+
+    transfer.linked = linked;
+
+    if !linked {
+        copy_source_to_target(source, target, target_file_path, options)?;
+    } else if source_hashes.cached {
+        info!("Trusting hardlinked backup of unchanged source file. Skipping hashing.");
+        return Ok(expected_hash.to_owned());
+    }
+
+    info!("Hashing target file.");
+    let mut target_hash = timed(&mut transfer.hash_duration, || {
+        hash_target_file(target_file_path, tree_hash, options)
+    })?;
+    info!("Target file {}: {}", hash_name, &target_hash);
+
+    if linked && target_hash != expected_hash {
+        warn!("Hardlinked backup does not match the source file. Falling back to copying.");
+        std::fs::remove_file(target_file_path)
+            .wrap_err("Failed to remove mismatching hardlink.")?;
+        copy_source_to_target(source, target, target_file_path, options)?;
+
+        transfer.linked = false;
+
+        info!("Hashing target file.");
+        target_hash = timed(&mut transfer.hash_duration, || {
+            hash_target_file(target_file_path, tree_hash, options)
+        })?;
+        info!("Target file {}: {}", hash_name, &target_hash);
+    }
+
+    Ok(target_hash)
+}
+
+/// Hashes the source file, and with `parallel_hash` also computes its tree hash concurrently.
+fn hash_source_file(
+    path: &Path,
+    parallel_hash: bool,
+    options: &BackupOptions,
+) -> Result<(String, Option<String>)> {
+    let hash_sequential = || {
+        options
+            .retry
+            .run("Hashing source file", || hash_path(path, &options.io))
+            .wrap_err("Failed to hash source file.")
+    };
+    if !parallel_hash {
+        return Ok((hash_sequential()?, None));
+    }
+
+    std::thread::scope(|scope| {
+        let tree_hash = scope.spawn(|| {
+            options
+                .retry
+                .run("Tree hashing source file", || {
+                    tree_hash_path(path, &options.io)
+                })
+                .wrap_err("Failed to tree hash source file.")
+        });
+        let source_hash = hash_sequential()?;
+        let tree_hash = tree_hash
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))?;
+
+        Ok((source_hash, Some(tree_hash)))
+    })
+}
+
+/// Hashes the target file with the SHA-256 or the parallel tree hash.
+fn hash_target_file(
+    target_file_path: &Path,
+    tree_hash: bool,
+    options: &BackupOptions,
+) -> Result<String> {
+    options
+        .retry
+        .run("Hashing target file", || {
+            if tree_hash {
+                tree_hash_path(target_file_path, &options.io)
+            } else {
+                hash_path(target_file_path, &options.io)
+            }
+        })
+        .wrap_err("Failed to hash target file.")
+}
+
+/// Copies large files resumably through the partial folder of the target, unless they are cloned.
+fn copy_source_to_target(
+    source: &Path,
+    target: &Path,
+    target_file_path: &Path,
+    options: &BackupOptions,
+) -> Result<()> {
+    info!(
+        "Copying file '{}' to '{}'",
+        source.display(),
+        target_file_path.display()
+    );
+
+    let resumable =
+        std::fs::metadata(source).is_ok_and(|metadata| metadata.len() >= RESUME_PART_SIZE);
+    let copied = options
+        .retry
+        .run("Copying source file", || {
+            if resumable {
+                if options.io.is_default()
+                    && reflink_copy::reflink(source, target_file_path).is_ok()
+                {
+                    return Ok(None);
+                }
+                copy_file_resumable(
+                    source,
+                    target_file_path,
+                    &target.join(PARTIAL_DIR_NAME),
+                    &options.io,
+                )
+                .map(Some)
+            } else if options.io.is_default() {
+                reflink_copy::reflink_or_copy(source, target_file_path)
+            } else {
+                copy_file(source, target_file_path, &options.io).map(Some)
+            }
+        })
+        .wrap_err("Failed to copy source file to target dir.")
+        .suggestion("Check if the target dir exists and if you have permissions to access it.")?;
+
+    match copied {
+        Some(_) => info!("Finished copying."),
+        None => info!("Finished cloning (copy-on-write)."),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backup::{
+        delta::{read_delta_header, write_delta_content},
+        hash::hash_bytes,
+        store::collect_garbage,
+        test_util::{TempDir, tracked_row},
+    };
+
+    #[test]
+    fn test_sources_sharing_basename() {
+        let dir = std::env::temp_dir().join(format!("sfb-sources-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for file_name in [
+            "2025-01-01_00_report.2024.xlsx",
+            "2025-01-02_00_report.2024.xlsx",
+            "2025-01-03_00_report.2024.xlsx",
+            "2025-01-04_00_report.2025.xlsx",
+            "2025-01-05_00_report.2025.xlsx",
+        ] {
+            std::fs::write(dir.join(file_name), file_name).unwrap();
+        }
+        let mut conn = open_db(&dir).unwrap();
+        let options = BackupOptions {
+            keep_latest: Some(2),
+            keep_daily: Some(0),
+            keep_monthly: Some(0),
+            ..Default::default()
+        };
+
+        let backups = source_backups(
+            &mut conn,
+            &dir,
+            &options,
+            "2025-01-05",
+            OsStr::new("report.2024"),
+            Some(&OsString::from("xlsx")),
+        )
+        .unwrap();
+        assert_eq!(backups.len(), 3);
+        assert!(
+            backups
+                .iter()
+                .all(|file| file.path.to_string_lossy().ends_with("report.2024.xlsx"))
+        );
+
+        let (_, files_to_trash) = plan_prune(&dir, &mut conn, &options, None).unwrap();
+        let trashed: Vec<PathBuf> = files_to_trash.into_iter().map(|file| file.path).collect();
+        assert_eq!(trashed, vec![dir.join("2025-01-01_00_report.2024.xlsx")]);
+
+        // Pruned together, the last backup of each source is kept all the same.
+        let options = BackupOptions {
+            retention_scope: RetentionScope::Global,
+            ..options
+        };
+        let (_, files_to_trash) = plan_prune(&dir, &mut conn, &options, None).unwrap();
+        let trashed: Vec<PathBuf> = files_to_trash.into_iter().map(|file| file.path).collect();
+        assert_eq!(
+            trashed,
+            vec![
+                dir.join("2025-01-01_00_report.2024.xlsx"),
+                dir.join("2025-01-02_00_report.2024.xlsx"),
+            ]
+        );
+
+        drop(conn);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_untracked_backups_are_not_trashed() {
+        let dir = TempDir::new("untracked");
+        let mut conn = open_db(&dir).unwrap();
+        for (file_name, tracked) in [
+            ("2025-01-01_00_db.sql", false),
+            ("2025-01-02_00_db.sql", true),
+            ("2025-01-03_00_db.sql", false),
+            ("2025-01-04_00_db.sql", true),
+            ("2025-01-05_00_db.sql", true),
+        ] {
+            std::fs::write(dir.join(file_name), file_name).unwrap();
+            if tracked {
+                insert_backup_file(&mut conn, &tracked_row(file_name)).unwrap();
+            }
+        }
+        let options = BackupOptions {
+            keep_latest: Some(1),
+            keep_daily: Some(0),
+            keep_monthly: Some(0),
+            ..Default::default()
+        };
+        let trashed = |conn: &mut SqliteConnection, options: &BackupOptions| -> Vec<PathBuf> {
+            let (_, files_to_trash) = plan_prune(&dir, conn, options, None).unwrap();
+            files_to_trash.into_iter().map(|file| file.path).collect()
+        };
+
+        // Backups older than the oldest tracked one were created before tracking.
+        assert_eq!(
+            trashed(&mut conn, &options),
+            vec![
+                dir.join("2025-01-01_00_db.sql"),
+                dir.join("2025-01-02_00_db.sql"),
+                dir.join("2025-01-04_00_db.sql"),
+            ]
+        );
+        let options = BackupOptions {
+            allow_unmanaged_dir: true,
+            ..options
+        };
+        assert_eq!(trashed(&mut conn, &options).len(), 4);
+    }
+
+    #[test]
+    fn test_delta_base_of_source() {
+        let dir = std::env::temp_dir().join(format!("sfb-delta-base-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file_name, base_name) in [
+            ("2025-01-01_00_notes.txt", None),
+            (
+                "2025-01-02_00_notes.txt.delta",
+                Some("2025-01-01_00_notes.txt"),
+            ),
+            ("2025-01-03_00_todo.txt", None),
+            (
+                "2025-01-04_00_todo.txt.delta",
+                Some("2025-01-03_00_todo.txt"),
+            ),
+            (
+                "2025-01-05_00_todo.txt.delta",
+                Some("2025-01-03_00_todo.txt"),
+            ),
+        ] {
+            match base_name {
+                Some(base_name) => write_delta(
+                    &dir,
+                    dir.join(base_name),
+                    dir.join(base_name),
+                    dir.join(file_name),
+                )
+                .unwrap(),
+                None => std::fs::write(dir.join(file_name), file_name).unwrap(),
+            }
+        }
+        write_zip(
+            dir.join("2025-01-01_00_notes.txt"),
+            dir.join("2025-01-03_00_notes.txt.zip"),
+            "notes.txt",
+            None,
+            false,
+        )
+        .unwrap();
+        let mut conn = open_db(&dir).unwrap();
+        let options = BackupOptions {
+            incremental: true,
+            full_every: 3,
+            ..Default::default()
+        };
+        let base = |conn: &mut SqliteConnection, basename: &str| {
+            let candidates = delta_base_candidates(
+                conn,
+                &dir,
+                &options,
+                "2025-01-06",
+                OsStr::new(basename),
+                Some(&OsString::from("txt")),
+            )
+            .unwrap();
+            find_delta_base(&candidates, options.full_every).map(|file| file.path)
+        };
+
+        // The newer backups are of another source, or a zip archive.
+        assert_eq!(
+            base(&mut conn, "notes"),
+            Some(dir.join("2025-01-01_00_notes.txt"))
+        );
+        // Two incremental backups of the source since its full backup.
+        assert_eq!(base(&mut conn, "todo"), None);
+
+        drop(conn);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_backup_of_delta_source() {
+        let dir = TempDir::new("delta-source");
+        let target = dir.join("target");
+        std::fs::create_dir_all(&target).unwrap();
+        let source = dir.join("foo.delta");
+        let options = BackupOptions {
+            incremental: true,
+            full_every: 3,
+            ..Default::default()
+        };
+
+        std::fs::write(&source, "first").unwrap();
+        let full = backup(Source::File(source.clone()), target.clone(), &options).unwrap();
+        std::fs::write(&source, "second").unwrap();
+        let delta = backup(Source::File(source.clone()), target.clone(), &options).unwrap();
+        let mut content = vec![];
+        write_delta_content(&target, &delta, &mut content).unwrap();
+        let header = read_delta_header(&delta).unwrap();
+        let full_is_delta = is_delta(&full);
+        let delta_is_delta = is_delta(&delta);
+
+        // The plain backup of the source is the full backup the delta is based on.
+        assert!(!full_is_delta);
+        assert!(delta_is_delta);
+        assert_eq!(
+            header.base_name,
+            full.file_name().unwrap().to_string_lossy()
+        );
+        assert_eq!(content, b"second");
+    }
+
+    #[test]
+    fn test_backup_of_chunks_source() {
+        let dir = TempDir::new("chunks-source");
+        let target = dir.join("target");
+        std::fs::create_dir_all(&target).unwrap();
+        let source = dir.join("foo.chunks");
+        std::fs::write(&source, "not a manifest").unwrap();
+        let notes = dir.join("notes.txt");
+        std::fs::write(&notes, "notes").unwrap();
+
+        let plain = backup(
+            Source::File(source.clone()),
+            target.clone(),
+            &BackupOptions::default(),
+        )
+        .unwrap();
+        let options = BackupOptions {
+            dedup_store: true,
+            ..Default::default()
+        };
+        let manifest = backup(Source::File(notes.clone()), target.clone(), &options).unwrap();
+        // Reading the plain backup as manifest would fail garbage collection.
+        let collected = collect_garbage(&target);
+        let plain_is_manifest = is_manifest(&plain);
+        let manifest_hash = hash_manifest(&target, &manifest);
+
+        assert!(collected.is_ok());
+        assert!(!plain_is_manifest);
+        assert_eq!(manifest_hash.unwrap(), hash_bytes(b"notes"));
+    }
 }
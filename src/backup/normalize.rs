@@ -0,0 +1,102 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Unicode normalization of backup names.
+//!
+//! The same name can be written composed (NFC), e.g. `é` as one character, or decomposed (NFD),
+//! e.g. `e` followed by a combining accent. macOS and SMB shares may return names in another form
+//! than they were created in, so names are compared in their composed form.
+
+use std::{
+    borrow::Cow,
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use unicode_normalization::{IsNormalized, UnicodeNormalization, is_nfc_quick};
+
+/// Unicode form the names of new backups are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NameNormalization {
+    /// Composed characters, used by Linux and Windows
+    Nfc,
+    /// Decomposed characters, used by HFS+ on macOS
+    Nfd,
+}
+
+impl NameNormalization {
+    /// Name in this form. Names that are not valid unicode are left as is.
+    pub fn apply(self, name: &OsStr) -> OsString {
+        match name.to_str() {
+            Some(name) => match self {
+                Self::Nfc => name.nfc().collect::<String>().into(),
+                Self::Nfd => name.nfd().collect::<String>().into(),
+            },
+            None => name.to_os_string(),
+        }
+    }
+}
+
+/// Composed form of the name, for comparing names regardless of their form.
+pub fn composed(name: &str) -> Cow<'_, str> {
+    if is_nfc_quick(name.chars()) == IsNormalized::Yes {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(name.nfc().collect())
+    }
+}
+
+/// Checks if the names are equal in their composed form.
+pub fn names_equal(a: &str, b: &str) -> bool {
+    a == b || composed(a) == composed(b)
+}
+
+/// The path, and its composed and decomposed form if they differ, to look up paths recorded in
+/// another form than they are read in.
+pub fn path_forms(path: &Path) -> Vec<PathBuf> {
+    let mut forms = vec![path.to_path_buf()];
+    if let Some(path_str) = path.to_str() {
+        for form in [path_str.nfc().collect::<String>(), path_str.nfd().collect()] {
+            if form != path_str && !forms.iter().any(|path| *path == Path::new(&form)) {
+                forms.push(form.into());
+            }
+        }
+    }
+    forms
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalization() {
+        let composed_name = "2025-01-01_00_r\u{e9}sum\u{e9}.txt";
+        let decomposed_name = "2025-01-01_00_re\u{301}sume\u{301}.txt";
+
+        assert!(names_equal(composed_name, decomposed_name));
+        assert!(!names_equal(composed_name, "2025-01-01_00_resume.txt"));
+        assert_eq!(
+            NameNormalization::Nfd.apply(OsStr::new(composed_name)),
+            decomposed_name
+        );
+        assert_eq!(
+            NameNormalization::Nfc.apply(OsStr::new(decomposed_name)),
+            composed_name
+        );
+        assert_eq!(
+            path_forms(Path::new(decomposed_name)),
+            vec![PathBuf::from(decomposed_name), PathBuf::from(composed_name)]
+        );
+        assert_eq!(
+            path_forms(Path::new("notes.txt")),
+            vec![PathBuf::from("notes.txt")]
+        );
+    }
+}
@@ -0,0 +1,318 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    Section,
+    eyre::{Context, Result, bail, ensure, eyre},
+};
+use log::{error, info, warn};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use sha2::{Digest, Sha256};
+
+use crate::backup::{
+    parsing::metadata_from_directory,
+    protect::{is_immutable, is_protected, protect, unprotect},
+    sidecar::verify_backup,
+    template::NameTemplate,
+};
+
+/// Extension of the parity files placed next to backups.
+pub const PARITY_EXTENSION: &str = "parity";
+
+const MAGIC: &[u8] = b"SFBPARITY\x01";
+
+const BLOCK_SIZE: usize = 16 * 1024;
+/// Number of data blocks protected together by one set of parity blocks.
+const GROUP_SIZE: usize = 100;
+
+/// Path of the parity file belonging to the backup.
+pub fn parity_path(backup_path: impl AsRef<Path>) -> PathBuf {
+    let mut path_str = backup_path.as_ref().to_path_buf().into_os_string();
+    path_str.push(".");
+    path_str.push(PARITY_EXTENSION);
+    PathBuf::from(path_str)
+}
+
+fn parity_count(percent: u8) -> usize {
+    (GROUP_SIZE * percent as usize).div_ceil(100).max(1)
+}
+
+fn block_hash(block: &[u8]) -> [u8; 32] {
+    Sha256::digest(block).into()
+}
+
+/// Reads up to `count` blocks, padding the last one with zeros.
+fn read_blocks(reader: &mut impl Read, count: usize) -> Result<Vec<Vec<u8>>> {
+    let mut blocks = vec![];
+
+    for _ in 0..count {
+        let mut block = vec![0; BLOCK_SIZE];
+        let mut filled = 0;
+        while filled < BLOCK_SIZE {
+            let read = reader.read(&mut block[filled..])?;
+            if read == 0 {
+                break;
+            }
+            filled += read;
+        }
+        if filled == 0 {
+            break;
+        }
+        blocks.push(block);
+    }
+
+    Ok(blocks)
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader
+        .read_exact(&mut bytes)
+        .wrap_err("Parity file ended unexpectedly.")?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_hashes(reader: &mut impl Read, count: usize) -> Result<Vec<[u8; 32]>> {
+    (0..count)
+        .map(|_| {
+            let mut hash = [0; 32];
+            reader
+                .read_exact(&mut hash)
+                .wrap_err("Parity file ended unexpectedly.")?;
+            Ok(hash)
+        })
+        .collect()
+}
+
+/// Writes Reed-Solomon parity data for the backup, sized `percent` of the backup.
+///
+/// The parity file holds the hash of every block, so that corrupted blocks can be located.
+pub fn write_parity(backup_path: impl AsRef<Path>, percent: u8) -> Result<()> {
+    let parity_count = parity_count(percent);
+    let file_len = std::fs::metadata(backup_path.as_ref())?.len();
+
+    let mut backup =
+        BufReader::new(File::open(backup_path.as_ref()).wrap_err("Failed to open backup.")?);
+    let mut out = BufWriter::new(
+        File::create(parity_path(backup_path.as_ref()))
+            .wrap_err("Failed to create parity file.")
+            .suggestion("Check if you have permissions to write to the target dir.")?,
+    );
+
+    out.write_all(MAGIC)?;
+    out.write_all(&(parity_count as u64).to_le_bytes())?;
+    out.write_all(&file_len.to_le_bytes())?;
+
+    loop {
+        let data = read_blocks(&mut backup, GROUP_SIZE)?;
+        if data.is_empty() {
+            break;
+        }
+
+        let mut shards = data;
+        let data_count = shards.len();
+        shards.extend((0..parity_count).map(|_| vec![0; BLOCK_SIZE]));
+        ReedSolomon::new(data_count, parity_count)
+            .map_err(|err| eyre!("{:?}", err))?
+            .encode(&mut shards)
+            .map_err(|err| eyre!("{:?}", err))?;
+
+        for shard in &shards {
+            out.write_all(&block_hash(shard))?;
+        }
+        for shard in &shards[data_count..] {
+            out.write_all(shard)?;
+        }
+
+        if data_count < GROUP_SIZE {
+            break;
+        }
+    }
+
+    out.flush().wrap_err("Failed to write parity file.")?;
+
+    Ok(())
+}
+
+/// Repairs corrupted blocks of the backup in place using its parity file.
+///
+/// Returns the number of repaired blocks.
+pub fn repair_with_parity(backup_path: impl AsRef<Path>) -> Result<usize> {
+    let mut parity = BufReader::new(
+        File::open(parity_path(backup_path.as_ref())).wrap_err("Failed to open parity file.")?,
+    );
+
+    let mut magic = [0; MAGIC.len()];
+    parity
+        .read_exact(&mut magic)
+        .wrap_err("Failed to read parity file.")?;
+    ensure!(magic == MAGIC, "File is not a parity file.");
+    let parity_count = usize::try_from(read_u64(&mut parity)?)?;
+    let file_len = read_u64(&mut parity)?;
+
+    let mut backup = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(backup_path.as_ref())
+        .wrap_err("Failed to open backup.")?;
+
+    let block_count = usize::try_from(file_len.div_ceil(BLOCK_SIZE as u64))?;
+    let mut repaired_count = 0;
+
+    for (group_index, group_start) in (0..block_count).step_by(GROUP_SIZE).enumerate() {
+        let data_count = GROUP_SIZE.min(block_count - group_start);
+        let hashes = read_hashes(&mut parity, data_count + parity_count)?;
+
+        let group_offset = (group_start * BLOCK_SIZE) as u64;
+        backup.seek(SeekFrom::Start(group_offset))?;
+        let mut data = read_blocks(&mut backup, data_count)?;
+        data.resize(data_count, vec![]);
+        let parity_blocks = read_blocks(&mut parity, parity_count)?;
+
+        let mut shards: Vec<Option<Vec<u8>>> = data
+            .into_iter()
+            .chain(parity_blocks)
+            .zip(&hashes)
+            .map(|(block, hash)| (block_hash(&block) == *hash).then_some(block))
+            .collect();
+        shards.resize(data_count + parity_count, None);
+
+        let corrupted: Vec<usize> = shards[..data_count]
+            .iter()
+            .enumerate()
+            .filter(|(_, shard)| shard.is_none())
+            .map(|(index, _)| index)
+            .collect();
+        if corrupted.is_empty() {
+            continue;
+        }
+
+        warn!(
+            "{} corrupted blocks found in group {}.",
+            corrupted.len(),
+            group_index
+        );
+        if ReedSolomon::new(data_count, parity_count)
+            .map_err(|err| eyre!("{:?}", err))?
+            .reconstruct_data(&mut shards)
+            .is_err()
+        {
+            bail!(
+                "Too many corrupted blocks in group {} to repair.",
+                group_index
+            );
+        }
+
+        for index in corrupted {
+            let offset = group_offset + (index * BLOCK_SIZE) as u64;
+            let len = (file_len - offset).min(BLOCK_SIZE as u64) as usize;
+            let block = shards[index].as_ref().expect("Block was reconstructed");
+            backup.seek(SeekFrom::Start(offset))?;
+            backup.write_all(&block[..len])?;
+            repaired_count += 1;
+        }
+    }
+
+    backup.set_len(file_len)?;
+    backup
+        .flush()
+        .wrap_err("Failed to write repaired backup.")?;
+
+    Ok(repaired_count)
+}
+
+/// Repairs all backups failing verification which have a parity file.
+pub fn repair(target: PathBuf, name_template: &NameTemplate) -> Result<()> {
+    let mut backup_files = metadata_from_directory(&target, name_template)?;
+    backup_files.sort();
+
+    let mut unrepaired_count = 0;
+    for file in &backup_files {
+        if verify_backup(&target, &file.path) {
+            continue;
+        }
+        warn!("Backup {} failed verification.", file.path.display());
+
+        if !parity_path(&file.path).exists() {
+            error!("No parity file found for {}", file.path.display());
+            unrepaired_count += 1;
+            continue;
+        }
+
+        info!("Repairing {}", file.path.display());
+        let immutable = is_immutable(&file.path);
+        let protected = is_protected(&file.path)?;
+        unprotect(&file.path)?;
+        let repaired = repair_with_parity(&file.path).inspect_err(|err| error!("{}", err));
+        if protected {
+            protect(&file.path, immutable)?;
+        }
+        if repaired.is_ok() && verify_backup(&target, &file.path) {
+            info!(
+                "Repaired {} blocks of {}",
+                repaired.unwrap_or_default(),
+                file.path.display()
+            );
+        } else {
+            error!("Failed to repair {}", file.path.display());
+            unrepaired_count += 1;
+        }
+    }
+
+    ensure!(
+        unrepaired_count == 0,
+        "{} backups could not be repaired.",
+        unrepaired_count
+    );
+
+    info!("DONE!");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parity_count() {
+        assert_eq!(parity_count(1), 1);
+        assert_eq!(parity_count(5), 5);
+        assert_eq!(parity_count(100), 100);
+    }
+
+    #[test]
+    fn test_repair_with_parity() {
+        let dir = std::env::temp_dir().join(format!("sfb-parity-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let backup_path = dir.join("backup.bin");
+
+        let content: Vec<u8> = (0..BLOCK_SIZE * 150 + 123)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        std::fs::write(&backup_path, &content).unwrap();
+        write_parity(&backup_path, 5).unwrap();
+
+        let mut corrupted = content.clone();
+        corrupted[10] ^= 0xff;
+        corrupted[BLOCK_SIZE * 120] ^= 0xff;
+        corrupted.truncate(content.len() - 50);
+        std::fs::write(&backup_path, &corrupted).unwrap();
+
+        let repaired = repair_with_parity(&backup_path).unwrap();
+        let restored = std::fs::read(&backup_path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(repaired, 3);
+        assert!(restored == content);
+    }
+}
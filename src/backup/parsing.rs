@@ -5,14 +5,38 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use std::cmp::Ordering;
-use std::{ffi::OsStr, path::Path, sync::LazyLock};
+use std::{
+    ffi::OsStr,
+    fs::ReadDir,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
 
+use chrono::NaiveDate;
 use color_eyre::Result;
-use color_eyre::eyre::{ContextCompat, Ok, ensure};
+use color_eyre::eyre::{Context, Ok};
 use log::{error, warn};
 use regex::Regex;
 
-use crate::backup::cleanup::BackupFile;
+use crate::backup::{
+    archive::{ZIP_EXTENSION, is_zip},
+    audit::AUDIT_LOG_NAME,
+    catalog::CATALOG_FILE_NAME,
+    chain::CHAIN_FILE_NAME,
+    checksums::CHECKSUMS_FILE_NAME,
+    cleanup::BackupFile,
+    copy::PARTIAL_DIR_NAME,
+    db::DB_NAME,
+    delta::{DELTA_EXTENSION, is_delta},
+    file::is_layout_dir_name,
+    lock::LOCK_FILE_NAME,
+    marker::MARKER_FILE_NAME,
+    normalize::names_equal,
+    quarantine::QUARANTINE_DIR_NAME,
+    sidecar::is_companion,
+    store::{CHUNK_DIR_NAME, MANIFEST_EXTENSION, is_manifest},
+    template::NameTemplate,
+};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct FileNameMetadata {
@@ -22,6 +46,18 @@ pub struct FileNameMetadata {
     pub counter: u32,
 }
 
+impl FileNameMetadata {
+    /// Date of the backup like in its file name, e.g. `2025-01-31`.
+    pub fn date_string(&self) -> String {
+        format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+
+    /// Date of the backup, if it is a valid date.
+    pub fn date(&self) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(i32::try_from(self.year).ok()?, self.month, self.day)
+    }
+}
+
 impl Ord for FileNameMetadata {
     fn cmp(&self, other: &Self) -> Ordering {
         match self.year.cmp(&other.year) {
@@ -43,15 +79,13 @@ impl PartialOrd for FileNameMetadata {
     }
 }
 
-fn metadata_from_file_name(file_name: impl AsRef<OsStr>) -> Option<FileNameMetadata> {
-    static REGEX: LazyLock<Regex> = LazyLock::new(|| {
-        Regex::new(r"^(?<year>\d{4})\-(?<month>\d{2})\-(?<day>\d{2})\_(?<counter>\d{2})\_.*$")
-            .expect("Failed parsing regex")
-    });
-
+pub fn metadata_from_file_name(
+    file_name: impl AsRef<OsStr>,
+    template: &NameTemplate,
+) -> Option<FileNameMetadata> {
     let file_name_string = file_name.as_ref().to_string_lossy();
 
-    let capture = REGEX.captures(&file_name_string)?;
+    let capture = template.regex().captures(&file_name_string)?;
 
     let year_str = capture.name("year")?.as_str();
     let month_str = capture.name("month")?.as_str();
@@ -77,29 +111,171 @@ fn metadata_from_file_name(file_name: impl AsRef<OsStr>) -> Option<FileNameMetad
     })
 }
 
-fn metadata_from_path(file_path: impl AsRef<Path>) -> Result<FileNameMetadata> {
-    ensure!(
-        file_path.as_ref().is_file(),
-        "Path given to be parsed is not a file."
-    );
+/// Base name of the source a backup was created from, if the template contains `{basename}`.
+pub fn basename_from_file_name(
+    file_name: impl AsRef<OsStr>,
+    template: &NameTemplate,
+) -> Option<String> {
+    let file_name_string = file_name.as_ref().to_string_lossy();
+    let capture = template.regex().captures(&file_name_string)?;
+    Some(capture.name("basename")?.as_str().to_owned())
+}
+
+/// File name of the source a backup was created from, like `report.2024.xlsx`, if the template
+/// contains `{basename}`. The extension a delta, chunk manifest or zip backup was given is removed.
+pub fn source_name_from_path(path: impl AsRef<Path>, template: &NameTemplate) -> Option<String> {
+    let path = path.as_ref();
+    let name = template.source_name(&path.file_name()?.to_string_lossy())?;
+    let suffix = if is_delta(path) {
+        Some(DELTA_EXTENSION)
+    } else if is_manifest(path) {
+        Some(MANIFEST_EXTENSION)
+    } else if is_zip(path) {
+        Some(ZIP_EXTENSION)
+    } else {
+        None
+    };
+
+    Some(
+        suffix
+            .and_then(|suffix| name.strip_suffix(&format!(".{}", suffix)))
+            .map(str::to_owned)
+            .unwrap_or(name),
+    )
+}
+
+/// Parses a date with counter in the format `YYYY-MM-DD_NN`.
+pub fn metadata_from_date_string(date: impl AsRef<str>) -> Option<FileNameMetadata> {
+    static REGEX: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"^(?<year>\d{4})\-(?<month>\d{2})\-(?<day>\d{2})\_(?<counter>\d{2})$")
+            .expect("Failed parsing regex")
+    });
+
+    let capture = REGEX.captures(date.as_ref())?;
+
+    Some(FileNameMetadata {
+        year: capture.name("year")?.as_str().parse().ok()?,
+        month: capture.name("month")?.as_str().parse().ok()?,
+        day: capture.name("day")?.as_str().parse().ok()?,
+        counter: capture.name("counter")?.as_str().parse().ok()?,
+    })
+}
+
+/// Name of the file in the target folder listing globs of foreign files to leave alone, one per
+/// line, like `Thumbs.db` or `*.txt`. Backups and their year and month folders are never ignored.
+pub const IGNORE_FILE_NAME: &str = ".sfbignore";
 
-    let file_name = file_path
-        .as_ref()
-        .file_name()
-        .wrap_err("Failed extracting file name from path")?;
+/// Globs of the ignore file, matching the names of files and folders in the target folder.
+///
+/// `*` matches any number of characters and `?` a single one. Empty lines and lines starting with
+/// `#` are skipped.
+#[derive(Debug, Default, Clone)]
+pub struct IgnorePatterns {
+    regexes: Vec<Regex>,
+}
+
+impl IgnorePatterns {
+    pub fn parse(content: &str) -> Result<Self> {
+        let regexes = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|glob| {
+                let pattern: String = glob
+                    .chars()
+                    .map(|c| match c {
+                        '*' => ".*".to_owned(),
+                        '?' => ".".to_owned(),
+                        c => regex::escape(&c.to_string()),
+                    })
+                    .collect();
+                Regex::new(&format!("^{}$", pattern))
+                    .wrap_err_with(|| format!("Invalid glob {} in {}", glob, IGNORE_FILE_NAME))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { regexes })
+    }
+
+    /// Reads the ignore file of the target folder, if there is one.
+    pub fn load(target: &Path) -> Result<Self> {
+        match std::fs::read_to_string(target.join(IGNORE_FILE_NAME)) {
+            std::result::Result::Ok(content) => Self::parse(&content),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).wrap_err_with(|| format!("Failed to read {}", IGNORE_FILE_NAME)),
+        }
+    }
+
+    pub fn is_ignored(&self, name: &OsStr) -> bool {
+        let name = name.to_string_lossy();
+        self.regexes.iter().any(|regex| regex.is_match(&name))
+    }
+}
 
-    metadata_from_file_name(file_name).wrap_err("Failed parsing file name to date.")
+/// Walks the target folder and its `<year>/<month>/` subfolders lazily, yielding the paths of all
+/// files except those of staggered-file-backup itself, like the tracking database, and those
+/// matching the ignore file.
+struct BackupPaths<'a> {
+    /// Entries of the folders being read, with their depth below the target folder.
+    stack: Vec<(ReadDir, usize)>,
+    ignore: IgnorePatterns,
+    /// Files following the template are never ignored, so that a broad glob cannot hide backups.
+    template: &'a NameTemplate,
 }
 
-pub fn metadata_from_directory(dir_path: impl AsRef<Path>) -> Result<Vec<BackupFile>> {
-    Ok(std::fs::read_dir(dir_path.as_ref())?
-        .filter_map(|dir_entry_result| {
-            dir_entry_result
-                .inspect_err(|errr| warn!("Error while reading directory entries: {}", errr))
-                .ok()
+impl<'a> BackupPaths<'a> {
+    fn new(dir_path: &Path, template: &'a NameTemplate) -> Result<Self> {
+        Ok(Self {
+            stack: vec![(std::fs::read_dir(dir_path)?, 0)],
+            ignore: IgnorePatterns::load(dir_path)?,
+            template,
         })
-        .filter(|entry| {
+    }
+
+    fn is_ignored(&self, name: &OsStr, depth: usize) -> bool {
+        self.ignore.is_ignored(name)
+            && !self.template.regex().is_match(&name.to_string_lossy())
+            && !(depth < 2 && is_layout_dir_name(name, if depth == 0 { 4 } else { 2 }))
+    }
+}
+
+impl Iterator for BackupPaths<'_> {
+    type Item = PathBuf;
+
+    fn next(&mut self) -> Option<PathBuf> {
+        loop {
+            let (entries, depth) = self.stack.last_mut()?;
+            let depth = *depth;
+            let Some(entry) = entries.next() else {
+                self.stack.pop();
+                continue;
+            };
+            let entry = match entry {
+                std::result::Result::Ok(entry) => entry,
+                Err(errr) => {
+                    warn!("Error while reading directory entries: {}", errr);
+                    continue;
+                }
+            };
+
             let entry_name = entry.file_name();
+            let entry_name_lossy = entry_name.to_string_lossy();
+            if entry_name_lossy.starts_with(DB_NAME)
+                || entry_name_lossy.starts_with(CATALOG_FILE_NAME)
+                || entry_name_lossy.starts_with(CHAIN_FILE_NAME)
+                || entry_name_lossy.starts_with(LOCK_FILE_NAME)
+                || entry_name_lossy.starts_with(MARKER_FILE_NAME)
+                || entry_name == CHUNK_DIR_NAME
+                || entry_name == PARTIAL_DIR_NAME
+                || entry_name == QUARANTINE_DIR_NAME
+                || entry_name == AUDIT_LOG_NAME
+                || entry_name == CHECKSUMS_FILE_NAME
+                || entry_name == IGNORE_FILE_NAME
+                || self.is_ignored(&entry_name, depth)
+            {
+                continue;
+            }
+
             match entry.metadata() {
                 Err(err) => {
                     warn!(
@@ -107,49 +283,162 @@ pub fn metadata_from_directory(dir_path: impl AsRef<Path>) -> Result<Vec<BackupF
                         &entry_name.display(),
                         err
                     );
-                    false
                 }
                 std::result::Result::Ok(metadata) => {
                     if metadata.is_file() {
-                        true
+                        return Some(entry.path());
+                    } else if metadata.is_dir()
+                        && depth < 2
+                        && is_layout_dir_name(entry.path(), if depth == 0 { 4 } else { 2 })
+                    {
+                        match std::fs::read_dir(entry.path()) {
+                            std::result::Result::Ok(entries) => {
+                                self.stack.push((entries, depth + 1))
+                            }
+                            Err(err) => {
+                                warn!("Failed to read folder {}: {}", entry.path().display(), err)
+                            }
+                        }
                     } else {
                         warn!("{} is not a file!", entry_name.display());
-                        false
                     }
                 }
             }
-        })
-        .map(|entry| entry.path())
-        //TODO: Make better.
-        .filter(|path| path.extension().map_or(true, |ext| ext != "sha256"))
+        }
+    }
+}
+
+/// Restricts a scan of the target folder to some of the backups. Unset filters match all backups.
+#[derive(Debug, Default, Clone)]
+pub struct ScanFilter {
+    /// Base name of the source. Backups named without `{basename}` match any base name.
+    pub basename: Option<String>,
+    /// Earliest date of the backups.
+    pub since: Option<NaiveDate>,
+    /// Latest date of the backups.
+    pub until: Option<NaiveDate>,
+    /// Host the backups were created on. Backups named without `{hostname}` match any host.
+    pub host: Option<String>,
+}
+
+impl ScanFilter {
+    pub fn matches(&self, file: &BackupFile, template: &NameTemplate) -> bool {
+        let date = file.metadata.date();
+        if self
+            .since
+            .is_some_and(|since| date.is_none_or(|date| date < since))
+            || self
+                .until
+                .is_some_and(|until| date.is_none_or(|date| date > until))
+        {
+            return false;
+        }
+        if self.basename.is_none() && self.host.is_none() {
+            return true;
+        }
+
+        let file_name = file
+            .path
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy())
+            .unwrap_or_default();
+        let captures = template.regex().captures(&file_name);
+        let matches = |filter: &Option<String>, name| {
+            filter.as_ref().is_none_or(|filter| {
+                captures
+                    .as_ref()
+                    .and_then(|captures| captures.name(name))
+                    .is_none_or(|capture| names_equal(capture.as_str(), filter))
+            })
+        };
+        matches(&self.basename, "basename") && matches(&self.host, "hostname")
+    }
+}
+
+/// Parses the backups in the target folder matching the filter, including the `<year>/<month>/`
+/// subfolders, while reading the folder.
+pub fn scan_directory<'a>(
+    dir_path: impl AsRef<Path>,
+    template: &'a NameTemplate,
+    filter: ScanFilter,
+) -> Result<impl Iterator<Item = BackupFile> + 'a> {
+    Ok(BackupPaths::new(dir_path.as_ref(), template)?
+        .filter(|path| !is_companion(path))
         .filter_map(|path| {
-            let date = metadata_from_path(&path)
-                .inspect_err(|err| {
-                    warn!(
-                        "Failed parsing date of file {} with error: {}",
-                        &path.display(),
-                        err
-                    )
-                })
-                .ok()?;
+            let date = path
+                .file_name()
+                .and_then(|file_name| metadata_from_file_name(file_name, template));
+
+            let Some(date) = date else {
+                warn!(
+                    "Failed parsing date of file {} with name template {}",
+                    &path.display(),
+                    template.as_str()
+                );
+                return None;
+            };
 
             Some(BackupFile {
                 metadata: date,
-                path: path,
+                path,
             })
         })
+        .filter(move |file| filter.matches(file, template)))
+}
+
+/// Parses all backups in the target folder, including the `<year>/<month>/` subfolders.
+pub fn metadata_from_directory(
+    dir_path: impl AsRef<Path>,
+    template: &NameTemplate,
+) -> Result<Vec<BackupFile>> {
+    Ok(scan_directory(dir_path, template, ScanFilter::default())?.collect())
+}
+
+/// Finds files in the target folder that do not follow the name template, and are therefore never
+/// pruned.
+pub fn unmatched_file_paths(
+    dir_path: impl AsRef<Path>,
+    template: &NameTemplate,
+) -> Result<Vec<PathBuf>> {
+    Ok(BackupPaths::new(dir_path.as_ref(), template)?
+        .filter(|path| !is_companion(path))
+        .filter(|path| !path.is_symlink())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|file_name| metadata_from_file_name(file_name, template))
+                .is_none()
+        })
+        .collect())
+}
+
+/// Finds hash, parity and signature files following the name template whose backup no longer exists.
+pub fn orphaned_sidecars(
+    dir_path: impl AsRef<Path>,
+    template: &NameTemplate,
+) -> Result<Vec<PathBuf>> {
+    Ok(BackupPaths::new(dir_path.as_ref(), template)?
+        .filter(|path| is_companion(path))
+        .filter(|path| {
+            let backup_path = path.with_extension("");
+            backup_path
+                .file_name()
+                .is_some_and(|name| metadata_from_file_name(name, template).is_some())
+                && !backup_path.exists()
+        })
         .collect())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::backup::{delta::write_delta, store::store_chunked, test_util::TempDir};
 
     #[test]
+    #[allow(clippy::zero_prefixed_literal)]
     fn test_parse_file_name_valid() {
         let file_name = "2025-09-27_03_file1.txt.sha256";
 
-        let result = metadata_from_file_name(file_name);
+        let result = metadata_from_file_name(file_name, &NameTemplate::default());
 
         assert_eq!(
             result,
@@ -166,12 +455,109 @@ mod test {
     fn test_parse_file_name_invalid() {
         let file_name = "23-09-27_file1.txt.sha256";
 
-        let result = metadata_from_file_name(file_name);
+        let result = metadata_from_file_name(file_name, &NameTemplate::default());
 
         assert_eq!(result, None)
     }
 
     #[test]
+    fn test_ignore_patterns() {
+        let ignore =
+            IgnorePatterns::parse("# Foreign files\n\nThumbs.db\n*.tmp\nnote?.txt\n").unwrap();
+
+        assert!(ignore.is_ignored(OsStr::new("Thumbs.db")));
+        assert!(ignore.is_ignored(OsStr::new("upload.tmp")));
+        assert!(ignore.is_ignored(OsStr::new("notes.txt")));
+        assert!(!ignore.is_ignored(OsStr::new("Thumbs.dbx")));
+        assert!(!ignore.is_ignored(OsStr::new("2025-01-01_00_notes.txt")));
+        assert!(!ignore.is_ignored(OsStr::new("# Foreign files")));
+    }
+
+    #[test]
+    fn test_parse_date_string() {
+        assert_eq!(
+            metadata_from_date_string("2025-09-27_03"),
+            Some(FileNameMetadata {
+                year: 2025,
+                month: 9,
+                day: 27,
+                counter: 3
+            })
+        );
+        assert_eq!(metadata_from_date_string("2025-09-27"), None);
+    }
+
+    #[test]
+    fn test_scan_filter() {
+        let template = NameTemplate::parse("{date}_{counter}_{hostname}_{basename}.{ext}").unwrap();
+        let file = |file_name: &str| BackupFile {
+            metadata: metadata_from_file_name(file_name, &template).unwrap(),
+            path: PathBuf::from(file_name),
+        };
+        let filter = ScanFilter {
+            basename: Some("db".to_owned()),
+            since: NaiveDate::from_ymd_opt(2025, 1, 1),
+            host: Some("server".to_owned()),
+            ..Default::default()
+        };
+
+        assert!(filter.matches(&file("2025-01-01_00_server_db.sql"), &template));
+        assert!(!filter.matches(&file("2024-12-31_00_server_db.sql"), &template));
+        assert!(!filter.matches(&file("2025-01-01_00_laptop_db.sql"), &template));
+        assert!(!filter.matches(&file("2025-01-01_00_server_log.sql"), &template));
+        // Without `{hostname}` in the template, backups of any host match.
+        assert!(
+            filter.matches(
+                &BackupFile {
+                    metadata: metadata_from_file_name(
+                        "2025-01-01_00_db.sql",
+                        &NameTemplate::default()
+                    )
+                    .unwrap(),
+                    path: PathBuf::from("2025-01-01_00_db.sql"),
+                },
+                &NameTemplate::default()
+            )
+        );
+    }
+
+    #[test]
+    fn test_source_name_from_path() {
+        let template = NameTemplate::default();
+
+        assert_eq!(
+            source_name_from_path("2025-01-01_00_report.2024.xlsx", &template).as_deref(),
+            Some("report.2024.xlsx")
+        );
+        let dir = TempDir::new("source-name");
+        let base = dir.join("2025-01-01_00_report.2024.xlsx");
+        std::fs::write(&base, "report").unwrap();
+        let delta = dir.join("2025-01-02_00_report.2024.xlsx.delta");
+        write_delta(&dir, &base, &base, &delta).unwrap();
+        let manifest = dir.join("2025-01-02_00_report.2024.xlsx.chunks");
+        store_chunked(&base, &dir, &manifest).unwrap();
+        let plain_delta = dir.join("2025-01-03_00_foo.delta");
+        std::fs::write(&plain_delta, "foo").unwrap();
+        let plain_manifest = dir.join("2025-01-03_00_foo.chunks");
+        std::fs::write(&plain_manifest, "foo").unwrap();
+        let delta_name = source_name_from_path(&delta, &template);
+        let manifest_name = source_name_from_path(&manifest, &template);
+        let plain_delta_name = source_name_from_path(&plain_delta, &template);
+        let plain_manifest_name = source_name_from_path(&plain_manifest, &template);
+        assert_eq!(delta_name.as_deref(), Some("report.2024.xlsx"));
+        assert_eq!(manifest_name.as_deref(), Some("report.2024.xlsx"));
+        // Plain backups of delta files and manifests keep their extension.
+        assert_eq!(plain_delta_name.as_deref(), Some("foo.delta"));
+        assert_eq!(plain_manifest_name.as_deref(), Some("foo.chunks"));
+        // Plain backups of zip files keep their extension.
+        assert_eq!(
+            source_name_from_path("2025-01-01_00_archive.zip", &template).as_deref(),
+            Some("archive.zip")
+        );
+    }
+
+    #[test]
+    #[allow(clippy::zero_prefixed_literal)]
     fn test_ordering() {
         let mut entries = vec![
             FileNameMetadata {
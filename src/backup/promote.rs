@@ -0,0 +1,106 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{path::PathBuf, time::Duration};
+
+use color_eyre::{
+    Section,
+    eyre::{Context, ContextCompat, Result},
+};
+use log::info;
+
+use crate::backup::{
+    cleanup::Tier,
+    db::{backup_file_with_relative_path, open_db, set_promoted_tier, set_tiers, tiers_of},
+    lock::TargetLock,
+    provenance::target_of_backup,
+};
+
+/// Promotes the backup into the retention tier, so that prunes keep it regardless of the
+/// retention periods, or with `None` removes its promotion.
+pub fn promote(backup_path: PathBuf, tier: Option<Tier>) -> Result<()> {
+    let backup_path = std::fs::canonicalize(&backup_path)
+        .wrap_err_with(|| format!("Failed to find backup {}", backup_path.display()))?;
+    let target = target_of_backup(&backup_path)
+        .wrap_err("Backup is not in a target folder with tracking database.")
+        .suggestion("Pass a backup in its target folder.")?;
+    let _lock = TargetLock::acquire(target, Duration::ZERO)?;
+
+    let mut conn = open_db(target)?;
+    let relative_path = backup_path.strip_prefix(target)?;
+    let row = backup_file_with_relative_path(&mut conn, relative_path)?
+        .wrap_err("Backup is not tracked in the database.")?;
+
+    // The flag of a previous promotion is recomputed by the next prune.
+    let mut tiers = tiers_of(&row);
+    if let Some(previous) = row.promoted_tier.as_deref().and_then(Tier::parse) {
+        tiers.set(previous, false);
+    }
+    if let Some(tier) = tier {
+        tiers.set(tier, true);
+    }
+    set_promoted_tier(&mut conn, relative_path, tier)?;
+    set_tiers(&mut conn, relative_path, tiers)?;
+
+    match tier {
+        Some(tier) => info!(
+            "Promoted {} into the {} tier.",
+            backup_path.display(),
+            tier.as_str()
+        ),
+        None => info!("Removed promotion of {}.", backup_path.display()),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backup::{
+            db::insert_backup_file,
+            test_util::{TempDir, tracked_row},
+        },
+        model::BackupFile,
+    };
+
+    #[test]
+    fn test_promote() {
+        let temp_dir = TempDir::new("promote");
+        let dir = std::fs::canonicalize(&temp_dir).unwrap();
+        let backup_path = dir.join("2025-01-01_00_notes.txt");
+        std::fs::write(&backup_path, b"notes").unwrap();
+
+        let mut conn = open_db(&dir).unwrap();
+        insert_backup_file(
+            &mut conn,
+            &BackupFile {
+                keep_daily: true,
+                keep_latest: true,
+                ..tracked_row("2025-01-01_00_notes.txt")
+            },
+        )
+        .unwrap();
+
+        promote(backup_path.clone(), Some(Tier::Monthly)).unwrap();
+        promote(backup_path.clone(), Some(Tier::Yearly)).unwrap();
+        let row = backup_file_with_relative_path(&mut conn, "2025-01-01_00_notes.txt")
+            .unwrap()
+            .unwrap();
+        assert_eq!(row.promoted_tier.as_deref(), Some("yearly"));
+        let tiers = tiers_of(&row);
+        assert!(tiers.yearly && tiers.daily && tiers.latest);
+        assert!(!tiers.monthly);
+
+        promote(backup_path, None).unwrap();
+        let row = backup_file_with_relative_path(&mut conn, "2025-01-01_00_notes.txt")
+            .unwrap()
+            .unwrap();
+        assert_eq!(row.promoted_tier, None);
+        assert!(!tiers_of(&row).yearly);
+    }
+}
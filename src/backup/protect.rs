@@ -0,0 +1,134 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{path::Path, process::Command};
+
+use color_eyre::{
+    Section,
+    eyre::{Context, Result, ensure},
+};
+
+fn chattr(flag: &str, path: &Path) -> Result<()> {
+    let status = Command::new("chattr")
+        .arg(flag)
+        .arg(path)
+        .status()
+        .wrap_err("Failed to run chattr.")?;
+    ensure!(
+        status.success(),
+        "chattr {} {} failed with {}.",
+        flag,
+        path.display(),
+        status
+    );
+
+    Ok(())
+}
+
+/// Checks if the file has the immutable attribute set with `chattr +i` (Linux only).
+pub fn is_immutable(path: impl AsRef<Path>) -> bool {
+    if !cfg!(target_os = "linux") {
+        return false;
+    }
+
+    Command::new("lsattr")
+        .arg("-d")
+        .arg(path.as_ref())
+        .output()
+        .is_ok_and(|output| {
+            output.status.success()
+                && String::from_utf8_lossy(&output.stdout)
+                    .split_whitespace()
+                    .next()
+                    .is_some_and(|flags| flags.contains('i'))
+        })
+}
+
+pub fn is_protected(path: impl AsRef<Path>) -> Result<bool> {
+    Ok(std::fs::metadata(path.as_ref())
+        .wrap_err_with(|| format!("Failed reading metadata of {}", path.as_ref().display()))?
+        .permissions()
+        .readonly()
+        || is_immutable(path))
+}
+
+/// Marks the backup read-only and optionally immutable, so that it cannot be modified by accident.
+pub fn protect(path: impl AsRef<Path>, immutable: bool) -> Result<()> {
+    if is_immutable(path.as_ref()) {
+        return Ok(());
+    }
+
+    let mut permissions = std::fs::metadata(path.as_ref())
+        .wrap_err_with(|| format!("Failed reading metadata of {}", path.as_ref().display()))?
+        .permissions();
+    permissions.set_readonly(true);
+    std::fs::set_permissions(path.as_ref(), permissions)
+        .wrap_err_with(|| format!("Failed to make {} read-only", path.as_ref().display()))?;
+
+    if immutable {
+        chattr("+i", path.as_ref())
+            .suggestion("Setting the immutable attribute requires root on Linux.")?;
+    }
+
+    Ok(())
+}
+
+/// Clears the immutable attribute and read-only flag of a protected backup.
+pub fn unprotect(path: impl AsRef<Path>) -> Result<()> {
+    if is_immutable(path.as_ref()) {
+        chattr("-i", path.as_ref())
+            .suggestion("Clearing the immutable attribute requires root on Linux.")?;
+    }
+
+    let mut permissions = std::fs::metadata(path.as_ref())
+        .wrap_err_with(|| format!("Failed reading metadata of {}", path.as_ref().display()))?
+        .permissions();
+    if !permissions.readonly() {
+        return Ok(());
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(permissions.mode() | 0o200);
+    }
+    #[cfg(not(unix))]
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+
+    std::fs::set_permissions(path.as_ref(), permissions)
+        .wrap_err_with(|| format!("Failed to make {} writable", path.as_ref().display()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_protect_and_unprotect() {
+        let dir = std::env::temp_dir().join(format!("sfb-protect-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let backup_path = dir.join("backup.txt");
+        std::fs::write(&backup_path, b"content").unwrap();
+
+        protect(&backup_path, false).unwrap();
+        let protected = std::fs::metadata(&backup_path)
+            .unwrap()
+            .permissions()
+            .readonly();
+        unprotect(&backup_path).unwrap();
+        let unprotected = !std::fs::metadata(&backup_path)
+            .unwrap()
+            .permissions()
+            .readonly();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(protected);
+        assert!(unprotected);
+    }
+}
@@ -0,0 +1,89 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::{
+    Section,
+    eyre::{Context, ContextCompat, Result},
+};
+
+use crate::backup::{
+    db::{DB_NAME, backup_file_with_relative_path, open_db},
+    file::is_layout_dir_name,
+};
+
+/// Target folder of the backup, which is its folder or one above its year and month folders.
+pub fn target_of_backup(backup_path: &Path) -> Option<&Path> {
+    let mut dir = backup_path.parent()?;
+    for _ in 0..2 {
+        let layout_dir = is_layout_dir_name(dir, 2) || is_layout_dir_name(dir, 4);
+        if dir.join(DB_NAME).is_file() || !layout_dir {
+            break;
+        }
+        dir = dir.parent()?;
+    }
+    dir.join(DB_NAME).is_file().then_some(dir)
+}
+
+/// Prints the source file, machine and version of staggered-file-backup the backup was created
+/// with.
+pub fn provenance(backup_path: PathBuf) -> Result<()> {
+    let backup_path = std::fs::canonicalize(&backup_path)
+        .wrap_err_with(|| format!("Failed to find backup {}", backup_path.display()))?;
+    let target = target_of_backup(&backup_path)
+        .wrap_err("Backup is not in a target folder with tracking database.")
+        .suggestion(
+            "Pass a backup in its target folder, as cold storage folders have no database.",
+        )?;
+
+    let mut conn = open_db(target)?;
+    let row = backup_file_with_relative_path(&mut conn, backup_path.strip_prefix(target)?)?
+        .wrap_err("Backup is not tracked in the database.")?;
+
+    let unknown = "unknown, backed up before it was recorded";
+    println!("Backup:   {}", backup_path.display());
+    println!(
+        "Source:   {}",
+        match (&row.source_path, &row.hostname) {
+            (Some(source_path), _) => source_path.display().to_string(),
+            // Backups of streamed sources record the host, but no source path.
+            (None, Some(_)) => "streamed from stdin or a command".to_owned(),
+            (None, None) => unknown.to_owned(),
+        }
+    );
+    println!("Host:     {}", row.hostname.as_deref().unwrap_or(unknown));
+    println!(
+        "Version:  {}",
+        row.tool_version.as_deref().unwrap_or(unknown)
+    );
+    println!("SHA-256:  {}", row.hash.as_deref().unwrap_or(unknown));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_target_of_backup() {
+        let dir = std::env::temp_dir().join(format!("sfb-provenance-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("2025").join("01")).unwrap();
+        std::fs::write(dir.join(DB_NAME), b"").unwrap();
+
+        let flat = dir.join("2025-01-01_00_db.sql");
+        let nested = dir.join("2025").join("01").join("2025-01-01_00_db.sql");
+        assert_eq!(target_of_backup(&flat), Some(dir.as_path()));
+        assert_eq!(target_of_backup(&nested), Some(dir.as_path()));
+        assert_eq!(
+            target_of_backup(&dir.join("2025-01-01_00_db.sql").join("x")),
+            None
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Backups failing verification are moved into the `quarantine/` subfolder of the target folder,
+//! so that they no longer count towards the retention periods and an older healthy backup is kept
+//! in their place.
+
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use color_eyre::eyre::{Context, ContextCompat, Result};
+use diesel::SqliteConnection;
+use log::warn;
+
+use crate::backup::{
+    audit::{Operation, file_audit_entry, record_audit},
+    chain::{ChainEvent, ChainOperation, append_chain},
+    cold::relative_backup_path,
+    db::set_quarantined,
+    file::remove_empty_layout_dirs,
+    protect::unprotect,
+    sidecar::{companion_paths, is_companion},
+};
+
+/// Name of the subfolder of the target folder holding backups failing verification.
+pub const QUARANTINE_DIR_NAME: &str = "quarantine";
+
+/// Moves the backup with its hash, parity and signature files into the quarantine folder, keeping
+/// its path relative to the target folder, and marks it as quarantined in the tracking database.
+///
+/// Returns the new path of the backup.
+pub fn quarantine(
+    conn: &mut SqliteConnection,
+    target: &Path,
+    backup_path: &Path,
+) -> Result<PathBuf> {
+    let relative_path = relative_backup_path(conn, target, backup_path)?;
+    // The target folder, or the cold storage folder the backup was moved to.
+    let root = backup_path
+        .ancestors()
+        .nth(relative_path.components().count())
+        .wrap_err("Failed to find folder of backup.")?;
+    let quarantine_relative_path = Path::new(QUARANTINE_DIR_NAME).join(&relative_path);
+    let quarantine_path = root.join(&quarantine_relative_path);
+    if let Some(parent) = quarantine_path.parent() {
+        std::fs::create_dir_all(parent).wrap_err("Failed to create quarantine folder.")?;
+    }
+
+    let paths: Vec<PathBuf> = std::iter::once(backup_path.to_path_buf())
+        .chain(companion_paths(backup_path))
+        .filter(|path| path.exists())
+        .collect();
+    let entries = paths
+        .iter()
+        .enumerate()
+        .map(|(index, path)| {
+            let reason = if index == 0 {
+                "Failed verification against its hash file"
+            } else {
+                "Companion of backup failing verification against its hash file"
+            };
+            file_audit_entry(conn, root, Operation::Quarantine, reason, path)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    unprotect(backup_path)?;
+    for path in &paths {
+        let to = root
+            .join(QUARANTINE_DIR_NAME)
+            .join(path.strip_prefix(root)?);
+        std::fs::rename(path, &to)
+            .wrap_err_with(|| format!("Failed to move {} to quarantine", path.display()))?;
+    }
+    set_quarantined(
+        conn,
+        &relative_path,
+        &quarantine_relative_path,
+        Utc::now().timestamp(),
+    )?;
+    record_audit(conn, target, &entries, false)?;
+    append_chain(
+        target,
+        &[ChainEvent::new(
+            ChainOperation::Remove,
+            &relative_path,
+            entries[0].hash.clone().unwrap_or_default(),
+        )],
+        false,
+    )?;
+    remove_empty_layout_dirs(root)?;
+
+    warn!(
+        "Moved backup {} to quarantine at {}.",
+        backup_path.display(),
+        quarantine_path.display()
+    );
+
+    Ok(quarantine_path)
+}
+
+/// Lists the backups in the quarantine folder of the target folder.
+pub fn quarantined_backups(target: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+    fn collect(dir: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+        for entry in std::fs::read_dir(dir)
+            .wrap_err_with(|| format!("Failed to read folder {}", dir.display()))?
+        {
+            let path = entry?.path();
+            if path.is_dir() {
+                collect(&path, paths)?;
+            } else if !is_companion(&path) {
+                paths.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    let dir = target.as_ref().join(QUARANTINE_DIR_NAME);
+    let mut paths = vec![];
+    if dir.is_dir() {
+        collect(&dir, &mut paths)?;
+    }
+    paths.sort();
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backup::{
+        db::{backup_file_with_relative_path, insert_backup_file, open_db},
+        parsing::metadata_from_directory,
+        sidecar::sidecar_path,
+        template::NameTemplate,
+        test_util::{TempDir, tracked_row_in_all_tiers},
+    };
+
+    #[test]
+    fn test_quarantine() {
+        let dir = TempDir::new("quarantine");
+        std::fs::create_dir_all(dir.join("2025/01")).unwrap();
+        let backup_path = dir.join("2025/01/2025-01-01_00_db.sql");
+        std::fs::write(&backup_path, b"corrupted").unwrap();
+        std::fs::write(sidecar_path(&backup_path), b"{}").unwrap();
+
+        let mut conn = open_db(&dir).unwrap();
+        insert_backup_file(
+            &mut conn,
+            &tracked_row_in_all_tiers("2025/01/2025-01-01_00_db.sql"),
+        )
+        .unwrap();
+
+        let quarantine_path = quarantine(&mut conn, &dir, &backup_path).unwrap();
+        assert_eq!(
+            quarantine_path,
+            dir.join("quarantine/2025/01/2025-01-01_00_db.sql")
+        );
+        assert!(quarantine_path.is_file());
+        assert!(sidecar_path(&quarantine_path).is_file());
+        assert!(!dir.join("2025").exists());
+        assert_eq!(quarantined_backups(&dir).unwrap(), vec![quarantine_path]);
+        assert!(
+            metadata_from_directory(&dir, &NameTemplate::default())
+                .unwrap()
+                .is_empty()
+        );
+
+        let row =
+            backup_file_with_relative_path(&mut conn, "quarantine/2025/01/2025-01-01_00_db.sql")
+                .unwrap()
+                .unwrap();
+        assert!(row.quarantined_at.is_some());
+    }
+}
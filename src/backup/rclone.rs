@@ -0,0 +1,198 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use color_eyre::{
+    Section,
+    eyre::{Context, ContextCompat, Result, ensure, eyre},
+};
+
+use crate::{
+    backup::mirror::RemoteFolder,
+    error_code::{ErrorCode, WithErrorCode},
+};
+
+/// Folder on a cloud storage provider, addressed as rclone remote like `gdrive:backups/saves`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remote {
+    remote: String,
+    /// Transfer limit in bytes per second.
+    bwlimit: Option<u64>,
+}
+
+/// Runs rclone with the arguments and returns its output.
+fn rclone(args: &[&str]) -> Result<String> {
+    let output = Command::new("rclone")
+        .args(args)
+        .output()
+        .wrap_err("Failed to run rclone.")
+        .suggestion(
+            "Install rclone from https://rclone.org and configure the remote with `rclone config`.",
+        )?;
+    ensure!(
+        output.status.success(),
+        "rclone {} failed with {}: {}",
+        args[0],
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parses the output of `rclone lsf --format sp` into sizes by path.
+fn parse_listing(listing: &str) -> HashMap<String, u64> {
+    listing
+        .lines()
+        .filter_map(|line| {
+            let (size, path) = line.split_once('\t')?;
+            Some((path.to_owned(), size.parse().ok()?))
+        })
+        .collect()
+}
+
+/// Parses the SHA-256 hash of the first line of `rclone hashsum` output.
+fn parse_hashsum(output: &str) -> Option<String> {
+    let hash = output.split_whitespace().next()?;
+    (hash.len() == 64 && hash.chars().all(|char| char.is_ascii_hexdigit()))
+        .then(|| hash.to_ascii_uppercase())
+}
+
+impl Remote {
+    pub fn parse(remote: &str) -> Result<Self> {
+        if !remote.contains(':') {
+            return Err(eyre!("{} is not an rclone remote.", remote))
+                .suggestion("Give the remote as `name:path`, e.g. `gdrive:backups/saves`.");
+        }
+        Ok(Self {
+            remote: remote.to_owned(),
+            bwlimit: None,
+        })
+    }
+
+    /// Limits transfers to the rate in bytes per second.
+    pub fn with_bwlimit(mut self, bwlimit: Option<u64>) -> Self {
+        self.bwlimit = bwlimit;
+        self
+    }
+
+    fn join(&self, relative_path: &str) -> String {
+        if self.remote.ends_with([':', '/']) {
+            format!("{}{}", self.remote, relative_path)
+        } else {
+            format!("{}/{}", self.remote, relative_path)
+        }
+    }
+}
+
+impl RemoteFolder for Remote {
+    fn name(&self) -> &str {
+        &self.remote
+    }
+
+    fn list(&self) -> Result<HashMap<String, u64>> {
+        let listing = rclone(&[
+            "lsf",
+            "--recursive",
+            "--files-only",
+            "--format",
+            "sp",
+            "--separator",
+            "\t",
+            &self.remote,
+        ])
+        .wrap_err_with(|| format!("Failed to list files of {}", self.remote))?;
+        Ok(parse_listing(&listing))
+    }
+
+    fn upload(&self, local_path: &Path, remote_path: &str) -> Result<()> {
+        let local_path = local_path.to_string_lossy();
+        let remote_path = self.join(remote_path);
+        match self.bwlimit {
+            Some(rate) => rclone(&[
+                "copyto",
+                "--bwlimit",
+                &format!("{}B", rate),
+                &local_path,
+                &remote_path,
+            ])?,
+            None => rclone(&["copyto", &local_path, &remote_path])?,
+        };
+        Ok(())
+    }
+
+    /// Uses the hash of the server if the backend offers SHA-256, otherwise downloads the file.
+    fn hash(&self, remote_path: &str) -> Result<String> {
+        let remote_path = self.join(remote_path);
+        if let Ok(output) = rclone(&["hashsum", "sha256", &remote_path])
+            && let Some(hash) = parse_hashsum(&output)
+        {
+            return Ok(hash);
+        }
+
+        let bwlimit = self.bwlimit.map(|rate| format!("{}B", rate));
+        let mut args = vec!["hashsum", "sha256", "--download"];
+        if let Some(bwlimit) = &bwlimit {
+            args.extend(["--bwlimit", bwlimit]);
+        }
+        args.push(&remote_path);
+        parse_hashsum(&rclone(&args)?)
+            .wrap_err_with(|| format!("rclone returned no hash of {}", remote_path))
+    }
+
+    fn remove(&self, remote_path: &str) -> Result<()> {
+        rclone(&["deletefile", &self.join(remote_path)])?;
+        Ok(())
+    }
+
+    /// Compares the hashes of the files with `rclone check`.
+    fn check(&self, target: &Path, _local_files: &[(PathBuf, String)]) -> Result<()> {
+        let target = target.to_string_lossy();
+        rclone(&["check", "--one-way", &target, &self.remote])
+            .wrap_err("Files on the remote do not match the target folder.")
+            .error_code(ErrorCode::RemoteMismatch)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_remote() {
+        let remote = Remote::parse("gdrive:backups/saves").unwrap();
+
+        assert_eq!(
+            remote.join("2025/2025-01-01_00_db.sql"),
+            "gdrive:backups/saves/2025/2025-01-01_00_db.sql"
+        );
+        assert_eq!(
+            Remote::parse("gdrive:").unwrap().join("db.sql"),
+            "gdrive:db.sql"
+        );
+        assert!(Remote::parse("./backups").is_err());
+        assert_eq!(
+            parse_hashsum(
+                "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08  db.sql\n"
+            ),
+            Some("9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08".to_owned())
+        );
+        assert_eq!(parse_hashsum("UNSUPPORTED  db.sql\n"), None);
+        assert_eq!(
+            parse_listing("6\t2025-01-01_00_db.sql\n64\t2025-01-01_00_db.sql.sha256\n"),
+            HashMap::from([
+                ("2025-01-01_00_db.sql".to_owned(), 6),
+                ("2025-01-01_00_db.sql.sha256".to_owned(), 64)
+            ])
+        );
+    }
+}
@@ -0,0 +1,233 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{Datelike, NaiveDate};
+use color_eyre::{
+    Section,
+    eyre::{Context, ContextCompat, Result, bail, eyre},
+};
+use log::{error, info, warn};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    backup::{
+        archive::{is_zip, write_zip_content},
+        cleanup::BackupFile,
+        cold::backups_of_target,
+        db::open_db,
+        delta::{is_delta, write_delta_content},
+        hash::hash_file,
+        list::list_backups,
+        parsing::{FileNameMetadata, metadata_from_date_string},
+        sidecar::sidecar_hash,
+        store::{is_manifest, write_manifest_content},
+        template::NameTemplate,
+    },
+    error_code::{ErrorCode, WithErrorCode},
+};
+
+/// Writes the original content of a backup into the writer, reassembling chunked and delta backups.
+pub fn write_backup_content(
+    target_dir: impl AsRef<Path>,
+    backup_path: impl AsRef<Path>,
+    writer: &mut impl Write,
+) -> Result<()> {
+    if is_manifest(backup_path.as_ref()) {
+        write_manifest_content(target_dir, backup_path, writer)
+    } else if is_delta(backup_path.as_ref()) {
+        write_delta_content(target_dir, backup_path, writer)
+    } else if is_zip(backup_path.as_ref()) {
+        write_zip_content(backup_path, writer, None)
+    } else {
+        let mut file = File::open(backup_path.as_ref()).wrap_err("Failed to open backup.")?;
+        io::copy(&mut file, writer).wrap_err("Failed to read backup.")?;
+        Ok(())
+    }
+}
+
+/// Hashes the original content of a backup.
+pub fn hash_backup_content(
+    target_dir: impl AsRef<Path>,
+    backup_path: impl AsRef<Path>,
+) -> Result<String> {
+    if is_manifest(backup_path.as_ref())
+        || is_delta(backup_path.as_ref())
+        || is_zip(backup_path.as_ref())
+    {
+        let mut hasher = Sha256::new();
+        write_backup_content(target_dir, backup_path, &mut hasher)?;
+        Ok(hex::encode_upper(hasher.finalize()))
+    } else {
+        hash_file(&mut File::open(backup_path.as_ref()).wrap_err("Failed to open backup.")?)
+    }
+}
+
+/// Lets the user pick one of the backups on the terminal, defaulting to the newest.
+fn pick_backup(target: &Path, name_template: &NameTemplate) -> Result<BackupFile> {
+    let mut backups = list_backups(target, name_template)?;
+    if backups.is_empty() {
+        bail!("No backups found in target folder.");
+    }
+
+    for (index, backup) in backups.iter().enumerate() {
+        eprintln!("{:>4})  {}", index + 1, backup.describe());
+    }
+
+    let count = backups.len();
+    loop {
+        eprint!("Backup to restore [1-{0}, default {0}]: ", count);
+        io::stderr().flush()?;
+
+        let mut answer = String::new();
+        if io::stdin()
+            .read_line(&mut answer)
+            .wrap_err("Failed to read choice.")?
+            == 0
+        {
+            bail!("No backup chosen.");
+        }
+
+        match answer.trim() {
+            "" => return Ok(backups.swap_remove(count - 1).file),
+            answer => match answer.parse::<usize>() {
+                Ok(number) if (1..=count).contains(&number) => {
+                    return Ok(backups.swap_remove(number - 1).file);
+                }
+                _ => eprintln!("Expected a number between 1 and {}.", count),
+            },
+        }
+    }
+}
+
+/// Picks the newest of the sorted backups made on the day or before.
+fn newest_as_of(backup_files: Vec<BackupFile>, as_of: NaiveDate) -> Option<BackupFile> {
+    let last_of_day = FileNameMetadata {
+        year: as_of.year_ce().1,
+        month: as_of.month(),
+        day: as_of.day(),
+        counter: u32::MAX,
+    };
+    backup_files
+        .into_iter()
+        .rev()
+        .find(|file| file.metadata <= last_of_day)
+}
+
+pub fn restore(
+    target: PathBuf,
+    output: PathBuf,
+    date: Option<String>,
+    as_of: Option<NaiveDate>,
+    interactive: bool,
+    name_template: &NameTemplate,
+) -> Result<()> {
+    if output.try_exists()? {
+        bail!("Output file {} already exists.", output.display());
+    }
+
+    let mut conn = open_db(&target)?;
+    let mut backup_files = backups_of_target(&mut conn, &target, name_template)?;
+    backup_files.sort();
+
+    let backup = if interactive {
+        pick_backup(&target, name_template)?
+    } else {
+        match (&date, as_of) {
+            (Some(date), _) => {
+                let metadata = metadata_from_date_string(date)
+                    .wrap_err("Failed parsing date.")
+                    .suggestion("Dates are expected in the format YYYY-MM-DD_NN.")?;
+                backup_files
+                    .into_iter()
+                    .find(|file| file.metadata == metadata)
+                    .wrap_err_with(|| format!("No backup found for {}", date))?
+            }
+            (None, Some(as_of)) => newest_as_of(backup_files, as_of)
+                .wrap_err_with(|| format!("No backup found from {} or earlier", as_of))?,
+            (None, None) => backup_files
+                .pop()
+                .wrap_err("No backups found in target folder.")?,
+        }
+    };
+
+    info!(
+        "Restoring '{}' to '{}'",
+        backup.path.display(),
+        output.display()
+    );
+
+    let mut writer = BufWriter::new(
+        File::create(&output)
+            .wrap_err("Failed to create output file.")
+            .suggestion(
+                "Check if the output folder exists and if you have permissions to access it.",
+            )?,
+    );
+    write_backup_content(&target, &backup.path, &mut writer)?;
+    writer.flush().wrap_err("Failed to write output file.")?;
+    drop(writer);
+
+    info!("Hashing restored file.");
+    let output_hash = hash_file(&mut File::open(&output)?)?;
+    info!("Restored file sh256: {}", &output_hash);
+
+    match sidecar_hash(&backup.path) {
+        Some(expected) if expected == output_hash => {
+            info!("Restored file matches the hash recorded for the backup.")
+        }
+        Some(_) => {
+            error!("Restored file does NOT match the hash recorded for the backup!");
+            return Err(eyre!("Restored file is corrupted."))
+                .error_code(ErrorCode::RestoreMismatch);
+        }
+        None => warn!("No hash file found for backup. Restored file could not be verified."),
+    }
+
+    info!("DONE!");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_newest_as_of() {
+        let backup_file = |year, month, day, counter| BackupFile {
+            metadata: FileNameMetadata {
+                year,
+                month,
+                day,
+                counter,
+            },
+            path: PathBuf::from(format!("{}-{}-{}_{}_db.sql", year, month, day, counter)),
+        };
+        let backup_files = vec![
+            backup_file(2025, 6, 14, 0),
+            backup_file(2025, 6, 15, 0),
+            backup_file(2025, 6, 15, 1),
+            backup_file(2025, 6, 16, 0),
+        ];
+        let as_of = |day| NaiveDate::from_ymd_opt(2025, 6, day).unwrap();
+
+        assert_eq!(
+            newest_as_of(backup_files.clone(), as_of(15)),
+            Some(backup_file(2025, 6, 15, 1))
+        );
+        assert_eq!(
+            newest_as_of(backup_files.clone(), as_of(30)),
+            Some(backup_file(2025, 6, 16, 0))
+        );
+        assert_eq!(newest_as_of(backup_files, as_of(13)), None);
+    }
+}
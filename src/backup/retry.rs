@@ -0,0 +1,91 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{fmt::Display, time::Duration};
+
+use log::warn;
+
+/// How often and after what delay failing file operations are retried.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each further retry.
+    pub delay: Duration,
+}
+
+impl RetryPolicy {
+    fn delay_before(&self, retry: u32) -> Duration {
+        self.delay.saturating_mul(2u32.saturating_pow(retry))
+    }
+
+    /// Runs the operation, retrying it with exponential backoff as long as it fails.
+    ///
+    /// The error of the last attempt is returned if all retries fail.
+    pub fn run<T, E: Display>(
+        &self,
+        description: &str,
+        mut operation: impl FnMut() -> Result<T, E>,
+    ) -> Result<T, E> {
+        let mut retry = 0;
+        loop {
+            match operation() {
+                Ok(value) => return Ok(value),
+                Err(err) if retry < self.retries => {
+                    let delay = self.delay_before(retry);
+                    retry += 1;
+                    warn!(
+                        "{} failed, retrying in {:?} ({}/{}): {}",
+                        description, delay, retry, self.retries, err
+                    );
+                    std::thread::sleep(delay);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let policy = RetryPolicy {
+            retries: 2,
+            delay: Duration::ZERO,
+        };
+
+        let mut attempts = 0;
+        let result: Result<u32, &str> = policy.run("Test", || {
+            attempts += 1;
+            if attempts < 3 {
+                Err("failed")
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result, Ok(3));
+
+        let mut attempts = 0;
+        let result: Result<u32, &str> = policy.run("Test", || {
+            attempts += 1;
+            Err("failed")
+        });
+        assert_eq!(result, Err("failed"));
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn test_delay_before() {
+        let policy = RetryPolicy {
+            retries: 3,
+            delay: Duration::from_secs(5),
+        };
+        assert_eq!(policy.delay_before(0), Duration::from_secs(5));
+        assert_eq!(policy.delay_before(2), Duration::from_secs(20));
+    }
+}
@@ -0,0 +1,355 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use color_eyre::eyre::{Context, Result};
+use log::{info, warn};
+
+use crate::backup::{
+    parity::{PARITY_EXTENSION, parity_path},
+    parsing::metadata_from_directory,
+    restore::hash_backup_content,
+    signature::{SIGNATURE_EXTENSION, signature_path},
+    template::NameTemplate,
+};
+
+/// Extension of the hash files placed next to each backup.
+pub const SIDECAR_EXTENSION: &str = "sha256";
+
+/// Extensions of the files accompanying a backup, named by appending the extension to its name.
+const COMPANION_EXTENSIONS: [&str; 3] = [SIDECAR_EXTENSION, PARITY_EXTENSION, SIGNATURE_EXTENSION];
+
+/// Format of the hash files placed next to each backup.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SidecarFormat {
+    /// `<hash> *<file name>`, like `sha256sum --binary` and checked by `sha256sum -c`
+    #[default]
+    Gnu,
+    /// `SHA256 (<file name>) = <hash>`, like `sha256sum --tag` and `sha256` on BSD
+    Bsd,
+    /// No hash files, the hashes in the tracking database are used instead
+    None,
+}
+
+impl SidecarFormat {
+    /// Line of a checksum file in the format, escaping file names like `sha256sum`.
+    ///
+    /// Returns `None` for [`SidecarFormat::None`].
+    pub fn line(self, hash: &str, file_name: &str) -> Option<String> {
+        let hash = hash.to_ascii_lowercase();
+        // `sha256sum` escapes file names with backslashes or newlines and marks the line with a
+        // leading backslash.
+        let (prefix, file_name) = if file_name.contains(['\\', '\n']) {
+            ("\\", file_name.replace('\\', "\\\\").replace('\n', "\\n"))
+        } else {
+            ("", file_name.to_owned())
+        };
+
+        match self {
+            Self::Gnu => Some(format!("{}{} *{}\n", prefix, hash, file_name)),
+            Self::Bsd => Some(format!("{}SHA256 ({}) = {}\n", prefix, file_name, hash)),
+            Self::None => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sidecar {
+    /// Upper case hex SHA-256, like the hashes stored in the tracking database.
+    pub hash: String,
+    pub file_name: String,
+    pub format: SidecarFormat,
+}
+
+/// Path of the hash file belonging to the backup.
+pub fn sidecar_path(backup_path: impl AsRef<Path>) -> PathBuf {
+    let mut path_str = backup_path.as_ref().to_path_buf().into_os_string();
+    path_str.push(".");
+    path_str.push(SIDECAR_EXTENSION);
+    PathBuf::from(path_str)
+}
+
+/// Checks if the file accompanies a backup, like hash, parity and signature files.
+pub fn is_companion(path: impl AsRef<Path>) -> bool {
+    path.as_ref().extension().is_some_and(|ext| {
+        COMPANION_EXTENSIONS
+            .iter()
+            .any(|companion| ext == *companion)
+    })
+}
+
+/// Paths of the hash, parity and signature files of the backup, whether they exist or not.
+pub fn companion_paths(backup_path: impl AsRef<Path>) -> [PathBuf; 3] {
+    [
+        sidecar_path(backup_path.as_ref()),
+        parity_path(backup_path.as_ref()),
+        signature_path(backup_path.as_ref()),
+    ]
+}
+
+/// Parses a hash file in the GNU or BSD format of `sha256sum`.
+fn parse_sidecar(content: &str) -> Option<Sidecar> {
+    let line = content.lines().next()?;
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+
+    let (hash, file_name, format) = match line.strip_prefix("SHA256 (") {
+        Some(rest) => {
+            let (file_name, hash) = rest.rsplit_once(") = ")?;
+            (hash, file_name, SidecarFormat::Bsd)
+        }
+        None => {
+            let (hash, file_name) = line.split_once(' ')?;
+            let file_name = file_name.strip_prefix(['*', ' ']).unwrap_or(file_name);
+            (hash, file_name, SidecarFormat::Gnu)
+        }
+    };
+    let file_name = if escaped {
+        unescape_file_name(file_name)
+    } else {
+        file_name.to_owned()
+    };
+
+    Some(Sidecar {
+        hash: hash.to_ascii_uppercase(),
+        file_name,
+        format,
+    })
+}
+
+/// Reverts the escaping of backslashes and newlines by `sha256sum`.
+fn unescape_file_name(file_name: &str) -> String {
+    let mut unescaped = String::with_capacity(file_name.len());
+    let mut chars = file_name.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                unescaped.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                unescaped.push('\\');
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Writes the hash file of the backup in the format, or nothing with [`SidecarFormat::None`].
+pub fn write_sidecar(
+    backup_path: impl AsRef<Path>,
+    hash: &str,
+    format: SidecarFormat,
+) -> Result<()> {
+    let file_name = backup_path
+        .as_ref()
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let Some(line) = format.line(hash, &file_name) else {
+        return Ok(());
+    };
+
+    std::fs::write(sidecar_path(backup_path), line).wrap_err("Failed to write hash file.")
+}
+
+pub fn read_sidecar(backup_path: impl AsRef<Path>) -> Option<Sidecar> {
+    parse_sidecar(&std::fs::read_to_string(sidecar_path(backup_path)).ok()?)
+}
+
+impl Sidecar {
+    /// Whether the hash file references the backup, rather than a file it was renamed or copied
+    /// from.
+    pub fn references(&self, backup_path: impl AsRef<Path>) -> bool {
+        backup_path
+            .as_ref()
+            .file_name()
+            .is_some_and(|file_name| file_name == self.file_name.as_str())
+    }
+}
+
+/// Hash recorded in the hash file next to the backup.
+pub fn sidecar_hash(backup_path: impl AsRef<Path>) -> Option<String> {
+    read_sidecar(backup_path).map(|sidecar| sidecar.hash)
+}
+
+/// Outcome of checking a backup against its hash file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Integrity {
+    Intact,
+    /// The content does not match the hash file.
+    Corrupted,
+    /// The hash file references another file, e.g. after renaming the backup or copying the hash
+    /// file of another backup.
+    Misnamed,
+    /// The hash file is missing or the content cannot be read.
+    Unknown,
+}
+
+/// Checks if the content of the backup matches its hash file.
+pub fn check_backup(target_dir: impl AsRef<Path>, backup_path: impl AsRef<Path>) -> Integrity {
+    let Some(sidecar) = read_sidecar(backup_path.as_ref()) else {
+        warn!("No hash file found for {}", backup_path.as_ref().display());
+        return Integrity::Unknown;
+    };
+    if !sidecar.references(backup_path.as_ref()) {
+        warn!(
+            "Hash file of {} references {}",
+            backup_path.as_ref().display(),
+            sidecar.file_name
+        );
+        return Integrity::Misnamed;
+    }
+
+    check_backup_hash(target_dir, backup_path, &sidecar.hash)
+}
+
+/// Checks if the content of the backup matches the hash, e.g. the one in the tracking database.
+pub fn check_backup_hash(
+    target_dir: impl AsRef<Path>,
+    backup_path: impl AsRef<Path>,
+    expected: &str,
+) -> Integrity {
+    match hash_backup_content(target_dir, backup_path.as_ref()) {
+        Ok(hash) if hash == expected => Integrity::Intact,
+        Ok(_) => Integrity::Corrupted,
+        Err(err) => {
+            warn!("Failed to read {}: {}", backup_path.as_ref().display(), err);
+            Integrity::Unknown
+        }
+    }
+}
+
+/// Checks if the content of the backup matches its hash file.
+///
+/// Backups without hash file or whose content cannot be read are reported as not intact.
+pub fn verify_backup(target_dir: impl AsRef<Path>, backup_path: impl AsRef<Path>) -> bool {
+    check_backup(target_dir, backup_path) == Integrity::Intact
+}
+
+/// Regenerates missing hash files and hash files referencing a different file name.
+pub fn repair_sidecars(target: PathBuf, name_template: &NameTemplate) -> Result<()> {
+    let mut backup_files = metadata_from_directory(&target, name_template)?;
+    backup_files.sort();
+
+    let mut repaired_count = 0;
+    for file in &backup_files {
+        let format = match read_sidecar(&file.path) {
+            Some(sidecar) if sidecar.references(&file.path) => continue,
+            Some(sidecar) => {
+                warn!(
+                    "Hash file of {} references {}",
+                    file.path.display(),
+                    sidecar.file_name
+                );
+                sidecar.format
+            }
+            None => {
+                warn!(
+                    "Hash file of {} is missing or unreadable.",
+                    file.path.display()
+                );
+                SidecarFormat::Gnu
+            }
+        };
+
+        info!("Hashing {}", file.path.display());
+        let hash = hash_backup_content(&target, &file.path)?;
+
+        write_sidecar(&file.path, &hash, format)?;
+        repaired_count += 1;
+    }
+
+    info!(
+        "Repaired {} of {} hash files.",
+        repaired_count,
+        backup_files.len()
+    );
+    info!("DONE!");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backup::hash::hash_bytes;
+
+    #[test]
+    fn test_parse_sidecar() {
+        assert_eq!(
+            parse_sidecar("ABCD *2025-09-27_03_file 1.txt\n"),
+            Some(Sidecar {
+                hash: "ABCD".to_owned(),
+                file_name: "2025-09-27_03_file 1.txt".to_owned(),
+                format: SidecarFormat::Gnu,
+            })
+        );
+        assert_eq!(parse_sidecar(""), None);
+    }
+
+    #[test]
+    fn test_sidecar_formats() {
+        assert_eq!(
+            SidecarFormat::Gnu.line("ABCD", "a b.txt").unwrap(),
+            "abcd *a b.txt\n"
+        );
+        assert_eq!(
+            SidecarFormat::Bsd.line("ABCD", "a b.txt").unwrap(),
+            "SHA256 (a b.txt) = abcd\n"
+        );
+        assert_eq!(
+            SidecarFormat::Gnu.line("ABCD", "a\\b\nc").unwrap(),
+            "\\abcd *a\\\\b\\nc\n"
+        );
+        assert_eq!(SidecarFormat::None.line("ABCD", "a"), None);
+
+        for format in [SidecarFormat::Gnu, SidecarFormat::Bsd] {
+            for file_name in ["a b.txt", "a\\b\nc", "a) = b"] {
+                assert_eq!(
+                    parse_sidecar(&format.line("ABCD", file_name).unwrap()),
+                    Some(Sidecar {
+                        hash: "ABCD".to_owned(),
+                        file_name: file_name.to_owned(),
+                        format,
+                    })
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_backup() {
+        let dir = std::env::temp_dir().join(format!("sfb-sidecar-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let backup_path = dir.join("2025-01-01_00_db.sql");
+        std::fs::write(&backup_path, b"backup").unwrap();
+        let hash = hash_bytes(b"backup");
+        let write_sidecar = |hash: &str, file_name: &str| {
+            std::fs::write(
+                sidecar_path(&backup_path),
+                SidecarFormat::Gnu.line(hash, file_name).unwrap(),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(check_backup(&dir, &backup_path), Integrity::Unknown);
+        write_sidecar(&hash, "2025-01-01_00_db.sql");
+        assert_eq!(check_backup(&dir, &backup_path), Integrity::Intact);
+        write_sidecar(&hash, "2025-01-02_00_db.sql");
+        assert_eq!(check_backup(&dir, &backup_path), Integrity::Misnamed);
+        write_sidecar(&"0".repeat(64), "2025-01-01_00_db.sql");
+        assert_eq!(check_backup(&dir, &backup_path), Integrity::Corrupted);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use color_eyre::{
+    Section,
+    eyre::{Context, Result},
+};
+use log::{info, warn};
+use minisign::{PublicKey, SecretKey, SecretKeyBox, SignatureBox};
+
+use crate::credentials::{delete_secret, get_secret, key_password_name, store_secret};
+
+/// Extension of the detached minisign signatures placed next to backups.
+pub const SIGNATURE_EXTENSION: &str = "minisig";
+
+/// Path of the signature belonging to the backup.
+pub fn signature_path(backup_path: impl AsRef<Path>) -> PathBuf {
+    let mut path_str = backup_path.as_ref().to_path_buf().into_os_string();
+    path_str.push(".");
+    path_str.push(SIGNATURE_EXTENSION);
+    PathBuf::from(path_str)
+}
+
+fn read_secret_key_box(key_path: impl AsRef<Path>) -> Result<SecretKeyBox> {
+    let content = std::fs::read_to_string(key_path.as_ref())
+        .wrap_err("Failed to read secret key.")
+        .suggestion("Check if the path of the key is correct.")?;
+    Ok(SecretKeyBox::from_string(&content)?)
+}
+
+/// Loads a minisign secret key.
+///
+/// The password of encrypted keys is taken from the keyring of the OS if stored there with the
+/// `store-key-password` command and prompted for otherwise.
+pub fn load_secret_key(key_path: impl AsRef<Path>) -> Result<SecretKey> {
+    let key_box = read_secret_key_box(key_path.as_ref())?;
+
+    if let Ok(secret_key) = key_box.clone().into_unencrypted_secret_key() {
+        return Ok(secret_key);
+    }
+
+    if let Some(password) = get_secret(&key_password_name(key_path.as_ref())?) {
+        match key_box.clone().into_secret_key(Some(password)) {
+            Ok(secret_key) => return Ok(secret_key),
+            Err(_) => warn!("Password of the key stored in the keyring is wrong."),
+        }
+    }
+
+    key_box
+        .into_secret_key(None)
+        .wrap_err("Failed to load secret key.")
+}
+
+/// Prompts for the password of an encrypted secret key and stores it in the keyring of the OS.
+pub fn store_key_password(key_path: PathBuf) -> Result<()> {
+    let key_box = read_secret_key_box(&key_path)?;
+    let password = rpassword::prompt_password("Password: ").wrap_err("Failed to read password.")?;
+
+    key_box
+        .into_secret_key(Some(password.clone()))
+        .wrap_err("Failed to decrypt secret key.")
+        .suggestion("Check if the password is correct.")?;
+
+    store_secret(&key_password_name(&key_path)?, &password)?;
+    info!("DONE!");
+
+    Ok(())
+}
+
+/// Removes the password of a secret key from the keyring of the OS.
+pub fn forget_key_password(key_path: PathBuf) -> Result<()> {
+    delete_secret(&key_password_name(&key_path)?)?;
+    info!("DONE!");
+
+    Ok(())
+}
+
+pub fn load_public_key(key_path: impl AsRef<Path>) -> Result<PublicKey> {
+    PublicKey::from_file(key_path.as_ref())
+        .wrap_err("Failed to load public key.")
+        .suggestion("Check if the path of the key is correct.")
+}
+
+/// Writes a detached minisign signature of the backup, compatible with `minisign -V`.
+pub fn sign_backup(secret_key: &SecretKey, backup_path: impl AsRef<Path>) -> Result<()> {
+    let file_name = backup_path
+        .as_ref()
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let trusted_comment = format!("timestamp:{}\tfile:{}", Utc::now().timestamp(), file_name);
+
+    let backup =
+        BufReader::new(File::open(backup_path.as_ref()).wrap_err("Failed to open backup.")?);
+    let signature = minisign::sign(None, secret_key, backup, Some(&trusted_comment), None)
+        .wrap_err("Failed to sign backup.")?;
+
+    std::fs::write(signature_path(backup_path), signature.to_string())
+        .wrap_err("Failed to write signature.")?;
+
+    Ok(())
+}
+
+/// Checks if the backup has a valid signature made by the key.
+pub fn verify_signature(public_key: &PublicKey, backup_path: impl AsRef<Path>) -> bool {
+    let Ok(signature) = SignatureBox::from_file(signature_path(backup_path.as_ref())) else {
+        warn!(
+            "No readable signature found for {}",
+            backup_path.as_ref().display()
+        );
+        return false;
+    };
+
+    let Ok(backup) = File::open(backup_path.as_ref()) else {
+        return false;
+    };
+
+    minisign::verify(
+        public_key,
+        &signature,
+        BufReader::new(backup),
+        true,
+        false,
+        false,
+    )
+    .is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use minisign::KeyPair;
+
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let dir = std::env::temp_dir().join(format!("sfb-signature-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let backup_path = dir.join("backup.txt");
+        std::fs::write(&backup_path, b"content").unwrap();
+
+        let KeyPair { pk, sk } = KeyPair::generate_unencrypted_keypair().unwrap();
+        sign_backup(&sk, &backup_path).unwrap();
+        let valid = verify_signature(&pk, &backup_path);
+
+        std::fs::write(&backup_path, b"tampered").unwrap();
+        let tampered = verify_signature(&pk, &backup_path);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(valid);
+        assert!(!tampered);
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::path::PathBuf;
+
+use chrono::{Datelike, Months, NaiveDate, NaiveDateTime, TimeDelta};
+use clap::ValueEnum;
+use color_eyre::{
+    Section,
+    eyre::{Result, eyre},
+};
+
+use crate::backup::{
+    cleanup::{BackupFile, Tiered, Tiers, identify_files_to_keep, identify_tiers, tiers_flags},
+    parsing::FileNameMetadata,
+};
+
+/// How often the simulated backups are created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Frequency {
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Frequency {
+    fn next(self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        match self {
+            Self::Hourly => time.checked_add_signed(TimeDelta::hours(1)),
+            Self::Daily => time.checked_add_signed(TimeDelta::days(1)),
+            Self::Weekly => time.checked_add_signed(TimeDelta::weeks(1)),
+            Self::Monthly => time.checked_add_months(Months::new(1)),
+        }
+    }
+}
+
+/// Backups retained after the last run of a month.
+#[derive(Debug)]
+pub struct MonthEnd {
+    pub year: i32,
+    pub month: u32,
+    pub retained: Vec<(BackupFile, Tiers)>,
+}
+
+fn date_string(metadata: &FileNameMetadata) -> String {
+    format!(
+        "{:04}-{:02}-{:02}_{:02}",
+        metadata.year, metadata.month, metadata.day, metadata.counter
+    )
+}
+
+/// Runs the retention over backups created with the frequency from `from` until `to`, both
+/// included, pruning after each backup like a real run.
+///
+/// Returns the backups retained at the end of each month.
+pub fn simulate_retention(
+    from: NaiveDate,
+    to: NaiveDate,
+    frequency: Frequency,
+    retention: Tiered,
+) -> Result<Vec<MonthEnd>> {
+    let mut backups: Vec<BackupFile> = vec![];
+    let mut month_ends = vec![];
+    let mut time = Some(from.and_time(Default::default()));
+
+    while let Some(now) = time.filter(|now| now.date() <= to) {
+        let date = now.date();
+        let counter = backups
+            .last()
+            .filter(|last| {
+                (last.metadata.year, last.metadata.month, last.metadata.day)
+                    == (date.year() as u32, date.month(), date.day())
+            })
+            .map_or(0, |last| last.metadata.counter + 1);
+        let metadata = FileNameMetadata {
+            year: date.year() as u32,
+            month: date.month(),
+            day: date.day(),
+            counter,
+        };
+        backups.push(BackupFile {
+            path: PathBuf::from(date_string(&metadata)),
+            metadata,
+        });
+        backups = identify_files_to_keep(&backups, &retention)?;
+
+        time = frequency.next(now);
+        let month_ended = time.is_none_or(|next| {
+            next.date() > to || (next.year(), next.month()) != (date.year(), date.month())
+        });
+        if month_ended {
+            month_ends.push(MonthEnd {
+                year: date.year(),
+                month: date.month(),
+                retained: identify_tiers(&backups, &retention)?,
+            });
+        }
+    }
+
+    Ok(month_ends)
+}
+
+/// Prints how many backups of each tier the retention keeps at the end of each month, and the
+/// backups retained after the last run.
+pub fn simulate(
+    from: NaiveDate,
+    to: NaiveDate,
+    frequency: Frequency,
+    retention: Tiered,
+) -> Result<()> {
+    if from > to {
+        return Err(eyre!("Simulation starts after it ends.")).suggestion("Swap --from and --to.");
+    }
+
+    let month_ends = simulate_retention(from, to, frequency, retention)?;
+    println!(
+        "{:<7}  {:>7}  {:>6}  {:>5}  {:>6}  {:>7}  {:>9}  {:>6}  Oldest",
+        "Month", "Backups", "Latest", "Daily", "Weekly", "Monthly", "Quarterly", "Yearly"
+    );
+    for month_end in &month_ends {
+        let count = |tier: fn(&Tiers) -> bool| {
+            month_end
+                .retained
+                .iter()
+                .filter(|(_, tiers)| tier(tiers))
+                .count()
+        };
+        println!(
+            "{:04}-{:02}  {:>7}  {:>6}  {:>5}  {:>6}  {:>7}  {:>9}  {:>6}  {}",
+            month_end.year,
+            month_end.month,
+            month_end.retained.len(),
+            count(|tiers| tiers.latest),
+            count(|tiers| tiers.daily),
+            count(|tiers| tiers.weekly),
+            count(|tiers| tiers.monthly),
+            count(|tiers| tiers.quarterly),
+            count(|tiers| tiers.yearly),
+            month_end
+                .retained
+                .first()
+                .map(|(file, _)| date_string(&file.metadata))
+                .unwrap_or_default()
+        );
+    }
+
+    if let Some(last) = month_ends.last() {
+        println!();
+        println!("Retained after the last run:");
+        for (file, tiers) in &last.retained {
+            println!("{}  {}", date_string(&file.metadata), tiers_flags(*tiers));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_simulate_retention() {
+        let month_ends = simulate_retention(
+            NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2025, 12, 31).unwrap(),
+            Frequency::Daily,
+            Tiered {
+                keep_latest: Some(3),
+                keep_daily: Some(7),
+                keep_monthly: Some(6),
+                keep_yearly: Some(5),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let last = month_ends.last().unwrap();
+        let dates: Vec<String> = last
+            .retained
+            .iter()
+            .map(|(file, _)| date_string(&file.metadata))
+            .collect();
+
+        assert_eq!(month_ends.len(), 24);
+        assert_eq!(dates.len(), 15);
+        assert_eq!(dates.first().unwrap(), "2024-01-01_00");
+        assert_eq!(dates.last().unwrap(), "2025-12-31_00");
+        assert!(dates.contains(&"2025-07-01_00".to_owned()));
+        assert!(!dates.contains(&"2025-06-01_00".to_owned()));
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{Context, Result};
+
+use crate::backup::{
+    cleanup::{BackupFile, Tiered, Tiers, identify_tiers},
+    list::format_size,
+    normalize::composed,
+    parsing::{basename_from_file_name, metadata_from_directory},
+    sidecar::companion_paths,
+    template::NameTemplate,
+};
+
+/// Number and size of backups.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub count: usize,
+    /// Size on disk in bytes, including hash, parity and signature files.
+    pub size: u64,
+}
+
+impl Usage {
+    fn add(&mut self, size: u64) {
+        self.count += 1;
+        self.size += size;
+    }
+}
+
+/// Disk usage of the backups of a target folder, grouped in several ways.
+#[derive(Debug, Default)]
+pub struct DiskUsage {
+    pub total: Usage,
+    /// By basename of the source.
+    pub sources: BTreeMap<String, Usage>,
+    /// By retention tier, in the order of [`TIER_NAMES`]. Backups kept by
+    /// several tiers count towards each of them.
+    pub tiers: [Usage; 7],
+    /// By month, like `2025-01`.
+    pub months: BTreeMap<String, Usage>,
+}
+
+const TIER_NAMES: [&str; 7] = [
+    "newest",
+    "daily",
+    "weekly",
+    "monthly",
+    "quarterly",
+    "yearly",
+    "none",
+];
+
+fn tier_indices(tiers: Tiers) -> Vec<usize> {
+    let indices: Vec<usize> = [
+        tiers.latest,
+        tiers.daily,
+        tiers.weekly,
+        tiers.monthly,
+        tiers.quarterly,
+        tiers.yearly,
+    ]
+    .iter()
+    .enumerate()
+    .filter_map(|(index, set)| set.then_some(index))
+    .collect();
+    if indices.is_empty() { vec![6] } else { indices }
+}
+
+/// Size of the backup and its companion files. Files hardlinked to files counted before are left
+/// out on Unix, so that identical backups count once.
+fn backup_size(backup_path: &Path, seen: &mut HashSet<(u64, u64)>) -> Result<u64> {
+    let mut size = 0;
+    for path in std::iter::once(backup_path.to_path_buf())
+        .chain(companion_paths(backup_path))
+        .filter(|path| path.exists())
+    {
+        let metadata = std::fs::metadata(&path)
+            .wrap_err_with(|| format!("Failed reading metadata of {}", path.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if metadata.nlink() > 1 && !seen.insert((metadata.dev(), metadata.ino())) {
+                continue;
+            }
+        }
+        size += metadata.len();
+    }
+
+    Ok(size)
+}
+
+/// Groups the backups by source, retention tier and month. Tiers are determined per source.
+pub fn disk_usage(
+    backup_files: &[BackupFile],
+    name_template: &NameTemplate,
+    retention: &Tiered,
+) -> Result<DiskUsage> {
+    let mut by_source: BTreeMap<String, Vec<BackupFile>> = BTreeMap::new();
+    for file in backup_files {
+        let basename = file
+            .path
+            .file_name()
+            .and_then(|file_name| basename_from_file_name(file_name, name_template))
+            .map(|basename| composed(&basename).into_owned())
+            .unwrap_or_default();
+        by_source.entry(basename).or_default().push(file.clone());
+    }
+
+    let mut usage = DiskUsage::default();
+    let mut seen = HashSet::new();
+    for (basename, mut files) in by_source {
+        files.sort();
+        let tiered_files = identify_tiers(&files, retention)?;
+
+        for (file, tiers) in tiered_files {
+            let size = backup_size(&file.path, &mut seen)?;
+            usage.total.add(size);
+            usage.sources.entry(basename.clone()).or_default().add(size);
+            for index in tier_indices(tiers) {
+                usage.tiers[index].add(size);
+            }
+            usage
+                .months
+                .entry(format!(
+                    "{:04}-{:02}",
+                    file.metadata.year, file.metadata.month
+                ))
+                .or_default()
+                .add(size);
+        }
+    }
+
+    Ok(usage)
+}
+
+fn print_usage(name: &str, usage: Usage) {
+    println!(
+        "  {:<24}  {:>6} backups  {:>10}",
+        name,
+        usage.count,
+        format_size(usage.size)
+    );
+}
+
+/// Prints the disk usage of the backups of the target folder by source, retention tier and month.
+pub fn stats(target: PathBuf, name_template: &NameTemplate, retention: Tiered) -> Result<()> {
+    let backup_files = metadata_from_directory(&target, name_template)?;
+    let usage = disk_usage(&backup_files, name_template, &retention)?;
+
+    println!("By source:");
+    for (basename, source_usage) in &usage.sources {
+        print_usage(basename, *source_usage);
+    }
+    println!("By retention tier, backups kept by several tiers count for each:");
+    for (name, tier_usage) in TIER_NAMES.iter().zip(usage.tiers) {
+        print_usage(name, tier_usage);
+    }
+    println!("By month:");
+    for (month, month_usage) in &usage.months {
+        print_usage(month, *month_usage);
+    }
+    print_usage("Total", usage.total);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_disk_usage() {
+        let dir = std::env::temp_dir().join(format!("sfb-stats-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let template = NameTemplate::default();
+        for (name, size) in [
+            ("2025-01-31_00_a.txt", 10),
+            ("2025-02-01_00_a.txt", 20),
+            ("2025-02-01_00_b.txt", 40),
+        ] {
+            std::fs::write(dir.join(name), vec![0; size]).unwrap();
+        }
+        std::fs::write(dir.join("2025-02-01_00_b.txt.sha256"), vec![0; 2]).unwrap();
+
+        let files = metadata_from_directory(&dir, &template).unwrap();
+        let usage = disk_usage(
+            &files,
+            &template,
+            &Tiered {
+                keep_latest: Some(1),
+                keep_daily: Some(0),
+                keep_monthly: Some(0),
+                keep_yearly: Some(0),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(usage.total, Usage { count: 3, size: 72 });
+        assert_eq!(usage.sources["a"], Usage { count: 2, size: 30 });
+        assert_eq!(usage.sources["b"], Usage { count: 1, size: 42 });
+        assert_eq!(usage.tiers[0], Usage { count: 2, size: 62 });
+        assert_eq!(usage.tiers[6], Usage { count: 1, size: 10 });
+        assert_eq!(usage.months["2025-02"], Usage { count: 2, size: 62 });
+    }
+}
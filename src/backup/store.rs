@@ -0,0 +1,288 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufReader, Read, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use color_eyre::{
+    Section,
+    eyre::{Context, ContextCompat, Result, bail, eyre},
+};
+use fastcdc::v2020::StreamCDC;
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+
+use crate::backup::{
+    audit::{Operation, audit_entry, record_audit},
+    db::open_db,
+    file::is_layout_dir_name,
+    hash::hash_bytes,
+    lock::TargetLock,
+};
+
+/// Folder inside the target folder containing the content addressed chunks.
+pub const CHUNK_DIR_NAME: &str = "chunks";
+/// Extension appended to backups stored as chunk manifest.
+pub const MANIFEST_EXTENSION: &str = "chunks";
+
+const MANIFEST_HEADER: &str = "staggered-file-backup chunk manifest v1";
+
+const MIN_CHUNK_SIZE: usize = 16 * 1024;
+const AVG_CHUNK_SIZE: usize = 64 * 1024;
+const MAX_CHUNK_SIZE: usize = 256 * 1024;
+
+#[derive(Debug, Default)]
+pub struct StoreStats {
+    pub chunks: usize,
+    pub new_chunks: usize,
+    pub new_bytes: u64,
+}
+
+/// Checks if the file is a chunk manifest, not a plain backup of a file with the manifest
+/// extension.
+pub fn is_manifest(path: impl AsRef<Path>) -> bool {
+    let header = format!("{}\n", MANIFEST_HEADER);
+    let mut start = vec![0; header.len()];
+    path.as_ref()
+        .extension()
+        .is_some_and(|ext| ext == MANIFEST_EXTENSION)
+        && File::open(path.as_ref())
+            .and_then(|mut file| file.read_exact(&mut start))
+            .is_ok()
+        && start == header.as_bytes()
+}
+
+fn chunk_path(target_dir: impl AsRef<Path>, hash: &str) -> PathBuf {
+    target_dir
+        .as_ref()
+        .join(CHUNK_DIR_NAME)
+        .join(&hash[..2])
+        .join(hash)
+}
+
+fn parse_manifest(content: &str) -> Result<Vec<String>> {
+    let mut lines = content.lines();
+
+    if lines.next() != Some(MANIFEST_HEADER) {
+        bail!("Chunk manifest has an unknown header.");
+    }
+
+    lines
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let hash = line
+                .split_whitespace()
+                .next()
+                .wrap_err("Chunk manifest contains an empty entry.")?;
+            if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                bail!("Chunk manifest contains an invalid hash: {}", hash);
+            }
+            Ok(hash.to_owned())
+        })
+        .collect()
+}
+
+pub fn read_manifest(manifest_path: impl AsRef<Path>) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(manifest_path.as_ref())
+        .wrap_err("Failed to read chunk manifest.")?;
+    parse_manifest(&content)
+        .wrap_err_with(|| format!("Invalid manifest {}", manifest_path.as_ref().display()))
+}
+
+/// Splits the source into content defined chunks, stores missing chunks and writes the manifest.
+pub fn store_chunked(
+    source: impl AsRef<Path>,
+    target_dir: impl AsRef<Path>,
+    manifest_path: impl AsRef<Path>,
+) -> Result<StoreStats> {
+    let source_file = File::open(source.as_ref()).wrap_err("Failed to open source file.")?;
+    let chunker = StreamCDC::new(
+        BufReader::new(source_file),
+        MIN_CHUNK_SIZE,
+        AVG_CHUNK_SIZE,
+        MAX_CHUNK_SIZE,
+    );
+
+    let mut stats = StoreStats::default();
+    let mut manifest = format!("{}\n", MANIFEST_HEADER);
+
+    for chunk in chunker {
+        let chunk = chunk
+            .map_err(|err| eyre!(err))
+            .wrap_err("Failed to chunk source file.")?;
+        let hash = hash_bytes(&chunk.data);
+        let path = chunk_path(target_dir.as_ref(), &hash);
+
+        if !path.try_exists()? {
+            let dir = path.parent().wrap_err("Chunk path has no parent.")?;
+            std::fs::create_dir_all(dir).wrap_err("Failed to create chunk folder.")?;
+
+            let mut tmp_path = path.clone().into_os_string();
+            tmp_path.push(".tmp");
+            std::fs::write(&tmp_path, &chunk.data)
+                .wrap_err("Failed to write chunk.")
+                .suggestion("Check if there is enough space left in the target folder.")?;
+            std::fs::rename(&tmp_path, &path).wrap_err("Failed to move chunk into place.")?;
+
+            stats.new_chunks += 1;
+            stats.new_bytes += chunk.length as u64;
+        }
+
+        stats.chunks += 1;
+        manifest.push_str(&format!("{} {}\n", hash, chunk.length));
+    }
+
+    std::fs::write(manifest_path.as_ref(), manifest).wrap_err("Failed to write chunk manifest.")?;
+
+    Ok(stats)
+}
+
+/// Reassembles the file described by the manifest into the writer.
+pub fn write_manifest_content(
+    target_dir: impl AsRef<Path>,
+    manifest_path: impl AsRef<Path>,
+    writer: &mut impl Write,
+) -> Result<()> {
+    for hash in read_manifest(manifest_path)? {
+        let path = chunk_path(target_dir.as_ref(), &hash);
+        let data = std::fs::read(&path)
+            .wrap_err_with(|| format!("Failed to read chunk {}", path.display()))
+            .suggestion("The chunk store might be damaged. Run a new backup.")?;
+        writer
+            .write_all(&data)
+            .wrap_err("Failed to write reassembled chunk.")?;
+    }
+
+    Ok(())
+}
+
+/// Hashes the reassembled content of the manifest.
+pub fn hash_manifest(
+    target_dir: impl AsRef<Path>,
+    manifest_path: impl AsRef<Path>,
+) -> Result<String> {
+    let mut hasher = Sha256::new();
+    write_manifest_content(target_dir, manifest_path, &mut hasher)?;
+    Ok(hex::encode_upper(hasher.finalize()))
+}
+
+/// Removes all chunks not referenced by any manifest in the target folder.
+pub fn collect_garbage(target_dir: impl AsRef<Path>) -> Result<()> {
+    let chunk_dir = target_dir.as_ref().join(CHUNK_DIR_NAME);
+    if !chunk_dir.is_dir() {
+        info!("No chunk store found in {}.", target_dir.as_ref().display());
+        return Ok(());
+    }
+    let _lock = TargetLock::acquire(target_dir.as_ref(), Duration::ZERO)?;
+
+    let mut referenced = HashSet::new();
+    let mut dirs = vec![(target_dir.as_ref().to_path_buf(), 0)];
+    while let Some((dir, depth)) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_file() && is_manifest(&path) {
+                referenced.extend(read_manifest(&path)?);
+            } else if path.is_dir() && depth < 2 && is_layout_dir_name(&path, 4 - depth * 2) {
+                dirs.push((path, depth + 1));
+            }
+        }
+    }
+    info!("{} chunks are referenced by manifests.", referenced.len());
+
+    let mut removed_bytes = 0;
+    let mut audit_entries = vec![];
+    let result = (|| -> Result<()> {
+        for prefix_entry in std::fs::read_dir(&chunk_dir)? {
+            let prefix_path = prefix_entry?.path();
+            if !prefix_path.is_dir() {
+                warn!("Unexpected file in chunk store: {}", prefix_path.display());
+                continue;
+            }
+
+            for chunk_entry in std::fs::read_dir(&prefix_path)? {
+                let chunk_entry = chunk_entry?;
+                let name = chunk_entry.file_name().to_string_lossy().into_owned();
+                if referenced.contains(&name) {
+                    continue;
+                }
+
+                removed_bytes += chunk_entry.metadata()?.len();
+                // Chunks are named by the hash of their content.
+                let entry = audit_entry(
+                    Operation::Delete,
+                    "Chunk not referenced by any manifest",
+                    &chunk_entry.path(),
+                    Some(name.clone()),
+                );
+                std::fs::remove_file(chunk_entry.path())
+                    .wrap_err_with(|| format!("Failed to remove chunk {}", name))?;
+                audit_entries.push(entry);
+            }
+
+            if std::fs::read_dir(&prefix_path)?.next().is_none() {
+                std::fs::remove_dir(&prefix_path)?;
+            }
+        }
+        Ok(())
+    })();
+
+    // Chunks removed before a failure are recorded as well.
+    let mut conn = open_db(target_dir.as_ref())?;
+    record_audit(&mut conn, target_dir.as_ref(), &audit_entries, false)?;
+    result?;
+    let removed_count = audit_entries.len();
+
+    info!(
+        "Removed {} unreferenced chunks ({} bytes).",
+        removed_count, removed_bytes
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backup::test_util::TempDir;
+
+    #[test]
+    fn test_parse_manifest_valid() {
+        let hash = "A".repeat(64);
+        let content = format!("{}\n{} 42\n{} 7\n", MANIFEST_HEADER, hash, hash);
+
+        assert_eq!(parse_manifest(&content).unwrap(), vec![hash.clone(), hash]);
+    }
+
+    #[test]
+    fn test_parse_manifest_invalid() {
+        assert!(parse_manifest("not a manifest\n").is_err());
+        assert!(parse_manifest(&format!("{}\nXYZ 42\n", MANIFEST_HEADER)).is_err());
+    }
+
+    #[test]
+    fn test_is_manifest() {
+        let dir = TempDir::new("is-manifest");
+        let source = dir.join("notes.txt");
+        std::fs::write(&source, "notes").unwrap();
+        let manifest = dir.join("2025-01-01_00_notes.txt.chunks");
+        store_chunked(&source, &dir, &manifest).unwrap();
+        let plain = dir.join("2025-01-02_00_foo.chunks");
+        std::fs::write(&plain, MANIFEST_HEADER).unwrap();
+
+        let is_manifest_backup = is_manifest(&manifest);
+        let is_plain_manifest = is_manifest(&plain);
+        let is_missing_manifest = is_manifest(dir.join("2025-01-03_00_foo.chunks"));
+
+        assert!(is_manifest_backup);
+        assert!(!is_plain_manifest);
+        assert!(!is_missing_manifest);
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use color_eyre::{
+    Section,
+    eyre::{Context, ContextCompat, Result, bail},
+};
+use log::info;
+use sha2::{Digest, Sha256};
+
+/// File to back up, or data streamed in under a virtual file name.
+#[derive(Debug, Clone)]
+pub enum Source {
+    File(PathBuf),
+    Stream { input: StreamInput, name: PathBuf },
+}
+
+impl Source {
+    /// Path the backup is named after.
+    pub fn name(&self) -> &Path {
+        match self {
+            Source::File(path) => path,
+            Source::Stream { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum StreamInput {
+    Stdin,
+    /// Shell command whose output is backed up.
+    Command(String),
+}
+
+/// Reader hashing everything read through it.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+fn shell_command(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    }
+}
+
+/// Copies `reader` into the new file at `target_file_path`.
+///
+/// Returns the hash of the copied data.
+fn write_hashed(reader: impl Read, target_file_path: &Path) -> Result<String> {
+    let mut reader = HashingReader {
+        inner: reader,
+        hasher: Sha256::new(),
+    };
+    let mut writer = BufWriter::new(
+        File::create(target_file_path)
+            .wrap_err("Failed to create target file.")
+            .suggestion("Check if you have permissions to write to the target dir.")?,
+    );
+
+    let len = io::copy(&mut reader, &mut writer).wrap_err("Failed to write target file.")?;
+    writer.flush().wrap_err("Failed to write target file.")?;
+    info!("Finished writing {} bytes.", len);
+
+    Ok(hex::encode_upper(reader.hasher.finalize()))
+}
+
+/// Streams the input into the new file at `target_file_path` without intermediate file.
+///
+/// Returns the hash of the streamed data. The target file is removed if streaming fails.
+pub fn store_stream(input: &StreamInput, target_file_path: &Path) -> Result<String> {
+    let hash = match input {
+        StreamInput::Stdin => {
+            info!("Writing stdin to '{}'", target_file_path.display());
+            write_hashed(io::stdin().lock(), target_file_path)
+        }
+        StreamInput::Command(command) => {
+            info!(
+                "Writing output of `{}` to '{}'",
+                command,
+                target_file_path.display()
+            );
+            store_command_output(command, target_file_path)
+        }
+    };
+
+    if hash.is_err() && target_file_path.exists() {
+        std::fs::remove_file(target_file_path)
+            .wrap_err("Failed to remove incomplete target file.")?;
+    }
+
+    hash
+}
+
+fn store_command_output(command: &str, target_file_path: &Path) -> Result<String> {
+    let mut child = shell_command(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .wrap_err("Failed to run source command.")?;
+
+    let hash = write_hashed(
+        child
+            .stdout
+            .take()
+            .wrap_err("Failed to capture output of source command.")?,
+        target_file_path,
+    );
+    let status = child.wait().wrap_err("Failed to run source command.")?;
+    if !status.success() {
+        bail!("Source command failed with {}.", status);
+    }
+
+    hash
+}
@@ -0,0 +1,279 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::ffi::{OsStr, OsString};
+
+use color_eyre::eyre::{Result, bail, ensure};
+use regex::Regex;
+
+pub const DEFAULT_NAME_TEMPLATE: &str = "{date}_{counter}_{basename}.{ext}";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Date,
+    Counter,
+    Hostname,
+    Basename,
+    /// Extension, optionally prefixed by a dot which is omitted if the source has no extension.
+    Ext {
+        dot: bool,
+    },
+}
+
+/// File name template used to name backups and to parse their names back.
+#[derive(Debug, Clone)]
+pub struct NameTemplate {
+    template: String,
+    parts: Vec<Part>,
+    regex: Regex,
+}
+
+impl NameTemplate {
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut parts = vec![];
+        let mut rest = template;
+
+        while !rest.is_empty() {
+            match rest.find('{') {
+                Some(0) => {
+                    let Some(end) = rest.find('}') else {
+                        bail!("Unclosed placeholder in name template.");
+                    };
+                    let part = match &rest[1..end] {
+                        "date" => Part::Date,
+                        "counter" => Part::Counter,
+                        "hostname" => Part::Hostname,
+                        "basename" => Part::Basename,
+                        "ext" => match parts.last_mut() {
+                            Some(Part::Literal(literal)) if literal.ends_with('.') => {
+                                literal.pop();
+                                if literal.is_empty() {
+                                    parts.pop();
+                                }
+                                Part::Ext { dot: true }
+                            }
+                            _ => Part::Ext { dot: false },
+                        },
+                        other => bail!("Unknown placeholder in name template: {{{}}}", other),
+                    };
+                    parts.push(part);
+                    rest = &rest[end + 1..];
+                }
+                Some(start) => {
+                    parts.push(Part::Literal(rest[..start].to_owned()));
+                    rest = &rest[start..];
+                }
+                None => {
+                    parts.push(Part::Literal(rest.to_owned()));
+                    rest = "";
+                }
+            }
+        }
+
+        ensure!(
+            parts.contains(&Part::Date),
+            "Name template must contain {{date}}."
+        );
+        ensure!(
+            parts.contains(&Part::Counter),
+            "Name template must contain {{counter}}."
+        );
+        ensure!(
+            !parts.iter().any(
+                |part| matches!(part, Part::Literal(literal) if literal.contains(['/', '\\']))
+            ),
+            "Name template must not contain path separators."
+        );
+
+        let mut pattern = String::from("^");
+        for part in &parts {
+            match part {
+                Part::Literal(literal) => pattern.push_str(&regex::escape(literal)),
+                Part::Date => pattern.push_str(r"(?<year>\d{4})\-(?<month>\d{2})\-(?<day>\d{2})"),
+                Part::Counter => pattern.push_str(r"(?<counter>\d{2})"),
+                Part::Hostname => pattern.push_str(r"(?<hostname>.+?)"),
+                Part::Basename => pattern.push_str(r"(?<basename>.+?)"),
+                Part::Ext { dot: true } => pattern.push_str(r"(?:\.(?<ext>.+?))?"),
+                Part::Ext { dot: false } => pattern.push_str(r"(?<ext>.*?)"),
+            }
+        }
+        pattern.push('$');
+
+        Ok(Self {
+            template: template.to_owned(),
+            parts,
+            regex: Regex::new(&pattern)?,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+
+    /// Regex matching whole file names with the named groups `year`, `month`, `day` and `counter`,
+    /// as well as `hostname`, `basename` and `ext` if present in the template.
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    /// File name of the source the file was named after, its base name joined with its whole
+    /// extension, if the template contains `{basename}`.
+    ///
+    /// Unlike the base name, which ends at the first dot, this tells sources like
+    /// `report.2024.xlsx` and `report.2025.xlsx` apart.
+    pub fn source_name(&self, file_name: &str) -> Option<String> {
+        let captures = self.regex.captures(file_name)?;
+        let mut name = captures.name("basename")?.as_str().to_owned();
+        if let Some(ext) = captures.name("ext") {
+            if self.parts.contains(&Part::Ext { dot: true }) {
+                name.push('.');
+            }
+            name.push_str(ext.as_str());
+        }
+
+        Some(name)
+    }
+
+    pub fn render(
+        &self,
+        date: &str,
+        counter: u32,
+        hostname: &OsStr,
+        base_name: &OsStr,
+        extension: Option<&OsStr>,
+    ) -> OsString {
+        let mut file_name = OsString::new();
+
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => file_name.push(literal),
+                Part::Date => file_name.push(date),
+                Part::Counter => file_name.push(format!("{:02}", counter)),
+                Part::Hostname => file_name.push(hostname),
+                Part::Basename => file_name.push(base_name),
+                Part::Ext { dot } => {
+                    if let Some(ext) = extension {
+                        if *dot {
+                            file_name.push(".");
+                        }
+                        file_name.push(ext);
+                    }
+                }
+            }
+        }
+
+        file_name
+    }
+}
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        Self::parse(DEFAULT_NAME_TEMPLATE).expect("Failed parsing default name template")
+    }
+}
+
+pub fn hostname() -> OsString {
+    gethostname::gethostname()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render_default() {
+        let template = NameTemplate::default();
+
+        assert_eq!(
+            template.render(
+                "2025-09-27",
+                3,
+                OsStr::new("host"),
+                OsStr::new("file1"),
+                Some(OsStr::new("txt"))
+            ),
+            OsString::from("2025-09-27_03_file1.txt")
+        );
+        assert_eq!(
+            template.render(
+                "2025-09-27",
+                3,
+                OsStr::new("host"),
+                OsStr::new("file1"),
+                None
+            ),
+            OsString::from("2025-09-27_03_file1")
+        );
+    }
+
+    #[test]
+    fn test_render_and_match_hostname() {
+        let template = NameTemplate::parse("{date}_{counter}_{hostname}_{basename}.{ext}").unwrap();
+
+        let name = template.render(
+            "2025-09-27",
+            12,
+            OsStr::new("desktop"),
+            OsStr::new("save"),
+            Some(OsStr::new("dat")),
+        );
+        assert_eq!(name, OsString::from("2025-09-27_12_desktop_save.dat"));
+
+        let captures = template
+            .regex()
+            .captures("2025-09-27_12_desktop_save.dat")
+            .unwrap();
+        assert_eq!(&captures["year"], "2025");
+        assert_eq!(&captures["counter"], "12");
+    }
+
+    #[test]
+    fn test_match_whole_name() {
+        let template = NameTemplate::default();
+
+        let captures = template
+            .regex()
+            .captures("2025-09-27_03_file1.txt.delta")
+            .unwrap();
+        assert_eq!(&captures["basename"], "file1");
+        assert_eq!(&captures["ext"], "txt.delta");
+    }
+
+    #[test]
+    fn test_source_name() {
+        let template = NameTemplate::default();
+        assert_eq!(
+            template.source_name("2025-01-01_00_report.2024.xlsx"),
+            Some("report.2024.xlsx".to_owned())
+        );
+        assert_eq!(
+            template.source_name("2025-01-01_00_Makefile"),
+            Some("Makefile".to_owned())
+        );
+
+        let template = NameTemplate::parse("{date}_{counter}_{basename}{ext}").unwrap();
+        assert_eq!(
+            template.source_name("2025-01-01_00_notes"),
+            Some("notes".to_owned())
+        );
+        assert_eq!(
+            NameTemplate::parse("{date}_{counter}")
+                .unwrap()
+                .source_name("2025-01-01_00"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(NameTemplate::parse("{basename}.{ext}").is_err());
+        assert!(NameTemplate::parse("{date}_{basename}").is_err());
+        assert!(NameTemplate::parse("{date}_{counter}_{unknown}").is_err());
+        assert!(NameTemplate::parse("{date}_{counter}_{basename").is_err());
+        assert!(NameTemplate::parse("{date}/{counter}_{basename}").is_err());
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use crate::model::{BackupFile, PathBufSql, UuidSQL};
+
+/// Temporary folder of a test, removed when dropped, even if the test panics.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Creates an empty folder named after the test and the process.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("sfb-{}-test-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).expect("Failed creating temporary folder");
+        Self { path }
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Tracked row of the backup at the path relative to the target folder, in no retention tier.
+pub fn tracked_row(relative_path: impl Into<PathBuf>) -> BackupFile {
+    BackupFile {
+        uuid: UuidSQL::new(),
+        relative_path: PathBufSql {
+            path: relative_path.into(),
+        },
+        keep_yearly: false,
+        keep_monthly: false,
+        keep_daily: false,
+        keep_latest: false,
+        hash: None,
+        last_verified: None,
+        tags: None,
+        comment: None,
+        cold_target: None,
+        source_path: None,
+        hostname: None,
+        tool_version: None,
+        created_at: None,
+        quarantined_at: None,
+        keep_weekly: false,
+        keep_quarterly: false,
+        promoted_tier: None,
+    }
+}
+
+/// Tracked row of the backup, kept by every retention tier.
+pub fn tracked_row_in_all_tiers(relative_path: impl Into<PathBuf>) -> BackupFile {
+    BackupFile {
+        keep_yearly: true,
+        keep_monthly: true,
+        keep_daily: true,
+        keep_latest: true,
+        keep_weekly: true,
+        keep_quarterly: true,
+        ..tracked_row(relative_path)
+    }
+}
@@ -0,0 +1,83 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    io::{self, Read},
+    time::{Duration, Instant},
+};
+
+/// Largest read waited for at once, so that slow rates still transfer steadily.
+const MAX_READ: usize = 64 * 1024;
+
+/// Reader limited to a rate in bytes per second by a token bucket holding up to one second of
+/// transfer.
+pub struct Throttle<R> {
+    inner: R,
+    rate: u64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl<R: Read> Throttle<R> {
+    pub fn new(inner: R, rate: u64) -> Self {
+        Self {
+            inner,
+            rate: rate.max(1),
+            tokens: 0.0,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        self.last_refill = now;
+    }
+}
+
+impl<R: Read> Read for Throttle<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.refill();
+        let wanted = buf
+            .len()
+            .min(MAX_READ)
+            .min(usize::try_from(self.rate).unwrap_or(MAX_READ));
+        if self.tokens < wanted as f64 {
+            std::thread::sleep(Duration::from_secs_f64(
+                (wanted as f64 - self.tokens) / self.rate as f64,
+            ));
+            self.refill();
+        }
+
+        let allowed = (self.tokens as usize).clamp(1, wanted);
+        let read = self.inner.read(&mut buf[..allowed])?;
+        self.tokens -= read as f64;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_throttle() {
+        let data = vec![7u8; 256 * 1024];
+        let start = Instant::now();
+        let mut copy = vec![];
+        Throttle::new(data.as_slice(), 1024 * 1024)
+            .read_to_end(&mut copy)
+            .unwrap();
+
+        assert_eq!(copy, data);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+}
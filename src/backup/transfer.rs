@@ -0,0 +1,192 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    Section,
+    eyre::{Context, Result, bail, eyre},
+};
+use log::info;
+
+use crate::{
+    backup::{
+        copy::PARTIAL_DIR_NAME,
+        db::{DB_NAME, clear_cold_targets, cold_backup_files, open_db},
+        file::{Preserve, preserve_metadata},
+        hash::hash_file,
+        latest::symlink,
+        lock::LOCK_FILE_NAME,
+        sidecar::companion_paths,
+    },
+    error_code::{ErrorCode, WithErrorCode},
+};
+
+/// Paths of all files below the folder, relative to it, except partial copies of interrupted
+/// backups and the lock of the folder.
+pub fn relative_file_paths(dir: &Path, relative_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for entry in std::fs::read_dir(dir.join(relative_dir))
+        .wrap_err_with(|| format!("Failed to read folder {}", dir.join(relative_dir).display()))?
+    {
+        let entry = entry?;
+        if entry.file_name() == PARTIAL_DIR_NAME
+            || entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(LOCK_FILE_NAME)
+        {
+            continue;
+        }
+        let relative_path = relative_dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            paths.extend(relative_file_paths(dir, &relative_path)?);
+        } else {
+            paths.push(relative_path);
+        }
+    }
+    Ok(paths)
+}
+
+fn hash_path(path: &Path) -> Result<String> {
+    hash_file(&mut File::open(path).wrap_err_with(|| format!("Failed to open {}", path.display()))?)
+}
+
+/// Copies the file, or recreates the symlink, and checks that the copy matches the original.
+fn copy_verified(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)
+            .wrap_err_with(|| format!("Failed to create folder {}", parent.display()))?;
+    }
+
+    if from.symlink_metadata()?.is_symlink() {
+        let original = std::fs::read_link(from)?;
+        if symlink(&original, to).is_ok() {
+            return Ok(());
+        }
+    }
+
+    std::fs::copy(from, to).wrap_err_with(|| format!("Failed to copy {}", from.display()))?;
+    preserve_metadata(from, to, &[Preserve::Mtime])?;
+
+    if hash_path(from)? != hash_path(to)? {
+        return Err(eyre!(
+            "Copy {} does not match {}.",
+            to.display(),
+            from.display()
+        ))
+        .error_code(ErrorCode::CopyMismatch);
+    }
+
+    Ok(())
+}
+
+/// Copies the whole backup set with hash files, chunk store, list of backups and tracking
+/// database into an empty folder, verifying each copied file.
+///
+/// Backups in cold storage are copied back into the folder, so that the copy is self-contained.
+/// Returns the number of copied files.
+pub fn copy_backup_set(from: &Path, to: &Path) -> Result<usize> {
+    if !from.join(DB_NAME).is_file() {
+        return Err(eyre!(
+            "{} is not a backup folder, as it contains no tracking database.",
+            from.display()
+        ))
+        .suggestion("Give the target folder of the backups.");
+    }
+    if to.try_exists()? && std::fs::read_dir(to)?.next().is_some() {
+        return Err(eyre!("Destination {} is not empty.", to.display()))
+            .suggestion("Copy backup sets into an empty folder, to not mix their histories.");
+    }
+    if to.starts_with(from) {
+        bail!("Destination must not be inside the backup folder.");
+    }
+
+    let mut files: Vec<(PathBuf, PathBuf)> = relative_file_paths(from, Path::new(""))?
+        .into_iter()
+        .map(|relative_path| (from.join(&relative_path), to.join(relative_path)))
+        .collect();
+
+    let mut conn = open_db(from)?;
+    for row in cold_backup_files(&mut conn)? {
+        let Some(cold_target) = row.cold_target else {
+            continue;
+        };
+        let backup_path = cold_target.join(&*row.relative_path);
+        if !backup_path.is_file() {
+            continue;
+        }
+        let cold_files = std::iter::once(backup_path.clone())
+            .chain(companion_paths(&backup_path))
+            .filter(|path| path.exists());
+        for path in cold_files {
+            let destination = to.join(path.strip_prefix(&*cold_target)?);
+            files.push((path, destination));
+        }
+    }
+    drop(conn);
+
+    for (from_path, to_path) in &files {
+        info!("COPY: {}", from_path.display());
+        copy_verified(from_path, to_path)?;
+    }
+
+    let mut conn = open_db(to)?;
+    clear_cold_targets(&mut conn)?;
+
+    Ok(files.len())
+}
+
+/// Copies the backup set of the target folder to another location, e.g. an external drive.
+pub fn export(target: PathBuf, destination: PathBuf) -> Result<()> {
+    info!(
+        "Exporting backups of {} to {}",
+        target.display(),
+        destination.display()
+    );
+    let count = copy_backup_set(&target, &destination).wrap_err("Failed to export backups.")?;
+    info!("Exported and verified {} files.", count);
+    Ok(())
+}
+
+/// Copies an exported backup set into the target folder, keeping its retention history.
+pub fn import(source: PathBuf, target: PathBuf) -> Result<()> {
+    info!(
+        "Importing backups of {} into {}",
+        source.display(),
+        target.display()
+    );
+    let count = copy_backup_set(&source, &target).wrap_err("Failed to import backups.")?;
+    info!("Imported and verified {} files.", count);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_copy_backup_set() {
+        let dir = std::env::temp_dir().join(format!("sfb-transfer-test-{}", std::process::id()));
+        let from = dir.join("from");
+        let to = dir.join("to");
+        std::fs::create_dir_all(from.join("2025")).unwrap();
+        open_db(&from).unwrap();
+        std::fs::write(from.join("2025").join("2025-01-01_00_db.sql"), b"backup").unwrap();
+
+        let count = copy_backup_set(&from, &to).unwrap();
+        let copy = std::fs::read(to.join("2025").join("2025-01-01_00_db.sql")).unwrap();
+        let into_non_empty = copy_backup_set(&from, &to);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(copy, b"backup");
+        assert!(into_non_empty.is_err());
+    }
+}
@@ -0,0 +1,568 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, Local, Utc};
+use clap::ValueEnum;
+use color_eyre::{
+    Section,
+    eyre::{Context, ContextCompat, Result, bail, eyre},
+};
+use diesel::SqliteConnection;
+use log::{info, warn};
+use trash::{TrashItem, TrashItemSize};
+
+use crate::{
+    backup::{
+        BackupOptions,
+        audit::{Operation, audit_entry, file_audit_entry, record_audit},
+        chain::{ChainEvent, ChainOperation, append_chain},
+        cold::relative_backup_path,
+        db::{
+            all_pruned_files, delete_pruned_files, forget_pruned_files, insert_pruned_files,
+            last_pruned_files, open_db,
+        },
+        list::{format_duration, format_size},
+        lock::TargetLock,
+        sidecar::is_companion,
+    },
+    model::{PathBufSql, PrunedFile, UuidSQL},
+};
+
+// Listing and restoring the recycle bin is not supported on macOS.
+#[cfg(any(
+    windows,
+    all(
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    )
+))]
+use trash::os_limited::{
+    list as list_trash, metadata as trash_metadata, purge_all as purge_trash,
+    restore_all as restore_trash,
+};
+
+#[cfg(not(any(
+    windows,
+    all(
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    )
+)))]
+mod unsupported {
+    use trash::{Error, TrashItem, TrashItemMetadata};
+
+    pub fn list_trash() -> Result<Vec<TrashItem>, Error> {
+        Err(Error::Unknown {
+            description: "Listing the recycle bin is not supported on this platform.".to_owned(),
+        })
+    }
+
+    pub fn restore_trash(_items: Vec<TrashItem>) -> Result<(), Error> {
+        Err(Error::Unknown {
+            description: "Restoring from the recycle bin is not supported on this platform."
+                .to_owned(),
+        })
+    }
+
+    pub fn purge_trash(_items: Vec<TrashItem>) -> Result<(), Error> {
+        Err(Error::Unknown {
+            description: "Emptying the recycle bin is not supported on this platform.".to_owned(),
+        })
+    }
+
+    pub fn trash_metadata(_item: &TrashItem) -> Result<TrashItemMetadata, Error> {
+        Err(Error::Unknown {
+            description: "Reading the recycle bin is not supported on this platform.".to_owned(),
+        })
+    }
+}
+#[cfg(not(any(
+    windows,
+    all(
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    )
+)))]
+use unsupported::{list_trash, purge_trash, restore_trash, trash_metadata};
+
+/// Picks the most recently trashed item of each original path.
+fn newest_by_original_path(items: Vec<TrashItem>) -> HashMap<PathBuf, TrashItem> {
+    let mut newest: HashMap<PathBuf, TrashItem> = HashMap::new();
+    for item in items {
+        let original_path = item.original_path();
+        if newest
+            .get(&original_path)
+            .is_none_or(|other| other.time_deleted < item.time_deleted)
+        {
+            newest.insert(original_path, item);
+        }
+    }
+
+    newest
+}
+
+/// Finds the items of the pruned files in the recycle bin, by their recorded identifier or else as
+/// the most recently trashed item of their original path. Each item is found for one file at most.
+fn find_trash_items(
+    pruned_files: &[PrunedFile],
+    trash_items: Vec<TrashItem>,
+) -> Vec<Option<TrashItem>> {
+    let mut items_by_id: HashMap<PathBuf, TrashItem> = trash_items
+        .iter()
+        .map(|item| (PathBuf::from(&item.id), item.clone()))
+        .collect();
+    let mut items_by_original_path = newest_by_original_path(trash_items);
+
+    let mut found: Vec<Option<TrashItem>> = pruned_files
+        .iter()
+        .map(|file| {
+            file.trash_id
+                .as_ref()
+                .and_then(|id| items_by_id.remove(&id.path))
+        })
+        .collect();
+    let found_ids: HashSet<PathBuf> = found
+        .iter()
+        .flatten()
+        .map(|item| PathBuf::from(&item.id))
+        .collect();
+    for (file, item) in pruned_files.iter().zip(&mut found) {
+        if item.is_none() {
+            *item = items_by_original_path
+                .remove(&file.original_path.path)
+                .filter(|other| !found_ids.contains(Path::new(&other.id)));
+        }
+    }
+
+    found
+}
+
+fn format_pruned_at(pruned_at: i64) -> String {
+    DateTime::from_timestamp_millis(pruned_at)
+        .map(|time| {
+            time.with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_default()
+}
+
+/// Prints the files moved into the recycle bin by prunes of the target folder that are still
+/// there, oldest first.
+pub fn list_trashed(target: impl AsRef<Path>) -> Result<()> {
+    let mut conn = open_db(target.as_ref())?;
+    let pruned_files = all_pruned_files(&mut conn)?;
+    let trash_items = list_trash().wrap_err("Failed to list recycle bin.")?;
+
+    let mut missing = 0;
+    for (file, item) in pruned_files
+        .iter()
+        .zip(find_trash_items(&pruned_files, trash_items))
+    {
+        let Some(item) = item else {
+            missing += 1;
+            continue;
+        };
+        let size = match trash_metadata(&item).map(|metadata| metadata.size) {
+            Ok(TrashItemSize::Bytes(size)) => format_size(size),
+            Ok(TrashItemSize::Entries(entries)) => format!("{} entries", entries),
+            Err(_) => String::new(),
+        };
+        println!(
+            "{}  {:>10}  {}",
+            format_pruned_at(file.pruned_at),
+            size,
+            file.original_path.display()
+        );
+    }
+    if missing > 0 {
+        info!(
+            "{} pruned files are no longer in the recycle bin. Purge them to forget them.",
+            missing
+        );
+    }
+
+    Ok(())
+}
+
+/// Removes the files moved into the recycle bin by prunes of the target folder longer ago than
+/// `older_than` from the recycle bin for good.
+///
+/// Files no longer in the recycle bin are forgotten as well.
+pub fn purge_trashed(target: impl AsRef<Path>, older_than: Duration) -> Result<()> {
+    let _lock = TargetLock::acquire(target.as_ref(), Duration::ZERO)?;
+    let mut conn = open_db(target.as_ref())?;
+    let cutoff =
+        Utc::now().timestamp_millis() - i64::try_from(older_than.as_millis()).unwrap_or(i64::MAX);
+    let pruned_files: Vec<PrunedFile> = all_pruned_files(&mut conn)?
+        .into_iter()
+        .filter(|file| file.pruned_at <= cutoff)
+        .collect();
+    if pruned_files.is_empty() {
+        info!(
+            "No files pruned longer than {} ago.",
+            format_duration(older_than)
+        );
+        return Ok(());
+    }
+
+    let trash_items = list_trash().wrap_err("Failed to list recycle bin.")?;
+    let reason = format!(
+        "Purged from the recycle bin, pruned longer than {} ago",
+        format_duration(older_than)
+    );
+    let mut items = vec![];
+    let mut audit_entries = vec![];
+    for (file, item) in pruned_files
+        .iter()
+        .zip(find_trash_items(&pruned_files, trash_items))
+    {
+        match item {
+            Some(item) => {
+                items.push(item);
+                audit_entries.push(audit_entry(
+                    Operation::Delete,
+                    reason.as_str(),
+                    &file.original_path.path,
+                    None,
+                ));
+            }
+            None => info!(
+                "{} is no longer in the recycle bin.",
+                file.original_path.display()
+            ),
+        }
+    }
+
+    if !items.is_empty() {
+        purge_trash(items).wrap_err("Failed to purge files from recycle bin.")?;
+    }
+    audit_entries
+        .iter()
+        .for_each(|entry| info!("PURGED: {}", entry.path.display()));
+    record_audit(&mut conn, target.as_ref(), &audit_entries, false)?;
+    let uuids: Vec<UuidSQL> = pruned_files.into_iter().map(|file| file.uuid).collect();
+    forget_pruned_files(&mut conn, &uuids)?;
+    info!("Purged {} files.", audit_entries.len());
+    info!("DONE!");
+
+    Ok(())
+}
+
+/// Name of the empty file moved into the recycle bin by [`check_trash`].
+const TRASH_PROBE_NAME: &str = ".sfb-trash-probe";
+
+/// Checks that pruned backups can be moved into the recycle bin, by moving an empty file there.
+///
+/// The file is purged from the recycle bin again, where the platform supports listing it.
+pub fn check_trash(target: impl AsRef<Path>) -> Result<()> {
+    probe_trash(target.as_ref())?.wrap_err("Failed to move file into recycle bin.")
+}
+
+/// Moves an empty file into the recycle bin, returning the error of the recycle bin separately.
+fn probe_trash(target: &Path) -> Result<std::result::Result<(), trash::Error>> {
+    let probe_path = std::fs::canonicalize(target)?.join(TRASH_PROBE_NAME);
+    std::fs::write(&probe_path, b"")
+        .wrap_err_with(|| format!("Failed to create {}", probe_path.display()))?;
+    if let Err(err) = trash::delete(&probe_path) {
+        let _ = std::fs::remove_file(&probe_path);
+        return Ok(Err(err));
+    }
+
+    if let Ok(items) = list_trash() {
+        let probe_items: Vec<TrashItem> = items
+            .into_iter()
+            .filter(|item| item.original_path() == probe_path)
+            .collect();
+        if let Err(err) = purge_trash(probe_items) {
+            warn!(
+                "Failed to remove {} from recycle bin: {}",
+                TRASH_PROBE_NAME, err
+            );
+        }
+    }
+
+    Ok(Ok(()))
+}
+
+/// What pruning does when the recycle bin is unavailable, e.g. on headless Linux or network shares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnNoTrash {
+    /// Fail, keeping the files
+    Fail,
+    /// Remove the files for good
+    Delete,
+    /// Move the files into the folder of `--archive-dir`
+    ArchiveDir,
+}
+
+/// Moves the file into the folder, copying it if the folder is on another file system.
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)
+            .wrap_err_with(|| format!("Failed to create folder {}", parent.display()))?;
+    }
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to)
+            .wrap_err_with(|| format!("Failed to copy {} to {}", from.display(), to.display()))?;
+        std::fs::remove_file(from)
+            .wrap_err_with(|| format!("Failed to remove {}", from.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Moves the files of the target folder into the recycle bin.
+///
+/// If that fails and the recycle bin turns out to be unavailable for the target folder, the files
+/// are handled as configured by [`OnNoTrash`] instead. Returns what was done with the files.
+pub fn trash_files(
+    target: &Path,
+    paths: &[PathBuf],
+    description: &str,
+    options: &BackupOptions,
+) -> Result<Operation> {
+    let Err(err) = options.retry.run(description, || trash::delete_all(paths)) else {
+        return Ok(Operation::Trash);
+    };
+    // The recycle bin works, so the files themselves are the problem, e.g. missing permissions.
+    if probe_trash(target)?.is_ok() {
+        return Err(err).wrap_err("Failed to move files into recycle bin.");
+    }
+
+    // Files moved before the failure stay in the recycle bin.
+    let remaining_paths: Vec<&PathBuf> = paths.iter().filter(|path| path.exists()).collect();
+    match options.on_no_trash {
+        OnNoTrash::Fail => Err(eyre!("{}", err))
+            .wrap_err("The recycle bin is unavailable for the target folder.")
+            .suggestion(
+                "Pass `--on-no-trash delete` to remove pruned files for good or `--on-no-trash archive-dir` to move them into another folder.",
+            ),
+        OnNoTrash::Delete => {
+            warn!(
+                "The recycle bin is unavailable ({}). Removing {} files for good.",
+                err,
+                remaining_paths.len()
+            );
+            for path in remaining_paths {
+                std::fs::remove_file(path)
+                    .wrap_err_with(|| format!("Failed to remove {}", path.display()))?;
+            }
+            Ok(Operation::Delete)
+        }
+        OnNoTrash::ArchiveDir => {
+            let archive_dir = options
+                .archive_dir
+                .as_deref()
+                .wrap_err("`--on-no-trash archive-dir` requires `--archive-dir`.")?;
+            warn!(
+                "The recycle bin is unavailable ({}). Moving {} files into {}.",
+                err,
+                remaining_paths.len(),
+                archive_dir.display()
+            );
+            for path in remaining_paths {
+                let relative_path = path
+                    .strip_prefix(target)
+                    .ok()
+                    .or_else(|| path.file_name().map(Path::new))
+                    .wrap_err_with(|| format!("Invalid path {}", path.display()))?;
+                move_file(path, &archive_dir.join(relative_path))?;
+            }
+            Ok(Operation::Archive)
+        }
+    }
+}
+
+/// Records the files moved into the recycle bin, so that the prune can be undone.
+///
+/// The paths have to be absolute, as recorded by the recycle bin.
+pub fn record_prune(conn: &mut SqliteConnection, original_paths: &[PathBuf]) -> Result<()> {
+    let pruned_at = Utc::now();
+    let trash_items = match list_trash() {
+        Ok(items) => newest_by_original_path(items),
+        Err(err) => {
+            warn!("Failed to list recycle bin: {}", err);
+            HashMap::new()
+        }
+    };
+
+    let prune_uuid = UuidSQL::new();
+    let files: Vec<PrunedFile> = original_paths
+        .iter()
+        .map(|path| PrunedFile {
+            uuid: UuidSQL::new(),
+            prune_uuid: prune_uuid.clone(),
+            pruned_at: pruned_at.timestamp_millis(),
+            original_path: PathBufSql { path: path.clone() },
+            trash_id: trash_items.get(path).map(|item| PathBufSql {
+                path: PathBuf::from(&item.id),
+            }),
+        })
+        .collect();
+
+    insert_pruned_files(conn, &files)
+}
+
+/// Restores the files moved into the recycle bin by the most recent prune of the target folder.
+pub fn undo_prune(target: impl AsRef<Path>) -> Result<()> {
+    let _lock = TargetLock::acquire(target.as_ref(), Duration::ZERO)?;
+    let mut conn = open_db(target.as_ref())?;
+    let pruned_files = last_pruned_files(&mut conn)?;
+    let Some(prune_uuid) = pruned_files.first().map(|file| file.prune_uuid.clone()) else {
+        info!("No prune recorded to undo.");
+        return Ok(());
+    };
+
+    let trash_items = list_trash().wrap_err("Failed to list recycle bin.")?;
+    let mut items = vec![];
+    for (file, item) in pruned_files
+        .iter()
+        .zip(find_trash_items(&pruned_files, trash_items))
+    {
+        match item {
+            Some(item) => items.push(item),
+            None => warn!(
+                "{} is no longer in the recycle bin.",
+                file.original_path.display()
+            ),
+        }
+    }
+    if items.is_empty() {
+        bail!("None of the files of the last prune are in the recycle bin anymore.");
+    }
+
+    let restored_paths: Vec<PathBuf> = items.iter().map(TrashItem::original_path).collect();
+    restore_trash(items)
+        .wrap_err("Failed to restore files from recycle bin.")
+        .suggestion("Move files in the way of the restored files out of the target folder.")?;
+    restored_paths
+        .iter()
+        .for_each(|path| info!("RESTORED: {}", path.display()));
+
+    let audit_entries = restored_paths
+        .iter()
+        .map(|path| {
+            file_audit_entry(
+                &mut conn,
+                target.as_ref(),
+                Operation::Restore,
+                "Undo of the last prune",
+                path,
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+    record_audit(&mut conn, target.as_ref(), &audit_entries, false)?;
+    let chain_events = restored_paths
+        .iter()
+        .zip(&audit_entries)
+        .filter(|(path, _)| !is_companion(path))
+        .map(|(path, entry)| {
+            Ok(ChainEvent::new(
+                ChainOperation::Add,
+                relative_backup_path(&mut conn, target.as_ref(), path)?,
+                entry.hash.clone().unwrap_or_default(),
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    append_chain(target.as_ref(), &chain_events, false)?;
+    delete_pruned_files(&mut conn, &prune_uuid)?;
+    info!("Restored {} files.", restored_paths.len());
+    info!("DONE!");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsStr;
+
+    use super::*;
+
+    fn item(id: &str, name: &str, time_deleted: i64) -> TrashItem {
+        TrashItem {
+            id: id.into(),
+            name: name.into(),
+            original_parent: PathBuf::from("/backups"),
+            time_deleted,
+        }
+    }
+
+    #[test]
+    fn test_newest_by_original_path() {
+        let newest = newest_by_original_path(vec![
+            item("a", "2025-01-01_00_db.sql", 10),
+            item("b", "2025-01-01_00_db.sql", 20),
+            item("c", "2025-01-02_00_db.sql", 5),
+        ]);
+
+        assert_eq!(newest.len(), 2);
+        assert_eq!(
+            newest[Path::new("/backups/2025-01-01_00_db.sql")].id,
+            OsStr::new("b")
+        );
+    }
+
+    #[test]
+    fn test_move_file() {
+        let dir = std::env::temp_dir().join(format!("sfb-move-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let from = dir.join("2025-01-01_00_db.sql");
+        let to = dir
+            .join("archive")
+            .join("2025")
+            .join("2025-01-01_00_db.sql");
+        std::fs::write(&from, b"backup").unwrap();
+
+        move_file(&from, &to).unwrap();
+        let content = std::fs::read(&to).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(!from.exists());
+        assert_eq!(content, b"backup");
+    }
+
+    #[test]
+    fn test_find_trash_items() {
+        let pruned_file = |name: &str, trash_id: Option<&str>| PrunedFile {
+            uuid: UuidSQL::new(),
+            prune_uuid: UuidSQL::new(),
+            pruned_at: 0,
+            original_path: PathBufSql {
+                path: Path::new("/backups").join(name),
+            },
+            trash_id: trash_id.map(|id| PathBufSql { path: id.into() }),
+        };
+        let found = find_trash_items(
+            &[
+                pruned_file("2025-01-01_00_db.sql", None),
+                pruned_file("2025-01-01_00_db.sql", Some("b")),
+                pruned_file("2025-01-02_00_db.sql", Some("gone")),
+                pruned_file("2025-01-03_00_db.sql", None),
+            ],
+            vec![
+                item("a", "2025-01-01_00_db.sql", 10),
+                item("b", "2025-01-01_00_db.sql", 20),
+                item("c", "2025-01-02_00_db.sql", 5),
+            ],
+        );
+
+        let ids: Vec<Option<&OsStr>> = found
+            .iter()
+            .map(|item| item.as_ref().map(|item| item.id.as_os_str()))
+            .collect();
+        assert_eq!(
+            ids,
+            [None, Some(OsStr::new("b")), Some(OsStr::new("c")), None]
+        );
+    }
+}
@@ -0,0 +1,356 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::Utc;
+use clap::ValueEnum;
+use color_eyre::eyre::{Context, Result, eyre};
+use diesel::SqliteConnection;
+use log::{error, info};
+
+use crate::{
+    backup::{
+        archive::{is_zip, write_zip_content},
+        chain::check_chain,
+        cleanup::BackupFile,
+        cold::{backups_of_target, relative_backup_path},
+        db::{backup_file_with_relative_path, open_db, set_last_verified},
+        lock::TargetLock,
+        quarantine::quarantine,
+        restore::write_backup_content,
+        sidecar::{Integrity, check_backup, check_backup_hash, sidecar_path},
+        signature::{load_public_key, verify_signature},
+        template::NameTemplate,
+    },
+    error_code::{ErrorCode, WithErrorCode},
+};
+
+/// How a new backup is checked against its source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum VerifyMode {
+    /// Compare the hashes of source and backup
+    Hash,
+    /// Compare the content of source and backup byte by byte
+    Bytes,
+    /// Compare both the hashes and the content
+    Both,
+}
+
+impl VerifyMode {
+    pub fn compares_hashes(self) -> bool {
+        self != Self::Bytes
+    }
+
+    pub fn compares_bytes(self) -> bool {
+        self != Self::Hash
+    }
+}
+
+/// Writer comparing everything written to it with the content of a reader.
+struct ComparingWriter<R> {
+    expected: R,
+    buffer: Vec<u8>,
+    offset: u64,
+    /// Describes the first difference, after which writing fails.
+    mismatch: Option<String>,
+}
+
+impl<R: Read> ComparingWriter<R> {
+    fn mismatch(&mut self, message: String) -> io::Error {
+        self.mismatch = Some(message.clone());
+        io::Error::other(message)
+    }
+}
+
+impl<R: Read> Write for ComparingWriter<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.resize(buf.len(), 0);
+        if let Err(err) = self.expected.read_exact(&mut self.buffer) {
+            return Err(match err.kind() {
+                ErrorKind::UnexpectedEof => self.mismatch(format!(
+                    "Backup is longer than the source of less than {} bytes.",
+                    self.offset + buf.len() as u64
+                )),
+                _ => err,
+            });
+        }
+        if let Some(index) = buf.iter().zip(&self.buffer).position(|(a, b)| a != b) {
+            return Err(self.mismatch(format!(
+                "Backup differs from the source at byte {}.",
+                self.offset + index as u64
+            )));
+        }
+        self.offset += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compares the original content of the backup with the source byte by byte, reassembling
+/// chunked, delta and zip backups.
+pub fn compare_backup_content(
+    target_dir: impl AsRef<Path>,
+    backup_path: impl AsRef<Path>,
+    source_path: impl AsRef<Path>,
+    zip_password: Option<&str>,
+    buffer_size: usize,
+) -> Result<()> {
+    let source = File::open(source_path.as_ref()).wrap_err("Failed to open source file.")?;
+    let mut writer = ComparingWriter {
+        expected: BufReader::with_capacity(buffer_size, source),
+        buffer: vec![],
+        offset: 0,
+        mismatch: None,
+    };
+
+    let result = if is_zip(backup_path.as_ref()) {
+        write_zip_content(backup_path.as_ref(), &mut writer, zip_password)
+    } else {
+        write_backup_content(target_dir, backup_path.as_ref(), &mut writer)
+    };
+    if let Some(mismatch) = writer.mismatch {
+        return Err(eyre!(mismatch)).error_code(ErrorCode::CopyMismatch);
+    }
+    result?;
+    if writer
+        .expected
+        .read(&mut [0])
+        .wrap_err("Failed to read source file.")?
+        > 0
+    {
+        return Err(eyre!(
+            "Backup is shorter than the source, ending after {} bytes.",
+            writer.offset
+        ))
+        .error_code(ErrorCode::CopyMismatch);
+    }
+
+    Ok(())
+}
+
+/// Checks the backup against its hash file, or against the hash in the tracking database if it has
+/// no hash file, e.g. with `--sidecar-format none`.
+pub fn check_tracked_backup(
+    conn: &mut SqliteConnection,
+    target_dir: &Path,
+    backup_path: &Path,
+) -> Result<Integrity> {
+    if !sidecar_path(backup_path).exists() {
+        let relative_path = relative_backup_path(conn, target_dir, backup_path)?;
+        if let Some(hash) =
+            backup_file_with_relative_path(conn, relative_path)?.and_then(|row| row.hash)
+        {
+            return Ok(check_backup_hash(target_dir, backup_path, &hash));
+        }
+    }
+
+    Ok(check_backup(target_dir, backup_path))
+}
+
+/// Verifies the backups against their hash files and records when they were verified.
+///
+/// Corrupted backups are moved into the quarantine folder. Returns the number of backups failing
+/// verification.
+fn verify_files(
+    conn: &mut SqliteConnection,
+    target_dir: &Path,
+    files: &[BackupFile],
+) -> Result<usize> {
+    let mut failed_count = 0;
+
+    for file in files {
+        info!("Verifying {}", file.path.display());
+        match check_tracked_backup(conn, target_dir, &file.path)? {
+            Integrity::Intact => {
+                let relative_path = relative_backup_path(conn, target_dir, &file.path)?;
+                set_last_verified(conn, relative_path, Utc::now().timestamp())?;
+            }
+            Integrity::Corrupted => {
+                error!("Backup {} is corrupted!", file.path.display());
+                failed_count += 1;
+                quarantine(conn, target_dir, &file.path)?;
+            }
+            Integrity::Misnamed => {
+                error!(
+                    "Backup {} failed verification! Use `repair-sidecars` to regenerate its hash file.",
+                    file.path.display()
+                );
+                failed_count += 1;
+            }
+            Integrity::Unknown => {
+                error!("Backup {} failed verification!", file.path.display());
+                failed_count += 1;
+            }
+        }
+    }
+
+    Ok(failed_count)
+}
+
+/// Verifies the `count` backups verified the longest time ago, starting with never verified ones.
+///
+/// The target folder has to be locked by the caller, as corrupted backups are quarantined.
+pub fn scrub(
+    conn: &mut SqliteConnection,
+    target_dir: impl AsRef<Path>,
+    files: &[BackupFile],
+    count: u32,
+) -> Result<()> {
+    let mut files_by_last_verified = files
+        .iter()
+        .map(|file| {
+            let relative_path = relative_backup_path(conn, target_dir.as_ref(), &file.path)?;
+            let row = backup_file_with_relative_path(conn, relative_path)?;
+            Ok((row.and_then(|row| row.last_verified), file))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    files_by_last_verified.sort_by_key(|(last_verified, _)| *last_verified);
+
+    let selected: Vec<BackupFile> = files_by_last_verified
+        .into_iter()
+        .take(usize::try_from(count)?)
+        .map(|(_, file)| file.clone())
+        .collect();
+
+    let failed_count = verify_files(conn, target_dir.as_ref(), &selected)?;
+    if failed_count > 0 {
+        return Err(eyre!(
+            "{} of {} scrubbed backups failed verification.",
+            failed_count,
+            selected.len()
+        ))
+        .error_code(ErrorCode::VerificationFailed);
+    }
+    info!("{} scrubbed backups passed verification.", selected.len());
+
+    Ok(())
+}
+
+/// Verifies the backups and records when they were verified.
+///
+/// Returns the number of backups failing verification.
+pub fn verify_backups(target_dir: impl AsRef<Path>, files: &[BackupFile]) -> Result<usize> {
+    // Corrupted backups are quarantined, which must not race a backup or prune.
+    let _lock = TargetLock::acquire(target_dir.as_ref(), Duration::ZERO)?;
+    let mut conn = open_db(target_dir.as_ref())?;
+    verify_files(&mut conn, target_dir.as_ref(), files)
+}
+
+/// Verifies all backups of the target folder against their hash files and optionally signatures
+/// and the chain of added and removed backups.
+pub fn verify(
+    target: PathBuf,
+    verify_key: Option<PathBuf>,
+    chain: bool,
+    name_template: &NameTemplate,
+) -> Result<()> {
+    let public_key = verify_key.map(load_public_key).transpose()?;
+    // Corrupted backups are quarantined, which must not race a backup or prune.
+    let _lock = TargetLock::acquire(&target, Duration::ZERO)?;
+    let mut conn = open_db(&target)?;
+
+    let mut backup_files = backups_of_target(&mut conn, &target, name_template)?;
+    backup_files.sort();
+
+    let failed_count = verify_files(&mut conn, &target, &backup_files)?;
+
+    let mut invalid_signature_count = 0;
+    if let Some(public_key) = &public_key {
+        for file in &backup_files {
+            if !verify_signature(public_key, &file.path) {
+                error!("Signature of backup {} is invalid!", file.path.display());
+                invalid_signature_count += 1;
+            }
+        }
+    }
+
+    let chain_problem_count = if chain {
+        check_chain(&mut conn, &target, public_key.as_ref())?
+    } else {
+        0
+    };
+
+    if failed_count > 0 {
+        return Err(eyre!(
+            "{} of {} backups failed verification.",
+            failed_count,
+            backup_files.len()
+        ))
+        .error_code(ErrorCode::VerificationFailed);
+    }
+    if invalid_signature_count > 0 {
+        return Err(eyre!(
+            "{} of {} backups have an invalid signature.",
+            invalid_signature_count,
+            backup_files.len()
+        ))
+        .error_code(ErrorCode::InvalidSignature);
+    }
+    if chain_problem_count > 0 {
+        return Err(eyre!(
+            "Found {} problems with the chain of the target folder.",
+            chain_problem_count
+        ))
+        .error_code(ErrorCode::ChainBroken);
+    }
+
+    info!("All {} backups passed verification.", backup_files.len());
+    info!("DONE!");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::exit_code::{ExitCode, exit_code_of};
+
+    #[test]
+    fn test_compare_backup_content() {
+        let dir = std::env::temp_dir().join(format!("sfb-compare-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("source.txt");
+        let backup = dir.join("2025-01-01_00_source.txt");
+        std::fs::write(&source, b"content").unwrap();
+        let compare = |content: &[u8]| {
+            std::fs::write(&backup, content).unwrap();
+            compare_backup_content(&dir, &backup, &source, None, 2)
+        };
+
+        assert!(compare(b"content").is_ok());
+        for content in [&b"contest"[..], b"content!", b"conte"] {
+            let err = compare(content).unwrap_err();
+            assert_eq!(exit_code_of(&err), ExitCode::VerificationFailed);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_verify_locks_target() {
+        let dir = std::env::temp_dir().join(format!("sfb-verify-lock-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let template = NameTemplate::default();
+
+        let lock = TargetLock::acquire(&dir, Duration::ZERO).unwrap();
+        let err = verify(dir.clone(), None, false, &template).unwrap_err();
+        assert_eq!(exit_code_of(&err), ExitCode::LockContention);
+        assert!(verify_backups(&dir, &[]).is_err());
+        drop(lock);
+        assert!(verify(dir.clone(), None, false, &template).is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    path::{Component, Path, PathBuf, Prefix},
+    process::Command,
+};
+
+use color_eyre::{
+    Section,
+    eyre::{Context, ContextCompat, Result, bail, ensure},
+};
+use log::{info, warn};
+
+/// Volume Shadow Copy of a Windows volume, deleted when dropped.
+///
+/// Allows reading files locked by other processes, like Outlook PST files.
+#[derive(Debug)]
+pub struct ShadowCopy {
+    id: String,
+    /// Device path of the snapshot, e.g. `\\?\GLOBALROOT\Device\HarddiskVolumeShadowCopy3`.
+    device: String,
+}
+
+/// Runs the PowerShell script and returns its output (Windows only).
+pub fn powershell(script: &str) -> Result<String> {
+    let output = Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", script])
+        .output()
+        .wrap_err("Failed to run PowerShell.")?;
+    ensure!(
+        output.status.success(),
+        "PowerShell failed with {}: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Splits an absolute Windows path into its volume, e.g. `C:\`, and the path on the volume.
+fn split_volume(path: &Path) -> Option<(String, PathBuf)> {
+    let mut components = path.components();
+    let volume = match components.next()? {
+        Component::Prefix(prefix) => match prefix.kind() {
+            Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => {
+                format!("{}:\\", letter as char)
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    Some((
+        volume,
+        components
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect(),
+    ))
+}
+
+impl ShadowCopy {
+    /// Creates a snapshot of the volume containing the file.
+    ///
+    /// Requires Windows and administrator privileges.
+    pub fn create_for(path: impl AsRef<Path>) -> Result<Self> {
+        if !cfg!(windows) {
+            bail!("Volume Shadow Copies are only supported on Windows.");
+        }
+
+        let path =
+            std::fs::canonicalize(path.as_ref()).wrap_err("Failed to resolve source path.")?;
+        let (volume, _) = split_volume(&path)
+            .wrap_err("Source file is not located on a volume with drive letter.")?;
+
+        info!("Creating Volume Shadow Copy of {}", volume);
+        let output = powershell(&format!(
+            "$result = (Get-WmiObject -List Win32_ShadowCopy).Create('{volume}', 'ClientAccessible'); \
+             if ($result.ReturnValue -ne 0) {{ Write-Error \"Error code $($result.ReturnValue)\"; exit 1 }}; \
+             $copy = Get-WmiObject Win32_ShadowCopy | Where-Object {{ $_.ID -eq $result.ShadowID }}; \
+             Write-Output $copy.ID; Write-Output $copy.DeviceObject"
+        ))
+        .wrap_err("Failed to create Volume Shadow Copy.")
+        .suggestion("Run as administrator to create Volume Shadow Copies.")?;
+
+        let mut lines = output.lines().map(str::trim);
+        let (Some(id), Some(device)) = (lines.next(), lines.next()) else {
+            bail!("Failed to read ID of Volume Shadow Copy.");
+        };
+        info!("Created Volume Shadow Copy {}", id);
+
+        Ok(Self {
+            id: id.to_owned(),
+            device: device.to_owned(),
+        })
+    }
+
+    /// Path of the file inside the snapshot.
+    pub fn path_of(&self, path: impl AsRef<Path>) -> Result<PathBuf> {
+        let path =
+            std::fs::canonicalize(path.as_ref()).wrap_err("Failed to resolve source path.")?;
+        let (_, path_on_volume) = split_volume(&path)
+            .wrap_err("Source file is not located on a volume with drive letter.")?;
+
+        Ok(PathBuf::from(format!(r"{}\", self.device)).join(path_on_volume))
+    }
+}
+
+impl Drop for ShadowCopy {
+    fn drop(&mut self) {
+        info!("Deleting Volume Shadow Copy {}", self.id);
+        let deleted = powershell(&format!(
+            "Get-WmiObject Win32_ShadowCopy | Where-Object {{ $_.ID -eq '{}' }} | ForEach-Object {{ $_.Delete() }}",
+            self.id
+        ));
+        if let Err(err) = deleted {
+            warn!("Failed to delete Volume Shadow Copy {}: {}", self.id, err);
+        }
+    }
+}
@@ -0,0 +1,668 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use color_eyre::{
+    Section,
+    eyre::{Context, ContextCompat, Result, eyre},
+};
+use log::info;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use quick_xml::{Reader, escape::resolve_predefined_entity, events::Event};
+use sha2::{Digest, Sha256};
+use ureq::{
+    Agent, SendBody,
+    http::{Method, Request, Response, StatusCode},
+};
+
+use crate::{
+    backup::{hash::hash_file, mirror::RemoteFolder, throttle::Throttle},
+    credentials::{delete_secret, get_secret, store_secret},
+};
+
+/// Folder on the server pruned backups are moved into, instead of deleting them.
+pub const TRASH_FOLDER: &str = ".trash";
+
+/// Files larger than this are uploaded in chunks to Nextcloud, which rejects large single uploads
+/// behind many proxies.
+const CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?><d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/><d:getcontentlength/></d:prop></d:propfind>"#;
+
+/// Characters left unencoded in path segments.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Folder on a WebDAV server, e.g. Nextcloud or ownCloud, the target folder is mirrored onto.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebDavTarget {
+    /// URL of the folder, ending with `/`.
+    pub url: String,
+    pub user: String,
+}
+
+/// Entry of a folder listing.
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    /// Decoded path on the server.
+    path: String,
+    /// Size of files, `None` for folders.
+    size: Option<u64>,
+}
+
+/// Parses the entries of a PROPFIND multistatus response.
+///
+/// Elements are matched by their local name, as servers differ in the prefix of the `DAV:`
+/// namespace.
+fn parse_multistatus(xml: &str) -> Result<Vec<Entry>> {
+    /// Response being parsed.
+    #[derive(Default)]
+    struct Response {
+        href: String,
+        length: String,
+        collection: bool,
+    }
+
+    let mut reader = Reader::from_str(xml);
+    let mut elements: Vec<String> = vec![];
+    let mut response: Option<Response> = None;
+    let mut entries = vec![];
+    loop {
+        let event = reader
+            .read_event()
+            .wrap_err("Failed to parse folder listing.")?;
+        let is_start = matches!(event, Event::Start(_));
+        let text = match event {
+            Event::Start(element) | Event::Empty(element) => {
+                let name = element.local_name().as_ref().to_owned();
+                match name.as_str() {
+                    "response" => response = Some(Response::default()),
+                    "collection" => {
+                        if let Some(response) = &mut response {
+                            response.collection = true;
+                        }
+                    }
+                    _ => {}
+                }
+                if is_start {
+                    elements.push(name);
+                }
+                continue;
+            }
+            Event::End(_) => {
+                if elements.pop().as_deref() == Some("response")
+                    && let Some(response) = response.take()
+                    && let Some(entry) =
+                        response_entry(&response.href, &response.length, response.collection)
+                {
+                    entries.push(entry);
+                }
+                continue;
+            }
+            Event::Text(text) => text.xml10_content().into_owned(),
+            Event::CData(cdata) => cdata.xml10_content().into_owned(),
+            Event::GeneralRef(reference) => match reference.resolve_char_ref() {
+                Ok(Some(character)) => character.to_string(),
+                _ => resolve_predefined_entity(&reference.into_inner())
+                    .wrap_err("Unknown entity in folder listing.")?
+                    .to_owned(),
+            },
+            Event::Eof => break,
+            _ => continue,
+        };
+
+        let Some(response) = &mut response else {
+            continue;
+        };
+        match elements.last().map(String::as_str) {
+            Some("href") => response.href.push_str(&text),
+            Some("getcontentlength") => response.length.push_str(&text),
+            _ => {}
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Entry of a response with the href and content length, `None` if the href is invalid.
+fn response_entry(href: &str, length: &str, collection: bool) -> Option<Entry> {
+    let path = percent_decode_str(href.trim()).decode_utf8().ok()?;
+    // Hrefs may be absolute URLs or absolute paths.
+    let path = match path.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("/", |index| &rest[index..]),
+        None => &path,
+    };
+    let size = if collection {
+        None
+    } else {
+        Some(length.trim().parse().unwrap_or(0))
+    };
+
+    Some(Entry {
+        path: path.to_owned(),
+        size,
+    })
+}
+
+/// Name of the secret holding the password of the WebDAV user.
+fn password_name(target: &WebDavTarget) -> String {
+    format!("webdav-password:{}@{}", target.user, target.url)
+}
+
+impl WebDavTarget {
+    pub fn new(url: &str, user: &str) -> Result<Self> {
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(eyre!("{} is not a WebDAV URL.", url)).suggestion(
+                "Give the URL of the folder, e.g. `https://cloud.example.com/remote.php/dav/files/alice/backups/`.",
+            );
+        }
+        let url = if url.ends_with('/') {
+            url.to_owned()
+        } else {
+            format!("{}/", url)
+        };
+        Ok(Self {
+            url,
+            user: user.to_owned(),
+        })
+    }
+}
+
+/// Connection to a WebDAV folder.
+pub struct WebDav {
+    target: WebDavTarget,
+    authorization: String,
+    agent: Agent,
+    /// Folders known to exist on the server.
+    folders: RefCell<HashSet<String>>,
+    /// Transfer limit in bytes per second.
+    bwlimit: Option<u64>,
+}
+
+impl WebDav {
+    /// Connects with the password stored in the keyring of the OS, limiting transfers to `bwlimit`
+    /// bytes per second.
+    pub fn connect(target: &WebDavTarget, bwlimit: Option<u64>) -> Result<Self> {
+        let password = get_secret(&password_name(target))
+            .wrap_err("No WebDAV password stored in the keyring.")
+            .suggestion("Store it with `staggered-file-backup store-webdav-password`.")?;
+        let credentials = format!("{}:{}", target.user, password);
+
+        Ok(Self {
+            target: target.clone(),
+            authorization: format!("Basic {}", BASE64_STANDARD.encode(credentials)),
+            agent: Agent::config_builder()
+                .http_status_as_error(false)
+                .allow_non_standard_methods(true)
+                .build()
+                .into(),
+            folders: RefCell::new(HashSet::new()),
+            bwlimit,
+        })
+    }
+
+    /// URL of the path relative to the folder.
+    fn url(&self, remote_path: &str) -> String {
+        let encoded: Vec<String> = remote_path
+            .split('/')
+            .map(|segment| utf8_percent_encode(segment, SEGMENT).to_string())
+            .collect();
+        format!("{}{}", self.target.url, encoded.join("/"))
+    }
+
+    /// Absolute path of the folder on the server, as contained in listings.
+    fn base_path(&self) -> String {
+        let url = &self.target.url;
+        let rest = url.split_once("://").map_or(url.as_str(), |(_, rest)| rest);
+        let path = rest.find('/').map_or("/", |index| &rest[index..]);
+        percent_decode_str(path).decode_utf8_lossy().into_owned()
+    }
+
+    /// Sends the request, returning the response whatever its status.
+    fn send(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: impl ureq::AsSendBody,
+    ) -> Result<Response<ureq::Body>> {
+        let mut request = Request::builder()
+            .method(Method::from_bytes(method.as_bytes())?)
+            .uri(url)
+            .header("Authorization", &self.authorization);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+
+        let response = self
+            .agent
+            .run(request.body(body)?)
+            .wrap_err_with(|| format!("WebDAV {} of {} failed.", method, url))?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            return Err(eyre!("WebDAV server rejected the credentials.")).suggestion(
+                "Store the password with `staggered-file-backup store-webdav-password`.",
+            );
+        }
+
+        Ok(response)
+    }
+
+    /// Sends the request and fails unless it succeeded.
+    fn request(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: impl ureq::AsSendBody,
+    ) -> Result<Response<ureq::Body>> {
+        let response = self.send(method, url, headers, body)?;
+        if !response.status().is_success() {
+            return Err(eyre!(
+                "WebDAV {} of {} failed with {}.",
+                method,
+                url,
+                response.status()
+            ));
+        }
+        Ok(response)
+    }
+
+    /// Creates the folder and its parents, relative to the folder of the target.
+    fn create_folders(&self, folder: &str) -> Result<()> {
+        let mut path = String::new();
+        for segment in folder.split('/').filter(|segment| !segment.is_empty()) {
+            if !path.is_empty() {
+                path.push('/');
+            }
+            path.push_str(segment);
+            if self.folders.borrow().contains(&path) {
+                continue;
+            }
+            let url = format!("{}/", self.url(&path));
+            let response = self.send("MKCOL", &url, &[], ())?;
+            // Method Not Allowed is returned for existing folders.
+            if !response.status().is_success()
+                && response.status() != StatusCode::METHOD_NOT_ALLOWED
+            {
+                return Err(eyre!(
+                    "WebDAV MKCOL of {} failed with {}.",
+                    url,
+                    response.status()
+                ));
+            }
+            self.folders.borrow_mut().insert(path.clone());
+        }
+        Ok(())
+    }
+
+    fn create_parent_folders(&self, remote_path: &str) -> Result<()> {
+        match remote_path.rsplit_once('/') {
+            Some((parent, _)) => self.create_folders(parent),
+            None => Ok(()),
+        }
+    }
+
+    /// Lists the entries of the folder at the URL, including the folder itself.
+    ///
+    /// Returns `None` if the folder does not exist.
+    fn propfind(&self, url: &str) -> Result<Option<Vec<Entry>>> {
+        let response = self.send(
+            "PROPFIND",
+            url,
+            &[("Depth", "1"), ("Content-Type", "application/xml")],
+            PROPFIND_BODY,
+        )?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(eyre!(
+                "WebDAV PROPFIND of {} failed with {}.",
+                url,
+                response.status()
+            ));
+        }
+        let xml = response
+            .into_body()
+            .with_config()
+            .limit(256 * 1024 * 1024)
+            .read_to_string()
+            .wrap_err("Failed to read folder listing.")?;
+
+        Ok(Some(parse_multistatus(&xml)?))
+    }
+
+    /// Lists the folder relative to the folder of the target and its subfolders, except the trash
+    /// folder.
+    ///
+    /// Returns `false` if the folder does not exist.
+    fn list_folder(&self, folder: &str, files: &mut HashMap<String, u64>) -> Result<bool> {
+        let url = match folder {
+            "" => self.target.url.clone(),
+            folder => format!("{}/", self.url(folder)),
+        };
+        let Some(entries) = self.propfind(&url)? else {
+            return Ok(false);
+        };
+
+        let base_path = self.base_path();
+        for entry in entries {
+            let Some(relative) = entry.path.strip_prefix(&base_path) else {
+                continue;
+            };
+            let relative = relative.trim_end_matches('/');
+            if relative == folder {
+                continue;
+            }
+            match entry.size {
+                Some(size) => {
+                    files.insert(relative.to_owned(), size);
+                }
+                None if relative != TRASH_FOLDER => {
+                    self.list_folder(relative, files)?;
+                }
+                None => {}
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Upload URL of chunks for Nextcloud folders, e.g. `…/remote.php/dav/uploads/alice/`.
+    fn nextcloud_uploads_url(&self) -> Option<String> {
+        let (origin, rest) = self.target.url.split_once("/remote.php/dav/files/")?;
+        let user = rest.split('/').next()?;
+        Some(format!("{}/remote.php/dav/uploads/{}/", origin, user))
+    }
+
+    /// Uploads the content of the reader, limited to the bandwidth limit.
+    fn put(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+        mut reader: impl Read,
+        length: u64,
+    ) -> Result<()> {
+        let length_header = length.to_string();
+        let headers = [headers, &[("Content-Length", length_header.as_str())]].concat();
+        match self.bwlimit {
+            Some(rate) => self.request(
+                "PUT",
+                url,
+                &headers,
+                SendBody::from_reader(&mut Throttle::new(reader, rate)),
+            )?,
+            None => self.request("PUT", url, &headers, SendBody::from_reader(&mut reader))?,
+        };
+        Ok(())
+    }
+
+    /// Sizes of the chunks already uploaded into the upload folder, by their names.
+    fn uploaded_chunks(&self, upload_url: &str) -> Result<HashMap<String, u64>> {
+        let entries = self.propfind(upload_url)?.unwrap_or_default();
+        Ok(entries
+            .into_iter()
+            .filter_map(|entry| {
+                let name = entry.path.trim_end_matches('/').rsplit('/').next()?;
+                Some((name.to_owned(), entry.size?))
+            })
+            .collect())
+    }
+
+    /// Uploads the file in chunks with the chunked upload of Nextcloud.
+    ///
+    /// The upload folder is named after the destination, size and modification time of the file, so
+    /// that an interrupted upload of the same file resumes with the chunks missing on the server.
+    fn upload_chunked(
+        &self,
+        uploads_url: &str,
+        local_path: &Path,
+        destination: &str,
+    ) -> Result<()> {
+        let mut file = File::open(local_path).wrap_err("Failed to open file to upload.")?;
+        let metadata = file.metadata()?;
+        let size = metadata.len();
+        let upload_url = format!(
+            "{}sfb-{}/",
+            uploads_url,
+            upload_id(destination, size, metadata.modified()?)
+        );
+        let destination_header = [("Destination", destination)];
+
+        let uploaded_chunks = self.uploaded_chunks(&upload_url)?;
+        if uploaded_chunks.is_empty() {
+            self.create_upload_folder(&upload_url, &destination_header)?;
+        } else {
+            info!(
+                "Resuming upload with {} of {} chunks on the server.",
+                uploaded_chunks.len(),
+                size.div_ceil(CHUNK_SIZE)
+            );
+        }
+
+        let mut offset = 0;
+        let mut index = 1;
+        while offset < size {
+            let length = CHUNK_SIZE.min(size - offset);
+            let chunk_name = format!("{:05}", index);
+            if uploaded_chunks.get(&chunk_name) == Some(&length) {
+                offset += length;
+                index += 1;
+                file.seek(SeekFrom::Start(offset))?;
+                continue;
+            }
+            self.put(
+                &format!("{}{}", upload_url, chunk_name),
+                &[("Destination", destination)],
+                (&mut file).take(length),
+                length,
+            )?;
+            offset += length;
+            index += 1;
+        }
+
+        let size_header = size.to_string();
+        self.request(
+            "MOVE",
+            &format!("{}.file", upload_url),
+            &[
+                ("Destination", destination),
+                ("OC-Total-Length", &size_header),
+                ("Overwrite", "T"),
+            ],
+            (),
+        )?;
+
+        Ok(())
+    }
+
+    fn create_upload_folder(&self, upload_url: &str, headers: &[(&str, &str)]) -> Result<()> {
+        let response = self.send("MKCOL", upload_url, headers, ())?;
+        // Method Not Allowed is returned if the folder exists, but holds no chunks yet.
+        if !response.status().is_success() && response.status() != StatusCode::METHOD_NOT_ALLOWED {
+            return Err(eyre!(
+                "WebDAV MKCOL of {} failed with {}.",
+                upload_url,
+                response.status()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Name of the chunked upload of the file, equal for uploads of the same unchanged file.
+fn upload_id(destination: &str, size: u64, modified: SystemTime) -> String {
+    let modified = modified
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos());
+    let digest = Sha256::digest(format!("{}\n{}\n{}", destination, size, modified));
+    hex::encode(&digest[..16])
+}
+
+impl RemoteFolder for WebDav {
+    fn name(&self) -> &str {
+        &self.target.url
+    }
+
+    fn list(&self) -> Result<HashMap<String, u64>> {
+        let mut files = HashMap::new();
+        if !self.list_folder("", &mut files)? {
+            info!("Creating folder {}", self.target.url);
+            self.request("MKCOL", &self.target.url, &[], ())?;
+        }
+        Ok(files)
+    }
+
+    fn upload(&self, local_path: &Path, remote_path: &str) -> Result<()> {
+        self.create_parent_folders(remote_path)?;
+        let url = self.url(remote_path);
+
+        let size = std::fs::metadata(local_path)?.len();
+        if size > CHUNK_SIZE
+            && let Some(uploads_url) = self.nextcloud_uploads_url()
+        {
+            return self.upload_chunked(&uploads_url, local_path, &url);
+        }
+
+        let file = File::open(local_path).wrap_err("Failed to open file to upload.")?;
+        self.put(&url, &[], file, size)
+    }
+
+    /// Downloads the file, as WebDAV servers offer no SHA-256 hashes.
+    fn hash(&self, remote_path: &str) -> Result<String> {
+        let reader = self
+            .request("GET", &self.url(remote_path), &[], ())?
+            .into_body()
+            .into_reader();
+        match self.bwlimit {
+            Some(rate) => hash_file(&mut Throttle::new(reader, rate)),
+            None => hash_file(&mut { reader }),
+        }
+        .wrap_err_with(|| format!("Failed to download {}", remote_path))
+    }
+
+    /// Moves the file into the trash folder on the server.
+    fn remove(&self, remote_path: &str) -> Result<()> {
+        let trash_path = format!("{}/{}", TRASH_FOLDER, remote_path);
+        self.create_parent_folders(&trash_path)?;
+        self.request(
+            "MOVE",
+            &self.url(remote_path),
+            &[("Destination", &self.url(&trash_path)), ("Overwrite", "T")],
+            (),
+        )?;
+        Ok(())
+    }
+}
+
+/// Prompts for the password of the WebDAV user and stores it in the keyring of the OS.
+pub fn store_webdav_password(target: WebDavTarget) -> Result<()> {
+    let password = rpassword::prompt_password("Password: ").wrap_err("Failed to read password.")?;
+    store_secret(&password_name(&target), &password)?;
+
+    let webdav = WebDav::connect(&target, None)?;
+    webdav
+        .list()
+        .wrap_err("Failed to list the WebDAV folder with the password.")?;
+    info!("DONE!");
+
+    Ok(())
+}
+
+/// Removes the password of the WebDAV user from the keyring of the OS.
+pub fn forget_webdav_password(target: WebDavTarget) -> Result<()> {
+    delete_secret(&password_name(&target))?;
+    info!("DONE!");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_multistatus() {
+        let xml = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:">
+<d:response><d:href>/remote.php/dav/files/alice/backups/</d:href><d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat></d:response>
+<d:response><d:href>/remote.php/dav/files/alice/backups/2025-01-01_00_my%20db.sql</d:href><d:propstat><d:prop><d:resourcetype/><d:getcontentlength>6</d:getcontentlength></d:prop></d:propstat></d:response>
+<D:response><D:href>https://cloud.example.com/remote.php/dav/files/alice/backups/2025/</D:href><D:propstat><D:prop><D:resourcetype><D:collection /></D:resourcetype></D:prop></D:propstat></D:response>
+</d:multistatus>"#;
+
+        assert_eq!(
+            parse_multistatus(xml).unwrap(),
+            vec![
+                Entry {
+                    path: "/remote.php/dav/files/alice/backups/".to_owned(),
+                    size: None
+                },
+                Entry {
+                    path: "/remote.php/dav/files/alice/backups/2025-01-01_00_my db.sql".to_owned(),
+                    size: Some(6)
+                },
+                Entry {
+                    path: "/remote.php/dav/files/alice/backups/2025/".to_owned(),
+                    size: None
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_multistatus_escaped() {
+        let xml = r#"<?xml version="1.0"?>
+<multistatus xmlns="DAV:">
+  <response>
+    <href>/backups/2025-01-01_00_Tom &amp; Jerry.txt</href>
+    <propstat>
+      <prop>
+        <resourcetype/>
+        <getcontentlength>
+          12
+        </getcontentlength>
+      </prop>
+    </propstat>
+  </response>
+  <response>
+    <href><![CDATA[/backups/2025-01-02_00_a&b.txt]]></href>
+    <propstat><prop><getcontentlength>3</getcontentlength></prop></propstat>
+  </response>
+  <lp1:response xmlns:lp1="DAV:"><lp1:href>/backups/caf&#233;/</lp1:href><lp1:propstat><lp1:prop><lp1:resourcetype><lp1:collection></lp1:collection></lp1:resourcetype></lp1:prop></lp1:propstat></lp1:response>
+</multistatus>"#;
+
+        assert_eq!(
+            parse_multistatus(xml).unwrap(),
+            vec![
+                Entry {
+                    path: "/backups/2025-01-01_00_Tom & Jerry.txt".to_owned(),
+                    size: Some(12)
+                },
+                Entry {
+                    path: "/backups/2025-01-02_00_a&b.txt".to_owned(),
+                    size: Some(3)
+                },
+                Entry {
+                    path: "/backups/café/".to_owned(),
+                    size: None
+                },
+            ]
+        );
+        assert!(parse_multistatus("<multistatus><response><href>/a</response>").is_err());
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Completion of values read from the target folder or config file, for shells sourcing
+//! `COMPLETE=<shell> staggered-file-backup`.
+
+use std::ffi::{OsStr, OsString};
+
+use clap::CommandFactory;
+use clap_complete::CompletionCandidate;
+
+use crate::{
+    Cli,
+    backup::{
+        parsing::{ScanFilter, scan_directory},
+        template::NameTemplate,
+    },
+    config::{default_config_path, load_config},
+};
+
+/// Words of the command line being completed, passed after `--` by the shell integration.
+fn command_line() -> Vec<OsString> {
+    std::env::args_os()
+        .skip_while(|arg| arg != "--")
+        .skip(1)
+        .collect()
+}
+
+/// Value of the option in the command line, given as `--<long> <value>` or `--<long>=<value>`.
+fn option_value(args: &[OsString], long: &str) -> Option<OsString> {
+    let flag = format!("--{}", long);
+    let prefix = format!("{}=", flag);
+    args.iter().enumerate().find_map(|(index, arg)| {
+        let arg = arg.to_string_lossy();
+        if arg == flag {
+            args.get(index + 1).cloned()
+        } else {
+            arg.strip_prefix(&prefix).map(OsString::from)
+        }
+    })
+}
+
+/// Positional arguments given to the subcommand so far, without the word being completed.
+fn positionals(args: &[OsString], subcommand: &str) -> Vec<OsString> {
+    let command = Cli::command();
+    let Some(subcommand_index) = args.iter().position(|arg| arg == subcommand) else {
+        return vec![];
+    };
+    let Some(subcommand) = command.find_subcommand(subcommand) else {
+        return vec![];
+    };
+    let options_with_value: Vec<String> = subcommand
+        .get_arguments()
+        .filter(|arg| !arg.is_positional() && arg.get_action().takes_values())
+        .flat_map(|arg| {
+            arg.get_long()
+                .map(|long| format!("--{}", long))
+                .into_iter()
+                .chain(arg.get_short().map(|short| format!("-{}", short)))
+        })
+        .collect();
+
+    let mut positionals = vec![];
+    let end = args.len().saturating_sub(1).max(subcommand_index + 1);
+    let mut words = args[subcommand_index + 1..end].iter();
+    while let Some(word) = words.next() {
+        let word_str = word.to_string_lossy();
+        if options_with_value.iter().any(|option| *option == word_str) {
+            words.next();
+        } else if !word_str.starts_with('-') {
+            positionals.push(word.clone());
+        }
+    }
+
+    positionals
+}
+
+/// Dates and counters of the backups in the target folder of `restore`, like `2025-09-27_03`.
+pub fn complete_backup_date(current: &OsStr) -> Vec<CompletionCandidate> {
+    let args = command_line();
+    let Some(target) = positionals(&args, "restore").into_iter().next() else {
+        return vec![];
+    };
+    let name_template = option_value(&args, "name-template")
+        .and_then(|template| NameTemplate::parse(&template.to_string_lossy()).ok())
+        .unwrap_or_default();
+    let Ok(backup_files) = scan_directory(&target, &name_template, ScanFilter::default()) else {
+        return vec![];
+    };
+
+    let current = current.to_string_lossy();
+    let mut backup_files: Vec<_> = backup_files.collect();
+    backup_files.sort();
+    backup_files
+        .into_iter()
+        .rev()
+        .map(|file| {
+            let date = format!(
+                "{}_{:02}",
+                file.metadata.date_string(),
+                file.metadata.counter
+            );
+            let file_name = file
+                .path
+                .file_name()
+                .map(|file_name| file_name.to_string_lossy().into_owned());
+            (date, file_name)
+        })
+        .filter(|(date, _)| date.starts_with(current.as_ref()))
+        .map(|(date, file_name)| CompletionCandidate::new(date).help(file_name.map(Into::into)))
+        .collect()
+}
+
+/// Names of the jobs in the config file given to `run`, or the default one.
+pub fn complete_job_name(current: &OsStr) -> Vec<CompletionCandidate> {
+    let args = command_line();
+    let config_path = match option_value(&args, "config").or_else(|| std::env::var_os("SFB_CONFIG"))
+    {
+        Some(path) => path.into(),
+        None => match default_config_path() {
+            Ok(path) => path,
+            Err(_) => return vec![],
+        },
+    };
+    let Ok(config) = load_config(config_path) else {
+        return vec![];
+    };
+
+    let current = current.to_string_lossy();
+    config
+        .jobs
+        .into_iter()
+        .filter(|job| job.name.starts_with(current.as_ref()))
+        .map(|job| {
+            CompletionCandidate::new(job.name).help(Some(job.target.display().to_string().into()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_positionals() {
+        let args: Vec<OsString> = [
+            "staggered-file-backup",
+            "restore",
+            "--name-template",
+            "{date}_{counter}_{basename}.{ext}",
+            "/backups",
+            "-i",
+            "restored.txt",
+            "--date",
+            "2025",
+        ]
+        .into_iter()
+        .map(OsString::from)
+        .collect();
+
+        assert_eq!(
+            positionals(&args, "restore"),
+            vec![OsString::from("/backups"), OsString::from("restored.txt")]
+        );
+        assert_eq!(
+            option_value(&args, "name-template"),
+            Some(OsString::from("{date}_{counter}_{basename}.{ext}"))
+        );
+        assert_eq!(option_value(&args, "as-of"), None);
+    }
+}
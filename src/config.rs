@@ -0,0 +1,904 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use clap::{CommandFactory, ValueEnum};
+use color_eyre::{
+    Section,
+    eyre::{Context, Result, eyre},
+};
+use log::info;
+use serde::Deserialize;
+
+use crate::{
+    Cli,
+    backup::{
+        BackupOptions,
+        archive::Format,
+        cleanup::{PeriodAnchor, RetentionScope, Strategy},
+        normalize::NameNormalization,
+        rclone::Remote,
+        webdav::WebDavTarget,
+    },
+};
+
+/// Backup jobs run by the `run` command.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Settings of all jobs that do not set them themselves.
+    #[serde(default)]
+    pub defaults: Defaults,
+    #[serde(default)]
+    pub jobs: Vec<Job>,
+}
+
+/// Backup of one source file into a target folder.
+///
+/// Retention periods follow the command line flags, with -1 implying no cleanup. Unset settings
+/// are taken from the defaults of the config file, then from the defaults of the command line
+/// flags.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    pub name: String,
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub keep_newest: Option<i32>,
+    pub keep_daily: Option<i32>,
+    pub keep_weekly: Option<i32>,
+    pub keep_monthly: Option<i32>,
+    pub keep_quarterly: Option<i32>,
+    pub keep_yearly: Option<i32>,
+    /// Never move tagged backups into the recycle bin, see `--keep-tagged`.
+    pub keep_tagged: Option<bool>,
+    pub format: Option<Format>,
+    /// File containing the password zip backups are encrypted with, see `--zip-password`.
+    pub zip_password_file: Option<PathBuf>,
+    /// Password set with `SFB_ZIP_PASSWORD`, taking precedence over `zip_password_file`.
+    #[serde(skip)]
+    pub zip_password: Option<String>,
+    /// Deflate zip backups even if the source looks already compressed, see `--compress-force`.
+    pub compress_force: Option<bool>,
+    pub incremental: Option<bool>,
+    pub dedup_store: Option<bool>,
+    pub cold_target: Option<PathBuf>,
+    pub cold_after: Option<u32>,
+    pub rclone_remote: Option<String>,
+    pub webdav_url: Option<String>,
+    pub webdav_user: Option<String>,
+    /// Append trashed and restored files to `audit.log` in the target folder, see `--audit-log`.
+    pub audit_log: Option<bool>,
+    pub retention_scope: Option<RetentionScope>,
+    pub strategy: Option<Strategy>,
+    pub period_anchor: Option<PeriodAnchor>,
+    pub content_epochs: Option<bool>,
+    pub normalize_names: Option<NameNormalization>,
+}
+
+/// Settings of the `[defaults]` table, with the same meaning as those of the jobs.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    pub keep_newest: Option<i32>,
+    pub keep_daily: Option<i32>,
+    pub keep_weekly: Option<i32>,
+    pub keep_monthly: Option<i32>,
+    pub keep_quarterly: Option<i32>,
+    pub keep_yearly: Option<i32>,
+    pub keep_tagged: Option<bool>,
+    pub format: Option<Format>,
+    pub zip_password_file: Option<PathBuf>,
+    pub compress_force: Option<bool>,
+    pub incremental: Option<bool>,
+    pub dedup_store: Option<bool>,
+    pub cold_target: Option<PathBuf>,
+    pub cold_after: Option<u32>,
+    pub rclone_remote: Option<String>,
+    pub webdav_url: Option<String>,
+    pub webdav_user: Option<String>,
+    pub audit_log: Option<bool>,
+    pub retention_scope: Option<RetentionScope>,
+    pub strategy: Option<Strategy>,
+    pub period_anchor: Option<PeriodAnchor>,
+    pub content_epochs: Option<bool>,
+    pub normalize_names: Option<NameNormalization>,
+}
+
+fn keep_count(count: Option<i32>, default: Option<u32>) -> Option<u32> {
+    match count {
+        Some(count) => u32::try_from(count).ok(),
+        None => default,
+    }
+}
+
+impl Job {
+    /// Takes each setting the job does not set from the defaults.
+    ///
+    /// Settings are merged one by one, so a job setting `webdav_url` still uses the default
+    /// `webdav_user`.
+    fn inherit(&mut self, defaults: &Defaults) {
+        let Defaults {
+            keep_newest,
+            keep_daily,
+            keep_weekly,
+            keep_monthly,
+            keep_quarterly,
+            keep_yearly,
+            keep_tagged,
+            format,
+            zip_password_file,
+            compress_force,
+            incremental,
+            dedup_store,
+            cold_target,
+            cold_after,
+            rclone_remote,
+            webdav_url,
+            webdav_user,
+            audit_log,
+            retention_scope,
+            strategy,
+            period_anchor,
+            content_epochs,
+            normalize_names,
+        } = defaults.clone();
+
+        self.keep_newest = self.keep_newest.or(keep_newest);
+        self.keep_daily = self.keep_daily.or(keep_daily);
+        self.keep_weekly = self.keep_weekly.or(keep_weekly);
+        self.keep_monthly = self.keep_monthly.or(keep_monthly);
+        self.keep_quarterly = self.keep_quarterly.or(keep_quarterly);
+        self.keep_yearly = self.keep_yearly.or(keep_yearly);
+        self.keep_tagged = self.keep_tagged.or(keep_tagged);
+        self.format = self.format.or(format);
+        self.zip_password_file = self.zip_password_file.take().or(zip_password_file);
+        self.compress_force = self.compress_force.or(compress_force);
+        self.incremental = self.incremental.or(incremental);
+        self.dedup_store = self.dedup_store.or(dedup_store);
+        self.cold_target = self.cold_target.take().or(cold_target);
+        self.cold_after = self.cold_after.or(cold_after);
+        self.rclone_remote = self.rclone_remote.take().or(rclone_remote);
+        self.webdav_url = self.webdav_url.take().or(webdav_url);
+        self.webdav_user = self.webdav_user.take().or(webdav_user);
+        self.audit_log = self.audit_log.or(audit_log);
+        self.retention_scope = self.retention_scope.or(retention_scope);
+        self.strategy = self.strategy.or(strategy);
+        self.period_anchor = self.period_anchor.or(period_anchor);
+        self.content_epochs = self.content_epochs.or(content_epochs);
+        self.normalize_names = self.normalize_names.or(normalize_names);
+    }
+
+    pub fn backup_options(&self) -> Result<BackupOptions> {
+        let defaults = BackupOptions::default();
+
+        let zip_password = match &self.zip_password {
+            Some(password) => Some(password.clone()),
+            None => self
+                .zip_password_file
+                .as_ref()
+                .map(|path| {
+                    std::fs::read_to_string(path)
+                        .map(|password| password.trim_end_matches(['\r', '\n']).to_owned())
+                        .wrap_err_with(|| {
+                            format!("Failed to read zip password of job {}", self.name)
+                        })
+                })
+                .transpose()?,
+        };
+        let webdav = match (&self.webdav_url, &self.webdav_user) {
+            (Some(url), Some(user)) => Some(WebDavTarget::new(url, user)?),
+            (None, None) => None,
+            _ => {
+                return Err(eyre!(
+                    "Job {} sets only one of webdav_url and webdav_user.",
+                    self.name
+                ))
+                .suggestion("Set both, in the job or in the defaults.");
+            }
+        };
+
+        Ok(BackupOptions {
+            keep_latest: keep_count(self.keep_newest, defaults.keep_latest),
+            keep_daily: keep_count(self.keep_daily, defaults.keep_daily),
+            keep_weekly: keep_count(self.keep_weekly, defaults.keep_weekly),
+            keep_monthly: keep_count(self.keep_monthly, defaults.keep_monthly),
+            keep_quarterly: keep_count(self.keep_quarterly, defaults.keep_quarterly),
+            keep_yearly: keep_count(self.keep_yearly, defaults.keep_yearly),
+            keep_tagged: self.keep_tagged.unwrap_or(defaults.keep_tagged),
+            format: self.format.unwrap_or(defaults.format),
+            zip_password,
+            compress_force: self.compress_force.unwrap_or(defaults.compress_force),
+            incremental: self.incremental.unwrap_or(defaults.incremental),
+            dedup_store: self.dedup_store.unwrap_or(defaults.dedup_store),
+            cold_target: self.cold_target.clone(),
+            cold_after: self.cold_after,
+            rclone_remote: self
+                .rclone_remote
+                .as_deref()
+                .map(Remote::parse)
+                .transpose()?,
+            webdav,
+            audit_log: self.audit_log.unwrap_or(defaults.audit_log),
+            retention_scope: self.retention_scope.unwrap_or(defaults.retention_scope),
+            strategy: self.strategy.unwrap_or(defaults.strategy),
+            period_anchor: self.period_anchor.unwrap_or(defaults.period_anchor),
+            content_epochs: self.content_epochs.unwrap_or(defaults.content_epochs),
+            normalize_names: self.normalize_names.or(defaults.normalize_names),
+            ..defaults
+        })
+    }
+}
+
+/// Path of the config file in the platform specific config folder.
+pub fn default_config_path() -> Result<PathBuf> {
+    let dirs = directories::BaseDirs::new()
+        .ok_or(eyre!("Failed getting base dirs like AppData on Windows."))?;
+
+    Ok(dirs
+        .config_dir()
+        .join(Cli::command().get_name())
+        .join("config.toml"))
+}
+
+/// Commented config file written by `config init`.
+const CONFIG_TEMPLATE: &str = r#"# Config file of staggered-file-backup.
+#
+# Run all jobs with `staggered-file-backup run`, or single ones with `--job <NAME>`.
+# Check this file with `staggered-file-backup config validate`.
+
+# Defaults of all jobs, each overridden by the same setting of a job.
+#
+# [defaults]
+# keep_daily = 7
+#
+# Store backups as zip archives, encrypted with the password in the file.
+# format = "zip"
+# zip_password_file = "/path/to/zip/password"
+#
+# Deflate zip backups even of sources which look already compressed, like photos or videos.
+# compress_force = true
+#
+# Mirror the target folders onto an rclone remote or a WebDAV folder.
+# rclone_remote = "gdrive:backups"
+# webdav_url = "https://cloud.example.com/remote.php/dav/files/alice/backups/"
+# webdav_user = "alice"
+
+# Each job backs up one source file into a target folder.
+#
+# [[jobs]]
+# name = "database"
+# source = "/path/to/source/file"
+# target = "/path/to/target/backup/dir/"
+#
+# Number of backups to keep per period, -1 keeps all of them.
+# Unset values use the defaults of the command line flags.
+# keep_newest = 8
+# keep_daily = 7
+# keep_monthly = 6
+# keep_yearly = 5
+#
+# Keep one backup of each ISO week or calendar quarter too.
+# keep_weekly = 4
+# keep_quarterly = 8
+#
+# Apply the retention to the backups of each machine separately, or to all of them with "global".
+# retention_scope = "per-host"
+#
+# Algorithm selecting the backups to keep with the retention periods, or "exponential" keeping
+# keep_newest backups and thinning out older ones exponentially with age.
+# strategy = "tiered"
+#
+# Keep the "first" or "last" backup of each day, month and year.
+# period_anchor = "first"
+#
+# Count only backups whose content changed toward the retention periods.
+# content_epochs = false
+#
+# Write the names of new backups composed ("nfc") or decomposed ("nfd").
+# normalize_names = "nfc"
+#
+# Never move backups with a tag into the recycle bin.
+# keep_tagged = false
+#
+# Append trashed and restored files to audit.log in the target folder.
+# audit_log = false
+#
+# Store backups incrementally or deduplicated, and move old ones to a second folder.
+# incremental = false
+# dedup_store = false
+# cold_target = "/path/to/cold/storage/"
+# cold_after = 90
+"#;
+
+pub fn load_config(path: impl AsRef<Path>) -> Result<Config> {
+    let content = std::fs::read_to_string(path.as_ref())
+        .wrap_err_with(|| format!("Failed to read config file {}", path.as_ref().display()))
+        .suggestion("Check if the path of the config file is correct.")?;
+
+    let mut config: Config = toml::from_str(&content)
+        .wrap_err_with(|| format!("Failed to parse config file {}", path.as_ref().display()))?;
+    for job in &mut config.jobs {
+        job.inherit(&config.defaults);
+    }
+    apply_env_overrides(&mut config, &std::env::vars().collect())?;
+
+    Ok(config)
+}
+
+/// Environment variables setting the job settings of the same name, like the command line flags.
+const JOB_ENV_VARS: &[&str] = &[
+    "SFB_KEEP_NEWEST",
+    "SFB_KEEP_DAILY",
+    "SFB_KEEP_WEEKLY",
+    "SFB_KEEP_MONTHLY",
+    "SFB_KEEP_QUARTERLY",
+    "SFB_KEEP_YEARLY",
+    "SFB_KEEP_TAGGED",
+    "SFB_FORMAT",
+    "SFB_ZIP_PASSWORD",
+    "SFB_COMPRESS_FORCE",
+    "SFB_INCREMENTAL",
+    "SFB_DEDUP_STORE",
+    "SFB_COLD_TARGET",
+    "SFB_COLD_AFTER",
+    "SFB_RCLONE_REMOTE",
+    "SFB_WEBDAV_URL",
+    "SFB_WEBDAV_USER",
+    "SFB_AUDIT_LOG",
+    "SFB_RETENTION_SCOPE",
+    "SFB_STRATEGY",
+    "SFB_PERIOD_ANCHOR",
+    "SFB_CONTENT_EPOCHS",
+    "SFB_NORMALIZE_NAMES",
+];
+
+/// Environment variables read by the `run` command itself.
+const RUN_ENV_VARS: &[&str] = &["SFB_CONFIG"];
+
+fn parse_env_bool(value: &str) -> bool {
+    !matches!(
+        value.trim().to_lowercase().as_str(),
+        "" | "0" | "n" | "no" | "f" | "false" | "off"
+    )
+}
+
+fn parse_env_value<T: ValueEnum>(name: &str, value: &str) -> Result<T> {
+    T::from_str(value.trim(), true)
+        .map_err(|_| eyre!("Invalid value {} of {}", value, name))
+        .suggestion(format!(
+            "Use one of: {}",
+            T::value_variants()
+                .iter()
+                .filter_map(|variant| variant.to_possible_value())
+                .map(|value| value.get_name().to_owned())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+}
+
+/// Overrides the settings of all jobs with the environment variables in [`JOB_ENV_VARS`], which
+/// also set the command line flags of the same name.
+///
+/// Applied after the defaults of the config file, so that the variables take precedence. Other
+/// `SFB_*` variables, like `SFB_TARGET` or `SFB_FSYNC`, set flags jobs have no setting for and are
+/// rejected instead of being ignored.
+fn apply_env_overrides(config: &mut Config, vars: &HashMap<String, String>) -> Result<()> {
+    let mut unsupported: Vec<&str> = vars
+        .keys()
+        .map(String::as_str)
+        .filter(|name| {
+            name.starts_with("SFB_") && !JOB_ENV_VARS.contains(name) && !RUN_ENV_VARS.contains(name)
+        })
+        .collect();
+    if !unsupported.is_empty() {
+        unsupported.sort_unstable();
+        return Err(eyre!(
+            "Environment variables do not apply to the jobs of the config file: {}",
+            unsupported.join(", ")
+        ))
+        .suggestion("Set them per job in the config file if supported, or unset them.");
+    }
+
+    let var = |name: &str| vars.get(name).map(String::as_str);
+    for job in &mut config.jobs {
+        let counts = [
+            ("SFB_KEEP_NEWEST", &mut job.keep_newest),
+            ("SFB_KEEP_DAILY", &mut job.keep_daily),
+            ("SFB_KEEP_WEEKLY", &mut job.keep_weekly),
+            ("SFB_KEEP_MONTHLY", &mut job.keep_monthly),
+            ("SFB_KEEP_QUARTERLY", &mut job.keep_quarterly),
+            ("SFB_KEEP_YEARLY", &mut job.keep_yearly),
+        ];
+        for (name, count) in counts {
+            if let Some(value) = var(name) {
+                let parsed = value.trim().parse().ok().filter(|count| *count >= -1);
+                *count = Some(
+                    parsed
+                        .ok_or_else(|| eyre!("Invalid value {} of {}", value, name))
+                        .suggestion("Use the number of backups to keep, or -1 to keep all.")?,
+                );
+            }
+        }
+
+        let flags = [
+            ("SFB_KEEP_TAGGED", &mut job.keep_tagged),
+            ("SFB_COMPRESS_FORCE", &mut job.compress_force),
+            ("SFB_INCREMENTAL", &mut job.incremental),
+            ("SFB_DEDUP_STORE", &mut job.dedup_store),
+            ("SFB_AUDIT_LOG", &mut job.audit_log),
+            ("SFB_CONTENT_EPOCHS", &mut job.content_epochs),
+        ];
+        for (name, flag) in flags {
+            if let Some(value) = var(name) {
+                *flag = Some(parse_env_bool(value));
+            }
+        }
+
+        let texts = [
+            ("SFB_ZIP_PASSWORD", &mut job.zip_password),
+            ("SFB_RCLONE_REMOTE", &mut job.rclone_remote),
+            ("SFB_WEBDAV_URL", &mut job.webdav_url),
+            ("SFB_WEBDAV_USER", &mut job.webdav_user),
+        ];
+        for (name, text) in texts {
+            if let Some(value) = var(name) {
+                *text = Some(value.to_owned());
+            }
+        }
+
+        if let Some(value) = var("SFB_COLD_TARGET") {
+            job.cold_target = Some(PathBuf::from(value));
+        }
+        if let Some(value) = var("SFB_COLD_AFTER") {
+            job.cold_after = Some(
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|_| eyre!("Invalid value {} of SFB_COLD_AFTER", value))
+                    .suggestion("Use the number of days.")?,
+            );
+        }
+        if let Some(value) = var("SFB_FORMAT") {
+            job.format = Some(parse_env_value("SFB_FORMAT", value)?);
+        }
+        if let Some(value) = var("SFB_RETENTION_SCOPE") {
+            job.retention_scope = Some(parse_env_value("SFB_RETENTION_SCOPE", value)?);
+        }
+        if let Some(value) = var("SFB_STRATEGY") {
+            job.strategy = Some(parse_env_value("SFB_STRATEGY", value)?);
+        }
+        if let Some(value) = var("SFB_PERIOD_ANCHOR") {
+            job.period_anchor = Some(parse_env_value("SFB_PERIOD_ANCHOR", value)?);
+        }
+        if let Some(value) = var("SFB_NORMALIZE_NAMES") {
+            job.normalize_names = Some(parse_env_value("SFB_NORMALIZE_NAMES", value)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a commented config file, if there is none yet or `force` is set.
+pub fn init_config(config_path: Option<PathBuf>, force: bool) -> Result<()> {
+    let config_path = match config_path {
+        Some(path) => path,
+        None => default_config_path()?,
+    };
+    if config_path.exists() && !force {
+        return Err(eyre!(
+            "Config file {} already exists.",
+            config_path.display()
+        ))
+        .suggestion("Use `--force` to overwrite it.");
+    }
+
+    if let Some(parent) = config_path.parent() {
+        std::fs::create_dir_all(parent).wrap_err("Failed to create config folder.")?;
+    }
+    std::fs::write(&config_path, CONFIG_TEMPLATE)
+        .wrap_err_with(|| format!("Failed to write config file {}", config_path.display()))?;
+    info!("Wrote config file {}", config_path.display());
+
+    Ok(())
+}
+
+/// Problem found in a config file, with a suggestion how to fix it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub message: String,
+    pub suggestion: &'static str,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Problem {}
+
+fn problems_of_job(job: &Job) -> Vec<Problem> {
+    let mut problems = vec![];
+    let mut problem = |message: String, suggestion| {
+        problems.push(Problem {
+            message,
+            suggestion,
+        })
+    };
+
+    if job.name.trim().is_empty() {
+        problem(
+            "A job has an empty name.".to_owned(),
+            "Give every job a name to select it with `--job`.",
+        );
+    }
+    if !job.source.is_file() {
+        problem(
+            format!(
+                "Source of job {} is not a file: {}",
+                job.name,
+                job.source.display()
+            ),
+            "Check if the source paths are correct and the files exist.",
+        );
+    }
+    if !job.target.is_dir() {
+        problem(
+            format!(
+                "Target of job {} is not a folder: {}",
+                job.name,
+                job.target.display()
+            ),
+            "Create the target folders before running the jobs.",
+        );
+    }
+
+    let retention = [
+        ("keep_newest", job.keep_newest),
+        ("keep_daily", job.keep_daily),
+        ("keep_weekly", job.keep_weekly),
+        ("keep_monthly", job.keep_monthly),
+        ("keep_quarterly", job.keep_quarterly),
+        ("keep_yearly", job.keep_yearly),
+    ];
+    for (key, count) in retention {
+        if let Some(count) = count
+            && count < -1
+        {
+            problem(
+                format!("{} of job {} is {}.", key, job.name, count),
+                "Use -1 to keep all backups of a period.",
+            );
+        }
+    }
+    // Weekly and quarterly backups are not kept unless set.
+    let keeps_none = |key: &str, count: Option<i32>| {
+        count == Some(0)
+            || (matches!(key, "keep_weekly" | "keep_quarterly")
+                && count.is_none_or(|count| count < 0))
+    };
+    if retention.iter().all(|(key, count)| keeps_none(key, *count)) {
+        problem(
+            format!("Job {} keeps no backups.", job.name),
+            "Keep at least one backup of one period.",
+        );
+    }
+
+    let format = job.format.unwrap_or(Format::Plain);
+    if format == Format::Zip && (job.incremental == Some(true) || job.dedup_store == Some(true)) {
+        problem(
+            format!(
+                "Job {} stores zip backups incrementally or in a chunk store.",
+                job.name
+            ),
+            "Zip backups are always full copies. Unset incremental and dedup_store of the job.",
+        );
+    }
+    if let Some(path) = &job.zip_password_file {
+        if format != Format::Zip {
+            problem(
+                format!(
+                    "Job {} encrypts backups that are not zip archives.",
+                    job.name
+                ),
+                "Set format = \"zip\" to encrypt backups.",
+            );
+        }
+        if !path.is_file() {
+            problem(
+                format!(
+                    "Zip password file of job {} is not a file: {}",
+                    job.name,
+                    path.display()
+                ),
+                "Write the password into the file.",
+            );
+        }
+    }
+    if job.cold_after.is_some() && job.cold_target.is_none() {
+        problem(
+            format!("Job {} sets cold_after without cold_target.", job.name),
+            "Set the cold storage folder with cold_target.",
+        );
+    }
+    if let Some(remote) = &job.rclone_remote
+        && Remote::parse(remote).is_err()
+    {
+        problem(
+            format!("rclone remote of job {} is invalid: {}", job.name, remote),
+            "Give the remote as `name:path`, e.g. `gdrive:backups/saves`.",
+        );
+    }
+    match (&job.webdav_url, &job.webdav_user) {
+        (Some(url), Some(user)) if WebDavTarget::new(url, user).is_err() => problem(
+            format!("WebDAV URL of job {} is invalid: {}", job.name, url),
+            "Give the URL of the folder, starting with `https://`.",
+        ),
+        (Some(_), None) | (None, Some(_)) => problem(
+            format!(
+                "Job {} sets only one of webdav_url and webdav_user.",
+                job.name
+            ),
+            "Set both, in the job or in the defaults.",
+        ),
+        _ => {}
+    }
+
+    problems
+}
+
+/// Checks the jobs of the config file for missing paths and invalid values.
+pub fn problems_of_config(config: &Config) -> Vec<Problem> {
+    let mut problems = vec![];
+    let mut names = HashSet::new();
+    for job in &config.jobs {
+        if !names.insert(&job.name) {
+            problems.push(Problem {
+                message: format!("Job name {} is used more than once.", job.name),
+                suggestion: "Give every job a unique name to select it with `--job`.",
+            });
+        }
+        problems.extend(problems_of_job(job));
+    }
+
+    problems
+}
+
+/// Loads the config file and reports all problems found in it at once.
+pub fn validate_config(config_path: Option<PathBuf>) -> Result<()> {
+    let config_path = match config_path {
+        Some(path) => path,
+        None => default_config_path()?,
+    };
+    let config = load_config(&config_path)?;
+    let problems = problems_of_config(&config);
+    if problems.is_empty() {
+        info!(
+            "Config file {} with {} jobs is valid.",
+            config_path.display(),
+            config.jobs.len()
+        );
+        return Ok(());
+    }
+
+    let mut report = eyre!(
+        "Found {} problems in config file {}",
+        problems.len(),
+        config_path.display()
+    );
+    let mut suggestions = vec![];
+    for problem in problems {
+        if !suggestions.contains(&problem.suggestion) {
+            suggestions.push(problem.suggestion);
+        }
+        report = report.error(problem);
+    }
+    for suggestion in suggestions {
+        report = report.suggestion(suggestion);
+    }
+
+    Err(report)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [[jobs]]
+            name = "db"
+            source = "/var/backups/db.sql"
+            target = "/mnt/backups/db"
+            keep_daily = 7
+            keep_monthly = -1
+            "#,
+        )
+        .unwrap();
+
+        let options = config.jobs[0].backup_options().unwrap();
+        assert_eq!(config.jobs[0].name, "db");
+        assert_eq!(options.keep_latest, Some(8));
+        assert_eq!(options.keep_daily, Some(7));
+        assert_eq!(options.keep_monthly, None);
+    }
+
+    #[test]
+    fn test_apply_env_overrides() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [[jobs]]
+            name = "db"
+            source = "/var/backups/db.sql"
+            target = "/mnt/backups/db"
+            keep_daily = 7
+            keep_monthly = 3
+            "#,
+        )
+        .unwrap();
+        let env = |vars: &[(&str, &str)]| -> HashMap<String, String> {
+            vars.iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        };
+
+        apply_env_overrides(
+            &mut config,
+            &env(&[
+                ("SFB_KEEP_DAILY", "14"),
+                ("SFB_KEEP_TAGGED", "true"),
+                ("SFB_FORMAT", "zip"),
+                ("SFB_ZIP_PASSWORD", "secret"),
+                ("SFB_STRATEGY", "exponential"),
+                ("SFB_COLD_AFTER", "30"),
+                ("SFB_CONFIG", "/etc/sfb.toml"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.jobs[0].keep_daily, Some(14));
+        assert_eq!(config.jobs[0].keep_monthly, Some(3));
+        assert_eq!(config.jobs[0].keep_tagged, Some(true));
+        assert_eq!(config.jobs[0].format, Some(Format::Zip));
+        assert_eq!(config.jobs[0].strategy, Some(Strategy::Exponential));
+        assert_eq!(config.jobs[0].cold_after, Some(30));
+        assert_eq!(
+            config.jobs[0].backup_options().unwrap().zip_password,
+            Some("secret".to_owned())
+        );
+
+        assert!(apply_env_overrides(&mut config, &env(&[("SFB_KEEP_DAILY", "-2")])).is_err());
+        assert!(apply_env_overrides(&mut config, &env(&[("SFB_FORMAT", "rar")])).is_err());
+        // Flags without job setting are rejected instead of being ignored.
+        assert!(apply_env_overrides(&mut config, &env(&[("SFB_TARGET", "/mnt/other")])).is_err());
+        assert!(apply_env_overrides(&mut config, &env(&[("SFB_FSYNC", "true")])).is_err());
+    }
+
+    #[test]
+    fn test_inherit_defaults() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [defaults]
+            keep_daily = 7
+            keep_yearly = 3
+            format = "zip"
+            compress_force = true
+            webdav_url = "https://cloud.example.com/remote.php/dav/files/alice/backups/"
+            webdav_user = "alice"
+
+            [[jobs]]
+            name = "db"
+            source = "/var/backups/db.sql"
+            target = "/mnt/backups/db"
+            keep_daily = 14
+            format = "plain"
+            compress_force = false
+            webdav_url = "https://cloud.example.com/remote.php/dav/files/alice/db/"
+
+            [[jobs]]
+            name = "wiki"
+            source = "/var/backups/wiki.sql"
+            target = "/mnt/backups/wiki"
+            "#,
+        )
+        .unwrap();
+        for job in &mut config.jobs {
+            job.inherit(&config.defaults);
+        }
+
+        let db = config.jobs[0].backup_options().unwrap();
+        let wiki = config.jobs[1].backup_options().unwrap();
+        assert_eq!(db.keep_daily, Some(14));
+        assert_eq!(db.keep_yearly, Some(3));
+        assert_eq!(db.format, Format::Plain);
+        assert_eq!(
+            db.webdav,
+            Some(
+                WebDavTarget::new(
+                    "https://cloud.example.com/remote.php/dav/files/alice/db/",
+                    "alice"
+                )
+                .unwrap()
+            )
+        );
+        assert_eq!(wiki.keep_daily, Some(7));
+        assert_eq!(wiki.format, Format::Zip);
+        assert!(!db.compress_force);
+        assert!(wiki.compress_force);
+        assert!(toml::from_str::<Config>("[defaults]\nname = \"db\"\n").is_err());
+    }
+
+    #[test]
+    fn test_config_template() {
+        let config: Config = toml::from_str(CONFIG_TEMPLATE).unwrap();
+
+        assert!(config.jobs.is_empty());
+    }
+
+    #[test]
+    fn test_problems_of_config() {
+        let dir = std::env::temp_dir();
+        let config: Config = toml::from_str(&format!(
+            r#"
+            [[jobs]]
+            name = "db"
+            source = "/nonexistent/db.sql"
+            target = '{0}'
+            keep_daily = -2
+
+            [[jobs]]
+            name = "db"
+            source = "/nonexistent/db.sql"
+            target = '{0}'
+            keep_newest = 0
+            keep_daily = 0
+            keep_monthly = 0
+            keep_yearly = 0
+
+            [[jobs]]
+            name = "wiki"
+            source = "/nonexistent/wiki.sql"
+            target = '{0}'
+            format = "zip"
+            incremental = true
+            cold_after = 30
+            rclone_remote = "./backups"
+            webdav_user = "alice"
+            "#,
+            dir.display()
+        ))
+        .unwrap();
+
+        let messages: Vec<String> = problems_of_config(&config)
+            .into_iter()
+            .map(|problem| problem.message)
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "Source of job db is not a file: /nonexistent/db.sql",
+                "keep_daily of job db is -2.",
+                "Job name db is used more than once.",
+                "Source of job db is not a file: /nonexistent/db.sql",
+                "Job db keeps no backups.",
+                "Source of job wiki is not a file: /nonexistent/wiki.sql",
+                "Job wiki stores zip backups incrementally or in a chunk store.",
+                "Job wiki sets cold_after without cold_target.",
+                "rclone remote of job wiki is invalid: ./backups",
+                "Job wiki sets only one of webdav_url and webdav_user.",
+            ]
+        );
+    }
+}
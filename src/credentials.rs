@@ -0,0 +1,69 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::path::Path;
+
+use color_eyre::{
+    Section,
+    eyre::{Context, Result},
+};
+use keyring::Entry;
+use log::{info, warn};
+
+/// Service name the secrets are stored under in the keyring of the OS, e.g. Windows Credential
+/// Manager, macOS Keychain or the Secret Service on Linux.
+const SERVICE_NAME: &str = "staggered-file-backup";
+
+fn entry(name: &str) -> Result<Entry> {
+    Entry::new(SERVICE_NAME, name).wrap_err("Failed to access the keyring of the OS.")
+}
+
+/// Name of the secret holding the password of the key file.
+pub fn key_password_name(key_path: impl AsRef<Path>) -> Result<String> {
+    let key_path = std::fs::canonicalize(key_path.as_ref())
+        .wrap_err("Failed to resolve path of key.")
+        .suggestion("Check if the path of the key is correct.")?;
+    Ok(format!("key-password:{}", key_path.display()))
+}
+
+/// Fetches a secret from the keyring.
+///
+/// A missing secret or unavailable keyring is reported as `None`.
+pub fn get_secret(name: &str) -> Option<String> {
+    let secret = entry(name).ok()?.get_password();
+    match secret {
+        Ok(secret) => Some(secret),
+        Err(keyring::Error::NoEntry) => None,
+        Err(err) => {
+            warn!("Failed to read {} from the keyring: {}", name, err);
+            None
+        }
+    }
+}
+
+pub fn store_secret(name: &str, secret: &str) -> Result<()> {
+    entry(name)?
+        .set_password(secret)
+        .wrap_err("Failed to store secret in the keyring of the OS.")
+        .suggestion("Check if a keyring or Secret Service is available and unlocked.")?;
+    info!("Stored {} in the keyring.", name);
+
+    Ok(())
+}
+
+pub fn delete_secret(name: &str) -> Result<()> {
+    match entry(name)?.delete_credential() {
+        Ok(()) => info!("Removed {} from the keyring.", name),
+        Err(keyring::Error::NoEntry) => warn!("No {} stored in the keyring.", name),
+        Err(err) => {
+            return Err(err)
+                .wrap_err("Failed to remove secret from the keyring of the OS.")
+                .suggestion("Check if a keyring or Secret Service is available and unlocked.");
+        }
+    }
+
+    Ok(())
+}
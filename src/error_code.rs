@@ -0,0 +1,165 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{fmt::Display, io};
+
+use color_eyre::eyre::{Report, Result};
+
+use crate::exit_code::ExitCode;
+
+/// Stable identifiers of failures, shown in error messages and the summary file so that they can
+/// be looked up in the documentation and classified by monitoring.
+///
+/// Identifiers are never reused for another failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// Another process holds the lock of the target folder.
+    TargetLocked,
+    /// A file system ran out of space.
+    StorageFull,
+    /// Backups of the target folder do not match their hash files.
+    VerificationFailed,
+    /// Backups of the target folder have an invalid signature.
+    InvalidSignature,
+    /// Entries of the hash chain were removed or rewritten, or backups it records are missing.
+    ChainBroken,
+    /// Backups outside the retention periods could not be pruned.
+    PruneFailed,
+    /// A copy of a file does not match the original after copying.
+    CopyMismatch,
+    /// A file on a remote does not match the target folder.
+    RemoteMismatch,
+    /// A restored file does not match the hash recorded for the backup.
+    RestoreMismatch,
+    /// None of the backups to keep passed verification, so nothing was pruned.
+    NoIntactBackup,
+    /// A downloaded release does not match its checksum or signature.
+    UpdateMismatch,
+    /// SQLite found problems in the tracking database.
+    DatabaseCorrupted,
+    /// The tracking database records backups missing from the target folder, or lacks backups of
+    /// it.
+    DatabaseMismatch,
+}
+
+impl ErrorCode {
+    /// Identifier like `E0007`.
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::TargetLocked => "E0001",
+            Self::StorageFull => "E0002",
+            Self::VerificationFailed => "E0003",
+            Self::InvalidSignature => "E0004",
+            Self::ChainBroken => "E0005",
+            Self::PruneFailed => "E0006",
+            Self::CopyMismatch => "E0007",
+            Self::RemoteMismatch => "E0008",
+            Self::RestoreMismatch => "E0009",
+            Self::NoIntactBackup => "E0010",
+            Self::UpdateMismatch => "E0011",
+            Self::DatabaseCorrupted => "E0012",
+            Self::DatabaseMismatch => "E0013",
+        }
+    }
+
+    pub fn exit_code(self) -> ExitCode {
+        match self {
+            Self::TargetLocked => ExitCode::LockContention,
+            Self::StorageFull => ExitCode::StorageFull,
+            Self::PruneFailed => ExitCode::PruneFailed,
+            Self::VerificationFailed
+            | Self::InvalidSignature
+            | Self::ChainBroken
+            | Self::CopyMismatch
+            | Self::RemoteMismatch
+            | Self::RestoreMismatch
+            | Self::NoIntactBackup
+            | Self::UpdateMismatch
+            | Self::DatabaseCorrupted
+            | Self::DatabaseMismatch => ExitCode::VerificationFailed,
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            Self::TargetLocked => "target folder is locked",
+            Self::StorageFull => "storage is full",
+            Self::VerificationFailed => "backup verification failed",
+            Self::InvalidSignature => "invalid backup signature",
+            Self::ChainBroken => "hash chain is broken",
+            Self::PruneFailed => "pruning failed",
+            Self::CopyMismatch => "hash mismatch after copy",
+            Self::RemoteMismatch => "remote does not match target folder",
+            Self::RestoreMismatch => "restored file is corrupted",
+            Self::NoIntactBackup => "no intact backup to keep",
+            Self::UpdateMismatch => "update verification failed",
+            Self::DatabaseCorrupted => "tracking database is corrupted",
+            Self::DatabaseMismatch => "tracking database does not match target folder",
+        };
+        write!(f, "{}: {}", self.id(), message)
+    }
+}
+
+pub trait WithErrorCode<T> {
+    /// Attaches the error code to the error, unless it already has one.
+    fn error_code(self, code: ErrorCode) -> Result<T>;
+}
+
+impl<T> WithErrorCode<T> for Result<T> {
+    fn error_code(self, code: ErrorCode) -> Result<T> {
+        self.map_err(|report| {
+            if report.downcast_ref::<ErrorCode>().is_some() {
+                report
+            } else {
+                report.wrap_err(code)
+            }
+        })
+    }
+}
+
+/// Error code attached to the error, or one derived from its causes.
+pub fn error_code_of(report: &Report) -> Option<ErrorCode> {
+    if let Some(code) = report.downcast_ref::<ErrorCode>() {
+        return Some(*code);
+    }
+
+    report
+        .chain()
+        .any(|err| {
+            err.downcast_ref::<io::Error>()
+                .is_some_and(|err| err.kind() == io::ErrorKind::StorageFull)
+        })
+        .then_some(ErrorCode::StorageFull)
+}
+
+#[cfg(test)]
+mod test {
+    use color_eyre::eyre::{Context, eyre};
+
+    use super::*;
+
+    #[test]
+    fn test_error_code_of() {
+        let mismatch: Result<()> = Err(eyre!("Target and source file hash are NOT equal!"));
+        let mismatch = mismatch
+            .error_code(ErrorCode::CopyMismatch)
+            .wrap_err("Backup failed")
+            .error_code(ErrorCode::PruneFailed)
+            .unwrap_err();
+        let storage_full: Result<()> =
+            Err(io::Error::from(io::ErrorKind::StorageFull)).wrap_err("Failed to copy source file");
+
+        assert_eq!(error_code_of(&mismatch), Some(ErrorCode::CopyMismatch));
+        assert!(format!("{:?}", mismatch).contains("E0007: hash mismatch after copy"));
+        assert_eq!(
+            error_code_of(&storage_full.unwrap_err()),
+            Some(ErrorCode::StorageFull)
+        );
+        assert_eq!(error_code_of(&eyre!("Something else")), None);
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fmt::Display;
+
+use color_eyre::eyre::{Report, Result};
+
+use crate::error_code::{ErrorCode, error_code_of};
+
+/// Exit codes of the process, so that monitoring can tell failures apart.
+///
+/// 0 is success.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    /// Any failure without a more specific code.
+    Failure = 1,
+    /// The backup was skipped due to `--min-interval` or `--max-per-day`.
+    Skipped = 2,
+    /// A backup does not match its hash or signature.
+    VerificationFailed = 3,
+    /// Backups outside the retention periods could not be pruned.
+    PruneFailed = 4,
+    /// Another process holds the lock of the target folder.
+    LockContention = 5,
+    /// A file system ran out of space.
+    StorageFull = 6,
+    /// `diff` found the source to differ from the newest backup.
+    Differs = 7,
+    /// Invalid command line arguments.
+    Usage = 64,
+}
+
+impl Display for ExitCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            Self::Failure => "Failed.",
+            Self::Skipped => "Backup skipped.",
+            Self::VerificationFailed => "Verification failed.",
+            Self::PruneFailed => "Pruning failed.",
+            Self::LockContention => "Target folder is locked.",
+            Self::StorageFull => "Storage is full.",
+            Self::Differs => "Source differs from the newest backup.",
+            Self::Usage => "Invalid arguments.",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl From<ExitCode> for std::process::ExitCode {
+    fn from(code: ExitCode) -> Self {
+        Self::from(code as u8)
+    }
+}
+
+pub trait WithExitCode<T> {
+    /// Attaches the exit code to the error, unless it already has one.
+    fn exit_code(self, code: ExitCode) -> Result<T>;
+}
+
+impl<T> WithExitCode<T> for Result<T> {
+    fn exit_code(self, code: ExitCode) -> Result<T> {
+        self.map_err(|report| {
+            if report.downcast_ref::<ExitCode>().is_some()
+                || report.downcast_ref::<ErrorCode>().is_some()
+            {
+                report
+            } else {
+                report.wrap_err(code)
+            }
+        })
+    }
+}
+
+/// Exit code attached to the error, or the one of its error code.
+pub fn exit_code_of(report: &Report) -> ExitCode {
+    if let Some(code) = report.downcast_ref::<ExitCode>() {
+        return *code;
+    }
+
+    error_code_of(report).map_or(ExitCode::Failure, ErrorCode::exit_code)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use color_eyre::eyre::{Context, eyre};
+
+    use super::*;
+
+    #[test]
+    fn test_exit_code_of() {
+        let verification: Result<()> = Err(eyre!("Hash mismatch"));
+        let verification = verification
+            .exit_code(ExitCode::VerificationFailed)
+            .wrap_err("Backup failed")
+            .exit_code(ExitCode::PruneFailed)
+            .unwrap_err();
+        let storage_full: Result<()> =
+            Err(io::Error::from(io::ErrorKind::StorageFull)).wrap_err("Failed to copy source file");
+        let failure = eyre!("Something else");
+
+        assert_eq!(exit_code_of(&verification), ExitCode::VerificationFailed);
+        assert_eq!(
+            exit_code_of(&storage_full.unwrap_err()),
+            ExitCode::StorageFull
+        );
+        assert_eq!(exit_code_of(&failure), ExitCode::Failure);
+    }
+}
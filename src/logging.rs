@@ -4,15 +4,78 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::fs::{OpenOptions, create_dir_all};
+use std::{
+    cell::RefCell,
+    fs::{File, OpenOptions, create_dir_all},
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use clap::CommandFactory;
 use color_eyre::eyre::{Result, eyre};
-use log::{LevelFilter, info};
-use simplelog::{ColorChoice, CombinedLogger, Config, TermLogger, TerminalMode, WriteLogger};
+use log::{LevelFilter, Log, Metadata, Record, info};
+use simplelog::{ColorChoice, Config, TermLogger, TerminalMode, WriteLogger};
 
 use crate::Cli;
 
+thread_local! {
+    static JOB_NAME: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+static TERMINAL_LOGGING: AtomicBool = AtomicBool::new(true);
+
+/// Prefixes the log messages of the current thread with the name of the job it runs.
+pub fn set_job_name(name: Option<String>) {
+    JOB_NAME.with(|job_name| *job_name.borrow_mut() = name);
+}
+
+/// Stops or resumes logging to the terminal, e.g. while the terminal UI is shown.
+///
+/// Messages are still written to the log file.
+pub fn set_terminal_logging(enabled: bool) {
+    TERMINAL_LOGGING.store(enabled, Ordering::Relaxed);
+}
+
+/// Logger prefixing messages with the job name of the logging thread, if any.
+struct JobLogger {
+    terminal: Box<TermLogger>,
+    file: Box<WriteLogger<File>>,
+}
+
+impl JobLogger {
+    fn log_to_all(&self, record: &Record) {
+        if TERMINAL_LOGGING.load(Ordering::Relaxed) {
+            self.terminal.log(record);
+        }
+        self.file.log(record);
+    }
+}
+
+impl Log for JobLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.terminal.enabled(metadata) || self.file.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        JOB_NAME.with(|job_name| match &*job_name.borrow() {
+            Some(job_name) => self.log_to_all(
+                &Record::builder()
+                    .args(format_args!("[{}] {}", job_name, record.args()))
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            ),
+            None => self.log_to_all(record),
+        });
+    }
+
+    fn flush(&self) {
+        self.terminal.flush();
+        self.file.flush();
+    }
+}
+
 pub fn setup_logging() -> Result<()> {
     let dirs = directories::BaseDirs::new()
         .ok_or(eyre!("Failed getting base dirs like AppData on Windows."))?;
@@ -31,15 +94,18 @@ pub fn setup_logging() -> Result<()> {
         .create(true)
         .open(&log_file)?;
 
-    let _ = CombinedLogger::init(vec![
-        (TermLogger::new(
+    let logger = JobLogger {
+        terminal: TermLogger::new(
             LevelFilter::Info,
             Config::default(),
             TerminalMode::Stderr,
             ColorChoice::Auto,
-        )),
-        (WriteLogger::new(LevelFilter::Info, Config::default(), log_file_handle)),
-    ]);
+        ),
+        file: WriteLogger::new(LevelFilter::Info, Config::default(), log_file_handle),
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(LevelFilter::Info);
+    }
 
     info!("Logs are written to: '{}'", log_file.display());
 
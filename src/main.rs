@@ -4,22 +4,61 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::{path::PathBuf, str::FromStr};
+use std::{io::IsTerminal, path::PathBuf, str::FromStr, time::Duration};
 
-use clap::{CommandFactory, Parser, ValueEnum, ValueHint};
-use clap_complete::Shell;
-use color_eyre::eyre::{Ok, Result};
+use chrono::{NaiveDate, NaiveDateTime};
+use clap::{
+    CommandFactory, Parser, Subcommand, ValueEnum, ValueHint,
+    builder::{PathBufValueParser, TypedValueParser},
+    error::ErrorKind,
+};
+use clap_complete::{ArgValueCompleter, CompleteEnv, Shell};
+use color_eyre::eyre::{Context, Ok, Result, eyre};
+use exit_code::{ExitCode, WithExitCode, exit_code_of};
 use license_fetcher::read_package_list_from_out_dir;
 
-use crate::{logging::setup_logging, setup::setup_hooks};
+use crate::{
+    backup::{
+        archive::Format,
+        cleanup::{CapMode, PeriodAnchor, RetentionScope, Strategy, Tier, Tiered},
+        file::{Layout, Preserve, TimestampSource, Timezone, system_time_from_naive},
+        normalize::NameNormalization,
+        parsing::ScanFilter,
+        rclone::Remote,
+        sidecar::SidecarFormat,
+        simulate::Frequency,
+        template::{DEFAULT_NAME_TEMPLATE, NameTemplate},
+        undo::OnNoTrash,
+        verify::VerifyMode,
+        webdav::WebDavTarget,
+    },
+    logging::setup_logging,
+    setup::setup_hooks,
+};
 
 mod backup;
+mod completion;
+mod config;
+mod credentials;
+mod error_code;
+mod exit_code;
 mod logging;
 mod model;
+mod run;
 mod schema;
 mod setup;
+mod tui;
+mod update;
+mod version;
+
+/// Source argument reading the data to back up from stdin or `--source-cmd`.
+const STREAM_SOURCE: &str = "-";
 
 fn parse_str_to_source_pathbuf(s: &str) -> std::result::Result<PathBuf, String> {
+    if s == STREAM_SOURCE {
+        return std::result::Result::Ok(PathBuf::from(s));
+    }
+
     match PathBuf::from_str(s) {
         std::result::Result::Ok(path_buf) => {
             if path_buf.is_file() && path_buf.try_exists().map_err(|err| err.to_string())? {
@@ -32,61 +71,656 @@ fn parse_str_to_source_pathbuf(s: &str) -> std::result::Result<PathBuf, String>
     }
 }
 
-fn parse_str_to_target_pathbuf(s: &str) -> std::result::Result<PathBuf, String> {
-    match PathBuf::from_str(s) {
-        std::result::Result::Ok(path_buf) => {
-            if path_buf.is_dir() && path_buf.try_exists().map_err(|err| err.to_string())? {
-                std::result::Result::Ok(path_buf)
-            } else {
-                Err("Target folder path is not a directory".to_owned())
-            }
+fn parse_str_to_name_template(s: &str) -> std::result::Result<NameTemplate, String> {
+    NameTemplate::parse(s).map_err(|err| err.to_string())
+}
+
+fn parse_str_to_rclone_remote(s: &str) -> std::result::Result<Remote, String> {
+    Remote::parse(s).map_err(|err| err.to_string())
+}
+
+fn parse_str_to_tag(s: &str) -> std::result::Result<String, String> {
+    if s.is_empty() || s.contains(',') {
+        Err("Expected a non-empty tag without commas".to_owned())
+    } else {
+        std::result::Result::Ok(s.to_owned())
+    }
+}
+
+fn parse_str_to_percent(s: &str) -> std::result::Result<u8, String> {
+    match s.trim_end_matches('%').parse::<u8>() {
+        std::result::Result::Ok(percent) if (1..=100).contains(&percent) => {
+            std::result::Result::Ok(percent)
         }
-        Err(_) => Err("Target folder path is not a path".to_owned()),
+        _ => Err("Expected a percentage between 1% and 100%".to_owned()),
+    }
+}
+
+fn parse_str_to_duration(s: &str) -> std::result::Result<Duration, String> {
+    const ERROR: &str = "Expected a duration like 5s, 500ms, 1m or 6h";
+
+    let (number, unit) = s
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or((s, "s"), |index| s.split_at(index));
+    let number = number.parse::<u64>().map_err(|_| ERROR.to_owned())?;
+
+    let seconds = |factor: u64| {
+        number
+            .checked_mul(factor)
+            .map(Duration::from_secs)
+            .ok_or_else(|| ERROR.to_owned())
+    };
+    match unit {
+        "ms" => std::result::Result::Ok(Duration::from_millis(number)),
+        "s" => seconds(1),
+        "m" => seconds(60),
+        "h" => seconds(60 * 60),
+        "d" => seconds(24 * 60 * 60),
+        _ => Err(ERROR.to_owned()),
+    }
+}
+
+fn parse_str_to_size(s: &str) -> std::result::Result<usize, String> {
+    let (number, unit) = s
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or((s, ""), |index| s.split_at(index));
+    let factor = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        _ => return Err("Expected a size like 64K, 1M or 1G".to_owned()),
+    };
+
+    match number.parse::<usize>() {
+        std::result::Result::Ok(number) if number > 0 => number
+            .checked_mul(factor)
+            .ok_or_else(|| "Size is too large".to_owned()),
+        _ => Err("Expected a size like 64K, 1M or 1G".to_owned()),
+    }
+}
+
+fn parse_str_to_rate(s: &str) -> std::result::Result<u64, String> {
+    let size = s
+        .strip_suffix("/s")
+        .or_else(|| s.strip_suffix("/S"))
+        .unwrap_or(s);
+    parse_str_to_size(size)
+        .map(|size| size as u64)
+        .map_err(|_| "Expected a rate like 500K/s or 5MiB/s".to_owned())
+}
+
+fn parse_str_to_date_time(s: &str) -> std::result::Result<NaiveDateTime, String> {
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M")
+        .map_err(|_| "Expected a date and time like 2025-01-31T23:59".to_owned())
+}
+
+fn parse_str_to_date(s: &str) -> std::result::Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| "Expected a date like 2025-01-31".to_owned())
+}
+
+/// Checks the target folder path, which need not be valid UTF-8.
+fn check_target_pathbuf(path_buf: PathBuf) -> std::result::Result<PathBuf, String> {
+    if path_buf.is_dir() && path_buf.try_exists().map_err(|err| err.to_string())? {
+        std::result::Result::Ok(path_buf)
+    } else {
+        Err("Target folder path is not a directory".to_owned())
     }
 }
 
 /// An easy and secure staggered file backup solution
 #[derive(Parser, Debug)]
-#[command(version, about, author)]
+#[command(
+    version,
+    disable_version_flag = true,
+    about,
+    author,
+    args_conflicts_with_subcommands = true
+)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     /// Path to file to be backed up
-    #[arg(value_name = "FILE", value_hint = ValueHint::FilePath, value_parser = parse_str_to_source_pathbuf, requires = "target")]
+    ///
+    /// Use `-` to back up data from stdin or from the output of `--source-cmd`.
+    #[arg(value_name = "FILE", value_hint = ValueHint::FilePath, value_parser = parse_str_to_source_pathbuf, requires = "target", env = "SFB_SOURCE")]
     source: Option<PathBuf>,
 
     /// Path to folder to place backups in
     ///
     /// Please do not use the folder for anything else!
-    #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = parse_str_to_target_pathbuf)]
+    #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf), env = "SFB_TARGET")]
     target: Option<PathBuf>,
 
     /// Set retention period for the newest backups.
     ///
     /// Setting the retention to n implies that the last n backups are kept regardless.
     /// A value of -1 implies no cleanup.
-    #[arg(short = 'n', long = "keep-newest", default_value_t = 8, value_parser = clap::value_parser!(i32).range(-1..))]
+    #[arg(short = 'n', long = "keep-newest", default_value_t = 8, value_parser = clap::value_parser!(i32).range(-1..), env = "SFB_KEEP_NEWEST")]
     keep_newest_count: i32,
 
     /// Set retention period for the daily backups.
     ///
     /// Setting the retention to n implies that the last n daily backups are kept.
     /// A value of -1 implies no cleanup.
-    #[arg(short = 'd', long = "keep-daily", default_value_t = 32, value_parser = clap::value_parser!(i32).range(-1..))]
+    #[arg(short = 'd', long = "keep-daily", default_value_t = 32, value_parser = clap::value_parser!(i32).range(-1..), env = "SFB_KEEP_DAILY")]
     keep_daily_count: i32,
 
+    /// Set retention period for the weekly backups.
+    ///
+    /// Setting the retention to n implies that the last n weekly backups are kept, one of each ISO
+    /// week starting on Monday. A value of -1 disables the weekly backups.
+    #[arg(long = "keep-weekly", default_value_t = -1, value_parser = clap::value_parser!(i32).range(-1..), env = "SFB_KEEP_WEEKLY")]
+    keep_weekly_count: i32,
+
     /// Set retention period for the monthly backups.
     ///
     /// Setting the retention to n implies that the last n monthly backups are kept.
     /// A value of -1 implies no cleanup.
-    #[arg(short = 'm', long = "keep-monthly", default_value_t = 12, value_parser = clap::value_parser!(i32).range(-1..))]
+    #[arg(short = 'm', long = "keep-monthly", default_value_t = 12, value_parser = clap::value_parser!(i32).range(-1..), env = "SFB_KEEP_MONTHLY")]
     keep_monthly_count: i32,
 
+    /// Set retention period for the quarterly backups.
+    ///
+    /// Setting the retention to n implies that the last n quarterly backups are kept, one of each
+    /// calendar quarter. A value of -1 disables the quarterly backups.
+    #[arg(long = "keep-quarterly", default_value_t = -1, value_parser = clap::value_parser!(i32).range(-1..), env = "SFB_KEEP_QUARTERLY")]
+    keep_quarterly_count: i32,
+
     /// Set retention period for the yearly backups.
     ///
     /// Setting the retention to n implies that the last n yearly backups are kept.
     /// A value of -1 implies no cleanup.
-    #[arg(short = 'y', long = "keep-yearly", default_value_t = -1, value_parser = clap::value_parser!(i32).range(-1..))]
+    #[arg(short = 'y', long = "keep-yearly", default_value_t = -1, value_parser = clap::value_parser!(i32).range(-1..), env = "SFB_KEEP_YEARLY")]
     keep_yearly_count: i32,
 
+    /// Which backups of the target folder the retention periods apply to together
+    ///
+    /// With `per-host` machines sharing a target folder, e.g. on a network share, never prune each
+    /// other's backups, and backups of different source files are pruned separately. The host is
+    /// taken from `{hostname}` in the name template or else from the tracking database. Use
+    /// `global` once to prune the backups of retired machines.
+    #[arg(long, value_enum, default_value_t = RetentionScope::PerHost, env = "SFB_RETENTION_SCOPE")]
+    retention_scope: RetentionScope,
+
+    /// Algorithm selecting the backups to keep with the retention periods
+    #[arg(long, value_enum, default_value_t = Strategy::Tiered, env = "SFB_STRATEGY")]
+    strategy: Strategy,
+
+    /// Which backup of each period the retention periods keep
+    ///
+    /// `last` keeps the state at the end of each period instead of the state at its start.
+    #[arg(long, value_enum, default_value_t = PeriodAnchor::First, env = "SFB_PERIOD_ANCHOR")]
+    period_anchor: PeriodAnchor,
+
+    /// Only count backups whose content changed toward the retention periods
+    ///
+    /// Each run of identical backups counts once, by its first backup, so periods without changes
+    /// don't use up the daily and monthly retention periods. The newest backups are kept
+    /// regardless.
+    #[arg(long, env = "SFB_CONTENT_EPOCHS")]
+    content_epochs: bool,
+
+    /// Write the names of new backups in this unicode form
+    ///
+    /// Backups are named like the source by default. Names are compared regardless of their form,
+    /// as macOS and SMB shares may return them composed (NFC) or decomposed (NFD).
+    #[arg(long, value_enum, env = "SFB_NORMALIZE_NAMES")]
+    normalize_names: Option<NameNormalization>,
+
+    /// Time to wait for another backup into the target folder to finish, e.g. 10m
+    ///
+    /// Backups lock the target folder, so that machines backing up into the same network share
+    /// at once do not interfere. Exits with 5 if the lock is still held afterwards.
+    #[arg(long, value_name = "DURATION", default_value = "0s", value_parser = parse_str_to_duration, env = "SFB_LOCK_TIMEOUT")]
+    lock_timeout: Duration,
+
+    /// Skip the backup if the newest backup of the source is younger than this, e.g. 6h
+    ///
+    /// Useful when backups are triggered by events firing in bursts. Skipped backups exit with 2.
+    #[arg(long, value_name = "DURATION", value_parser = parse_str_to_duration, env = "SFB_MIN_INTERVAL")]
+    min_interval: Option<Duration>,
+
+    /// Skip or replace backups once this many backups of the source were created that day
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), env = "SFB_MAX_PER_DAY")]
+    max_per_day: Option<u32>,
+
+    /// What to do once `--max-per-day` is reached
+    #[arg(long, value_enum, default_value_t = CapMode::Skip, env = "SFB_CAP_MODE")]
+    cap_mode: CapMode,
+
+    /// Back up even if the newest backup is younger than `--min-interval` or `--max-per-day` is
+    /// reached
+    #[arg(long, env = "SFB_FORCE")]
+    force: bool,
+
+    /// Verify the newest backup of the source against its hash file before backing up
+    ///
+    /// A corrupted backup is moved into the `quarantine/` subfolder and a full backup is created,
+    /// even if the source is unchanged with `--trust-mtime` or the backup would be skipped by
+    /// `--min-interval` or `--max-per-day`.
+    #[arg(long, env = "SFB_VERIFY_NEWEST")]
+    verify_newest: bool,
+
+    /// Shell command whose output is backed up, when using `-` as source
+    ///
+    /// The output is streamed into the backup without intermediate file.
+    #[arg(long, value_name = "COMMAND", requires = "name")]
+    source_cmd: Option<String>,
+
+    /// File name the data streamed from stdin or `--source-cmd` is backed up as, e.g. mydb.sql
+    ///
+    /// Streamed backups are dated by the current time.
+    #[arg(
+        long,
+        value_name = "FILE_NAME",
+        required_if_eq("source", STREAM_SOURCE)
+    )]
+    name: Option<PathBuf>,
+
+    /// Point in time the backup is dated by
+    #[arg(long, value_enum, default_value_t = TimestampSource::Mtime, env = "SFB_TIMESTAMP")]
+    timestamp: TimestampSource,
+
+    /// Timezone the date of the backup is expressed in
+    ///
+    /// Using UTC avoids backups landing in unexpected days around midnight or DST shifts.
+    #[arg(long, value_enum, default_value_t = Timezone::Local, env = "SFB_TIMEZONE")]
+    timezone: Timezone,
+
+    /// Template backups are named by
+    ///
+    /// Available placeholders are {date}, {counter}, {hostname}, {basename} and {ext}.
+    /// {date} and {counter} are required so that backups can be pruned.
+    #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_NAME_TEMPLATE, value_parser = parse_str_to_name_template, env = "SFB_NAME_TEMPLATE")]
+    name_template: NameTemplate,
+
+    /// How backups are arranged inside the target folder
+    ///
+    /// The nested layout places backups in `<year>/<month>/` subfolders.
+    #[arg(long, value_enum, default_value_t = Layout::Flat, env = "SFB_LAYOUT")]
+    layout: Layout,
+
+    /// Store backups as delta against the newest full backup
+    ///
+    /// Only the binary difference to the newest full backup is stored.
+    /// Full backups are still created periodically, see `--full-every`.
+    #[arg(long, conflicts_with = "dedup_store", env = "SFB_INCREMENTAL")]
+    incremental: bool,
+
+    /// Create a full backup every n backups when using `--incremental`
+    #[arg(
+        long,
+        default_value_t = 7,
+        requires = "incremental",
+        env = "SFB_FULL_EVERY"
+    )]
+    full_every: u32,
+
+    /// How backups are stored
+    ///
+    /// Zip archives can be opened without this tool, e.g. by Windows Explorer.
+    #[arg(long, value_enum, default_value_t = Format::Plain, env = "SFB_FORMAT")]
+    format: Format,
+
+    /// Encrypt zip backups with AES-256 using the password
+    ///
+    /// Prefer setting the password with the environment variable, which is also read when
+    /// restoring and verifying encrypted zip backups. Windows Explorer cannot open encrypted
+    /// archives, 7-Zip can.
+    #[arg(
+        long,
+        value_name = "PASSWORD",
+        env = "SFB_ZIP_PASSWORD",
+        hide_env_values = true
+    )]
+    zip_password: Option<String>,
+
+    /// Deflate zip backups even if the source looks already compressed
+    ///
+    /// Sources with a compressed extension like jpg, mp4 or zst, or with a high entropy start,
+    /// are otherwise stored in the zip archive as is, since deflating them saves no space.
+    #[arg(long, env = "SFB_COMPRESS_FORCE")]
+    compress_force: bool,
+
+    /// Store backups deduplicated in a chunk store
+    ///
+    /// The source is split into content defined chunks, which are stored in a content addressed
+    /// subfolder of the target folder. Each backup is then a manifest of chunk hashes.
+    /// Unreferenced chunks can be removed with the `gc` command.
+    #[arg(long, env = "SFB_DEDUP_STORE")]
+    dedup_store: bool,
+
+    /// Allow backing up into a folder not used by this tool before, and pruning untracked backups
+    ///
+    /// Adopts the target folder like `--adopt`. Additionally, backups missing from the tracking
+    /// database are moved into the recycle bin by the retention periods as well, even if they are
+    /// newer than the oldest tracked backup and were thus put there by someone else.
+    #[arg(long, env = "SFB_ALLOW_UNMANAGED_DIR")]
+    allow_unmanaged_dir: bool,
+
+    /// Manage a non-empty target folder not used by this tool before from now on
+    ///
+    /// Target folders are marked as managed by `.staggered-file-backup.json` on their first
+    /// backup. Backups into non-empty folders without marker are refused, so that a mistyped
+    /// target folder is never pruned.
+    #[arg(long, env = "SFB_ADOPT")]
+    adopt: bool,
+
+    /// Fail if the target folder contains files not following the name template
+    ///
+    /// Foreign files like `Thumbs.db` are left alone and warned about otherwise. List globs of
+    /// files to ignore silently, like `*.txt`, one per line in `.sfbignore` in the target folder.
+    #[arg(long, env = "SFB_STRICT")]
+    strict: bool,
+
+    /// How the new backup is checked against the source
+    ///
+    /// `bytes` compares the content of source and backup instead of hashes computed by the same
+    /// process, `both` does both.
+    #[arg(long, value_enum, default_value_t = VerifyMode::Hash, env = "SFB_VERIFY_MODE")]
+    verify_mode: VerifyMode,
+
+    /// Verify the backups to keep before moving any backup into the recycle bin
+    ///
+    /// Pruning is refused if none of the backups to keep matches its hash file.
+    #[arg(long, env = "SFB_VERIFY_BEFORE_PRUNE")]
+    verify_before_prune: bool,
+
+    /// Allow cleanup to remove all backups of a source
+    ///
+    /// By default the newest intact backup of each source is kept regardless of the retention
+    /// periods.
+    #[arg(long, env = "SFB_ALLOW_EMPTY")]
+    allow_empty: bool,
+
+    /// Create the backup without applying the retention or moving anything into the recycle bin
+    ///
+    /// For extra copies before a risky change, leaving the other backups as they are. The next
+    /// backup without this flag prunes as usual.
+    #[arg(long, env = "SFB_NO_PRUNE")]
+    no_prune: bool,
+
+    /// What to do with pruned backups when the recycle bin is unavailable
+    ///
+    /// E.g. on headless Linux without a home trash folder or on network shares. By default the
+    /// backup fails after it was created, keeping all older backups.
+    #[arg(long, value_enum, default_value_t = OnNoTrash::Fail, env = "SFB_ON_NO_TRASH")]
+    on_no_trash: OnNoTrash,
+
+    /// Folder pruned backups are moved into with `--on-no-trash archive-dir`
+    #[arg(
+        long,
+        value_name = "DIR",
+        value_hint = ValueHint::DirPath,
+        required_if_eq("on_no_trash", "archive-dir"),
+        env = "SFB_ARCHIVE_DIR"
+    )]
+    archive_dir: Option<PathBuf>,
+
+    /// Move backups into the recycle bin without asking for confirmation
+    ///
+    /// When run in a terminal, confirmation is asked for before more than `--confirm-above`
+    /// backups are moved into the recycle bin.
+    #[arg(long, visible_alias = "non-interactive", env = "SFB_YES")]
+    yes: bool,
+
+    /// Number of backups that may be moved into the recycle bin without asking for confirmation
+    #[arg(
+        long,
+        value_name = "COUNT",
+        default_value_t = 3,
+        env = "SFB_CONFIRM_ABOVE"
+    )]
+    confirm_above: usize,
+
+    /// Verify the n least recently verified backups after each backup
+    ///
+    /// Spreads the detection of corrupted backups over time instead of verifying all backups at
+    /// once with the `verify` command.
+    #[arg(long, value_name = "N", env = "SFB_SCRUB")]
+    scrub: Option<u32>,
+
+    /// Format of the hash file written next to each backup
+    ///
+    /// Hash files of plain backups in the GNU format are checked by `sha256sum -c`. With `none`,
+    /// `verify` uses the hashes in the tracking database instead.
+    #[arg(long, value_enum, default_value_t = SidecarFormat::Gnu, env = "SFB_SIDECAR_FORMAT")]
+    sidecar_format: SidecarFormat,
+
+    /// Write parity data of the given size next to each backup, e.g. 5%
+    ///
+    /// Corrupted backups can be repaired with the parity data using the `repair` command.
+    #[arg(long, value_name = "PERCENT", value_parser = parse_str_to_percent, env = "SFB_PARITY")]
+    parity: Option<u8>,
+
+    /// Sign each backup with the given minisign secret key
+    ///
+    /// A detached signature is placed next to each backup, which can be checked with the `verify`
+    /// command or `minisign -V`. Encrypted keys prompt for their password, unless it was stored
+    /// with the `store-key-password` command.
+    #[arg(long, value_name = "KEY_FILE", value_hint = ValueHint::FilePath, env = "SFB_SIGN_KEY")]
+    sign_key: Option<PathBuf>,
+
+    /// Retry copying, hashing and moving into the recycle bin n times when failing
+    ///
+    /// Helps with network shares dropping for a moment.
+    #[arg(long, value_name = "N", default_value_t = 0, env = "SFB_RETRIES")]
+    retries: u32,
+
+    /// Delay before the first retry, doubled for each further retry, e.g. 5s or 500ms
+    #[arg(long, value_name = "DURATION", default_value = "5s", value_parser = parse_str_to_duration, env = "SFB_RETRY_DELAY")]
+    retry_delay: Duration,
+
+    /// Retry the backup n times when the source file changes while being backed up
+    ///
+    /// Changes are detected by the modification date and size of the source file, e.g. for a
+    /// log file still being written to.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 3,
+        env = "SFB_CHANGE_RETRIES"
+    )]
+    change_retries: u32,
+
+    /// Read the source file from a Volume Shadow Copy (Windows only)
+    ///
+    /// Allows backing up files locked by other processes, like Outlook PST files or save games
+    /// of running games. Requires administrator privileges.
+    #[arg(long, env = "SFB_USE_VSS")]
+    use_vss: bool,
+
+    /// Metadata of the source file to carry over to the backup, separated by commas
+    ///
+    /// Pass the flag without values to preserve nothing. Streamed sources have no metadata to
+    /// preserve.
+    #[arg(long, value_enum, value_delimiter = ',', num_args = 0.., default_value = "mtime", env = "SFB_PRESERVE")]
+    preserve: Vec<Preserve>,
+
+    /// Make each backup read-only after it passed verification
+    ///
+    /// Protected backups are made writable again before being moved into the recycle bin.
+    #[arg(long, env = "SFB_PROTECT")]
+    protect: bool,
+
+    /// Also set the immutable attribute with `chattr +i` on protected backups (Linux only)
+    ///
+    /// Requires root.
+    #[arg(long, requires = "protect", env = "SFB_IMMUTABLE")]
+    immutable: bool,
+
+    /// Flush the backup, its hash file and the target folder to disk after writing
+    ///
+    /// Guards against empty backups after a power loss on file systems with delayed allocation.
+    #[arg(long, env = "SFB_FSYNC")]
+    fsync: bool,
+
+    /// Copy and hash with a buffer of the given size, e.g. 64K or 8M
+    ///
+    /// By default files are cloned (copy-on-write) if supported or copied by the OS.
+    /// Compare settings with the `bench` command.
+    #[arg(long, value_name = "SIZE", value_parser = parse_str_to_size, env = "SFB_BUFFER_SIZE")]
+    buffer_size: Option<usize>,
+
+    /// Copy and hash bypassing the page cache (Linux only)
+    ///
+    /// Keeps backups of large files from evicting everything else from memory.
+    #[arg(long, env = "SFB_DIRECT_IO")]
+    direct_io: bool,
+
+    /// Verify copies of source files of at least this size with a parallel chunked hash
+    ///
+    /// Hashes the source concurrently with the SHA-256 stored in the sidecar, and hashes the copy
+    /// with all cores, instead of a second single-threaded SHA-256 pass.
+    #[arg(long, value_name = "SIZE", value_parser = parse_str_to_size, default_value = "1G", env = "SFB_PARALLEL_HASH_ABOVE")]
+    parallel_hash_above: usize,
+
+    /// Always verify copies with a single-threaded SHA-256 pass
+    #[arg(long, env = "SFB_NO_PARALLEL_HASH")]
+    no_parallel_hash: bool,
+
+    /// Skip hashing the source file if its size and modification time match the last backup
+    ///
+    /// Reuses the hash stored in the tracking database and trusts hardlinks to identical backups,
+    /// turning runs on unchanged huge files into a quick metadata check. Falls back to a full hash
+    /// otherwise.
+    #[arg(long, env = "SFB_TRUST_MTIME")]
+    trust_mtime: bool,
+
+    /// Maintain `latest_<file name>` in the target folder pointing at the newest backup
+    ///
+    /// A relative symlink, or a copy on file systems without symlinks, giving downstream tooling
+    /// a stable path.
+    #[arg(long, env = "SFB_LATEST_LINK")]
+    latest_link: bool,
+
+    /// Tag the backup, e.g. `release`, can be repeated
+    ///
+    /// Tags are stored in the tracking database and shown by the `list` command.
+    #[arg(long, value_name = "TAG", value_parser = parse_str_to_tag)]
+    tag: Vec<String>,
+
+    /// Comment stored with the backup and shown by the `list` command
+    #[arg(long)]
+    comment: Option<String>,
+
+    /// Never move tagged backups into the recycle bin
+    #[arg(long, env = "SFB_KEEP_TAGGED")]
+    keep_tagged: bool,
+
+    /// Move old backups into a second folder, e.g. on slower or cheaper storage
+    ///
+    /// By default backups only kept by the monthly and yearly retention periods are moved, or with
+    /// `--strategy exponential` those kept for their age range. Backups of other hosts stay. The
+    /// database remembers where they went, so `list`, `verify` and `restore` still find them.
+    #[arg(long, value_name = "COLD_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf), env = "SFB_COLD_TARGET")]
+    cold_target: Option<PathBuf>,
+
+    /// Move backups older than this many days into cold storage instead
+    #[arg(
+        long,
+        value_name = "DAYS",
+        requires = "cold_target",
+        env = "SFB_COLD_AFTER"
+    )]
+    cold_after: Option<u32>,
+
+    /// Mirror the target folder onto an rclone remote after each backup, e.g. `gdrive:backups`
+    ///
+    /// New backups are uploaded and pruned ones deleted with rclone, which has to be installed
+    /// and configured. Restore by downloading the remote with `rclone copy` and running `restore`
+    /// on the downloaded folder.
+    #[arg(long, value_name = "REMOTE", value_parser = parse_str_to_rclone_remote, env = "SFB_RCLONE_REMOTE")]
+    rclone_remote: Option<Remote>,
+
+    /// Mirror the target folder onto a WebDAV folder after each backup, e.g. on Nextcloud
+    ///
+    /// Large backups are uploaded in chunks to Nextcloud and pruned backups are moved into the
+    /// `.trash` folder on the server. The password is read from the keyring of the OS, where it is
+    /// stored with `store-webdav-password`.
+    #[arg(
+        long,
+        value_name = "URL",
+        requires = "webdav_user",
+        env = "SFB_WEBDAV_URL"
+    )]
+    webdav_url: Option<String>,
+
+    /// User to log into the WebDAV server as
+    #[arg(
+        long,
+        value_name = "USER",
+        requires = "webdav_url",
+        env = "SFB_WEBDAV_USER"
+    )]
+    webdav_user: Option<String>,
+
+    /// Limit uploads to rclone remotes and WebDAV folders to the rate, e.g. 5MiB/s
+    #[arg(long, value_name = "RATE", value_parser = parse_str_to_rate, env = "SFB_BWLIMIT")]
+    bwlimit: Option<u64>,
+
+    /// Read back each file uploaded to rclone remotes and WebDAV folders and compare its hash
+    ///
+    /// rclone remotes hash the files on the server where supported. Otherwise the files are
+    /// downloaded, so verifying takes as long as uploading.
+    #[arg(long, env = "SFB_VERIFY_UPLOADS")]
+    verify_uploads: bool,
+
+    /// Write metrics of each run to the file, for the textfile collector of node_exporter
+    ///
+    /// Contains gauges like sfb_last_success_timestamp, sfb_backup_size_bytes and
+    /// sfb_backups_total, so that alerts can fire when backups go stale. Failed runs keep the
+    /// values of the last successful run. Name the file with the extension `.prom`.
+    #[arg(long, value_name = "PATH", env = "SFB_METRICS_FILE")]
+    metrics_file: Option<PathBuf>,
+
+    /// Write the summary of each run to the file as JSON
+    ///
+    /// Contains the bytes copied, the throughput, the time spent hashing, the backups kept per
+    /// retention tier, the backups moved into the recycle bin and the size of the target folder
+    /// before and after the run. The summary is logged either way. Failed runs write their error
+    /// code, like `E0007`, and message instead.
+    #[arg(long, value_name = "PATH", env = "SFB_SUMMARY_FILE")]
+    summary_file: Option<PathBuf>,
+
+    /// Also append trashed, deleted and restored files to `audit.log` in the target folder
+    ///
+    /// All of them are recorded with time, reason, size and hash in the tracking database anyway,
+    /// see `audit list`. Once the file exists, other commands append to it as well.
+    #[arg(long, env = "SFB_AUDIT_LOG")]
+    audit_log: bool,
+
+    /// Start a hash chain of added and removed backups in `chain.jsonl` in the target folder
+    ///
+    /// Each entry contains the hash of the previous one and runs with `--sign-key` sign the chain,
+    /// so that `verify --chain` detects backups deleted or replaced by anyone without the key. Once
+    /// the file exists, other commands append to it as well.
+    #[arg(long, env = "SFB_CHAIN")]
+    chain: bool,
+
+    /// Simulate the current time, in the timezone of --timezone
+    ///
+    /// Backups are dated by the simulated time instead of the modification time of the source, so
+    /// that many runs against a temporary folder show which backups the retention keeps.
+    #[arg(
+        long,
+        hide = true,
+        value_name = "YYYY-MM-DDTHH:MM",
+        value_parser = parse_str_to_date_time
+    )]
+    now: Option<NaiveDateTime>,
+
+    /// Print version
+    #[arg(short = 'V', long = "version")]
+    print_version: bool,
+
+    /// Print the version, commit, build date, target and capabilities as JSON
+    #[arg(long, requires = "print_version")]
+    json: bool,
+
     /// Print licenses
     ///
     /// Print licenses of this project and all its dependencies
@@ -98,15 +732,633 @@ struct Cli {
     supported_shells: bool,
 
     /// Print shell completion for requested shell
+    ///
+    /// To also complete job names and backup dates, source the output of
+    /// `COMPLETE=<SHELL> staggered-file-backup` instead, e.g. `source <(COMPLETE=bash
+    /// staggered-file-backup)` in `~/.bashrc`.
     #[arg(long, exclusive = true, value_enum)]
     generate_completion: Option<Shell>,
+
+    /// Write man pages of the tool and each subcommand into the folder
+    #[arg(long, exclusive = true, value_name = "FOLDER", value_hint = ValueHint::DirPath)]
+    generate_man: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum AuditCommands {
+    /// List the trashed, deleted and restored files of the target folder, oldest first
+    List {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum DbCommands {
+    /// Show the schema version of the tracking database and the pending migrations
+    Status {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+    },
+    /// Apply the pending migrations to the tracking database
+    ///
+    /// The database is copied next to it first, named after its schema version like
+    /// `staggered-file-backup.keepme.202610151800000000.bak`. Backups apply pending migrations the
+    /// same way.
+    Upgrade {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+    },
+    /// Check the tracking database for corruption and against the backups of the target folder
+    ///
+    /// Lists tracked backups that are neither found nor were pruned, and backups missing from the
+    /// database, before they lead to wrong prune decisions. Exits with 3 if any are found.
+    Check {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+
+        /// Template the backups were named by
+        #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_NAME_TEMPLATE, value_parser = parse_str_to_name_template)]
+        name_template: NameTemplate,
+    },
+    /// Rebuild the tracking database, shrinking it to the space its rows take up
+    Vacuum {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum TrashCommands {
+    /// List the files moved into the recycle bin by prunes of the target folder, oldest first
+    List {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+    },
+    /// Remove files moved into the recycle bin by prunes of the target folder for good
+    Purge {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+
+        /// Only purge files pruned longer ago than this, like 30d
+        #[arg(long, value_name = "DURATION", value_parser = parse_str_to_duration)]
+        older_than: Duration,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ChecksumsCommands {
+    /// Write the hashes of all backups of the target folder into one `SHA256SUMS` file
+    ///
+    /// Paths are relative to the target folder, so `sha256sum -c SHA256SUMS` run in the target
+    /// folder verifies the backups without staggered-file-backup.
+    Export {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+
+        /// Template the backups were named by
+        #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_NAME_TEMPLATE, value_parser = parse_str_to_name_template)]
+        name_template: NameTemplate,
+
+        /// Format of the lines, `gnu` for `sha256sum -c` or `bsd` for `sha256 -c`
+        #[arg(long, value_enum, default_value_t = SidecarFormat::Gnu)]
+        format: SidecarFormat,
+
+        /// Path of the file to write, defaults to `SHA256SUMS` in the target folder
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommands {
+    /// Write a commented config file to get started
+    Init {
+        /// Path of the config file
+        ///
+        /// Defaults to `config.toml` in the platform specific config folder.
+        #[arg(long, value_name = "CONFIG_FILE", value_hint = ValueHint::FilePath, env = "SFB_CONFIG")]
+        config: Option<PathBuf>,
+
+        /// Overwrite an existing config file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check the config file for missing paths and invalid values
+    ///
+    /// Reports all problems at once.
+    Validate {
+        /// Path to the config file
+        ///
+        /// Defaults to `config.toml` in the platform specific config folder.
+        #[arg(long, value_name = "CONFIG_FILE", value_hint = ValueHint::FilePath, env = "SFB_CONFIG")]
+        config: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Run the backup jobs of the config file
+    ///
+    /// Jobs backing up into different target folders run concurrently, see `--jobs`.
+    Run {
+        /// Path to the config file
+        ///
+        /// Defaults to `config.toml` in the platform specific config folder.
+        #[arg(long, value_name = "CONFIG_FILE", value_hint = ValueHint::FilePath, env = "SFB_CONFIG")]
+        config: Option<PathBuf>,
+
+        /// Only run the job with the given name, can be repeated
+        #[arg(long, value_name = "NAME", add = ArgValueCompleter::new(completion::complete_job_name))]
+        job: Vec<String>,
+
+        /// Number of jobs to run concurrently
+        #[arg(short, long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        jobs: u32,
+    },
+    /// Create or check the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// Query the audit log of trashed, deleted and restored files
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommands,
+    },
+    /// Show, upgrade, check or vacuum the tracking database of the target folder
+    Db {
+        #[command(subcommand)]
+        command: DbCommands,
+    },
+    /// Export the hashes of the backups for standard tools like `sha256sum -c`
+    Checksums {
+        #[command(subcommand)]
+        command: ChecksumsCommands,
+    },
+    /// Browse the backups of the config file jobs or target folders in a terminal UI
+    ///
+    /// Shows sizes, retention tiers and verification status, and allows restoring, pinning and
+    /// verifying backups and previewing which backups the next run would prune.
+    Tui {
+        /// Path to the config file
+        ///
+        /// Defaults to `config.toml` in the platform specific config folder if no target folders
+        /// are given.
+        #[arg(long, value_name = "CONFIG_FILE", value_hint = ValueHint::FilePath, env = "SFB_CONFIG")]
+        config: Option<PathBuf>,
+
+        /// Paths to folders containing backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        targets: Vec<PathBuf>,
+    },
+    /// List the backups of the target folder with their size, tags and comment
+    List {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+
+        /// Template the backups were named by
+        #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_NAME_TEMPLATE, value_parser = parse_str_to_name_template)]
+        name_template: NameTemplate,
+
+        /// Only list backups of this day or later, like 2025-01-01
+        #[arg(long, value_name = "DATE", value_parser = parse_str_to_date)]
+        since: Option<NaiveDate>,
+
+        /// Only list backups of this day or earlier, like 2025-12-31
+        #[arg(long, value_name = "DATE", value_parser = parse_str_to_date)]
+        until: Option<NaiveDate>,
+
+        /// Only list backups of sources with this base name, if the template contains `{basename}`
+        #[arg(long, value_name = "BASENAME")]
+        basename: Option<String>,
+
+        /// Only list backups created on this host, if the template contains `{hostname}`
+        #[arg(long, value_name = "HOST")]
+        host: Option<String>,
+
+        /// Only list backups protected by this retention tier as of the last prune
+        #[arg(long, value_enum)]
+        tier: Option<Tier>,
+    },
+    /// Show the disk usage of the backups by source, retention tier and month
+    ///
+    /// Sizes include hash, parity and signature files, and hardlinked backups count once. Pass the
+    /// retention periods of the backups to see how much each tier takes up.
+    Stats {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+
+        /// Template the backups were named by
+        #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_NAME_TEMPLATE, value_parser = parse_str_to_name_template)]
+        name_template: NameTemplate,
+
+        /// Retention period for the newest backups, -1 for no cleanup
+        #[arg(short = 'n', long = "keep-newest", default_value_t = 8, value_parser = clap::value_parser!(i32).range(-1..))]
+        keep_newest_count: i32,
+
+        /// Retention period for the daily backups, -1 for no cleanup
+        #[arg(short = 'd', long = "keep-daily", default_value_t = 32, value_parser = clap::value_parser!(i32).range(-1..))]
+        keep_daily_count: i32,
+
+        /// Retention period for the weekly backups, -1 for none
+        #[arg(long = "keep-weekly", default_value_t = -1, value_parser = clap::value_parser!(i32).range(-1..))]
+        keep_weekly_count: i32,
+
+        /// Retention period for the monthly backups, -1 for no cleanup
+        #[arg(short = 'm', long = "keep-monthly", default_value_t = 12, value_parser = clap::value_parser!(i32).range(-1..))]
+        keep_monthly_count: i32,
+
+        /// Retention period for the quarterly backups, -1 for none
+        #[arg(long = "keep-quarterly", default_value_t = -1, value_parser = clap::value_parser!(i32).range(-1..))]
+        keep_quarterly_count: i32,
+
+        /// Retention period for the yearly backups, -1 for no cleanup
+        #[arg(short = 'y', long = "keep-yearly", default_value_t = -1, value_parser = clap::value_parser!(i32).range(-1..))]
+        keep_yearly_count: i32,
+
+        /// Which backup of each period the retention periods keep
+        #[arg(long, value_enum, default_value_t = PeriodAnchor::First)]
+        period_anchor: PeriodAnchor,
+    },
+    /// Restore the files moved into the recycle bin by the last prune
+    ///
+    /// Not supported on macOS.
+    UndoPrune {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+    },
+    /// List or purge the files moved into the recycle bin by prunes
+    ///
+    /// Not supported on macOS.
+    Trash {
+        #[command(subcommand)]
+        command: TrashCommands,
+    },
+    /// Remove chunks no longer referenced by any backup from the chunk store
+    Gc {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+    },
+    /// Replace identical backups by hardlinks to the oldest of them, reporting the space reclaimed
+    ///
+    /// Useful for folders filled before identical backups were hardlinked. Only plain backups
+    /// matching their hash are linked.
+    Compact {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+
+        /// Only list the backups that would be hardlinked
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Template the backups were named by
+        #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_NAME_TEMPLATE, value_parser = parse_str_to_name_template)]
+        name_template: NameTemplate,
+    },
+    /// Replace this executable by the newest release for this platform
+    ///
+    /// The download is checked against the checksum published with the release, and with
+    /// `--verify-key` against its minisign signature.
+    SelfUpdate {
+        /// Verify the signature of the download with the given minisign public key
+        #[arg(long, value_name = "KEY_FILE", value_hint = ValueHint::FilePath)]
+        verify_key: Option<PathBuf>,
+
+        /// Update without `--verify-key`, only checking the checksum published with the release
+        ///
+        /// The checksum is downloaded from the same place as the binary, so it does not prove who
+        /// published the release.
+        #[arg(long, conflicts_with = "verify_key")]
+        insecure: bool,
+
+        /// Only report whether a newer release is available
+        #[arg(long)]
+        check: bool,
+    },
+    /// Restore a backup to a new file
+    ///
+    /// Chunked and incremental backups are reassembled.
+    Restore {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+
+        /// Path to write the restored file to
+        #[arg(value_name = "OUTPUT_FILE", value_hint = ValueHint::FilePath)]
+        output: PathBuf,
+
+        /// Date and counter of the backup to restore, e.g. 2025-09-27_03
+        ///
+        /// Defaults to the newest backup.
+        #[arg(long, add = ArgValueCompleter::new(completion::complete_backup_date))]
+        date: Option<String>,
+
+        /// Restore the newest backup of this day or earlier, like 2025-06-15
+        ///
+        /// Useful when the file was last good around a date, but the exact backup is unknown.
+        #[arg(long, value_name = "DATE", value_parser = parse_str_to_date, conflicts_with = "date")]
+        as_of: Option<NaiveDate>,
+
+        /// Pick the backup to restore from a list
+        #[arg(short, long, conflicts_with_all = ["date", "as_of"])]
+        interactive: bool,
+
+        /// Template the backups were named by
+        #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_NAME_TEMPLATE, value_parser = parse_str_to_name_template)]
+        name_template: NameTemplate,
+    },
+    /// Compare a file to the newest backup
+    ///
+    /// Exits with status code 7 if the file differs from the newest backup or no backup exists.
+    Diff {
+        /// Path to file to compare
+        #[arg(value_name = "FILE", value_hint = ValueHint::FilePath, value_parser = parse_str_to_source_pathbuf)]
+        source: PathBuf,
+
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+
+        /// Template the backups were named by
+        #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_NAME_TEMPLATE, value_parser = parse_str_to_name_template)]
+        name_template: NameTemplate,
+    },
+    /// Verify all backups against their hash files
+    Verify {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+
+        /// Also verify the signatures of the backups with the given minisign public key
+        #[arg(long, value_name = "KEY_FILE", value_hint = ValueHint::FilePath)]
+        verify_key: Option<PathBuf>,
+
+        /// Also check the hash chain of added and removed backups started with `--chain`
+        ///
+        /// Fails if entries of the chain were removed or rewritten, or a backup it records is
+        /// missing or was replaced. With `--verify-key`, the signature of the chain is checked too.
+        #[arg(long)]
+        chain: bool,
+
+        /// Template the backups were named by
+        #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_NAME_TEMPLATE, value_parser = parse_str_to_name_template)]
+        name_template: NameTemplate,
+    },
+    /// Promote a backup into a retention tier, keeping it regardless of the retention periods
+    ///
+    /// The backup is listed in the tier by `list --tier` and is never moved into the recycle bin by
+    /// prunes until the promotion is cleared.
+    Promote {
+        /// Path to the backup in its target folder
+        #[arg(value_name = "BACKUP", value_hint = ValueHint::FilePath)]
+        backup: PathBuf,
+
+        /// Tier to promote the backup into
+        #[arg(value_enum, required_unless_present = "clear")]
+        tier: Option<Tier>,
+
+        /// Remove the promotion of the backup instead
+        #[arg(long, conflicts_with = "tier")]
+        clear: bool,
+    },
+    /// Show the source file, machine and version a backup was created with
+    Provenance {
+        /// Path to the backup in its target folder
+        #[arg(value_name = "BACKUP", value_hint = ValueHint::FilePath)]
+        backup: PathBuf,
+    },
+    /// Check the target folder for problems, printing PASS, WARN or FAIL per check
+    ///
+    /// Checks that the folder is writable, the recycle bin and tracking database work, the clock is
+    /// not behind the newest backup, the files follow the name template and there is free space.
+    Doctor {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+
+        /// Template the backups were named by
+        #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_NAME_TEMPLATE, value_parser = parse_str_to_name_template)]
+        name_template: NameTemplate,
+    },
+    /// Repair backups failing verification using their parity files
+    Repair {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+
+        /// Template the backups were named by
+        #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_NAME_TEMPLATE, value_parser = parse_str_to_name_template)]
+        name_template: NameTemplate,
+    },
+    /// Regenerate missing hash files and hash files referencing another file
+    RepairSidecars {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+
+        /// Template the backups were named by
+        #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_NAME_TEMPLATE, value_parser = parse_str_to_name_template)]
+        name_template: NameTemplate,
+    },
+    /// Compare the copy throughput of `--buffer-size` and `--direct-io` settings
+    Bench {
+        /// Path to file to copy
+        #[arg(value_name = "FILE", value_hint = ValueHint::FilePath, value_parser = parse_str_to_source_pathbuf)]
+        source: PathBuf,
+
+        /// Folder to copy the file into, e.g. the target folder of the backups
+        ///
+        /// Defaults to the temporary folder.
+        #[arg(value_name = "FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        dir: Option<PathBuf>,
+    },
+    /// Store the password of an encrypted signing key in the keyring of the OS
+    ///
+    /// The password is then taken from the keyring when signing backups with `--sign-key`
+    /// instead of being prompted for.
+    StoreKeyPassword {
+        /// Path to the minisign secret key
+        #[arg(value_name = "KEY_FILE", value_hint = ValueHint::FilePath)]
+        key: PathBuf,
+    },
+    /// Remove the password of a signing key from the keyring of the OS
+    ForgetKeyPassword {
+        /// Path to the minisign secret key
+        #[arg(value_name = "KEY_FILE", value_hint = ValueHint::FilePath)]
+        key: PathBuf,
+    },
+    /// Store the password of a WebDAV user in the keyring of the OS
+    ///
+    /// The password is checked by listing the folder and then used by `--webdav-url`.
+    StoreWebdavPassword {
+        /// URL of the WebDAV folder
+        #[arg(value_name = "URL")]
+        url: String,
+
+        /// User to log into the WebDAV server as
+        #[arg(value_name = "USER")]
+        user: String,
+    },
+    /// Remove the password of a WebDAV user from the keyring of the OS
+    ForgetWebdavPassword {
+        /// URL of the WebDAV folder
+        #[arg(value_name = "URL")]
+        url: String,
+
+        /// User to log into the WebDAV server as
+        #[arg(value_name = "USER")]
+        user: String,
+    },
+    /// Rename and move existing backups to a new name template or layout
+    ///
+    /// Hash files, delta backups and the tracking database are updated accordingly.
+    Migrate {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+
+        /// Template the backups are currently named by
+        #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_NAME_TEMPLATE, value_parser = parse_str_to_name_template)]
+        from_template: NameTemplate,
+
+        /// Template to rename the backups to
+        #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_NAME_TEMPLATE, value_parser = parse_str_to_name_template)]
+        name_template: NameTemplate,
+
+        /// Layout to arrange the backups in
+        #[arg(long, value_enum, default_value_t = Layout::Flat)]
+        layout: Layout,
+    },
+    /// Copy all backups with their hash files and tracking database to another folder
+    ///
+    /// Every copied file is verified. The retention history is kept, so the backups can be moved
+    /// to a new drive with `import` without starting over.
+    Export {
+        /// Path to folder containing the backups
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        target: PathBuf,
+
+        /// Empty folder to copy the backups into, e.g. on an external drive
+        #[arg(value_name = "DESTINATION_FOLDER", value_hint = ValueHint::DirPath)]
+        destination: PathBuf,
+    },
+    /// Copy exported backups into an empty target folder, keeping their retention history
+    Import {
+        /// Path to folder containing the exported backups
+        #[arg(value_name = "EXPORT_FOLDER", value_hint = ValueHint::DirPath, value_parser = PathBufValueParser::new().try_map(check_target_pathbuf))]
+        source: PathBuf,
+
+        /// Empty folder to copy the backups into
+        #[arg(value_name = "TARGET_FOLDER", value_hint = ValueHint::DirPath)]
+        target: PathBuf,
+    },
+    /// Simulate the retention over a date range and print the retained backups per tier
+    ///
+    /// No files are touched. Useful to tune `-n`, `-d`, `-m` and `-y` before using them.
+    Simulate {
+        /// First day of the simulated backups, like 2024-01-01
+        #[arg(long, value_name = "DATE", value_parser = parse_str_to_date)]
+        from: NaiveDate,
+
+        /// Last day of the simulated backups, like 2026-01-01
+        #[arg(long, value_name = "DATE", value_parser = parse_str_to_date)]
+        to: NaiveDate,
+
+        /// How often a backup is simulated
+        #[arg(long, value_enum, default_value_t = Frequency::Daily)]
+        frequency: Frequency,
+
+        /// Retention period for the newest backups, -1 for no cleanup
+        #[arg(short = 'n', long = "keep-newest", default_value_t = 8, value_parser = clap::value_parser!(i32).range(-1..))]
+        keep_newest_count: i32,
+
+        /// Retention period for the daily backups, -1 for no cleanup
+        #[arg(short = 'd', long = "keep-daily", default_value_t = 32, value_parser = clap::value_parser!(i32).range(-1..))]
+        keep_daily_count: i32,
+
+        /// Retention period for the weekly backups, -1 for none
+        #[arg(long = "keep-weekly", default_value_t = -1, value_parser = clap::value_parser!(i32).range(-1..))]
+        keep_weekly_count: i32,
+
+        /// Retention period for the monthly backups, -1 for no cleanup
+        #[arg(short = 'm', long = "keep-monthly", default_value_t = 12, value_parser = clap::value_parser!(i32).range(-1..))]
+        keep_monthly_count: i32,
+
+        /// Retention period for the quarterly backups, -1 for none
+        #[arg(long = "keep-quarterly", default_value_t = -1, value_parser = clap::value_parser!(i32).range(-1..))]
+        keep_quarterly_count: i32,
+
+        /// Retention period for the yearly backups, -1 for no cleanup
+        #[arg(short = 'y', long = "keep-yearly", default_value_t = -1, value_parser = clap::value_parser!(i32).range(-1..))]
+        keep_yearly_count: i32,
+
+        /// Which backup of each period the retention periods keep
+        #[arg(long, value_enum, default_value_t = PeriodAnchor::First)]
+        period_anchor: PeriodAnchor,
+    },
+}
+
+fn parse_cli_keep_count(count: i32) -> Result<Option<u32>> {
+    if count >= 0 {
+        Ok(Some(u32::try_from(count)?))
+    } else {
+        Ok(None)
+    }
 }
 
-fn main() -> Result<()> {
+/// Exits with the usage exit code, as clap's default of 2 is reserved for skipped backups.
+fn exit_with_usage_error(err: clap::Error) -> ! {
+    if !err.use_stderr() {
+        // Help and version output.
+        err.exit();
+    }
+    let _ = err.print();
+    std::process::exit(ExitCode::Usage as i32);
+}
+
+fn main() -> std::process::ExitCode {
+    CompleteEnv::with_factory(Cli::command).complete();
+
+    match try_main() {
+        std::result::Result::Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(report) if exit_code_of(&report) == ExitCode::Skipped => {
+            eprintln!("Skipped: {}", report.root_cause());
+            ExitCode::Skipped.into()
+        }
+        Err(report) if exit_code_of(&report) == ExitCode::Differs => {
+            eprintln!("{}", report.root_cause());
+            ExitCode::Differs.into()
+        }
+        Err(report) => {
+            eprintln!("Error: {:?}", report);
+            exit_code_of(&report).into()
+        }
+    }
+}
+
+fn try_main() -> Result<()> {
     setup_hooks()?;
     setup_logging()?;
 
-    let cli = Cli::parse();
+    let cli = Cli::try_parse().unwrap_or_else(|err| exit_with_usage_error(err));
+
+    if cli.print_version {
+        return version::print_version(cli.json);
+    }
 
     if cli.licenses {
         let package_list = read_package_list_from_out_dir!()?;
@@ -129,26 +1381,338 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
+    if let Some(dir) = cli.generate_man {
+        std::fs::create_dir_all(&dir)
+            .wrap_err_with(|| format!("Failed to create folder {}", dir.display()))?;
+        clap_mangen::generate_to(Cli::command(), &dir)
+            .wrap_err_with(|| format!("Failed to write man pages into {}", dir.display()))?;
+        eprintln!("Generated man pages in {}", dir.display());
+        return Ok(());
+    }
+
+    if let Some(command) = cli.command {
+        return match command {
+            Commands::Run { config, job, jobs } => run::run(config, job, usize::try_from(jobs)?),
+            Commands::Config { command } => match command {
+                ConfigCommands::Init { config, force } => config::init_config(config, force),
+                ConfigCommands::Validate { config } => config::validate_config(config),
+            },
+            Commands::Audit { command } => match command {
+                AuditCommands::List { target } => backup::audit::list_audit(target),
+            },
+            Commands::Db { command } => match command {
+                DbCommands::Status { target } => backup::db::db_status(target),
+                DbCommands::Upgrade { target } => backup::db::db_upgrade(target),
+                DbCommands::Check {
+                    target,
+                    name_template,
+                } => backup::db::db_check(target, &name_template),
+                DbCommands::Vacuum { target } => backup::db::db_vacuum(target),
+            },
+            Commands::Checksums { command } => match command {
+                ChecksumsCommands::Export {
+                    target,
+                    name_template,
+                    format,
+                    output,
+                } => backup::checksums::export_checksums(target, &name_template, format, output),
+            },
+            Commands::Tui { config, targets } => tui::tui(config, targets),
+            Commands::List {
+                target,
+                name_template,
+                since,
+                until,
+                basename,
+                host,
+                tier,
+            } => backup::list::list(
+                target,
+                &name_template,
+                ScanFilter {
+                    basename,
+                    since,
+                    until,
+                    host,
+                },
+                tier,
+            ),
+            Commands::Stats {
+                target,
+                name_template,
+                keep_newest_count,
+                keep_daily_count,
+                keep_weekly_count,
+                keep_monthly_count,
+                keep_quarterly_count,
+                keep_yearly_count,
+                period_anchor,
+            } => backup::stats::stats(
+                target,
+                &name_template,
+                Tiered {
+                    keep_latest: parse_cli_keep_count(keep_newest_count)?,
+                    keep_daily: parse_cli_keep_count(keep_daily_count)?,
+                    keep_weekly: parse_cli_keep_count(keep_weekly_count)?,
+                    keep_monthly: parse_cli_keep_count(keep_monthly_count)?,
+                    keep_quarterly: parse_cli_keep_count(keep_quarterly_count)?,
+                    keep_yearly: parse_cli_keep_count(keep_yearly_count)?,
+                    anchor: period_anchor,
+                },
+            ),
+            Commands::UndoPrune { target } => backup::undo::undo_prune(target),
+            Commands::Trash { command } => match command {
+                TrashCommands::List { target } => backup::undo::list_trashed(target),
+                TrashCommands::Purge { target, older_than } => {
+                    backup::undo::purge_trashed(target, older_than)
+                }
+            },
+            Commands::Gc { target } => backup::store::collect_garbage(target),
+            Commands::SelfUpdate {
+                verify_key,
+                insecure,
+                check,
+            } => update::self_update(verify_key, insecure, check),
+            Commands::Compact {
+                target,
+                dry_run,
+                name_template,
+            } => backup::compact::compact(target, dry_run, &name_template),
+            Commands::Restore {
+                target,
+                output,
+                date,
+                as_of,
+                interactive,
+                name_template,
+            } => backup::restore::restore(target, output, date, as_of, interactive, &name_template),
+            Commands::Diff {
+                source,
+                target,
+                name_template,
+            } => {
+                if !backup::diff::diff(source, target, &name_template)? {
+                    return Err(eyre!("Source differs from the newest backup."))
+                        .exit_code(ExitCode::Differs);
+                }
+                Ok(())
+            }
+            Commands::Verify {
+                target,
+                verify_key,
+                chain,
+                name_template,
+            } => backup::verify::verify(target, verify_key, chain, &name_template),
+            Commands::Promote {
+                backup,
+                tier,
+                clear: _,
+            } => backup::promote::promote(backup, tier),
+            Commands::Provenance { backup } => backup::provenance::provenance(backup),
+            Commands::Doctor {
+                target,
+                name_template,
+            } => backup::doctor::doctor(target, &name_template),
+            Commands::Repair {
+                target,
+                name_template,
+            } => backup::parity::repair(target, &name_template),
+            Commands::RepairSidecars {
+                target,
+                name_template,
+            } => backup::sidecar::repair_sidecars(target, &name_template),
+            Commands::Bench { source, dir } => backup::copy::bench(source, dir),
+            Commands::StoreKeyPassword { key } => backup::signature::store_key_password(key),
+            Commands::ForgetKeyPassword { key } => backup::signature::forget_key_password(key),
+            Commands::StoreWebdavPassword { url, user } => {
+                backup::webdav::store_webdav_password(WebDavTarget::new(&url, &user)?)
+            }
+            Commands::ForgetWebdavPassword { url, user } => {
+                backup::webdav::forget_webdav_password(WebDavTarget::new(&url, &user)?)
+            }
+            Commands::Migrate {
+                target,
+                from_template,
+                name_template,
+                layout,
+            } => backup::migrate::migrate(target, &from_template, &name_template, layout),
+            Commands::Export {
+                target,
+                destination,
+            } => backup::transfer::export(target, destination),
+            Commands::Import { source, target } => backup::transfer::import(source, target),
+            Commands::Simulate {
+                from,
+                to,
+                frequency,
+                keep_newest_count,
+                keep_daily_count,
+                keep_weekly_count,
+                keep_monthly_count,
+                keep_quarterly_count,
+                keep_yearly_count,
+                period_anchor,
+            } => backup::simulate::simulate(
+                from,
+                to,
+                frequency,
+                Tiered {
+                    keep_latest: parse_cli_keep_count(keep_newest_count)?,
+                    keep_daily: parse_cli_keep_count(keep_daily_count)?,
+                    keep_weekly: parse_cli_keep_count(keep_weekly_count)?,
+                    keep_monthly: parse_cli_keep_count(keep_monthly_count)?,
+                    keep_quarterly: parse_cli_keep_count(keep_quarterly_count)?,
+                    keep_yearly: parse_cli_keep_count(keep_yearly_count)?,
+                    anchor: period_anchor,
+                },
+            ),
+        };
+    }
+
     if let (Some(source_path), Some(target_dir_path)) = (cli.source, cli.target) {
-        let parse_cli_keep_count = |count: i32| -> Result<Option<u32>> {
-            if count >= 0 {
-                Ok(Some(u32::try_from(count)?))
-            } else {
-                Ok(None)
+        let options = backup::BackupOptions {
+            keep_latest: parse_cli_keep_count(cli.keep_newest_count)?,
+            keep_daily: parse_cli_keep_count(cli.keep_daily_count)?,
+            keep_weekly: parse_cli_keep_count(cli.keep_weekly_count)?,
+            keep_monthly: parse_cli_keep_count(cli.keep_monthly_count)?,
+            keep_quarterly: parse_cli_keep_count(cli.keep_quarterly_count)?,
+            keep_yearly: parse_cli_keep_count(cli.keep_yearly_count)?,
+            dedup_store: cli.dedup_store,
+            incremental: cli.incremental,
+            full_every: cli.full_every,
+            timestamp: match cli.now {
+                Some(_) => TimestampSource::Now,
+                None => cli.timestamp,
+            },
+            timezone: cli.timezone,
+            name_template: cli.name_template,
+            layout: cli.layout,
+            allow_unmanaged_dir: cli.allow_unmanaged_dir,
+            adopt: cli.adopt,
+            strict: cli.strict,
+            verify_before_prune: cli.verify_before_prune,
+            allow_empty: cli.allow_empty,
+            no_prune: cli.no_prune,
+            on_no_trash: cli.on_no_trash,
+            archive_dir: cli.archive_dir,
+            scrub: cli.scrub,
+            parity: cli.parity,
+            sidecar_format: cli.sidecar_format,
+            sign_key: cli.sign_key,
+            retry: backup::retry::RetryPolicy {
+                retries: cli.retries,
+                delay: cli.retry_delay,
+            },
+            change_retries: cli.change_retries,
+            use_vss: cli.use_vss,
+            preserve: cli.preserve,
+            protect: cli.protect,
+            immutable: cli.immutable,
+            fsync: cli.fsync,
+            io: backup::copy::IoOptions {
+                buffer_size: cli.buffer_size,
+                direct_io: cli.direct_io,
+            },
+            parallel_hash_above: (!cli.no_parallel_hash).then_some(cli.parallel_hash_above as u64),
+            trust_mtime: cli.trust_mtime,
+            latest_link: cli.latest_link,
+            // Stdin might be the source, and cron has no terminal to answer on.
+            interactive: !cli.yes
+                && source_path.as_os_str() != STREAM_SOURCE
+                && std::io::stdin().is_terminal()
+                && std::io::stderr().is_terminal(),
+            confirm_above: cli.confirm_above,
+            tags: cli.tag,
+            comment: cli.comment,
+            keep_tagged: cli.keep_tagged,
+            format: cli.format,
+            zip_password: cli.zip_password,
+            compress_force: cli.compress_force,
+            cold_target: cli.cold_target,
+            cold_after: cli.cold_after,
+            rclone_remote: cli.rclone_remote,
+            webdav: cli
+                .webdav_url
+                .zip(cli.webdav_user)
+                .map(|(url, user)| WebDavTarget::new(&url, &user))
+                .transpose()?,
+            bwlimit: cli.bwlimit,
+            verify_uploads: cli.verify_uploads,
+            metrics_file: cli.metrics_file,
+            summary_file: cli.summary_file,
+            audit_log: cli.audit_log,
+            chain: cli.chain,
+            retention_scope: cli.retention_scope,
+            strategy: cli.strategy,
+            period_anchor: cli.period_anchor,
+            content_epochs: cli.content_epochs,
+            normalize_names: cli.normalize_names,
+            lock_timeout: cli.lock_timeout,
+            min_interval: cli.min_interval,
+            max_per_day: cli.max_per_day,
+            cap_mode: cli.cap_mode,
+            verify_mode: cli.verify_mode,
+            force: cli.force,
+            verify_newest: cli.verify_newest,
+            now: cli
+                .now
+                .map(|now| system_time_from_naive(now, cli.timezone))
+                .transpose()?,
+        };
+
+        let source = if source_path.as_os_str() == STREAM_SOURCE {
+            backup::stream::Source::Stream {
+                input: match cli.source_cmd {
+                    Some(command) => backup::stream::StreamInput::Command(command),
+                    None => backup::stream::StreamInput::Stdin,
+                },
+                name: cli.name.unwrap_or_default(),
+            }
+        } else {
+            if cli.source_cmd.is_some() || cli.name.is_some() {
+                exit_with_usage_error(Cli::command().error(
+                    ErrorKind::ArgumentConflict,
+                    "`--source-cmd` and `--name` require `-` as source",
+                ));
             }
+            backup::stream::Source::File(source_path)
         };
 
-        return backup::backup(
-            source_path,
-            target_dir_path,
-            parse_cli_keep_count(cli.keep_newest_count)?,
-            parse_cli_keep_count(cli.keep_daily_count)?,
-            parse_cli_keep_count(cli.keep_monthly_count)?,
-            parse_cli_keep_count(cli.keep_yearly_count)?,
-        );
+        let backup_path = backup::backup(source, target_dir_path, &options)?;
+        println!("{}", backup_path.display());
+        return Ok(());
     }
 
     Cli::command().print_help()?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_parse_str_to_duration() {
+        assert_eq!(
+            parse_str_to_duration("90").unwrap(),
+            Duration::from_secs(90)
+        );
+        assert_eq!(
+            parse_str_to_duration("500ms").unwrap(),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            parse_str_to_duration("6h").unwrap(),
+            Duration::from_secs(6 * 60 * 60)
+        );
+        assert!(parse_str_to_duration("5w").is_err());
+        assert!(parse_str_to_duration(&format!("{}d", u64::MAX / 1000)).is_err());
+        assert!(parse_str_to_duration(&format!("{}m", u64::MAX)).is_err());
+    }
+}
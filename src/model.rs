@@ -9,7 +9,6 @@ use std::{
     path::PathBuf,
 };
 
-use bitcode::serialize;
 use diesel::{
     backend::Backend,
     deserialize::{FromSql, FromSqlRow},
@@ -32,8 +31,97 @@ pub struct BackupFile {
     pub keep_monthly: bool,
     pub keep_daily: bool,
     pub keep_latest: bool,
+    pub hash: Option<String>,
+    /// Unix timestamp of the last time the backup matched its hash.
+    pub last_verified: Option<i64>,
+    /// Comma separated tags given with `--tag`.
+    pub tags: Option<String>,
+    pub comment: Option<String>,
+    /// Cold storage folder the backup was moved to, at the same relative path.
+    pub cold_target: Option<PathBufSql>,
+    /// Absolute path of the source file, unknown for streamed sources.
+    pub source_path: Option<PathBufSql>,
+    /// Name of the machine the backup was created on.
+    pub hostname: Option<String>,
+    /// Version of staggered-file-backup that created the backup.
+    pub tool_version: Option<String>,
+    /// Unix timestamp in milliseconds of the time the backup was created.
+    pub created_at: Option<i64>,
+    /// Unix timestamp of the time the backup was moved into the quarantine folder after failing
+    /// verification.
+    pub quarantined_at: Option<i64>,
+    pub keep_weekly: bool,
+    pub keep_quarterly: bool,
+    /// Tier the backup was promoted into with `promote`, kept regardless of the retention periods.
+    pub promoted_tier: Option<String>,
 }
 
+/// File moved into the recycle bin by a prune, allowing the prune to be undone.
+#[derive(Debug, Clone, Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::pruned_files)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct PrunedFile {
+    pub uuid: UuidSQL,
+    /// Shared by all files moved into the recycle bin at once.
+    pub prune_uuid: UuidSQL,
+    /// Unix timestamp in milliseconds.
+    pub pruned_at: i64,
+    /// Absolute path of the file before it was moved into the recycle bin.
+    pub original_path: PathBufSql,
+    /// Identifier of the file in the recycle bin, if the platform supports listing it.
+    pub trash_id: Option<PathBufSql>,
+}
+
+/// Operation on the target folder recorded before it starts and removed once it finished, so that
+/// operations interrupted by a crash are detected and completed or rolled back.
+#[derive(Debug, Clone, Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::journal)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct JournalEntry {
+    pub uuid: UuidSQL,
+    /// Unix timestamp in milliseconds.
+    pub started_at: i64,
+    /// `backup` or `trash`.
+    pub operation: String,
+    /// Path of the backup relative to the target folder.
+    pub path: PathBufSql,
+    /// Hash of the content of the backup, once it was verified.
+    pub hash: Option<String>,
+}
+
+/// File moved into the recycle bin, deleted or restored, recorded in the append-only audit log.
+#[derive(Debug, Clone, Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::audit_log)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct AuditEntry {
+    pub uuid: UuidSQL,
+    /// Unix timestamp in milliseconds.
+    pub recorded_at: i64,
+    /// `trash`, `delete` or `restore`.
+    pub operation: String,
+    /// Why the file was touched, e.g. the retention periods a pruned backup fell out of.
+    pub reason: String,
+    /// Absolute path of the file.
+    pub path: PathBufSql,
+    pub size: Option<i64>,
+    /// SHA-256 hash of the file.
+    pub hash: Option<String>,
+}
+
+/// Last computed hash of a source file, reused with `--trust-mtime` while size and modification
+/// time are unchanged.
+#[derive(Debug, Clone, Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::source_hashes)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct SourceHash {
+    pub path: PathBufSql,
+    pub size: i64,
+    /// Modification time in nanoseconds since the Unix epoch.
+    pub mtime: i64,
+    pub hash: String,
+}
+
+/// UUID stored in the tracking database as its 16 bytes in big-endian order (RFC 9562).
 #[derive(Debug, Clone, AsExpression, FromSqlRow, Serialize, Deserialize)]
 #[diesel(sql_type = Binary)]
 pub struct UuidSQL {
@@ -48,6 +136,12 @@ impl UuidSQL {
     }
 }
 
+impl Default for UuidSQL {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Deref for UuidSQL {
     type Target = Uuid;
 
@@ -64,13 +158,9 @@ impl DerefMut for UuidSQL {
 
 impl FromSql<Binary, Sqlite> for UuidSQL {
     fn from_sql(bytes: <Sqlite as Backend>::RawValue<'_>) -> diesel::deserialize::Result<Self> {
-        match <*const [u8]>::from_sql(bytes) {
-            Ok(pointer) => {
-                let slice = unsafe { &*pointer }; // Very safe code
-                bitcode::deserialize(slice).map_err(|err| err.into())
-            }
-            Err(err) => Err(err),
-        }
+        Ok(Self {
+            uuid: Uuid::from_slice(&Vec::<u8>::from_sql(bytes)?)?,
+        })
     }
 }
 
@@ -79,12 +169,13 @@ impl ToSql<Binary, Sqlite> for UuidSQL {
         &'b self,
         out: &mut diesel::serialize::Output<'b, '_, Sqlite>,
     ) -> diesel::serialize::Result {
-        let bytes = bitcode::serialize(self)?;
-        out.set_value(bytes);
+        out.set_value(self.uuid.as_bytes().to_vec());
         Ok(IsNull::No)
     }
 }
 
+/// Path stored in the tracking database as the raw bytes of the path on Unix, and as UTF-8
+/// elsewhere.
 #[derive(Debug, Clone, AsExpression, FromSqlRow, Serialize, Deserialize)]
 #[diesel(sql_type = Binary)]
 pub struct PathBufSql {
@@ -104,25 +195,92 @@ impl DerefMut for PathBufSql {
     }
 }
 
+impl PathBufSql {
+    /// Bytes the path is stored as in the tracking database, the raw bytes of the path on Unix so
+    /// that it need not be valid UTF-8.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            Ok(self.path.as_os_str().as_bytes().to_vec())
+        }
+        #[cfg(not(unix))]
+        {
+            self.path
+                .to_str()
+                .map(|path| path.as_bytes().to_vec())
+                .ok_or_else(|| format!("Path {} is not valid unicode.", self.path.display()).into())
+        }
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        #[cfg(unix)]
+        let path = {
+            use std::os::unix::ffi::OsStringExt;
+            std::ffi::OsString::from_vec(bytes).into()
+        };
+        #[cfg(not(unix))]
+        let path = String::from_utf8(bytes)?.into();
+
+        Ok(Self { path })
+    }
+}
+
 impl ToSql<Binary, Sqlite> for PathBufSql {
     fn to_sql<'b>(
         &'b self,
         out: &mut diesel::serialize::Output<'b, '_, Sqlite>,
     ) -> diesel::serialize::Result {
-        let encoded = serialize(self)?;
-        out.set_value(encoded);
+        out.set_value(self.to_bytes()?);
         Ok(IsNull::No)
     }
 }
 
 impl FromSql<Binary, Sqlite> for PathBufSql {
     fn from_sql(bytes: <Sqlite as Backend>::RawValue<'_>) -> diesel::deserialize::Result<Self> {
-        match <*const [u8]>::from_sql(bytes) {
-            Ok(pointer) => {
-                let slice = unsafe { &*pointer }; // Very safe code
-                bitcode::deserialize(slice).map_err(|err| err.into())
-            }
-            Err(err) => Err(err),
-        }
+        Self::from_bytes(Vec::<u8>::from_sql(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use diesel::dsl::sql;
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+
+        let uuid = UuidSQL::new();
+        let bytes: Vec<u8> = diesel::select(uuid.clone().into_sql::<Binary>())
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(bytes, uuid.as_bytes());
+        let read: UuidSQL = diesel::select(uuid.clone().into_sql::<Binary>())
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(*read, *uuid);
+        let read: UuidSQL = diesel::select(sql::<Binary>("x'0123456789abcdef0123456789abcdef'"))
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(read.to_string(), "01234567-89ab-cdef-0123-456789abcdef");
+        assert!(
+            diesel::select(sql::<Binary>("x'0123'"))
+                .get_result::<UuidSQL>(&mut conn)
+                .is_err()
+        );
+
+        let path = PathBufSql {
+            path: PathBuf::from("dir/notes.txt"),
+        };
+        let bytes: Vec<u8> = diesel::select(path.clone().into_sql::<Binary>())
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(bytes, b"dir/notes.txt");
+        let read: PathBufSql = diesel::select(path.clone().into_sql::<Binary>())
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(*read, *path);
     }
 }
@@ -0,0 +1,157 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+};
+
+use color_eyre::{
+    Section,
+    eyre::{Result, bail, eyre},
+};
+use log::{error, info};
+
+use crate::{
+    backup::{backup, stream::Source},
+    config::{Job, default_config_path, load_config},
+    exit_code::{ExitCode, WithExitCode, exit_code_of},
+    logging::set_job_name,
+};
+
+/// Groups the jobs by target folder, as jobs sharing a target folder cannot run concurrently.
+fn group_by_target(jobs: Vec<Job>) -> Vec<Vec<Job>> {
+    let mut groups: BTreeMap<PathBuf, Vec<Job>> = BTreeMap::new();
+    for job in jobs {
+        let target = std::fs::canonicalize(&job.target).unwrap_or_else(|_| job.target.clone());
+        groups.entry(target).or_default().push(job);
+    }
+
+    groups.into_values().collect()
+}
+
+fn run_job(job: &Job) -> Result<PathBuf> {
+    backup(
+        Source::File(job.source.clone()),
+        job.target.clone(),
+        &job.backup_options()?,
+    )
+}
+
+/// Runs the jobs of the config file, or only the named ones.
+///
+/// Jobs backing up into different target folders run concurrently, up to `parallel` at once.
+pub fn run(config_path: Option<PathBuf>, job_names: Vec<String>, parallel: usize) -> Result<()> {
+    let config_path = match config_path {
+        Some(path) => path,
+        None => default_config_path()?,
+    };
+    info!("Config file: {}", config_path.display());
+    let config = load_config(&config_path)?;
+
+    for name in &job_names {
+        if !config.jobs.iter().any(|job| &job.name == name) {
+            return Err(eyre!("No job named {} found.", name))
+                .suggestion("Check the job names in the config file.");
+        }
+    }
+    let jobs: Vec<Job> = config
+        .jobs
+        .into_iter()
+        .filter(|job| job_names.is_empty() || job_names.contains(&job.name))
+        .collect();
+    if jobs.is_empty() {
+        bail!("No jobs found in config file.");
+    }
+    let job_count = jobs.len();
+
+    let groups = group_by_target(jobs);
+    let worker_count = parallel.max(1).min(groups.len());
+    info!("Running {} jobs with {} workers.", job_count, worker_count);
+
+    let queue = Mutex::new(groups.into_iter());
+    let results = Mutex::new(vec![]);
+    std::thread::scope(|scope| {
+        for _ in 0..worker_count {
+            scope.spawn(|| {
+                loop {
+                    let Some(group) = queue.lock().unwrap_or_else(PoisonError::into_inner).next()
+                    else {
+                        break;
+                    };
+                    for job in group {
+                        set_job_name(Some(job.name.clone()));
+                        let result = run_job(&job);
+                        set_job_name(None);
+                        results
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .push((job.name, result));
+                    }
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap_or_else(PoisonError::into_inner);
+    results.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut failed_count = 0;
+    let mut exit_codes = vec![];
+    info!("Summary:");
+    for (name, result) in &results {
+        match result {
+            Ok(backup_path) => {
+                info!("{}: {}", name, backup_path.display());
+                println!("{}", backup_path.display());
+            }
+            Err(err) => {
+                error!("{}: FAILED: {:#}", name, err);
+                failed_count += 1;
+                exit_codes.push(exit_code_of(err));
+            }
+        }
+    }
+
+    if failed_count > 0 {
+        // The exit code is only specific if all jobs failed for the same reason.
+        let exit_code = if exit_codes.iter().all(|code| *code == exit_codes[0]) {
+            exit_codes[0]
+        } else {
+            ExitCode::Failure
+        };
+        return Err(eyre!("{} of {} jobs failed.", failed_count, job_count)).exit_code(exit_code);
+    }
+    info!("DONE!");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn job(name: &str, target: &str) -> Job {
+        Job {
+            name: name.to_owned(),
+            source: PathBuf::from(format!("{}.txt", name)),
+            target: PathBuf::from(target),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_group_by_target() {
+        let groups = group_by_target(vec![job("a", "x"), job("b", "y"), job("c", "x")]);
+        let names: Vec<Vec<&str>> = groups
+            .iter()
+            .map(|group| group.iter().map(|job| job.name.as_str()).collect())
+            .collect();
+
+        assert_eq!(names, vec![vec!["a", "c"], vec!["b"]]);
+    }
+}
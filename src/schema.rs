@@ -1,5 +1,17 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    audit_log (uuid) {
+        uuid -> Binary,
+        recorded_at -> BigInt,
+        operation -> Text,
+        reason -> Text,
+        path -> Binary,
+        size -> Nullable<BigInt>,
+        hash -> Nullable<Text>,
+    }
+}
+
 diesel::table! {
     backup_files (uuid) {
         uuid -> Binary,
@@ -8,5 +20,55 @@ diesel::table! {
         keep_monthly -> Bool,
         keep_daily -> Bool,
         keep_latest -> Bool,
+        hash -> Nullable<Text>,
+        last_verified -> Nullable<BigInt>,
+        tags -> Nullable<Text>,
+        comment -> Nullable<Text>,
+        cold_target -> Nullable<Binary>,
+        source_path -> Nullable<Binary>,
+        hostname -> Nullable<Text>,
+        tool_version -> Nullable<Text>,
+        created_at -> Nullable<BigInt>,
+        quarantined_at -> Nullable<BigInt>,
+        keep_weekly -> Bool,
+        keep_quarterly -> Bool,
+        promoted_tier -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    journal (uuid) {
+        uuid -> Binary,
+        started_at -> BigInt,
+        operation -> Text,
+        path -> Binary,
+        hash -> Nullable<Text>,
     }
 }
+
+diesel::table! {
+    pruned_files (uuid) {
+        uuid -> Binary,
+        prune_uuid -> Binary,
+        pruned_at -> BigInt,
+        original_path -> Binary,
+        trash_id -> Nullable<Binary>,
+    }
+}
+
+diesel::table! {
+    source_hashes (path) {
+        path -> Binary,
+        size -> BigInt,
+        mtime -> BigInt,
+        hash -> Text,
+    }
+}
+
+diesel::allow_tables_to_appear_in_same_query!(
+    audit_log,
+    backup_files,
+    journal,
+    pruned_files,
+    source_hashes,
+);
@@ -0,0 +1,541 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{collections::HashMap, path::PathBuf};
+
+use chrono::{DateTime, Local};
+use color_eyre::{
+    Section,
+    eyre::{Result, eyre},
+};
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Flex, Layout, Rect},
+    style::{Style, Stylize},
+    widgets::{Block, Clear, List, ListState, Paragraph, Row, Table, TableState},
+};
+
+use crate::{
+    backup::{
+        BackupOptions,
+        cleanup::{Tiers, identify_tiers, tiers_flags},
+        list::{BackupDetails, format_size, list_backups, set_tag},
+        preview_prune,
+        restore::restore,
+        verify::verify_backups,
+    },
+    config::{default_config_path, load_config},
+    logging::set_terminal_logging,
+};
+
+/// Tag of backups pinned in the terminal UI, kept by jobs with `keep_tagged`.
+const PIN_TAG: &str = "pinned";
+
+const HELP: &str =
+    "↑↓ select  ←→/Tab switch  t tier  v verify  p pin  x prune preview  r restore  q quit";
+
+/// Target folder shown in the terminal UI, of a job or given on the command line.
+struct Folder {
+    name: String,
+    target: PathBuf,
+    options: BackupOptions,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TierFilter {
+    All,
+    Latest,
+    Daily,
+    Weekly,
+    Monthly,
+    Quarterly,
+    Yearly,
+}
+
+impl TierFilter {
+    fn next(self) -> Self {
+        match self {
+            Self::All => Self::Latest,
+            Self::Latest => Self::Daily,
+            Self::Daily => Self::Weekly,
+            Self::Weekly => Self::Monthly,
+            Self::Monthly => Self::Quarterly,
+            Self::Quarterly => Self::Yearly,
+            Self::Yearly => Self::All,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::All => "all",
+            Self::Latest => "latest",
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+            Self::Monthly => "monthly",
+            Self::Quarterly => "quarterly",
+            Self::Yearly => "yearly",
+        }
+    }
+
+    fn matches(self, tiers: Tiers) -> bool {
+        match self {
+            Self::All => true,
+            Self::Latest => tiers.latest,
+            Self::Daily => tiers.daily,
+            Self::Weekly => tiers.weekly,
+            Self::Monthly => tiers.monthly,
+            Self::Quarterly => tiers.quarterly,
+            Self::Yearly => tiers.yearly,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Folders,
+    Backups,
+}
+
+enum Mode {
+    Browse,
+    /// Backups the next backup would move into the recycle bin.
+    PrunePreview(Vec<String>),
+    /// Output path of the restore being typed.
+    RestoreInput(String),
+}
+
+struct App {
+    folders: Vec<Folder>,
+    folder_state: ListState,
+    /// Backups of the selected folder, newest first.
+    backups: Vec<(BackupDetails, Tiers)>,
+    backup_state: TableState,
+    /// Verification results of this session.
+    verified: HashMap<PathBuf, bool>,
+    tier_filter: TierFilter,
+    focus: Focus,
+    mode: Mode,
+    status: String,
+}
+
+fn popup_area(area: Rect) -> Rect {
+    let [area] = Layout::vertical([Constraint::Percentage(60)])
+        .flex(Flex::Center)
+        .areas(area);
+    let [area] = Layout::horizontal([Constraint::Percentage(60)])
+        .flex(Flex::Center)
+        .areas(area);
+    area
+}
+
+impl App {
+    fn new(folders: Vec<Folder>) -> Self {
+        let mut app = Self {
+            folders,
+            folder_state: ListState::default().with_selected(Some(0)),
+            backups: vec![],
+            backup_state: TableState::default(),
+            verified: HashMap::new(),
+            tier_filter: TierFilter::All,
+            focus: Focus::Backups,
+            mode: Mode::Browse,
+            status: String::new(),
+        };
+        app.load_backups();
+        app
+    }
+
+    fn folder(&self) -> &Folder {
+        &self.folders[self.folder_state.selected().unwrap_or_default()]
+    }
+
+    fn load_backups(&mut self) {
+        let folder = self.folder();
+        let loaded =
+            list_backups(&folder.target, &folder.options.name_template).and_then(|backups| {
+                let files: Vec<_> = backups.iter().map(|backup| backup.file.clone()).collect();
+                let tiers: HashMap<PathBuf, Tiers> =
+                    identify_tiers(&files, &folder.options.tiered())?
+                        .into_iter()
+                        .map(|(file, tiers)| (file.path, tiers))
+                        .collect();
+
+                Ok(backups
+                    .into_iter()
+                    .rev()
+                    .map(|backup| {
+                        let tiers = tiers.get(&backup.file.path).copied().unwrap_or_default();
+                        (backup, tiers)
+                    })
+                    .collect())
+            });
+
+        match loaded {
+            Ok(backups) => self.backups = backups,
+            Err(err) => {
+                self.backups = vec![];
+                self.status = format!("Failed to list backups: {}", err);
+            }
+        }
+
+        let count = self.visible_backups().len();
+        let selected = self.backup_state.selected().unwrap_or_default();
+        self.backup_state
+            .select((count > 0).then(|| selected.min(count - 1)));
+    }
+
+    fn visible_backups(&self) -> Vec<&(BackupDetails, Tiers)> {
+        self.backups
+            .iter()
+            .filter(|(_, tiers)| self.tier_filter.matches(*tiers))
+            .collect()
+    }
+
+    fn selected_backup(&self) -> Option<BackupDetails> {
+        let index = self.backup_state.selected()?;
+        self.visible_backups()
+            .get(index)
+            .map(|(backup, _)| backup.clone())
+    }
+
+    fn select_folder(&mut self, down: bool) {
+        if down {
+            self.folder_state.select_next();
+        } else {
+            self.folder_state.select_previous();
+        }
+        let index = self
+            .folder_state
+            .selected()
+            .unwrap_or_default()
+            .min(self.folders.len() - 1);
+        self.folder_state.select(Some(index));
+        self.backup_state.select(Some(0));
+        self.load_backups();
+    }
+
+    fn select_backup(&mut self, down: bool) {
+        let count = self.visible_backups().len();
+        if count == 0 {
+            return;
+        }
+        let index = self.backup_state.selected().unwrap_or_default();
+        let index = if down {
+            (index + 1).min(count - 1)
+        } else {
+            index.saturating_sub(1)
+        };
+        self.backup_state.select(Some(index));
+    }
+
+    fn verify(&mut self) {
+        let Some(backup) = self.selected_backup() else {
+            return;
+        };
+        let target = self.folder().target.clone();
+        match verify_backups(&target, std::slice::from_ref(&backup.file)) {
+            Ok(failed_count) => {
+                let intact = failed_count == 0;
+                self.status = if intact {
+                    format!("Backup {} passed verification.", backup.date())
+                } else {
+                    format!("Backup {} FAILED verification!", backup.date())
+                };
+                self.verified.insert(backup.file.path, intact);
+            }
+            Err(err) => self.status = format!("Failed to verify backup: {}", err),
+        }
+        self.load_backups();
+    }
+
+    fn toggle_pin(&mut self) {
+        let Some(backup) = self.selected_backup() else {
+            return;
+        };
+        let folder = self.folder();
+        let pin = !backup.tags.iter().any(|tag| tag == PIN_TAG);
+        self.status = match set_tag(&folder.target, &backup.file.path, PIN_TAG, pin) {
+            Ok(()) if pin && !folder.options.keep_tagged => format!(
+                "Pinned {}. Enable keep_tagged for the job to exclude it from pruning.",
+                backup.date()
+            ),
+            Ok(()) if pin => format!("Pinned {}.", backup.date()),
+            Ok(()) => format!("Unpinned {}.", backup.date()),
+            Err(err) => format!("Failed to pin backup: {}", err),
+        };
+        self.load_backups();
+    }
+
+    fn preview_prune(&mut self) {
+        let folder = self.folder();
+        match preview_prune(&folder.target, &folder.options) {
+            Ok(files) if files.is_empty() => {
+                self.status = "The next backup would not prune any backups.".to_owned();
+            }
+            Ok(files) => {
+                self.mode = Mode::PrunePreview(
+                    files
+                        .iter()
+                        .map(|file| {
+                            file.path
+                                .strip_prefix(&folder.target)
+                                .unwrap_or(&file.path)
+                                .display()
+                                .to_string()
+                        })
+                        .collect(),
+                );
+            }
+            Err(err) => self.status = format!("Failed to preview prune: {}", err),
+        }
+    }
+
+    fn start_restore(&mut self) {
+        let Some(backup) = self.selected_backup() else {
+            return;
+        };
+        let output = std::env::current_dir()
+            .unwrap_or_default()
+            .join(backup.file.path.file_name().unwrap_or_default());
+        self.mode = Mode::RestoreInput(output.display().to_string());
+    }
+
+    fn restore(&mut self, output: String) {
+        let Some(backup) = self.selected_backup() else {
+            return;
+        };
+        let folder = self.folder();
+        self.status = match restore(
+            folder.target.clone(),
+            PathBuf::from(&output),
+            Some(backup.date()),
+            None,
+            false,
+            &folder.options.name_template,
+        ) {
+            Ok(()) => format!("Restored {} to {}", backup.date(), output),
+            Err(err) => format!("Failed to restore backup: {}", err),
+        };
+    }
+
+    /// Handles the key press and returns if the UI should keep running.
+    fn handle_key(&mut self, code: KeyCode) -> bool {
+        match &mut self.mode {
+            Mode::PrunePreview(_) => {
+                if matches!(code, KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q')) {
+                    self.mode = Mode::Browse;
+                }
+                return true;
+            }
+            Mode::RestoreInput(output) => {
+                match code {
+                    KeyCode::Esc => self.mode = Mode::Browse,
+                    KeyCode::Enter => {
+                        let output = std::mem::take(output);
+                        self.mode = Mode::Browse;
+                        self.restore(output);
+                    }
+                    KeyCode::Backspace => {
+                        output.pop();
+                    }
+                    KeyCode::Char(char) => output.push(char),
+                    _ => {}
+                }
+                return true;
+            }
+            Mode::Browse => {}
+        }
+
+        self.status.clear();
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Tab | KeyCode::Left | KeyCode::Right | KeyCode::Char('h' | 'l') => {
+                self.focus = match self.focus {
+                    Focus::Folders => Focus::Backups,
+                    Focus::Backups => Focus::Folders,
+                };
+            }
+            KeyCode::Up | KeyCode::Char('k') => match self.focus {
+                Focus::Folders => self.select_folder(false),
+                Focus::Backups => self.select_backup(false),
+            },
+            KeyCode::Down | KeyCode::Char('j') => match self.focus {
+                Focus::Folders => self.select_folder(true),
+                Focus::Backups => self.select_backup(true),
+            },
+            KeyCode::Char('t') => {
+                self.tier_filter = self.tier_filter.next();
+                self.backup_state.select(Some(0));
+                self.load_backups();
+            }
+            KeyCode::Char('v') => self.verify(),
+            KeyCode::Char('p') => self.toggle_pin(),
+            KeyCode::Char('x') => self.preview_prune(),
+            KeyCode::Char('r') => self.start_restore(),
+            _ => {}
+        }
+
+        true
+    }
+
+    fn verified_text(&self, backup: &BackupDetails) -> String {
+        match self.verified.get(&backup.file.path) {
+            Some(true) => "OK".to_owned(),
+            Some(false) => "FAILED".to_owned(),
+            None => backup
+                .last_verified
+                .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+                .map_or("never".to_owned(), |time| {
+                    time.with_timezone(&Local).format("%Y-%m-%d").to_string()
+                }),
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main_area, status_area, help_area] = Layout::vertical([
+            Constraint::Min(0),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [folders_area, backups_area] =
+            Layout::horizontal([Constraint::Percentage(25), Constraint::Percentage(75)])
+                .areas(main_area);
+
+        let focused_style = |focus| {
+            if self.focus == focus {
+                Style::new().reversed()
+            } else {
+                Style::new().bold()
+            }
+        };
+
+        let folders = List::new(self.folders.iter().map(|folder| folder.name.clone()))
+            .block(Block::bordered().title("Folders"))
+            .highlight_style(focused_style(Focus::Folders));
+        frame.render_stateful_widget(folders, folders_area, &mut self.folder_state);
+
+        let rows: Vec<Row> = self
+            .visible_backups()
+            .into_iter()
+            .map(|(backup, tiers)| {
+                Row::new([
+                    backup.date(),
+                    format_size(backup.size),
+                    tiers_flags(*tiers),
+                    backup.tags.join(","),
+                    self.verified_text(backup),
+                    backup.comment.clone().unwrap_or_default(),
+                ])
+            })
+            .collect();
+        let backups = Table::new(
+            rows,
+            [
+                Constraint::Length(13),
+                Constraint::Length(10),
+                Constraint::Length(6),
+                Constraint::Length(16),
+                Constraint::Length(10),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(Row::new(["Date", "Size", "Tiers", "Tags", "Verified", "Comment"]).bold())
+        .block(Block::bordered().title(format!(
+            "Backups of {} ({} tier)",
+            self.folder().name,
+            self.tier_filter.name()
+        )))
+        .row_highlight_style(focused_style(Focus::Backups));
+        frame.render_stateful_widget(backups, backups_area, &mut self.backup_state);
+
+        frame.render_widget(Paragraph::new(self.status.as_str()), status_area);
+        frame.render_widget(Paragraph::new(HELP).dim(), help_area);
+
+        match &self.mode {
+            Mode::Browse => {}
+            Mode::PrunePreview(files) => {
+                let area = popup_area(frame.area());
+                frame.render_widget(Clear, area);
+                frame.render_widget(
+                    List::new(files.iter().map(String::as_str)).block(
+                        Block::bordered().title("The next backup would move into the recycle bin"),
+                    ),
+                    area,
+                );
+            }
+            Mode::RestoreInput(output) => {
+                let [area] = Layout::vertical([Constraint::Length(3)])
+                    .flex(Flex::Center)
+                    .areas(popup_area(frame.area()));
+                frame.render_widget(Clear, area);
+                frame.render_widget(
+                    Paragraph::new(output.as_str())
+                        .block(Block::bordered().title("Restore to (Enter to confirm)")),
+                    area,
+                );
+            }
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+                && !self.handle_key(key.code)
+            {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Collects the target folders of the config file jobs and the given target folders.
+///
+/// The config file is only read if given or if no target folders are given.
+fn folders(config_path: Option<PathBuf>, targets: Vec<PathBuf>) -> Result<Vec<Folder>> {
+    let mut folders = vec![];
+    if config_path.is_some() || targets.is_empty() {
+        let config_path = match config_path {
+            Some(path) => path,
+            None => default_config_path()?,
+        };
+        for job in load_config(&config_path)?.jobs {
+            folders.push(Folder {
+                name: job.name.clone(),
+                target: job.target.clone(),
+                options: job.backup_options()?,
+            });
+        }
+    }
+
+    folders.extend(targets.into_iter().map(|target| Folder {
+        name: target.display().to_string(),
+        target,
+        options: BackupOptions::default(),
+    }));
+
+    Ok(folders)
+}
+
+/// Shows the backups of the config file jobs or the given target folders in a terminal UI.
+pub fn tui(config_path: Option<PathBuf>, targets: Vec<PathBuf>) -> Result<()> {
+    let folders = folders(config_path, targets)?;
+    if folders.is_empty() {
+        return Err(eyre!("No backup folders to show."))
+            .suggestion("Add jobs to the config file or pass target folders.");
+    }
+
+    set_terminal_logging(false);
+    let mut terminal = ratatui::init();
+    let result = App::new(folders).run(&mut terminal);
+    ratatui::restore();
+    set_terminal_logging(true);
+
+    result
+}
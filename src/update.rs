@@ -0,0 +1,311 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    Section,
+    eyre::{Context, ContextCompat, Result, eyre},
+};
+use log::{info, warn};
+use semver::Version;
+use serde::Deserialize;
+use ureq::Agent;
+
+use crate::{
+    backup::{
+        hash::hash_file,
+        signature::{load_public_key, signature_path, verify_signature},
+    },
+    error_code::{ErrorCode, WithErrorCode},
+};
+
+/// Target triple the binary was built for, matching the names of the release assets.
+const TARGET: &str = env!("SFB_BUILD_TARGET");
+
+/// Largest checksum or signature file downloaded.
+const SMALL_FILE_LIMIT: u64 = 64 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    draft: bool,
+    prerelease: bool,
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    fn version(&self) -> Result<Version> {
+        Version::parse(self.tag_name.trim_start_matches('v'))
+            .wrap_err_with(|| format!("Invalid version of release {}", self.tag_name))
+    }
+
+    fn asset_url(&self, name: &str) -> Option<&str> {
+        self.assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .map(|asset| asset.browser_download_url.as_str())
+    }
+}
+
+/// Name of the release asset with the binary for this platform, e.g.
+/// `staggered-file-backup-x86_64-unknown-linux-musl`.
+fn binary_asset_name() -> String {
+    format!(
+        "{}-{}{}",
+        env!("CARGO_PKG_NAME"),
+        TARGET,
+        std::env::consts::EXE_SUFFIX
+    )
+}
+
+/// URL of the release feed of the GitHub repository of the project.
+fn releases_url() -> String {
+    let repository = env!("CARGO_PKG_REPOSITORY").trim_end_matches('/');
+    let path = repository
+        .strip_prefix("https://github.com/")
+        .unwrap_or(repository);
+    format!("https://api.github.com/repos/{}/releases", path)
+}
+
+/// Newest published release, a pre-release only if this version is one too.
+fn latest_release(agent: &Agent) -> Result<Release> {
+    let current_is_prerelease = env!("CARGO_PKG_VERSION").contains('-');
+    let json = agent
+        .get(releases_url())
+        .header("Accept", "application/vnd.github+json")
+        .header("User-Agent", env!("CARGO_PKG_NAME"))
+        .call()
+        .wrap_err("Failed to fetch the release feed.")
+        .suggestion("Check the internet connection.")?
+        .into_body()
+        .read_to_string()
+        .wrap_err("Failed to read the release feed.")?;
+    let releases: Vec<Release> =
+        serde_json::from_str(&json).wrap_err("Failed to parse the release feed.")?;
+
+    releases
+        .into_iter()
+        .find(|release| !release.draft && (current_is_prerelease || !release.prerelease))
+        .wrap_err("No release found.")
+}
+
+fn download_text(agent: &Agent, url: &str) -> Result<String> {
+    agent
+        .get(url)
+        .header("User-Agent", env!("CARGO_PKG_NAME"))
+        .call()
+        .wrap_err_with(|| format!("Failed to download {}", url))?
+        .into_body()
+        .with_config()
+        .limit(SMALL_FILE_LIMIT)
+        .read_to_string()
+        .wrap_err_with(|| format!("Failed to download {}", url))
+}
+
+fn download_file(agent: &Agent, url: &str, path: &Path) -> Result<()> {
+    let mut reader = agent
+        .get(url)
+        .header("User-Agent", env!("CARGO_PKG_NAME"))
+        .call()
+        .wrap_err_with(|| format!("Failed to download {}", url))?
+        .into_body()
+        .into_reader();
+    let mut writer = BufWriter::new(
+        File::create(path).wrap_err_with(|| format!("Failed to create {}", path.display()))?,
+    );
+    std::io::copy(&mut reader, &mut writer)
+        .wrap_err_with(|| format!("Failed to download {}", url))?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Hash of the checksum file, like written by `sha256sum`.
+fn parse_checksum(content: &str) -> Option<&str> {
+    content
+        .split_whitespace()
+        .next()
+        .filter(|hash| hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Replaces the running executable by the file, which must be in the same folder.
+fn replace_executable(executable: &Path, new_executable: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(new_executable, std::fs::Permissions::from_mode(0o755))
+            .wrap_err("Failed to make the new executable executable.")?;
+    }
+    // A running executable cannot be overwritten on Windows, but it can be renamed.
+    #[cfg(windows)]
+    {
+        let old_executable = executable.with_extension("old.exe");
+        let _ = std::fs::remove_file(&old_executable);
+        std::fs::rename(executable, &old_executable)
+            .wrap_err("Failed to move the running executable aside.")?;
+    }
+
+    std::fs::rename(new_executable, executable)
+        .wrap_err_with(|| format!("Failed to replace the executable {}", executable.display()))
+}
+
+/// Checks if the release is newer than the running version, which may be newer than any release
+/// if built from source.
+fn is_newer(release: &Version, current: &Version) -> bool {
+    release > current
+}
+
+/// Downloads the newest release for this platform from the release feed and replaces the running
+/// executable with it, after checking its checksum and signature.
+///
+/// The checksum is downloaded from the same place as the binary, so it only guards against broken
+/// downloads. Without `verify_key` the update is refused, unless `insecure` is set. With `check`
+/// the newer version is only reported.
+pub fn self_update(verify_key: Option<PathBuf>, insecure: bool, check: bool) -> Result<()> {
+    let public_key = verify_key.map(load_public_key).transpose()?;
+    let agent: Agent = Agent::config_builder().build().into();
+
+    let release = latest_release(&agent)?;
+    let version = release.version()?;
+    let current = Version::parse(env!("CARGO_PKG_VERSION"))?;
+    if !is_newer(&version, &current) {
+        info!(
+            "Version {} is up to date, the newest release is {}.",
+            current, version
+        );
+        return Ok(());
+    }
+    if check {
+        info!(
+            "Version {} is available, this is version {}.",
+            version, current
+        );
+        return Ok(());
+    }
+    if public_key.is_none() && !insecure {
+        return Err(eyre!(
+            "Refusing to update without checking the signature of the release."
+        ))
+        .suggestion("Pass the public key of the project with `--verify-key`.")
+        .suggestion("Use `--insecure` to trust the checksum published with the release instead.");
+    }
+
+    let asset_name = binary_asset_name();
+    let binary_url = release
+        .asset_url(&asset_name)
+        .wrap_err_with(|| format!("Release {} has no binary for {}", release.tag_name, TARGET))
+        .suggestion("Download the release manually from the project page.")?;
+    let checksum_url = release
+        .asset_url(&format!("{}.sha256", asset_name))
+        .wrap_err_with(|| {
+            format!(
+                "Release {} has no checksum of {}",
+                release.tag_name, asset_name
+            )
+        })?;
+
+    let executable = std::env::current_exe()
+        .and_then(|path| path.canonicalize())
+        .wrap_err("Failed to locate the running executable.")?;
+    let download_path = executable.with_file_name(format!(".{}.update", asset_name));
+
+    info!("Downloading {} {}.", asset_name, version);
+    let result = (|| -> Result<()> {
+        let checksum = download_text(&agent, checksum_url)?;
+        let expected = parse_checksum(&checksum)
+            .wrap_err_with(|| format!("Invalid checksum file of {}", asset_name))?;
+
+        download_file(&agent, binary_url, &download_path)?;
+        let hash = hash_file(
+            &mut File::open(&download_path)
+                .wrap_err("Failed to open the downloaded executable.")?,
+        )?;
+        if !hash.eq_ignore_ascii_case(expected) {
+            return Err(eyre!(
+                "Checksum of the downloaded {} does not match the release.",
+                asset_name
+            ))
+            .error_code(ErrorCode::UpdateMismatch);
+        }
+        info!("Checksum matches: {}", hash);
+
+        if let Some(public_key) = &public_key {
+            let signature_url = release
+                .asset_url(&format!("{}.minisig", asset_name))
+                .wrap_err_with(|| format!("Release {} has no signature", release.tag_name))
+                .error_code(ErrorCode::UpdateMismatch)?;
+            std::fs::write(
+                signature_path(&download_path),
+                download_text(&agent, signature_url)?,
+            )
+            .wrap_err("Failed to write the downloaded signature.")?;
+            if !verify_signature(public_key, &download_path) {
+                return Err(eyre!(
+                    "Signature of the downloaded {} is invalid!",
+                    asset_name
+                ))
+                .error_code(ErrorCode::UpdateMismatch);
+            }
+            info!("Signature is valid.");
+        } else {
+            warn!("Not checking the signature of the download, as `--insecure` is set.");
+        }
+
+        replace_executable(&executable, &download_path)
+    })();
+
+    for path in [signature_path(&download_path), download_path] {
+        if path.exists() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    result?;
+
+    info!("Updated from {} to {}.", current, version);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_checksum() {
+        let hash = "a".repeat(64);
+        assert_eq!(
+            parse_checksum(&format!("{}  staggered-file-backup\n", hash)),
+            Some(hash.as_str())
+        );
+        assert_eq!(parse_checksum(&hash), Some(hash.as_str()));
+        assert_eq!(parse_checksum("not a hash  staggered-file-backup"), None);
+        assert_eq!(parse_checksum(""), None);
+    }
+
+    #[test]
+    fn test_is_newer() {
+        let version = |version| Version::parse(version).unwrap();
+
+        assert!(is_newer(&version("0.2.0"), &version("0.1.9")));
+        assert!(is_newer(&version("0.10.0"), &version("0.9.0")));
+        assert!(is_newer(&version("0.1.0"), &version("0.1.0-alpha.3")));
+        assert!(!is_newer(&version("0.1.0"), &version("0.1.0")));
+        // Never downgrade builds newer than the latest release.
+        assert!(!is_newer(&version("0.1.0-alpha.3"), &version("0.1.0")));
+        assert!(!is_newer(&version("0.9.0"), &version("0.10.0")));
+    }
+}
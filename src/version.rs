@@ -0,0 +1,115 @@
+// Copyright 2025 Adam McKellar <dev@mckellar.eu>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use chrono::DateTime;
+use clap::CommandFactory;
+use color_eyre::eyre::Result;
+use serde::Serialize;
+
+use crate::Cli;
+
+/// Version and capabilities of the build, printed by `--version --json` for inventories.
+#[derive(Debug, Serialize)]
+struct VersionInfo {
+    name: &'static str,
+    version: &'static str,
+    /// Git commit the binary was built from, unknown outside a git checkout.
+    commit: Option<&'static str>,
+    /// Time the binary was built, like `2025-06-15T12:00:00+00:00`.
+    build_date: Option<String>,
+    target: &'static str,
+    /// Places backups can be copied to.
+    backends: Vec<&'static str>,
+    /// Formats backups can be stored in.
+    formats: Vec<&'static str>,
+    compression: Vec<&'static str>,
+    encryption: Vec<&'static str>,
+    /// Optional features available on this platform.
+    features: Vec<&'static str>,
+}
+
+impl VersionInfo {
+    fn new() -> Self {
+        let commit = env!("SFB_GIT_COMMIT");
+        let build_date = env!("SFB_BUILD_TIME")
+            .parse()
+            .ok()
+            .filter(|timestamp| *timestamp > 0)
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+            .map(|date| date.to_rfc3339());
+
+        let mut features = vec![
+            "signatures",
+            "hash-chain",
+            "incremental",
+            "dedup-store",
+            "parity",
+            "reflink",
+            "keyring",
+            "recycle-bin",
+        ];
+        // Listing and restoring the recycle bin is not supported on macOS.
+        if cfg!(any(
+            windows,
+            all(
+                unix,
+                not(any(
+                    target_os = "macos",
+                    target_os = "ios",
+                    target_os = "android"
+                ))
+            )
+        )) {
+            features.push("undo-prune");
+        }
+        if cfg!(windows) {
+            features.push("shadow-copy");
+        }
+
+        Self {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            commit: (!commit.is_empty()).then_some(commit),
+            build_date,
+            target: env!("SFB_BUILD_TARGET"),
+            backends: vec!["local", "rclone", "webdav"],
+            formats: vec!["plain", "zip"],
+            compression: vec!["deflate"],
+            encryption: vec!["zip-aes"],
+            features,
+        }
+    }
+}
+
+/// Prints the version like `--version`, or with `json` the version and capabilities of the build.
+pub fn print_version(json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(&VersionInfo::new())?);
+    } else {
+        print!("{}", Cli::command().render_version());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_version_info() {
+        let json = serde_json::to_value(VersionInfo::new()).unwrap();
+
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["target"], env!("SFB_BUILD_TARGET"));
+        assert!(
+            json["backends"]
+                .as_array()
+                .unwrap()
+                .contains(&"local".into())
+        );
+    }
+}